@@ -2,9 +2,7 @@ use std::env;
 use std::thread;
 use std::time::Duration;
 
-use futures::future::Future;
 use log::info;
-use pretty_env_logger;
 use tokio::runtime::Runtime;
 
 use reool::RedisPool;
@@ -19,7 +17,7 @@ fn main() {
     let pool = RedisPool::builder()
         .connect_to_node("redis://127.0.0.1:6379")
         .desired_pool_size(5)
-        .task_executor(runtime.executor())
+        .task_executor(runtime.handle().clone())
         .finish_redis_rs()
         .unwrap();
 
@@ -34,7 +32,7 @@ fn main() {
             "redis://127.0.0.1:6379".to_string(),
         ])
         .desired_pool_size(5)
-        .task_executor(runtime.executor())
+        .task_executor(runtime.handle().clone())
         .finish_redis_rs()
         .unwrap();
 
@@ -43,5 +41,5 @@ fn main() {
     drop(pool);
     info!("DROPPED multi node pool POOL");
 
-    runtime.shutdown_on_idle().wait().unwrap();
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
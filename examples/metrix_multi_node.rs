@@ -1,13 +1,12 @@
 use std::env;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use futures::future::{join_all, Future};
+use futures::future::join_all;
 use log::{debug, error, info};
 use metrix::driver::DriverBuilder;
-use pretty_env_logger;
 use tokio::runtime::Runtime;
 
-use reool::{config::*, *};
+use reool::*;
 
 /// Do many ping commands where many will fail because either
 /// the checkout ties out or the checkout queue is full
@@ -20,7 +19,7 @@ fn main() {
         .set_driver_metrics(false)
         .build();
 
-    let mut runtime = Runtime::new().unwrap();
+    let runtime = Runtime::new().unwrap();
 
     let pool = RedisPool::builder()
         .connect_to_nodes(vec![
@@ -31,7 +30,7 @@ fn main() {
         .desired_pool_size(10)
         .reservation_limit(1_000)
         .default_checkout_mode(Immediately)
-        .task_executor(runtime.executor())
+        .task_executor(runtime.handle().clone())
         .with_mounted_metrix_instrumentation(&mut driver, Default::default())
         .finish_redis_rs()
         .unwrap();
@@ -39,28 +38,23 @@ fn main() {
     info!("Do 10000 pings concurrently");
     let futs: Vec<_> = (0..10_000)
         .map(|i| {
-            pool.check_out_default()
-                .from_err()
-                .and_then(Commands::ping)
-                .then(move |res| match res {
-                    Err(err) => {
-                        error!("PING {} failed: {}", i, err);
-                        Ok(())
-                    }
-                    Ok(_) => {
-                        debug!("PING {} OK", i);
-                        Ok::<_, ()>(())
-                    }
-                })
+            let checkout = pool.check_out_default();
+            async move {
+                let res = match checkout.await {
+                    Ok(mut conn) => conn.ping().await,
+                    Err(err) => Err(err.into()),
+                };
+                match res {
+                    Err(err) => error!("PING {} failed: {}", i, err),
+                    Ok(()) => debug!("PING {} OK", i),
+                }
+            }
         })
         .collect();
 
-    let fut = join_all(futs).map(|_| {
-        info!("finished pinging");
-    });
-
     let start = Instant::now();
-    runtime.block_on(fut).unwrap();
+    runtime.block_on(join_all(futs));
+    info!("finished pinging");
     info!("PINGED 10000 times concurrently in {:?}", start.elapsed());
 
     let metrics_snapshot = driver.snapshot(false).unwrap();
@@ -69,5 +63,5 @@ fn main() {
 
     drop(pool);
     info!("pool dropped");
-    runtime.shutdown_on_idle().wait().unwrap();
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
@@ -1,10 +1,9 @@
 use std::env;
 use std::time::{Duration, Instant};
 
-use futures::future::{join_all, Future};
+use futures::future::join_all;
 use log::{debug, error, info};
 use metrix::driver::DriverBuilder;
-use pretty_env_logger;
 use tokio::runtime::Runtime;
 
 use reool::config::ActivationOrder;
@@ -21,7 +20,7 @@ fn main() {
         .set_driver_metrics(false)
         .build();
 
-    let mut runtime = Runtime::new().unwrap();
+    let runtime = Runtime::new().unwrap();
 
     let pool = RedisPool::builder()
         .connect_to_node("redis://127.0.0.1:6379")
@@ -29,7 +28,7 @@ fn main() {
         .reservation_limit(10_000)
         .default_checkout_mode(Duration::from_secs(10))
         .activation_order(ActivationOrder::LiFo)
-        .task_executor(runtime.executor())
+        .task_executor(runtime.handle().clone())
         .with_mounted_metrix_instrumentation(&mut driver, Default::default())
         .finish_redis_rs()
         .unwrap();
@@ -40,28 +39,23 @@ fn main() {
     info!("Do 20000 pings concurrently");
     let futs: Vec<_> = (0..20_000)
         .map(|i| {
-            pool.check_out_default()
-                .from_err()
-                .and_then(Commands::ping)
-                .then(move |res| match res {
-                    Err(err) => {
-                        error!("PING {} failed: {}", i, err);
-                        Ok(())
-                    }
-                    Ok(_) => {
-                        debug!("PING {} OK", i);
-                        Ok::<_, ()>(())
-                    }
-                })
+            let checkout = pool.check_out_default();
+            async move {
+                let res = match checkout.await {
+                    Ok(mut conn) => conn.ping().await,
+                    Err(err) => Err(err.into()),
+                };
+                match res {
+                    Err(err) => error!("PING {} failed: {}", i, err),
+                    Ok(()) => debug!("PING {} OK", i),
+                }
+            }
         })
         .collect();
 
-    let fut = join_all(futs).map(|_| {
-        info!("finished pinging");
-    });
-
     let start = Instant::now();
-    runtime.block_on(fut).unwrap();
+    runtime.block_on(join_all(futs));
+    info!("finished pinging");
     info!("PINGED 20000 times concurrently in {:?}", start.elapsed());
 
     let metrics_snapshot = driver.snapshot(false).unwrap();
@@ -71,10 +65,13 @@ fn main() {
     std::thread::sleep(Duration::from_millis(1500));
 
     runtime
-        .block_on(pool.check_out_default().from_err().and_then(Commands::ping))
+        .block_on(async {
+            let mut conn = pool.check_out_default().await?;
+            conn.ping().await
+        })
         .unwrap();
 
     drop(pool);
     info!("pool dropped");
-    runtime.shutdown_on_idle().wait().unwrap();
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
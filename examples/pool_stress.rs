@@ -4,12 +4,9 @@ use std::sync::{
     Arc,
 };
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
-use futures::{
-    future::{self, Future},
-    stream::{self, Stream},
-};
+use futures::future;
 use log::info;
 use metrix::cockpit::Cockpit;
 use metrix::instruments::*;
@@ -18,10 +15,8 @@ use metrix::{
     driver::{DriverBuilder, TelemetryDriver},
     TelemetryTransmitter, TransmitsTelemetryData,
 };
-
-use pretty_env_logger;
 use tokio::runtime::Builder as RuntimeBuilder;
-use tokio::timer::Delay;
+use tokio::time;
 
 use reool::connection_factory::*;
 use reool::CheckoutErrorKind;
@@ -36,7 +31,11 @@ fn main() {
 
     let mut driver = DriverBuilder::default().set_driver_metrics(false).build();
 
-    let mut runtime = RuntimeBuilder::new().core_threads(1).build().unwrap();
+    let runtime = RuntimeBuilder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .unwrap();
 
     let pool = RedisPool::builder()
         //.connect_to_nodes(vec!["C1".to_string()])
@@ -53,7 +52,7 @@ fn main() {
         .retry_on_checkout_limit(true)
         .pool_multiplier(1)
         .default_checkout_mode(Duration::from_millis(30))
-        .task_executor(runtime.executor())
+        .task_executor(runtime.handle().clone())
         .with_mounted_metrix_instrumentation(&mut driver, Default::default())
         .finish(|conn| Ok(MyConnectionFactory(Arc::new(conn), AtomicUsize::new(0))))
         .unwrap();
@@ -82,42 +81,23 @@ fn main() {
     let checkout_mode = PoolDefault;
     //let checkout_mode = Duration::from_millis(1);
 
-    let clients = future::lazy({
+    for _n in 0..num_clients {
         let running = Arc::clone(&running);
         let pool = pool.clone();
         let collect_result_metrics = collect_result_metrics.clone();
-        move || {
-            (0..num_clients).for_each(|_n| {
-                let running = Arc::clone(&running);
-                let pool = pool.clone();
-                let collect_result_metrics = collect_result_metrics.clone();
-                let f = stream::repeat(()).for_each(move |_| {
-                    if !running.load(Ordering::Relaxed) {
-                        Box::new(future::err(()))
-                    } else {
-                        let f = collect_result_metrics
-                            .collect(pool.check_out(checkout_mode))
-                            .map_err(|_err| ())
-                            .and_then(move |_c| {
-                                if let Some(delay) = delay_dur {
-                                    Box::new(Delay::new(Instant::now() + delay))
-                                } else {
-                                    Box::new(future::ok(()))
-                                        as Box<dyn Future<Item = _, Error = _> + Send>
-                                }
-                                .map(move |_c| ())
-                                .or_else(|_| Ok(()))
-                            });
-                        Box::new(f) as Box<dyn Future<Item = _, Error = _> + Send>
+        runtime.spawn(async move {
+            while running.load(Ordering::Relaxed) {
+                if let Ok(_c) = collect_result_metrics
+                    .collect(pool.check_out(checkout_mode))
+                    .await
+                {
+                    if let Some(delay) = delay_dur {
+                        time::sleep(delay).await;
                     }
-                });
-                tokio::spawn(f);
-            });
-            Ok(())
-        }
-    });
-
-    runtime.spawn(clients);
+                }
+            }
+        });
+    }
 
     thread::sleep(Duration::from_secs(60));
     info!("Finished");
@@ -125,13 +105,14 @@ fn main() {
     drop(pool);
     info!("pool dropped");
     running.store(false, Ordering::Relaxed);
-    runtime.shutdown_on_idle().wait().unwrap();
+    runtime.shutdown_timeout(Duration::from_secs(1));
     thread::sleep(Duration::from_secs(2));
     info!("final state:\n{:#?}", state);
     report_stats(&driver);
     info!("=== FINISHED ===");
 }
 
+#[allow(dead_code)]
 struct MyConn(usize, Arc<String>);
 
 impl Poolable for MyConn {
@@ -233,40 +214,58 @@ enum ResultMetric {
     NoPool,
     CheckoutLimitReached,
     TaskExecution,
+    ShuttingDown,
+    PartitionLimitReached,
+    UnknownPartition,
 }
 
 fn create_result_metrics(metrix: &mut TelemetryDriver) -> ResultCollector {
     let mut cockpit = Cockpit::without_name();
 
     let mut panel = Panel::named(ResultMetric::Checkout, "checkout");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(ResultMetric::NoConnection, "no_connection");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(ResultMetric::CheckoutTimeout, "checkout_timeout");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(
         ResultMetric::ReservationLimitReached,
         "reservation_limit_reached",
     );
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(ResultMetric::NoPool, "no_pool");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(ResultMetric::CheckoutLimitReached, "checkout_limit_reached");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(ResultMetric::TaskExecution, "task_execution");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
+    cockpit.add_panel(panel);
+
+    let mut panel = Panel::named(ResultMetric::ShuttingDown, "shutting_down");
+    panel.add_meter(Meter::new_with_defaults("per_second"));
+    cockpit.add_panel(panel);
+
+    let mut panel = Panel::named(
+        ResultMetric::PartitionLimitReached,
+        "partition_limit_reached",
+    );
+    panel.add_meter(Meter::new_with_defaults("per_second"));
+    cockpit.add_panel(panel);
+
+    let mut panel = Panel::named(ResultMetric::UnknownPartition, "unknown_partition");
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     cockpit.add_panel(panel);
 
     let (tx, mut rx) = TelemetryProcessor::new_pair("checkout_results");
@@ -281,12 +280,12 @@ fn create_result_metrics(metrix: &mut TelemetryDriver) -> ResultCollector {
 struct ResultCollector(TelemetryTransmitter<ResultMetric>);
 
 impl ResultCollector {
-    pub fn collect<T: Poolable>(
+    pub async fn collect<T: Poolable>(
         &self,
         c: Checkout<T>,
-    ) -> impl Future<Item = PoolConnection<T>, Error = CheckoutError> + Send {
-        let tx = self.0.clone();
-        c.then(move |r| match r {
+    ) -> Result<PoolConnection<T>, CheckoutError> {
+        let tx = &self.0;
+        match c.await {
             Ok(conn) => {
                 tx.observed_one_now(ResultMetric::Checkout);
                 Ok(conn)
@@ -309,9 +308,18 @@ impl ResultCollector {
                     CheckoutErrorKind::TaskExecution => {
                         tx.observed_one_now(ResultMetric::TaskExecution)
                     }
+                    CheckoutErrorKind::ShuttingDown => {
+                        tx.observed_one_now(ResultMetric::ShuttingDown)
+                    }
+                    CheckoutErrorKind::PartitionLimitReached => {
+                        tx.observed_one_now(ResultMetric::PartitionLimitReached)
+                    }
+                    CheckoutErrorKind::UnknownPartition => {
+                        tx.observed_one_now(ResultMetric::UnknownPartition)
+                    }
                 };
                 Err(err)
             }
-        })
+        }
     }
 }
@@ -1,9 +1,9 @@
 use std::env;
+use std::time::Duration;
 
-use futures::future::{join_all, lazy, Future};
+use futures::future::join_all;
 use log::{debug, error, info};
-use pretty_env_logger;
-use tokio::{self, runtime::Runtime};
+use tokio::runtime::Runtime;
 
 use reool::*;
 
@@ -13,50 +13,43 @@ fn main() {
     env::set_var("RUST_LOG", "reool=debug,runtime=debug");
     let _ = pretty_env_logger::try_init();
 
-    let mut runtime = Runtime::new().unwrap();
+    let runtime = Runtime::new().unwrap();
 
-    let fut = lazy(|| {
+    let fut = async {
         let pool = RedisPool::builder()
             .connect_to_node("redis://127.0.0.1:6379")
             .desired_pool_size(10)
             .reservation_limit(1_000_000)
             .default_checkout_mode(Immediately)
-            //.task_executor(runtime.executor()) no explicit executor!
+            //.task_executor(runtime.handle().clone()) no explicit executor!
             .finish_redis_rs()
             .unwrap();
 
         info!("Do one 1000 pings concurrently");
         let futs: Vec<_> = (0..1_000)
             .map(|i| {
-                pool.check_out(PoolDefault)
-                    .from_err()
-                    .and_then(Commands::ping)
-                    .then(move |res| match res {
-                        Err(err) => {
-                            error!("PING {} failed: {}", i, err);
-                            Ok(())
-                        }
-                        Ok(_) => {
-                            debug!("PING {} OK", i);
-                            Ok::<_, ()>(())
-                        }
-                    })
+                let checkout = pool.check_out(PoolDefault);
+                async move {
+                    let res = match checkout.await {
+                        Ok(mut conn) => conn.ping().await,
+                        Err(err) => Err(err.into()),
+                    };
+                    match res {
+                        Err(err) => error!("PING {} failed: {}", i, err),
+                        Ok(()) => debug!("PING {} OK", i),
+                    }
+                }
             })
             .collect();
 
-        let fut = join_all(futs).map(|_| {
-            info!("finished pinging");
-        });
+        join_all(futs).await;
+        info!("finished pinging");
 
-        fut.map(move |_| pool)
-    })
-    .map(|_| {
         info!("PINGED 1000 times concurrently");
         info!("pool goes out of scope");
-    })
-    .map_err(|_| ());
+    };
 
-    runtime.block_on(fut).unwrap();
+    runtime.block_on(fut);
 
-    runtime.shutdown_on_idle().wait().unwrap();
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
@@ -0,0 +1,39 @@
+use std::env;
+use std::time::Duration;
+
+use log::info;
+use tokio::runtime::Runtime;
+
+use reool::{Commands, RedisPool};
+
+/// Connect to redis via TLS and do a ping.
+///
+/// Expects a TLS terminator (e.g. `stunnel`) in front of
+/// redis listening on port 6380 using a self signed certificate
+/// given by `REOOL_TLS_CA_BUNDLE`.
+fn main() {
+    env::set_var("RUST_LOG", "reool=debug,tls=info");
+    let _ = pretty_env_logger::try_init();
+
+    let runtime = Runtime::new().unwrap();
+
+    let pool = RedisPool::builder()
+        .connect_to_node("rediss://localhost:6380")
+        .desired_pool_size(1)
+        .task_executor(runtime.handle().clone())
+        .updated_from_environment(None)
+        .unwrap()
+        .finish_redis_rs()
+        .unwrap();
+
+    let fut = async {
+        let mut conn = pool.check_out_default().await?;
+        conn.ping().await
+    };
+    runtime.block_on(fut).unwrap();
+    info!("PING via TLS succeeded");
+
+    drop(pool);
+
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
@@ -2,12 +2,13 @@ use std::error::Error as StdError;
 use std::fmt;
 
 /// Defines the strategy by which idle connections are taken from the pool.
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
 pub enum ActivationOrder {
     /// First In - First Out
     ///
     /// Connections are taken in the same order they were
     /// added/returned to the pool
+    #[default]
     FiFo,
     /// Last In - First Out
     ///
@@ -17,12 +18,6 @@ pub enum ActivationOrder {
     LiFo,
 }
 
-impl Default for ActivationOrder {
-    fn default() -> Self {
-        ActivationOrder::FiFo
-    }
-}
-
 impl fmt::Display for ActivationOrder {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -54,7 +54,7 @@ pub enum BackoffStrategy {
 impl BackoffStrategy {
     pub(crate) fn get_next_backoff(&self, attempt: usize) -> Option<Duration> {
         fn calc_backoff(attempt: usize) -> Duration {
-            let idx = (if attempt == 0 { 0 } else { attempt - 1 }) as usize;
+            let idx = if attempt == 0 { 0 } else { attempt - 1 };
             if idx < NEW_CONN_BACKOFFS_MS.len() {
                 NEW_CONN_BACKOFFS_MS[idx]
             } else {
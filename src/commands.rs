@@ -1,118 +1,123 @@
-use futures::future::Future;
+use futures::future::FutureExt;
 use redis::{aio::ConnectionLike, cmd, Cmd, ErrorKind, FromRedisValue, RedisFuture, ToRedisArgs};
 
-impl<T> Commands for T where T: ConnectionLike + Sized + Send + 'static {}
+impl<T> Commands for T where T: ConnectionLike + Sized + Send {}
 
 /// A helper trait to easily execute common
 /// asynchronous Redis commands on a
 /// `redis::aio::ConnectionLike`
-pub trait Commands: Sized + ConnectionLike + Send + 'static {
+pub trait Commands: Sized + ConnectionLike + Send {
     /// Execute a command and expect a result
-    fn query<T>(self, cmd: &Cmd) -> RedisFuture<(Self, T)>
+    fn query<'a, T>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, T>
     where
-        T: FromRedisValue + Send + 'static,
+        T: FromRedisValue + Send + 'a,
     {
-        cmd.query_async(self)
+        cmd.query_async(self).boxed()
     }
 
     /// Execute a command and do not expect a result and instead
     /// just check whether the command did not fail
-    fn execute<T>(self, cmd: &Cmd) -> RedisFuture<(Self, ())>
+    fn execute<'a, T>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, ()>
     where
-        T: FromRedisValue + Send + 'static,
+        T: FromRedisValue + Send + 'a,
     {
-        cmd.query_async(self)
+        cmd.query_async(self).boxed()
     }
 
     /// Send a ping command.
-    fn ping(self) -> RedisFuture<(Self, ())> {
-        Box::new(
-            Cmd::new()
-                .arg("PING")
-                .query_async::<_, String>(self)
-                .and_then(|(conn, rsp)| {
-                    if rsp == "PONG" {
-                        Ok((conn, ()))
-                    } else {
-                        Err((ErrorKind::IoError, "ping failed").into())
-                    }
-                }),
-        )
+    fn ping(&mut self) -> RedisFuture<'_, ()> {
+        async move {
+            let rsp: String = Cmd::new().arg("PING").query_async(self).await?;
+            if rsp == "PONG" {
+                Ok(())
+            } else {
+                Err((ErrorKind::IoError, "ping failed").into())
+            }
+        }
+        .boxed()
     }
 
     /// Gets all keys matching pattern
-    fn keys<K: ToRedisArgs, RV: FromRedisValue + Send + 'static>(
-        self,
-        key: K,
-    ) -> RedisFuture<(Self, RV)> {
-        cmd("KEYS").arg(key).query_async(self)
+    fn keys<K: ToRedisArgs, RV: FromRedisValue + Send>(&mut self, key: K) -> RedisFuture<'_, RV> {
+        let mut cmd = cmd("KEYS");
+        cmd.arg(key);
+        query_owned(self, cmd)
     }
 
     /// Get the value of a key.  If key is a vec this becomes an `MGET`.
-    fn get<K: ToRedisArgs, RV: FromRedisValue + Send + 'static>(
-        self,
-        key: K,
-    ) -> RedisFuture<(Self, RV)> {
-        cmd(if key.is_single_arg() { "GET" } else { "MGET" })
-            .arg(key)
-            .query_async(self)
+    fn get<K: ToRedisArgs, RV: FromRedisValue + Send>(&mut self, key: K) -> RedisFuture<'_, RV> {
+        let mut cmd = cmd(if key.is_single_arg() { "GET" } else { "MGET" });
+        cmd.arg(key);
+        query_owned(self, cmd)
     }
 
     /// Set the string value of a key.
-    fn set<K: ToRedisArgs, V: ToRedisArgs, RV: FromRedisValue + Send + 'static>(
-        self,
+    fn set<K: ToRedisArgs, V: ToRedisArgs, RV: FromRedisValue + Send>(
+        &mut self,
         key: K,
         value: V,
-    ) -> RedisFuture<(Self, RV)> {
-        cmd("SET").arg(key).arg(value).query_async(self)
+    ) -> RedisFuture<'_, RV> {
+        let mut cmd = cmd("SET");
+        cmd.arg(key).arg(value);
+        query_owned(self, cmd)
     }
 
     /// Set the value of a key, only if the key does not exist
-    fn set_nx<K: ToRedisArgs, V: ToRedisArgs, RV: FromRedisValue + Send + 'static>(
-        self,
+    fn set_nx<K: ToRedisArgs, V: ToRedisArgs, RV: FromRedisValue + Send>(
+        &mut self,
         key: K,
         value: V,
-    ) -> RedisFuture<(Self, RV)> {
-        cmd("SETNX").arg(key).arg(value).query_async(self)
+    ) -> RedisFuture<'_, RV> {
+        let mut cmd = cmd("SETNX");
+        cmd.arg(key).arg(value);
+        query_owned(self, cmd)
     }
 
     /// Sets multiple keys to their values.
-    fn set_multiple<K: ToRedisArgs, V: ToRedisArgs, RV: FromRedisValue + Send + 'static>(
-        self,
+    fn set_multiple<K: ToRedisArgs, V: ToRedisArgs, RV: FromRedisValue + Send>(
+        &mut self,
         items: &[(K, V)],
-    ) -> RedisFuture<(Self, RV)> {
-        cmd("MSET").arg(items).query_async(self)
+    ) -> RedisFuture<'_, RV> {
+        let mut cmd = cmd("MSET");
+        cmd.arg(items);
+        query_owned(self, cmd)
     }
 
     /// Sets multiple keys to their values failing if at least one already exists.
-    fn set_multiple_nx<K: ToRedisArgs, V: ToRedisArgs, RV: FromRedisValue + Send + 'static>(
-        self,
+    fn set_multiple_nx<K: ToRedisArgs, V: ToRedisArgs, RV: FromRedisValue + Send>(
+        &mut self,
         items: &[(K, V)],
-    ) -> RedisFuture<(Self, RV)> {
-        cmd("MSETNX").arg(items).query_async(self)
+    ) -> RedisFuture<'_, RV> {
+        let mut cmd = cmd("MSETNX");
+        cmd.arg(items);
+        query_owned(self, cmd)
     }
 
     /// Delete one or more keys.
-    fn del<K: ToRedisArgs, RV: FromRedisValue + Send + 'static>(
-        self,
-        key: K,
-    ) -> RedisFuture<(Self, RV)> {
-        cmd("DEL").arg(key).query_async(self)
+    fn del<K: ToRedisArgs, RV: FromRedisValue + Send>(&mut self, key: K) -> RedisFuture<'_, RV> {
+        let mut cmd = cmd("DEL");
+        cmd.arg(key);
+        query_owned(self, cmd)
     }
 
     /// Determine if one or more keys exist.
-    fn exists<K: ToRedisArgs, RV: FromRedisValue + Send + 'static>(
-        self,
-        key: K,
-    ) -> RedisFuture<(Self, RV)> {
-        cmd("EXISTS").arg(key).query_async(self)
+    fn exists<K: ToRedisArgs, RV: FromRedisValue + Send>(&mut self, key: K) -> RedisFuture<'_, RV> {
+        let mut cmd = cmd("EXISTS");
+        cmd.arg(key);
+        query_owned(self, cmd)
     }
 
     /// Determine the number of keys.
-    fn db_size<K: ToRedisArgs, RV: FromRedisValue + Send + 'static>(
-        self,
-        key: K,
-    ) -> RedisFuture<(Self, RV)> {
-        cmd("DBSIZE").arg(key).query_async(self)
+    fn db_size<RV: FromRedisValue + Send>(&mut self) -> RedisFuture<'_, RV> {
+        query_owned(self, cmd("DBSIZE"))
     }
 }
+
+/// Runs a command which was built by the `Commands` trait itself
+fn query_owned<C, RV>(conn: &mut C, cmd: Cmd) -> RedisFuture<'_, RV>
+where
+    C: ConnectionLike + Send,
+    RV: FromRedisValue + Send,
+{
+    async move { cmd.query_async(conn).await }.boxed()
+}
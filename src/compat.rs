@@ -0,0 +1,27 @@
+//! Compatibility with futures 0.1
+//!
+//! Enabled with the feature `compat`.
+//!
+//! The returned futures still need the context of a tokio 1.x runtime
+//! to make progress. They can be used to migrate code which is
+//! based on futures 0.1 step by step.
+use futures::compat::Compat;
+use futures::future::TryFutureExt;
+
+use crate::{Checkout, CheckoutOptions, Ping, Poolable, RedisPool, Timeout};
+
+impl<T: Poolable> RedisPool<T> {
+    /// Same as `check_out` but returns a futures 0.1 `Future`
+    #[track_caller]
+    pub fn check_out_compat<M: Into<CheckoutOptions>>(&self, mode: M) -> Compat<Checkout<T>> {
+        self.check_out(mode).compat()
+    }
+
+    /// Same as `ping` but returns a futures 0.1 `Future`
+    pub fn ping_compat<TO: Into<Timeout>>(
+        &self,
+        timeout: TO,
+    ) -> impl futures01::Future<Item = Vec<Ping>, Error = ()> + Send {
+        Box::pin(self.ping(timeout)).compat()
+    }
+}
@@ -1,26 +1,35 @@
-use std::sync::Arc;
+use std::any::Any;
+use std::error::Error as StdError;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
 
 use log::{debug, info, warn};
 
-use crate::connection_factory::ConnectionFactory;
+use crate::connection_factory::{ConnectionFactory, ConnectionInitializer};
 use crate::error::InitializationResult;
 use crate::executor_flavour::ExecutorFlavour;
 use crate::instrumentation::{Instrumentation, InstrumentationFlavour};
-use crate::pools::{PoolPerNode, SinglePool};
+use crate::partitions::Partitions;
+use crate::pools::{
+    cluster_pool, ClusterPool, PoolPerNode, PrimaryReplicaPool, SentinelPool, SinglePool,
+};
 use crate::redis_rs::RedisRsFactory;
-use crate::{RedisPool, RedisPoolFlavour};
+use crate::{ConnectionFlavour, RedisPool, RedisPoolFlavour};
 
 use super::*;
 
 pub use crate::activation_order::ActivationOrder;
 pub use crate::backoff_strategy::BackoffStrategy;
 pub use crate::error::InitializationError;
+pub use crate::validation_policy::ValidationPolicy;
 
 /// A builder for a `RedisPool`
 pub struct Builder {
     config: Config,
     executor_flavour: ExecutorFlavour,
     instrumentation: InstrumentationFlavour,
+    connection_initializer: Option<ConnectionInitializer<ConnectionFlavour>>,
 }
 
 impl Default for Builder {
@@ -29,6 +38,7 @@ impl Default for Builder {
             config: Config::default(),
             executor_flavour: ExecutorFlavour::Runtime,
             instrumentation: InstrumentationFlavour::NoInstrumentation,
+            connection_initializer: None,
         }
     }
 }
@@ -38,12 +48,74 @@ impl Builder {
     /// multiple sub pools is created, this value applies to each
     /// sub pool.
     ///
+    /// If `max_pool_size` is set, this is the minimum number of
+    /// connections the pool will keep.
+    ///
     /// The default is 50.
     pub fn desired_pool_size(mut self, v: usize) -> Self {
         self.config.desired_pool_size = v;
         self
     }
 
+    /// The maximum number of connections a pool may grow to when
+    /// checkouts have to wait for a connection. If a pool with
+    /// multiple sub pools is created, this value applies to each
+    /// sub pool.
+    ///
+    /// If not set the pool has a fixed size of `desired_pool_size`.
+    pub fn max_pool_size(mut self, v: usize) -> Self {
+        self.config.max_pool_size = Some(v);
+        self
+    }
+
+    /// The time a connection exceeding `desired_pool_size` may stay
+    /// idle before it gets closed.
+    ///
+    /// The default is 60 seconds.
+    pub fn surplus_idle_timeout(mut self, v: Duration) -> Self {
+        self.config.surplus_idle_timeout = v;
+        self
+    }
+
+    /// Connections which have been idle for longer than the given
+    /// `Duration` will be closed and replaced by a new connection.
+    pub fn idle_timeout(mut self, v: Duration) -> Self {
+        self.config.idle_timeout = Some(v);
+        self
+    }
+
+    /// Connections older than the given `Duration` will be closed and replaced
+    /// by a new connection when checked in or while idle.
+    pub fn max_connection_lifetime(mut self, v: Duration) -> Self {
+        self.config.max_connection_lifetime = Some(v);
+        self
+    }
+
+    /// The lifetime of each connection will be shortened by a random amount of
+    /// at most this value so that not all connections get recycled at once.
+    ///
+    /// The default is 0.
+    pub fn connection_lifetime_jitter(mut self, v: Duration) -> Self {
+        self.config.connection_lifetime_jitter = v;
+        self
+    }
+
+    /// Defines when connections are validated.
+    ///
+    /// The default is `ValidationPolicy::Never`.
+    pub fn validation_policy(mut self, v: ValidationPolicy) -> Self {
+        self.config.validation_policy = v;
+        self
+    }
+
+    /// A validation not finished within this time counts as failed.
+    ///
+    /// The default is 1 second.
+    pub fn validation_timeout(mut self, v: Duration) -> Self {
+        self.config.validation_timeout = v;
+        self
+    }
+
     /// Sets the behaviour of the pool on checkouts if no specific behaviour
     /// was requested by the user.
     pub fn default_checkout_mode<T: Into<DefaultPoolCheckoutMode>>(mut self, v: T) -> Self {
@@ -92,6 +164,25 @@ impl Builder {
         self
     }
 
+    /// The replicas of the primary given with `connect_to_node`.
+    ///
+    /// Connections to the replicas can be checked out with
+    /// `RedisPool::check_out_replica` while `RedisPool::check_out_primary`
+    /// checks out connections to the primary.
+    pub fn connect_to_replicas(mut self, v: Vec<String>) -> Self {
+        self.config.replicas = v;
+        self
+    }
+
+    /// If `true` checkouts for replicas are made on the primary
+    /// if no replica is available.
+    ///
+    /// The default is `false`.
+    pub fn replica_fallback_to_primary(mut self, v: bool) -> Self {
+        self.config.replica_fallback_to_primary = v;
+        self
+    }
+
     /// When the pool is created this is a multiplier for the amount of sub
     /// pools to be created.
     ///
@@ -99,11 +190,21 @@ impl Builder {
     ///
     /// * `reservation_limit`: Stays zero if zero, otherwise (`reservation_limit`/multiplier) +1
     /// * `desired_pool_size`: `desired_pool_size`/multiplier) +1
+    /// * `max_pool_size`: `max_pool_size`/multiplier) +1
     pub fn pool_multiplier(mut self, v: u32) -> Self {
         self.config.pool_multiplier = v;
         self
     }
 
+    /// Defines how checkouts are distributed among the sub pools
+    /// if there are multiple nodes or a `pool_multiplier` > 1.
+    ///
+    /// The default is `LoadBalancingStrategy::RoundRobin`.
+    pub fn load_balancing_strategy(mut self, v: LoadBalancingStrategy) -> Self {
+        self.config.load_balancing_strategy = v;
+        self
+    }
+
     /// The number of checkouts that can be enqueued. If a pool with
     /// multiple sub pools is created, this value applies to each
     /// sub pool.
@@ -123,10 +224,212 @@ impl Builder {
         self
     }
 
-    /// The executor to use for spawning tasks. If not set it is assumed
-    /// that the pool is created on the default runtime.
-    pub fn task_executor(mut self, executor: ::tokio::runtime::TaskExecutor) -> Self {
-        self.executor_flavour = ExecutorFlavour::TokioTaskExecutor(executor);
+    /// If `true` pooled connections are replaced once they are checked in
+    /// or idle after the resolved addresses of their node changed. The addresses
+    /// are looked up again once their TTL expired. Idle connections are
+    /// replaced one at a time.
+    ///
+    /// Only supported by `Builder::finish_redis_rs`.
+    ///
+    /// The default is `false`.
+    pub fn recycle_on_address_change(mut self, v: bool) -> Self {
+        self.config.recycle_on_address_change = v;
+        self
+    }
+
+    /// The number of consecutive failures after which the circuit breaker
+    /// of a sub pool opens. Failures are failed attempts to create a
+    /// connection, failed validations and commands failing with an IO error.
+    /// Errors replied by Redis do not count. While the circuit
+    /// is open checkouts are made on other sub pools if there are any.
+    ///
+    /// Zero disables the circuit breaker.
+    ///
+    /// The default is 0 (disabled).
+    pub fn circuit_breaker_threshold(mut self, v: usize) -> Self {
+        self.config.circuit_breaker_threshold = v;
+        self
+    }
+
+    /// The time a circuit stays open before it becomes half open. The
+    /// next success closes a half open circuit and the next failure
+    /// opens it again.
+    ///
+    /// The default is 10 seconds.
+    pub fn circuit_breaker_open_duration(mut self, v: Duration) -> Self {
+        self.config.circuit_breaker_open_duration = v;
+        self
+    }
+
+    /// Ping the node of each sub pool in the background with the
+    /// given interval. See `RedisPool::health`.
+    pub fn health_check_interval(mut self, v: Duration) -> Self {
+        self.config.health_check_interval = Some(v);
+        self
+    }
+
+    /// The number of consecutive failed pings after which a node is
+    /// unhealthy. Pools for multiple nodes make checkouts on unhealthy
+    /// nodes only if there are no healthy ones.
+    ///
+    /// Zero means that nodes never become unhealthy.
+    ///
+    /// The default is 3.
+    pub fn health_check_failure_threshold(mut self, v: usize) -> Self {
+        self.config.health_check_failure_threshold = v;
+        self
+    }
+
+    /// The number of idle connections only checkouts with
+    /// `Priority::High` may take. Checkouts with a lower priority
+    /// wait or fail when there are no more idle connections than this.
+    /// This value applies to each sub pool and must be less than
+    /// `desired_pool_size` divided by `pool_multiplier`.
+    ///
+    /// The default is 0.
+    pub fn reserved_for_high_priority(mut self, v: usize) -> Self {
+        self.config.reserved_for_high_priority = v;
+        self
+    }
+
+    /// Add a partition which may have at most `max_checked_out`
+    /// connections checked out at the same time. Checkouts are
+    /// made in a partition with `RedisPool::check_out_in`.
+    pub fn partition<N: Into<String>>(mut self, name: N, max_checked_out: usize) -> Self {
+        self.config.partitions.insert(name.into(), max_checked_out);
+        self
+    }
+
+    /// Log connections held by the client for longer than the given
+    /// threshold as suspected leaks along with the location where
+    /// they were checked out.
+    pub fn leak_detection_threshold(mut self, v: Duration) -> Self {
+        self.config.leak_detection_threshold = Some(v);
+        self
+    }
+
+    /// Capture a backtrace on each checkout which is logged along
+    /// with a suspected leak. Capturing backtraces is expensive.
+    ///
+    /// The default is `false`.
+    pub fn capture_leak_backtraces(mut self, v: bool) -> Self {
+        self.config.capture_leak_backtraces = v;
+        self
+    }
+
+    /// Options for connecting to nodes given with a `rediss://` URL.
+    ///
+    /// Connecting via TLS requires the feature `tls`.
+    pub fn tls(mut self, v: TlsConfig) -> Self {
+        self.config.tls = v;
+        self
+    }
+
+    /// A PEM file with additional root certificates to trust
+    /// when connecting via TLS, e.g. the certificate of a private CA.
+    pub fn tls_ca_bundle<P: Into<PathBuf>>(mut self, v: P) -> Self {
+        self.config.tls.ca_bundle = Some(v.into());
+        self
+    }
+
+    /// PEM files with the certificate chain and the PKCS#8 private key
+    /// used for client authentication when connecting via TLS.
+    pub fn tls_client_identity<C: Into<PathBuf>, K: Into<PathBuf>>(
+        mut self,
+        certificate: C,
+        key: K,
+    ) -> Self {
+        self.config.tls.client_certificate = Some(certificate.into());
+        self.config.tls.client_key = Some(key.into());
+        self
+    }
+
+    /// The name used for SNI and for verifying the certificate of a node
+    /// when connecting via TLS.
+    ///
+    /// If not set the host given in the URL is used.
+    pub fn tls_domain<T: Into<String>>(mut self, v: T) -> Self {
+        self.config.tls.domain = Some(v.into());
+        self
+    }
+
+    /// If `true` the host name in the certificate of a node is not
+    /// verified when connecting via TLS.
+    ///
+    /// The default is `false`.
+    pub fn tls_accept_invalid_hostnames(mut self, v: bool) -> Self {
+        self.config.tls.accept_invalid_hostnames = v;
+        self
+    }
+
+    /// Discover the nodes to connect to via Redis Sentinel.
+    /// The nodes set with `connect_to_nodes` are ignored then.
+    ///
+    /// The pool follows the master when the sentinels announce a failover.
+    ///
+    /// Sentinel mode is only supported by `finish_redis_rs`.
+    pub fn sentinel(mut self, v: SentinelConfig) -> Self {
+        self.config.sentinel = Some(v);
+        self
+    }
+
+    /// Connect to the master `master_name` as announced by the given
+    /// sentinels, e.g. `redis://127.0.0.1:26379`.
+    ///
+    /// This is a shortcut for `sentinel`.
+    pub fn connect_via_sentinels<T: Into<String>>(
+        self,
+        master_name: T,
+        sentinels: Vec<String>,
+    ) -> Self {
+        self.sentinel(SentinelConfig::new(master_name, sentinels))
+    }
+
+    /// Treat the nodes set with `connect_to_nodes` as seed nodes of a
+    /// Redis Cluster. The masters and the slots they serve are discovered
+    /// from the seed nodes and there will be a sub pool per master.
+    ///
+    /// Use `RedisPool::check_out_for_key` to get a connection
+    /// to the master serving a key.
+    ///
+    /// The connections to the masters use the credentials and the db
+    /// of the first seed node. With TLS the certificates of the masters
+    /// are verified against the host of the first seed node unless
+    /// a domain is set with `tls_domain`.
+    ///
+    /// Cluster mode is only supported by `finish_redis_rs`.
+    ///
+    /// The default is `false`.
+    pub fn cluster(mut self, v: bool) -> Self {
+        self.config.cluster = v;
+        self
+    }
+
+    /// The runtime to use for spawning tasks. If not set it is assumed
+    /// that the pool is created within the context of a tokio runtime.
+    pub fn task_executor(mut self, handle: ::tokio::runtime::Handle) -> Self {
+        self.executor_flavour = ExecutorFlavour::TokioHandle(handle);
+        self
+    }
+
+    /// Sets a callback which is run on each newly created connection
+    /// before it is put into the pool.
+    ///
+    /// This can be used to issue commands like `AUTH`, `SELECT`,
+    /// `READONLY` or `CLIENT SETNAME` once per connection.
+    ///
+    /// If the callback fails the connection is discarded and creating
+    /// a connection is retried according to the `BackoffStrategy`.
+    ///
+    /// The callback can only be used with connection factories
+    /// creating connections of type `ConnectionFlavour`.
+    pub fn connection_initializer<F, R, E>(mut self, f: F) -> Self
+    where
+        F: Fn(ConnectionFlavour) -> R + Send + Sync + 'static,
+        R: Future<Output = Result<ConnectionFlavour, E>> + Send + 'static,
+        E: StdError + Send + 'static,
+    {
+        self.connection_initializer = Some(ConnectionInitializer::new(f));
         self
     }
 
@@ -166,14 +469,42 @@ impl Builder {
     /// Otherwise the prefix is used with an automatically appended `_`.
     ///
     /// * `DESIRED_POOL_SIZE`: `usize`. Omit if you do not want to update the value
+    /// * `MAX_POOL_SIZE`: `usize`. Omit if you do not want to update the value
+    /// * `SURPLUS_IDLE_TIMEOUT_MS`: `u64`. Omit if you do not want to update the value
+    /// * `IDLE_TIMEOUT_MS`: `u64`. Omit if you do not want to update the value
+    /// * `MAX_CONNECTION_LIFETIME_MS`: `u64`. Omit if you do not want to update the value
+    /// * `CONNECTION_LIFETIME_JITTER_MS`: `u64`. Omit if you do not want to update the value
+    /// * `VALIDATION_POLICY`: `string`. Omit if you do not want to update the value
+    /// * `VALIDATION_TIMEOUT_MS`: `u64`. Omit if you do not want to update the value
     /// * `DEFAULT_POOL_CHECKOUT_MODE`: The default checkout mode to use. Omit if you do not want to update the value
     /// * `RESERVATION_LIMIT`: `usize`. Omit if you do not want to update the value
     /// * `ACTIVATION_ORDER`: `string`. Omit if you do not want to update the value
     /// * `MIN_REQUIRED_NODES`: `usize`. Omit if you do not want to update the value
     /// * `CONNECT_TO`: `[String]`. Separated by `;`. Omit if you do not want to update the value
+    /// * `REPLICAS`: `[String]`. Separated by `;`. Omit if you do not want to update the value
+    /// * `REPLICA_FALLBACK_TO_PRIMARY`: `bool`. Omit if you do not want to update the value
     /// * `POOL_MULTIPLIER`: Omit if you do not want to update the value
+    /// * `LOAD_BALANCING_STRATEGY`: `string`. Omit if you do not want to update the value
     /// * `CHECKOUT_QUEUE_SIZE`: Omit if you do not want to update the value
     /// * `RETRY_ON_CHECKOUT_LIMIT`: Omit if you do not want to update the value
+    /// * `RECYCLE_ON_ADDRESS_CHANGE`: `bool`. Omit if you do not want to update the value
+    /// * `CIRCUIT_BREAKER_THRESHOLD`: `usize`. Omit if you do not want to update the value
+    /// * `CIRCUIT_BREAKER_OPEN_DURATION_MS`: `u64`. Omit if you do not want to update the value
+    /// * `HEALTH_CHECK_INTERVAL_MS`: `u64`. Omit if you do not want to update the value
+    /// * `HEALTH_CHECK_FAILURE_THRESHOLD`: `usize`. Omit if you do not want to update the value
+    /// * `RESERVED_FOR_HIGH_PRIORITY`: `usize`. Omit if you do not want to update the value
+    /// * `PARTITIONS`: `[name=usize]`. Separated by `;`. Omit if you do not want to update the value
+    /// * `LEAK_DETECTION_THRESHOLD_MS`: `u64`. Omit if you do not want to update the value
+    /// * `CAPTURE_LEAK_BACKTRACES`: `bool`. Omit if you do not want to update the value
+    /// * `TLS_CA_BUNDLE`: `string`. A path. Omit if you do not want to update the value
+    /// * `TLS_CLIENT_CERTIFICATE`: `string`. A path. Omit if you do not want to update the value
+    /// * `TLS_CLIENT_KEY`: `string`. A path. Omit if you do not want to update the value
+    /// * `TLS_DOMAIN`: `string`. Omit if you do not want to update the value
+    /// * `TLS_ACCEPT_INVALID_HOSTNAMES`: `bool`. Omit if you do not want to update the value
+    /// * `SENTINEL_MASTER_NAME`: `string`. Enables sentinel mode. Omit if you do not want to update the value
+    /// * `SENTINELS`: `[String]`. Separated by `;`. Enables sentinel mode. Omit if you do not want to update the value
+    /// * `SENTINEL_CONNECT_TO_REPLICAS`: `bool`. Enables sentinel mode. Omit if you do not want to update the value
+    /// * `CLUSTER`: `bool`. Omit if you do not want to update the value
     pub fn update_from_environment(&mut self, prefix: Option<&str>) -> InitializationResult<()> {
         self.config.update_from_environment(prefix)?;
         Ok(())
@@ -185,14 +516,33 @@ impl Builder {
     /// Otherwise the prefix is used with an automatically appended `_`.
     ///
     /// * `DESIRED_POOL_SIZE`: `usize`. Omit if you do not want to update the value
+    /// * `MAX_POOL_SIZE`: `usize`. Omit if you do not want to update the value
+    /// * `SURPLUS_IDLE_TIMEOUT_MS`: `u64`. Omit if you do not want to update the value
+    /// * `IDLE_TIMEOUT_MS`: `u64`. Omit if you do not want to update the value
+    /// * `MAX_CONNECTION_LIFETIME_MS`: `u64`. Omit if you do not want to update the value
+    /// * `CONNECTION_LIFETIME_JITTER_MS`: `u64`. Omit if you do not want to update the value
+    /// * `VALIDATION_POLICY`: `string`. Omit if you do not want to update the value
+    /// * `VALIDATION_TIMEOUT_MS`: `u64`. Omit if you do not want to update the value
     /// * `DEFAULT_POOL_CHECKOUT_MODE`: The default checkout mode to use. Omit if you do not want to update the value
     /// * `RESERVATION_LIMIT`: `usize`. Omit if you do not want to update the value
     /// * `ACTIVATION_ORDER`: `string`. Omit if you do not want to update the value
     /// * `MIN_REQUIRED_NODES`: `usize`. Omit if you do not want to update the value
     /// * `CONNECT_TO`: `[String]`. Separated by `;`. Omit if you do not want to update the value
+    /// * `REPLICAS`: `[String]`. Separated by `;`. Omit if you do not want to update the value
+    /// * `REPLICA_FALLBACK_TO_PRIMARY`: `bool`. Omit if you do not want to update the value
     /// * `POOL_MULTIPLIER`: Omit if you do not want to update the value
+    /// * `LOAD_BALANCING_STRATEGY`: `string`. Omit if you do not want to update the value
     /// * `CHECKOUT_QUEUE_SIZE`: Omit if you do not want to update the value
     /// * `RETRY_ON_CHECKOUT_LIMIT`: Omit if you do not want to update the value
+    /// * `RECYCLE_ON_ADDRESS_CHANGE`: `bool`. Omit if you do not want to update the value
+    /// * `CIRCUIT_BREAKER_THRESHOLD`: `usize`. Omit if you do not want to update the value
+    /// * `CIRCUIT_BREAKER_OPEN_DURATION_MS`: `u64`. Omit if you do not want to update the value
+    /// * `HEALTH_CHECK_INTERVAL_MS`: `u64`. Omit if you do not want to update the value
+    /// * `HEALTH_CHECK_FAILURE_THRESHOLD`: `usize`. Omit if you do not want to update the value
+    /// * `RESERVED_FOR_HIGH_PRIORITY`: `usize`. Omit if you do not want to update the value
+    /// * `PARTITIONS`: `[name=usize]`. Separated by `;`. Omit if you do not want to update the value
+    /// * `LEAK_DETECTION_THRESHOLD_MS`: `u64`. Omit if you do not want to update the value
+    /// * `CAPTURE_LEAK_BACKTRACES`: `bool`. Omit if you do not want to update the value
     pub fn updated_from_environment(mut self, prefix: Option<&str>) -> InitializationResult<Self> {
         self.config.update_from_environment(prefix)?;
         Ok(self)
@@ -204,23 +554,25 @@ impl Builder {
         connection_factory: F,
     ) -> InitializationResult<RedisPool<CF::Connection>>
     where
-        F: Fn(String) -> InitializationResult<CF>,
+        F: Fn(String) -> InitializationResult<CF> + Send + Sync + 'static,
         CF: ConnectionFactory + Send + Sync + 'static,
     {
         let config = self.config;
 
-        if config.pool_multiplier == 0 {
+        if config.sentinel.is_some() {
             return Err(InitializationError::message_only(
-                "pool_multiplier must not be zero",
+                "sentinel mode is only supported by `finish_redis_rs`",
             ));
         }
 
-        if config.checkout_queue_size == 0 {
+        if config.cluster {
             return Err(InitializationError::message_only(
-                "checkout_queue_size must be greater than 0",
+                "cluster mode is only supported by `finish_redis_rs`",
             ));
         }
 
+        check_pool_config(&config)?;
+
         if config.connect_to_nodes.len() < config.min_required_nodes {
             return Err(InitializationError::message_only(format!(
                 "There must be at least {} node(s) defined. There are only {} defined.",
@@ -229,6 +581,22 @@ impl Builder {
             )));
         }
 
+        let connection_initializer = match self.connection_initializer {
+            Some(connection_initializer) => {
+                let connection_initializer: Box<dyn Any> = Box::new(connection_initializer);
+                match connection_initializer.downcast::<ConnectionInitializer<CF::Connection>>() {
+                    Ok(connection_initializer) => Some(*connection_initializer),
+                    Err(_) => {
+                        return Err(InitializationError::message_only(
+                            "a connection initializer can only be used with connections \
+                             of type `ConnectionFlavour`",
+                        ))
+                    }
+                }
+            }
+            None => None,
+        };
+
         if config.connect_to_nodes.is_empty() {
             warn!("Creating a pool with no nodes");
             return Ok(create_no_pool(self.instrumentation));
@@ -236,12 +604,28 @@ impl Builder {
 
         info!("Configuration: {:?}", config);
 
+        let create_primary_replica_pool = !config.replicas.is_empty();
         let create_single_pool = config.connect_to_nodes.len() == 1 && config.pool_multiplier == 1;
 
-        let default_checkout_mode = config.default_checkout_mode;
+        let default_checkout_mode = Arc::new(RwLock::new(config.default_checkout_mode));
         let retry_on_checkout_limit = config.retry_on_checkout_limit;
+        let capture_leak_backtraces = config.capture_leak_backtraces;
+        let partitions = Partitions::new(&config.partitions, self.instrumentation.clone());
+
+        let flavour = if create_primary_replica_pool {
+            debug!(
+                "Create primary replica pool with {} replicas",
+                config.replicas.len()
+            );
 
-        let flavour = if create_single_pool {
+            RedisPoolFlavour::PrimaryReplica(PrimaryReplicaPool::new(
+                config,
+                connection_factory,
+                self.executor_flavour,
+                self.instrumentation,
+                connection_initializer,
+            )?)
+        } else if create_single_pool {
             debug!("Create single pool for 1 node",);
 
             RedisPoolFlavour::Single(SinglePool::new(
@@ -249,6 +633,7 @@ impl Builder {
                 connection_factory,
                 self.executor_flavour,
                 self.instrumentation,
+                connection_initializer,
             )?)
         } else {
             debug!(
@@ -260,6 +645,7 @@ impl Builder {
                 connection_factory,
                 self.executor_flavour,
                 self.instrumentation,
+                connection_initializer,
             )?)
         };
 
@@ -267,15 +653,215 @@ impl Builder {
             flavour,
             default_checkout_mode,
             retry_on_checkout_limit,
+            capture_leak_backtraces,
+            partitions,
         })
     }
 
     /// Build a new `RedisPool`
     pub fn finish_redis_rs(self) -> InitializationResult<RedisPool> {
-        self.finish(RedisRsFactory::new)
+        if self.config.sentinel.is_some() && self.config.cluster {
+            return Err(InitializationError::message_only(
+                "sentinel mode and cluster mode can not be combined",
+            ));
+        }
+
+        if (self.config.sentinel.is_some() || self.config.cluster)
+            && !self.config.replicas.is_empty()
+        {
+            return Err(InitializationError::message_only(
+                "replicas can not be combined with sentinel mode or cluster mode",
+            ));
+        }
+
+        if self.config.sentinel.is_some() {
+            return self.finish_sentinel();
+        }
+
+        if self.config.cluster {
+            return self.finish_cluster();
+        }
+
+        let tls = self.config.tls.clone();
+        let recycle = self.config.recycle_on_address_change;
+        self.finish(move |connect_to| {
+            RedisRsFactory::new(connect_to, &tls).map(|f| f.recycle_on_address_change(recycle))
+        })
+    }
+
+    fn finish_sentinel(self) -> InitializationResult<RedisPool> {
+        let mut config = self.config;
+        let sentinel_config = config.sentinel.take().unwrap();
+
+        check_pool_config(&config)?;
+
+        let tls = config.tls.clone();
+        let sentinels = sentinel_config
+            .sentinels
+            .iter()
+            .map(|sentinel| RedisRsFactory::new(sentinel.clone(), &tls))
+            .collect::<InitializationResult<Vec<_>>>()?;
+
+        info!("Configuration: {:?}", config);
+
+        let default_checkout_mode = Arc::new(RwLock::new(config.default_checkout_mode));
+        let retry_on_checkout_limit = config.retry_on_checkout_limit;
+        let capture_leak_backtraces = config.capture_leak_backtraces;
+        let partitions = Partitions::new(&config.partitions, self.instrumentation.clone());
+        let recycle = config.recycle_on_address_change;
+
+        let pool = SentinelPool::new(
+            config,
+            sentinel_config,
+            sentinels,
+            move |connect_to| {
+                RedisRsFactory::new(connect_to, &tls).map(|f| f.recycle_on_address_change(recycle))
+            },
+            self.executor_flavour,
+            self.instrumentation,
+            self.connection_initializer,
+        )?;
+
+        Ok(RedisPool {
+            flavour: RedisPoolFlavour::Sentinel(pool),
+            default_checkout_mode,
+            retry_on_checkout_limit,
+            capture_leak_backtraces,
+            partitions,
+        })
+    }
+
+    fn finish_cluster(self) -> InitializationResult<RedisPool> {
+        let config = self.config;
+
+        check_pool_config(&config)?;
+
+        if config.connect_to_nodes.len() < config.min_required_nodes {
+            return Err(InitializationError::message_only(format!(
+                "There must be at least {} seed node(s) defined. There are only {} defined.",
+                config.min_required_nodes,
+                config.connect_to_nodes.len()
+            )));
+        }
+
+        info!("Configuration: {:?}", config);
+
+        let default_checkout_mode = Arc::new(RwLock::new(config.default_checkout_mode));
+        let retry_on_checkout_limit = config.retry_on_checkout_limit;
+        let capture_leak_backtraces = config.capture_leak_backtraces;
+        let partitions = Partitions::new(&config.partitions, self.instrumentation.clone());
+        let mut tls = config.tls.clone();
+        if tls.domain.is_none() {
+            tls.domain = cluster_pool::tls_domain(&config.connect_to_nodes);
+        }
+        let recycle = config.recycle_on_address_change;
+
+        let pool = ClusterPool::new(
+            config,
+            move |connect_to| {
+                RedisRsFactory::new(connect_to, &tls).map(|f| f.recycle_on_address_change(recycle))
+            },
+            self.executor_flavour,
+            self.instrumentation,
+            self.connection_initializer,
+        )?;
+
+        Ok(RedisPool {
+            flavour: RedisPoolFlavour::Cluster(pool),
+            default_checkout_mode,
+            retry_on_checkout_limit,
+            capture_leak_backtraces,
+            partitions,
+        })
+    }
+}
+
+/// Checks the values which apply to all kinds of pools
+fn check_pool_config(config: &Config) -> InitializationResult<()> {
+    if config.pool_multiplier == 0 {
+        return Err(InitializationError::message_only(
+            "pool_multiplier must not be zero",
+        ));
+    }
+
+    if config.checkout_queue_size == 0 {
+        return Err(InitializationError::message_only(
+            "checkout_queue_size must be greater than 0",
+        ));
+    }
+
+    if let Some(max_pool_size) = config.max_pool_size {
+        if max_pool_size < config.desired_pool_size {
+            return Err(InitializationError::message_only(format!(
+                "max_pool_size({}) must not be less than desired_pool_size({})",
+                max_pool_size, config.desired_pool_size
+            )));
+        }
+    }
+
+    // Otherwise checkouts without `Priority::High` would never get an idle connection
+    let desired_pool_size_per_pool = config.desired_pool_size / config.pool_multiplier as usize;
+    if config.reserved_for_high_priority > 0
+        && config.reserved_for_high_priority >= desired_pool_size_per_pool
+    {
+        return Err(InitializationError::message_only(format!(
+            "reserved_for_high_priority({}) must be less than desired_pool_size({}) \
+             divided by pool_multiplier({})",
+            config.reserved_for_high_priority, config.desired_pool_size, config.pool_multiplier
+        )));
+    }
+
+    if let Some(max_connection_lifetime) = config.max_connection_lifetime {
+        if max_connection_lifetime == Duration::from_secs(0) {
+            return Err(InitializationError::message_only(
+                "max_connection_lifetime must be greater than 0",
+            ));
+        }
+
+        // Otherwise some connections would expire right after being created
+        if config.connection_lifetime_jitter >= max_connection_lifetime {
+            return Err(InitializationError::message_only(format!(
+                "connection_lifetime_jitter({:?}) must be less than max_connection_lifetime({:?})",
+                config.connection_lifetime_jitter, max_connection_lifetime
+            )));
+        }
     }
+
+    Ok(())
 }
 
 fn create_no_pool<T: Poolable>(_instrumentation: InstrumentationFlavour) -> RedisPool<T> {
     RedisPool::no_pool()
 }
+
+#[test]
+fn connections_reserved_for_high_priority_must_leave_connections_for_others() {
+    let config = Config::default()
+        .desired_pool_size(4)
+        .reserved_for_high_priority(3);
+    assert!(check_pool_config(&config).is_ok());
+
+    assert!(check_pool_config(&config.clone().reserved_for_high_priority(4)).is_err());
+    assert!(check_pool_config(&config.pool_multiplier(2)).is_err());
+}
+
+#[test]
+fn the_connection_lifetime_must_exceed_the_jitter() {
+    let config = Config::default()
+        .max_connection_lifetime(Duration::from_secs(60))
+        .connection_lifetime_jitter(Duration::from_secs(59));
+    assert!(check_pool_config(&config).is_ok());
+
+    assert!(check_pool_config(
+        &config
+            .clone()
+            .connection_lifetime_jitter(Duration::from_secs(60))
+    )
+    .is_err());
+    assert!(check_pool_config(
+        &config
+            .max_connection_lifetime(Duration::from_secs(0))
+            .connection_lifetime_jitter(Duration::from_secs(0))
+    )
+    .is_err());
+}
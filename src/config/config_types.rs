@@ -1,8 +1,11 @@
 use std::fmt;
+use std::path::PathBuf;
 use std::time::Duration;
 
 use crate::{Immediately, Wait};
 
+use super::{ActivationOrder, BackoffStrategy, Config};
+
 /// Various options on retrieving a connection
 /// that can be applied if a user wants to use the pool defaults
 /// for retrieving a connection.
@@ -88,3 +91,164 @@ impl std::error::Error for ParseDefaultPoolCheckoutModeError {
         None
     }
 }
+
+/// Options for connections to nodes given with a `rediss://` URL.
+///
+/// Connecting via TLS requires the feature `tls`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// A PEM file with additional root certificates to trust,
+    /// e.g. the certificate of a private CA.
+    pub ca_bundle: Option<PathBuf>,
+    /// A PEM file with the certificate chain used for client authentication.
+    ///
+    /// Must be set together with `client_key`.
+    pub client_certificate: Option<PathBuf>,
+    /// A PEM file with the PKCS#8 private key of `client_certificate`.
+    pub client_key: Option<PathBuf>,
+    /// The name used for SNI and for verifying the certificate of the node.
+    ///
+    /// If not set the host given in the URL is used.
+    pub domain: Option<String>,
+    /// If `true` the host name in the certificate of the node is not verified.
+    ///
+    /// The default is `false`.
+    pub accept_invalid_hostnames: bool,
+}
+
+/// Settings for discovering the nodes to connect to via Redis Sentinel.
+///
+/// The pool follows the master when the sentinels announce a failover.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SentinelConfig {
+    /// The name of the master as monitored by the sentinels.
+    pub master_name: String,
+    /// The sentinels to ask, e.g. `redis://127.0.0.1:26379`.
+    ///
+    /// The sentinels are asked in the given order. If a sentinel
+    /// uses a `rediss://` URL the nodes are also connected via TLS.
+    pub sentinels: Vec<String>,
+    /// If `true` the pool also connects to the replicas of the master.
+    /// Use `RedisPool::check_out_replica` to check out a connection
+    /// to a replica.
+    ///
+    /// The default is `false`.
+    pub connect_to_replicas: bool,
+    /// The password of the nodes. This is not the password of the sentinels.
+    pub node_password: Option<String>,
+    /// The database to select on the nodes.
+    ///
+    /// The default is 0.
+    pub node_db: i64,
+}
+
+impl SentinelConfig {
+    pub fn new<T: Into<String>>(master_name: T, sentinels: Vec<String>) -> Self {
+        Self {
+            master_name: master_name.into(),
+            sentinels,
+            ..Self::default()
+        }
+    }
+
+    /// If `true` the pool also connects to the replicas of the master.
+    /// Use `RedisPool::check_out_replica` to check out a connection
+    /// to a replica.
+    ///
+    /// The default is `false`.
+    pub fn connect_to_replicas(mut self, v: bool) -> Self {
+        self.connect_to_replicas = v;
+        self
+    }
+
+    /// The password of the nodes. This is not the password of the sentinels.
+    pub fn node_password<T: Into<String>>(mut self, v: T) -> Self {
+        self.node_password = Some(v.into());
+        self
+    }
+
+    /// The database to select on the nodes.
+    ///
+    /// The default is 0.
+    pub fn node_db(mut self, v: i64) -> Self {
+        self.node_db = v;
+        self
+    }
+}
+
+/// Changes to apply to a running pool via `RedisPool::reconfigure`.
+///
+/// Only the values which are set will be changed. They have the same
+/// meaning as the corresponding values of `Config`.
+#[derive(Debug, Clone, Default)]
+pub struct Reconfiguration {
+    /// If the pool has to shrink, idle connections exceeding the
+    /// new size are closed after `surplus_idle_timeout`. Fewer connections
+    /// are reserved for high priority checkouts if they would not leave
+    /// a connection for others.
+    pub desired_pool_size: Option<usize>,
+    /// Waiting checkouts exceeding a lowered limit are not cancelled.
+    pub reservation_limit: Option<usize>,
+    pub default_checkout_mode: Option<DefaultPoolCheckoutMode>,
+    pub activation_order: Option<ActivationOrder>,
+    /// Applies to the next attempt to create a connection.
+    pub backoff_strategy: Option<BackoffStrategy>,
+}
+
+impl Reconfiguration {
+    /// The number of connections a pool should have. If a pool with
+    /// multiple sub pools was created, this value applies to each
+    /// sub pool.
+    pub fn desired_pool_size(mut self, v: usize) -> Self {
+        self.desired_pool_size = Some(v);
+        self
+    }
+
+    /// The maximum length of the queue for waiting checkouts
+    /// when no idle connections are available.
+    pub fn reservation_limit(mut self, v: usize) -> Self {
+        self.reservation_limit = Some(v);
+        self
+    }
+
+    /// Sets the behaviour of the pool on checkouts if no specific behaviour
+    /// was requested by the user.
+    pub fn default_checkout_mode<T: Into<DefaultPoolCheckoutMode>>(mut self, v: T) -> Self {
+        self.default_checkout_mode = Some(v.into());
+        self
+    }
+
+    /// Defines the `ActivationOrder` in which idle connections are
+    /// activated.
+    pub fn activation_order(mut self, v: ActivationOrder) -> Self {
+        self.activation_order = Some(v);
+        self
+    }
+
+    /// Sets the `BackoffStrategy` to use when retrying on
+    /// failures to create new connections
+    pub fn backoff_strategy(mut self, v: BackoffStrategy) -> Self {
+        self.backoff_strategy = Some(v);
+        self
+    }
+
+    /// Apply the changes to a `Config` e.g. to create new sub pools
+    /// with the changed values
+    pub(crate) fn apply_to(&self, config: &mut Config) {
+        if let Some(v) = self.desired_pool_size {
+            config.desired_pool_size = v;
+        }
+        if let Some(v) = self.reservation_limit {
+            config.reservation_limit = v;
+        }
+        if let Some(v) = self.default_checkout_mode {
+            config.default_checkout_mode = v;
+        }
+        if let Some(v) = self.activation_order {
+            config.activation_order = v;
+        }
+        if let Some(v) = self.backoff_strategy {
+            config.backoff_strategy = v;
+        }
+    }
+}
@@ -11,6 +11,7 @@
 //!
 //! Set the value `connect_to_nodes` to more than one node.
 //! Make sure not to write to that pool.
+use std::collections::BTreeMap;
 use std::time::Duration;
 
 use crate::error::InitializationResult;
@@ -20,6 +21,8 @@ use crate::Poolable;
 pub use crate::activation_order::ActivationOrder;
 pub use crate::backoff_strategy::BackoffStrategy;
 pub use crate::error::InitializationError;
+pub use crate::load_balancing::LoadBalancingStrategy;
+pub use crate::validation_policy::ValidationPolicy;
 pub use builder::Builder;
 pub use config_types::*;
 
@@ -33,8 +36,54 @@ pub struct Config {
     /// multiple sub pools is created, this value applies to each
     /// sub pool.
     ///
+    /// If `max_pool_size` is set, this is the minimum number of
+    /// connections the pool will keep.
+    ///
     /// The default is 50.
     pub desired_pool_size: usize,
+    /// The maximum number of connections a pool may grow to when
+    /// checkouts have to wait for a connection. If a pool with
+    /// multiple sub pools is created, this value applies to each
+    /// sub pool.
+    ///
+    /// If `None` the pool has a fixed size of `desired_pool_size`.
+    ///
+    /// The default is `None`.
+    pub max_pool_size: Option<usize>,
+    /// The time a connection exceeding `desired_pool_size` may stay
+    /// idle before it gets closed.
+    ///
+    /// The default is 60 seconds.
+    pub surplus_idle_timeout: Duration,
+    /// If set, connections which have been idle for longer than this
+    /// will be closed and replaced by a new connection.
+    ///
+    /// This is useful if there are load balancers or firewalls
+    /// between the pool and Redis which silently kill idle connections.
+    ///
+    /// The default is `None`.
+    pub idle_timeout: Option<Duration>,
+    /// If set, connections older than this will not be put back into the
+    /// pool when checked in. They will be closed and replaced by a new connection
+    /// instead. Idle connections are replaced once they are older than this.
+    ///
+    /// The default is `None`.
+    pub max_connection_lifetime: Option<Duration>,
+    /// The lifetime of each connection will be shortened by a random amount of
+    /// at most this value so that not all connections get recycled at once.
+    ///
+    /// Only relevant if `max_connection_lifetime` is set.
+    ///
+    /// The default is 0.
+    pub connection_lifetime_jitter: Duration,
+    /// Defines when connections are validated.
+    ///
+    /// The default is `ValidationPolicy::Never`.
+    pub validation_policy: ValidationPolicy,
+    /// A validation not finished within this time counts as failed.
+    ///
+    /// The default is 1 second.
+    pub validation_timeout: Duration,
     /// The timeout for a checkout if no specific timeout is given
     /// with a checkout.
     pub default_checkout_mode: DefaultPoolCheckoutMode,
@@ -57,6 +106,18 @@ pub struct Config {
     pub min_required_nodes: usize,
     /// The nodes to connect to.
     pub connect_to_nodes: Vec<String>,
+    /// The replicas of the primary.
+    ///
+    /// If not empty `connect_to_nodes` must contain exactly one node which
+    /// is the primary and `RedisPool::check_out_replica` checks out
+    /// connections to the replicas. `pool_multiplier` only applies
+    /// to the replicas then.
+    pub replicas: Vec<String>,
+    /// If `true` checkouts for replicas are made on the primary
+    /// if no replica is available.
+    ///
+    /// The default is `false`.
+    pub replica_fallback_to_primary: bool,
     /// When the pool is created this is a multiplier for the amount of sub
     /// pools to be created.
     ///
@@ -64,7 +125,13 @@ pub struct Config {
     ///
     /// * `reservation_limit`: Stays zero if zero, otherwise (`reservation_limit`/multiplier) +1
     /// * `desired_pool_size`: `desired_pool_size`/multiplier) +1
+    /// * `max_pool_size`: `max_pool_size`/multiplier) +1
     pub pool_multiplier: u32,
+    /// Defines how checkouts are distributed among the sub pools
+    /// if there are multiple nodes or a `pool_multiplier` > 1.
+    ///
+    /// The default is `LoadBalancingStrategy::RoundRobin`.
+    pub load_balancing_strategy: LoadBalancingStrategy,
     /// The number of checkouts that can be enqueued. If a pool with
     /// multiple sub pools is created, this value applies to each
     /// sub pool.
@@ -76,6 +143,85 @@ pub struct Config {
     ///
     /// The default is `true`.
     pub retry_on_checkout_limit: bool,
+    /// If `true` pooled connections are replaced once they are checked in
+    /// or idle after the resolved addresses of their node changed. The addresses
+    /// are looked up again once their TTL expired. Idle connections are
+    /// replaced one at a time.
+    ///
+    /// Only supported by `Builder::finish_redis_rs`.
+    ///
+    /// The default is `false`.
+    pub recycle_on_address_change: bool,
+    /// The number of consecutive failures after which the circuit breaker
+    /// of a sub pool opens. Failures are failed attempts to create a
+    /// connection, failed validations and commands failing with an IO error.
+    /// Errors replied by Redis do not count. While the circuit
+    /// is open checkouts are made on other sub pools if there are any.
+    ///
+    /// Zero disables the circuit breaker.
+    ///
+    /// The default is 0 (disabled).
+    pub circuit_breaker_threshold: usize,
+    /// The time a circuit stays open before it becomes half open. The
+    /// next success closes a half open circuit and the next failure
+    /// opens it again.
+    ///
+    /// The default is 10 seconds.
+    pub circuit_breaker_open_duration: Duration,
+    /// If set, the node of each sub pool is pinged in the background
+    /// with this interval. See `RedisPool::health`.
+    ///
+    /// The default is `None`.
+    pub health_check_interval: Option<Duration>,
+    /// The number of consecutive failed pings after which a node is
+    /// unhealthy. Pools for multiple nodes make checkouts on unhealthy
+    /// nodes only if there are no healthy ones.
+    ///
+    /// Zero means that nodes never become unhealthy.
+    ///
+    /// The default is 3.
+    pub health_check_failure_threshold: usize,
+    /// The number of idle connections only checkouts with
+    /// `Priority::High` may take. Checkouts with a lower priority
+    /// wait or fail when there are no more idle connections than this.
+    /// This value applies to each sub pool and must be less than
+    /// `desired_pool_size` divided by `pool_multiplier`.
+    ///
+    /// The default is 0.
+    pub reserved_for_high_priority: usize,
+    /// Named partitions with the maximum number of connections
+    /// each may have checked out at the same time. Checkouts are
+    /// made in a partition with `RedisPool::check_out_in`.
+    ///
+    /// The default is no partitions.
+    pub partitions: BTreeMap<String, usize>,
+    /// If set, connections held by the client for longer than this
+    /// are logged as suspected leaks along with the location where
+    /// they were checked out.
+    ///
+    /// The default is `None`.
+    pub leak_detection_threshold: Option<Duration>,
+    /// If `true` a backtrace is captured on each checkout and logged
+    /// along with a suspected leak. Capturing backtraces is expensive.
+    ///
+    /// The default is `false`.
+    pub capture_leak_backtraces: bool,
+    /// Options for connecting to nodes given with a `rediss://` URL.
+    ///
+    /// Connecting via TLS requires the feature `tls`.
+    pub tls: TlsConfig,
+    /// If set the nodes to connect to are discovered via Redis Sentinel
+    /// and `connect_to_nodes` is ignored.
+    ///
+    /// Sentinel mode is only supported by `Builder::finish_redis_rs`.
+    pub sentinel: Option<SentinelConfig>,
+    /// If `true` the nodes are the nodes of a Redis Cluster and
+    /// `connect_to_nodes` are only used to discover the masters.
+    ///
+    /// Cluster mode is only supported by `Builder::finish_redis_rs`.
+    ///
+    /// The default is `false`.
+    pub cluster: bool,
 }
 
 impl Config {
@@ -83,12 +229,74 @@ impl Config {
     /// multiple sub pools is created, this value applies to each
     /// sub pool.
     ///
+    /// If `max_pool_size` is set, this is the minimum number of
+    /// connections the pool will keep.
+    ///
     /// The default is 50.
     pub fn desired_pool_size(mut self, v: usize) -> Self {
         self.desired_pool_size = v;
         self
     }
 
+    /// The maximum number of connections a pool may grow to when
+    /// checkouts have to wait for a connection. If a pool with
+    /// multiple sub pools is created, this value applies to each
+    /// sub pool.
+    ///
+    /// If not set the pool has a fixed size of `desired_pool_size`.
+    pub fn max_pool_size(mut self, v: usize) -> Self {
+        self.max_pool_size = Some(v);
+        self
+    }
+
+    /// The time a connection exceeding `desired_pool_size` may stay
+    /// idle before it gets closed.
+    ///
+    /// The default is 60 seconds.
+    pub fn surplus_idle_timeout(mut self, v: Duration) -> Self {
+        self.surplus_idle_timeout = v;
+        self
+    }
+
+    /// Connections which have been idle for longer than the given
+    /// `Duration` will be closed and replaced by a new connection.
+    pub fn idle_timeout(mut self, v: Duration) -> Self {
+        self.idle_timeout = Some(v);
+        self
+    }
+
+    /// Connections older than the given `Duration` will be closed and replaced
+    /// by a new connection when checked in or while idle.
+    pub fn max_connection_lifetime(mut self, v: Duration) -> Self {
+        self.max_connection_lifetime = Some(v);
+        self
+    }
+
+    /// The lifetime of each connection will be shortened by a random amount of
+    /// at most this value so that not all connections get recycled at once.
+    ///
+    /// The default is 0.
+    pub fn connection_lifetime_jitter(mut self, v: Duration) -> Self {
+        self.connection_lifetime_jitter = v;
+        self
+    }
+
+    /// Defines when connections are validated.
+    ///
+    /// The default is `ValidationPolicy::Never`.
+    pub fn validation_policy(mut self, v: ValidationPolicy) -> Self {
+        self.validation_policy = v;
+        self
+    }
+
+    /// A validation not finished within this time counts as failed.
+    ///
+    /// The default is 1 second.
+    pub fn validation_timeout(mut self, v: Duration) -> Self {
+        self.validation_timeout = v;
+        self
+    }
+
     /// Sets the behaviour of the pool on checkouts if no specific behaviour
     /// was requested by the user.
     pub fn default_checkout_mode<T: Into<DefaultPoolCheckoutMode>>(mut self, v: T) -> Self {
@@ -142,6 +350,30 @@ impl Config {
         self
     }
 
+    /// The replicas of the primary given with `connect_to_node`
+    pub fn replicas(mut self, v: Vec<String>) -> Self {
+        self.replicas = v;
+        self
+    }
+
+    /// If `true` checkouts for replicas are made on the primary
+    /// if no replica is available.
+    ///
+    /// The default is `false`.
+    pub fn replica_fallback_to_primary(mut self, v: bool) -> Self {
+        self.replica_fallback_to_primary = v;
+        self
+    }
+
+    /// Defines how checkouts are distributed among the sub pools
+    /// if there are multiple nodes or a `pool_multiplier` > 1.
+    ///
+    /// The default is `LoadBalancingStrategy::RoundRobin`.
+    pub fn load_balancing_strategy(mut self, v: LoadBalancingStrategy) -> Self {
+        self.load_balancing_strategy = v;
+        self
+    }
+
     /// The number of checkouts that can be enqueued. If a pool with
     /// multiple sub pools is created, this value applies to each
     /// sub pool.
@@ -161,6 +393,97 @@ impl Config {
         self
     }
 
+    /// If `true` pooled connections are replaced once they are checked in
+    /// or idle after the resolved addresses of their node changed. The addresses
+    /// are looked up again once their TTL expired. Idle connections are
+    /// replaced one at a time.
+    ///
+    /// Only supported by `Builder::finish_redis_rs`.
+    ///
+    /// The default is `false`.
+    pub fn recycle_on_address_change(mut self, v: bool) -> Self {
+        self.recycle_on_address_change = v;
+        self
+    }
+
+    /// The number of consecutive failures after which the circuit breaker
+    /// of a sub pool opens. Failures are failed attempts to create a
+    /// connection, failed validations and commands failing with an IO error.
+    /// Errors replied by Redis do not count. While the circuit
+    /// is open checkouts are made on other sub pools if there are any.
+    ///
+    /// Zero disables the circuit breaker.
+    ///
+    /// The default is 0 (disabled).
+    pub fn circuit_breaker_threshold(mut self, v: usize) -> Self {
+        self.circuit_breaker_threshold = v;
+        self
+    }
+
+    /// The time a circuit stays open before it becomes half open. The
+    /// next success closes a half open circuit and the next failure
+    /// opens it again.
+    ///
+    /// The default is 10 seconds.
+    pub fn circuit_breaker_open_duration(mut self, v: Duration) -> Self {
+        self.circuit_breaker_open_duration = v;
+        self
+    }
+
+    /// Ping the node of each sub pool in the background with the
+    /// given interval. See `RedisPool::health`.
+    pub fn health_check_interval(mut self, v: Duration) -> Self {
+        self.health_check_interval = Some(v);
+        self
+    }
+
+    /// The number of consecutive failed pings after which a node is
+    /// unhealthy. Pools for multiple nodes make checkouts on unhealthy
+    /// nodes only if there are no healthy ones.
+    ///
+    /// Zero means that nodes never become unhealthy.
+    ///
+    /// The default is 3.
+    pub fn health_check_failure_threshold(mut self, v: usize) -> Self {
+        self.health_check_failure_threshold = v;
+        self
+    }
+
+    /// The number of idle connections only checkouts with
+    /// `Priority::High` may take. Checkouts with a lower priority
+    /// wait or fail when there are no more idle connections than this.
+    /// This value applies to each sub pool and must be less than
+    /// `desired_pool_size` divided by `pool_multiplier`.
+    ///
+    /// The default is 0.
+    pub fn reserved_for_high_priority(mut self, v: usize) -> Self {
+        self.reserved_for_high_priority = v;
+        self
+    }
+
+    /// Add a partition which may have at most `max_checked_out`
+    /// connections checked out at the same time. Checkouts are
+    /// made in a partition with `RedisPool::check_out_in`.
+    pub fn partition<N: Into<String>>(mut self, name: N, max_checked_out: usize) -> Self {
+        self.partitions.insert(name.into(), max_checked_out);
+        self
+    }
+
+    /// Log connections held by the client for longer than the given
+    /// threshold as suspected leaks along with the location where
+    /// they were checked out.
+    pub fn leak_detection_threshold(mut self, v: Duration) -> Self {
+        self.leak_detection_threshold = Some(v);
+        self
+    }
+
+    /// Capture a backtrace on each checkout which is logged along
+    /// with a suspected leak. Capturing backtraces is expensive.
+    pub fn capture_leak_backtraces(mut self, v: bool) -> Self {
+        self.capture_leak_backtraces = v;
+        self
+    }
+
     /// When the pool is created this is a multiplier for the amount of sub
     /// pools to be created.
     ///
@@ -168,30 +491,113 @@ impl Config {
     ///
     /// * `reservation_limit`: Stays zero if zero, otherwise (`reservation_limit`/multiplier) +1
     /// * `desired_pool_size`: `desired_pool_size`/multiplier) +1
+    /// * `max_pool_size`: `max_pool_size`/multiplier) +1
     pub fn pool_multiplier(mut self, v: u32) -> Self {
         self.pool_multiplier = v;
         self
     }
 
+    /// Options for connecting to nodes given with a `rediss://` URL.
+    ///
+    /// Connecting via TLS requires the feature `tls`.
+    pub fn tls(mut self, v: TlsConfig) -> Self {
+        self.tls = v;
+        self
+    }
+
+    /// Discover the nodes to connect to via Redis Sentinel.
+    /// `connect_to_nodes` is ignored then.
+    ///
+    /// Sentinel mode is only supported by `Builder::finish_redis_rs`.
+    pub fn sentinel(mut self, v: SentinelConfig) -> Self {
+        self.sentinel = Some(v);
+        self
+    }
+
+    /// Treat the nodes as the nodes of a Redis Cluster.
+    /// `connect_to_nodes` are only used to discover the masters then.
+    ///
+    /// Cluster mode is only supported by `Builder::finish_redis_rs`.
+    pub fn cluster(mut self, v: bool) -> Self {
+        self.cluster = v;
+        self
+    }
+
     /// Updates this configuration from the environment.
     ///
     /// If no `prefix` is set all the given env key start with `REOOL_`.
     /// Otherwise the prefix is used with an automatically appended `_`.
     ///
     /// * `DESIRED_POOL_SIZE`: `usize`. Omit if you do not want to update the value
+    /// * `MAX_POOL_SIZE`: `usize`. Omit if you do not want to update the value
+    /// * `SURPLUS_IDLE_TIMEOUT_MS`: `u64`. Omit if you do not want to update the value
+    /// * `IDLE_TIMEOUT_MS`: `u64`. Omit if you do not want to update the value
+    /// * `MAX_CONNECTION_LIFETIME_MS`: `u64`. Omit if you do not want to update the value
+    /// * `CONNECTION_LIFETIME_JITTER_MS`: `u64`. Omit if you do not want to update the value
+    /// * `VALIDATION_POLICY`: `string`. Omit if you do not want to update the value
+    /// * `VALIDATION_TIMEOUT_MS`: `u64`. Omit if you do not want to update the value
     /// * `DEFAULT_POOL_CHECKOUT_MODE`: The default checkout mode to use. Omit if you do not want to update the value
     /// * `RESERVATION_LIMIT`: `usize`. Omit if you do not want to update the value
     /// * `ACTIVATION_ORDER`: `string`. Omit if you do not want to update the value
     /// * `MIN_REQUIRED_NODES`: `usize`. Omit if you do not want to update the value
     /// * `CONNECT_TO`: `[String]`. Separated by `;`. Omit if you do not want to update the value
+    /// * `REPLICAS`: `[String]`. Separated by `;`. Omit if you do not want to update the value
+    /// * `REPLICA_FALLBACK_TO_PRIMARY`: `bool`. Omit if you do not want to update the value
     /// * `POOL_MULTIPLIER`: Omit if you do not want to update the value
+    /// * `LOAD_BALANCING_STRATEGY`: `string`. Omit if you do not want to update the value
     /// * `CHECKOUT_QUEUE_SIZE`: Omit if you do not want to update the value
     /// * `RETRY_ON_CHECKOUT_LIMIT`: Omit if you do not want to update the value
+    /// * `RECYCLE_ON_ADDRESS_CHANGE`: `bool`. Omit if you do not want to update the value
+    /// * `CIRCUIT_BREAKER_THRESHOLD`: `usize`. Omit if you do not want to update the value
+    /// * `CIRCUIT_BREAKER_OPEN_DURATION_MS`: `u64`. Omit if you do not want to update the value
+    /// * `HEALTH_CHECK_INTERVAL_MS`: `u64`. Omit if you do not want to update the value
+    /// * `HEALTH_CHECK_FAILURE_THRESHOLD`: `usize`. Omit if you do not want to update the value
+    /// * `RESERVED_FOR_HIGH_PRIORITY`: `usize`. Omit if you do not want to update the value
+    /// * `PARTITIONS`: `[name=usize]`. Separated by `;`. Omit if you do not want to update the value
+    /// * `LEAK_DETECTION_THRESHOLD_MS`: `u64`. Omit if you do not want to update the value
+    /// * `CAPTURE_LEAK_BACKTRACES`: `bool`. Omit if you do not want to update the value
+    /// * `TLS_CA_BUNDLE`: `string`. A path. Omit if you do not want to update the value
+    /// * `TLS_CLIENT_CERTIFICATE`: `string`. A path. Omit if you do not want to update the value
+    /// * `TLS_CLIENT_KEY`: `string`. A path. Omit if you do not want to update the value
+    /// * `TLS_DOMAIN`: `string`. Omit if you do not want to update the value
+    /// * `TLS_ACCEPT_INVALID_HOSTNAMES`: `bool`. Omit if you do not want to update the value
+    /// * `SENTINEL_MASTER_NAME`: `string`. Enables sentinel mode. Omit if you do not want to update the value
+    /// * `SENTINELS`: `[String]`. Separated by `;`. Enables sentinel mode. Omit if you do not want to update the value
+    /// * `SENTINEL_CONNECT_TO_REPLICAS`: `bool`. Enables sentinel mode. Omit if you do not want to update the value
+    /// * `CLUSTER`: `bool`. Omit if you do not want to update the value
     pub fn update_from_environment(&mut self, prefix: Option<&str>) -> InitializationResult<()> {
         helpers::set_desired_pool_size(prefix, |v| {
             self.desired_pool_size = v;
         })?;
 
+        helpers::set_max_pool_size(prefix, |v| {
+            self.max_pool_size = Some(v);
+        })?;
+
+        helpers::set_surplus_idle_timeout(prefix, |v| {
+            self.surplus_idle_timeout = v;
+        })?;
+
+        helpers::set_idle_timeout(prefix, |v| {
+            self.idle_timeout = Some(v);
+        })?;
+
+        helpers::set_max_connection_lifetime(prefix, |v| {
+            self.max_connection_lifetime = Some(v);
+        })?;
+
+        helpers::set_connection_lifetime_jitter(prefix, |v| {
+            self.connection_lifetime_jitter = v;
+        })?;
+
+        helpers::set_validation_policy(prefix, |v| {
+            self.validation_policy = v;
+        })?;
+
+        helpers::set_validation_timeout(prefix, |v| {
+            self.validation_timeout = v;
+        })?;
+
         helpers::set_default_checkout_mode(prefix, |v| {
             self.default_checkout_mode = v;
         })?;
@@ -212,10 +618,22 @@ impl Config {
             self.connect_to_nodes = v;
         };
 
+        if let Some(v) = helpers::get_replicas(prefix)? {
+            self.replicas = v;
+        };
+
+        helpers::set_replica_fallback_to_primary(prefix, |v| {
+            self.replica_fallback_to_primary = v;
+        })?;
+
         helpers::set_pool_multiplier(prefix, |v| {
             self.pool_multiplier = v;
         })?;
 
+        helpers::set_load_balancing_strategy(prefix, |v| {
+            self.load_balancing_strategy = v;
+        })?;
+
         helpers::set_checkout_queue_size(prefix, |v| {
             self.checkout_queue_size = v;
         })?;
@@ -224,21 +642,157 @@ impl Config {
             self.retry_on_checkout_limit = v;
         })?;
 
+        helpers::set_recycle_on_address_change(prefix, |v| {
+            self.recycle_on_address_change = v;
+        })?;
+
+        helpers::set_circuit_breaker_threshold(prefix, |v| {
+            self.circuit_breaker_threshold = v;
+        })?;
+
+        helpers::set_circuit_breaker_open_duration(prefix, |v| {
+            self.circuit_breaker_open_duration = v;
+        })?;
+
+        helpers::set_health_check_interval(prefix, |v| {
+            self.health_check_interval = Some(v);
+        })?;
+
+        helpers::set_health_check_failure_threshold(prefix, |v| {
+            self.health_check_failure_threshold = v;
+        })?;
+
+        helpers::set_reserved_for_high_priority(prefix, |v| {
+            self.reserved_for_high_priority = v;
+        })?;
+
+        if let Some(v) = helpers::get_partitions(prefix)? {
+            self.partitions = v;
+        }
+
+        helpers::set_leak_detection_threshold(prefix, |v| {
+            self.leak_detection_threshold = Some(v);
+        })?;
+
+        helpers::set_capture_leak_backtraces(prefix, |v| {
+            self.capture_leak_backtraces = v;
+        })?;
+
+        helpers::set_tls_ca_bundle(prefix, |v| {
+            self.tls.ca_bundle = Some(v);
+        })?;
+
+        helpers::set_tls_client_certificate(prefix, |v| {
+            self.tls.client_certificate = Some(v);
+        })?;
+
+        helpers::set_tls_client_key(prefix, |v| {
+            self.tls.client_key = Some(v);
+        })?;
+
+        helpers::set_tls_domain(prefix, |v| {
+            self.tls.domain = Some(v);
+        })?;
+
+        helpers::set_tls_accept_invalid_hostnames(prefix, |v| {
+            self.tls.accept_invalid_hostnames = v;
+        })?;
+
+        helpers::set_sentinel_master_name(prefix, |v| {
+            self.sentinel
+                .get_or_insert_with(SentinelConfig::default)
+                .master_name = v;
+        })?;
+
+        if let Some(v) = helpers::get_sentinels(prefix)? {
+            self.sentinel
+                .get_or_insert_with(SentinelConfig::default)
+                .sentinels = v;
+        };
+
+        helpers::set_sentinel_connect_to_replicas(prefix, |v| {
+            self.sentinel
+                .get_or_insert_with(SentinelConfig::default)
+                .connect_to_replicas = v;
+        })?;
+
+        helpers::set_cluster(prefix, |v| {
+            self.cluster = v;
+        })?;
+
         Ok(())
     }
 
     /// Create a `Builder` initialized with the values from this `Config`
     pub fn builder(&self) -> Builder {
-        Builder::default()
+        let builder = Builder::default()
             .desired_pool_size(self.desired_pool_size)
+            .surplus_idle_timeout(self.surplus_idle_timeout)
+            .connection_lifetime_jitter(self.connection_lifetime_jitter)
+            .validation_policy(self.validation_policy)
+            .validation_timeout(self.validation_timeout)
             .default_checkout_mode(self.default_checkout_mode)
             .backoff_strategy(self.backoff_strategy)
             .reservation_limit(self.reservation_limit)
             .min_required_nodes(self.min_required_nodes)
             .connect_to_nodes(self.connect_to_nodes.clone())
+            .connect_to_replicas(self.replicas.clone())
+            .replica_fallback_to_primary(self.replica_fallback_to_primary)
             .pool_multiplier(self.pool_multiplier)
+            .load_balancing_strategy(self.load_balancing_strategy.clone())
             .checkout_queue_size(self.checkout_queue_size)
             .retry_on_checkout_limit(self.retry_on_checkout_limit)
+            .recycle_on_address_change(self.recycle_on_address_change)
+            .circuit_breaker_threshold(self.circuit_breaker_threshold)
+            .circuit_breaker_open_duration(self.circuit_breaker_open_duration)
+            .health_check_failure_threshold(self.health_check_failure_threshold)
+            .reserved_for_high_priority(self.reserved_for_high_priority)
+            .capture_leak_backtraces(self.capture_leak_backtraces)
+            .tls(self.tls.clone())
+            .cluster(self.cluster);
+
+        let builder = if let Some(max_pool_size) = self.max_pool_size {
+            builder.max_pool_size(max_pool_size)
+        } else {
+            builder
+        };
+
+        let builder = if let Some(idle_timeout) = self.idle_timeout {
+            builder.idle_timeout(idle_timeout)
+        } else {
+            builder
+        };
+
+        let builder = if let Some(max_connection_lifetime) = self.max_connection_lifetime {
+            builder.max_connection_lifetime(max_connection_lifetime)
+        } else {
+            builder
+        };
+
+        let builder = if let Some(health_check_interval) = self.health_check_interval {
+            builder.health_check_interval(health_check_interval)
+        } else {
+            builder
+        };
+
+        let builder = if let Some(leak_detection_threshold) = self.leak_detection_threshold {
+            builder.leak_detection_threshold(leak_detection_threshold)
+        } else {
+            builder
+        };
+
+        let builder = self
+            .partitions
+            .iter()
+            .fold(builder, |builder, (name, &max_checked_out)| {
+                builder.partition(name.clone(), max_checked_out)
+            });
+
+        if let Some(ref sentinel) = self.sentinel {
+            builder.sentinel(sentinel.clone())
+        } else {
+            builder
+        }
     }
 }
 
@@ -246,15 +800,37 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             desired_pool_size: 50,
+            max_pool_size: None,
+            surplus_idle_timeout: Duration::from_secs(60),
+            idle_timeout: None,
+            max_connection_lifetime: None,
+            connection_lifetime_jitter: Duration::from_secs(0),
+            validation_policy: ValidationPolicy::default(),
+            validation_timeout: Duration::from_secs(1),
             default_checkout_mode: DefaultPoolCheckoutMode::WaitAtMost(Duration::from_millis(30)),
             backoff_strategy: BackoffStrategy::default(),
             reservation_limit: 50,
             activation_order: ActivationOrder::default(),
             min_required_nodes: 1,
             connect_to_nodes: Vec::new(),
+            replicas: Vec::new(),
+            replica_fallback_to_primary: false,
             pool_multiplier: 1,
+            load_balancing_strategy: LoadBalancingStrategy::default(),
             checkout_queue_size: 100,
             retry_on_checkout_limit: true,
+            recycle_on_address_change: false,
+            circuit_breaker_threshold: 0,
+            circuit_breaker_open_duration: Duration::from_secs(10),
+            health_check_interval: None,
+            health_check_failure_threshold: 3,
+            reserved_for_high_priority: 0,
+            partitions: BTreeMap::new(),
+            leak_detection_threshold: None,
+            capture_leak_backtraces: false,
+            tls: TlsConfig::default(),
+            sentinel: None,
+            cluster: false,
         }
     }
 }
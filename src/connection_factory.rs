@@ -1,12 +1,13 @@
 //! Building blocks for creating a `ConnectionFactory`
 use std::error::Error as StdError;
 use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
 use std::time::Instant;
 
-use futures::{
-    future::{self, Future},
-    Poll,
-};
+use futures::future::{self, BoxFuture, FutureExt, TryFutureExt};
 
 use crate::{Ping, Poolable};
 
@@ -27,8 +28,37 @@ pub trait ConnectionFactory {
     /// This will create a new connection and try a ping on it.
     ///
     /// If a factory does not support `ping` it will simply fail with `()`.
-    fn ping(&self, _timeout: Instant) -> Box<dyn Future<Item = Ping, Error = ()> + Send> {
-        Box::new(future::err(()))
+    fn ping(&self, _timeout: Instant) -> BoxFuture<'static, Result<Ping, ()>> {
+        future::err(()).boxed()
+    }
+    /// Check whether a connection is still usable, e.g. by sending a `PING`.
+    ///
+    /// The connection is handed back if it is still usable. Otherwise
+    /// the future fails and the connection will be discarded.
+    ///
+    /// This is used by the pool depending on the configured `ValidationPolicy`.
+    /// The default implementation considers all connections to be usable.
+    fn validate(&self, conn: Self::Connection) -> BoxFuture<'static, Result<Self::Connection, ()>> {
+        future::ok(conn).boxed()
+    }
+    /// Close a connection which will not be used anymore, e.g. by sending a `QUIT`.
+    ///
+    /// This is used by the pool when it shuts down.
+    /// The default implementation simply drops the connection.
+    fn close(&self, conn: Self::Connection) -> BoxFuture<'static, ()> {
+        drop(conn);
+        future::ready(()).boxed()
+    }
+    /// The generation of the connections this factory creates.
+    ///
+    /// A factory increases the generation if the connections created
+    /// before should be replaced, e.g. because the addresses of
+    /// the node changed. Pooled connections of an older generation
+    /// are closed and replaced once they are checked in.
+    ///
+    /// The default implementation always returns 0.
+    fn generation(&self) -> u64 {
+        0
     }
 }
 
@@ -77,23 +107,64 @@ impl StdError for NewConnectionError {
 
 /// A `Future` that might complete with a new connection
 pub struct NewConnection<T: Poolable> {
-    inner: Box<dyn Future<Item = T, Error = NewConnectionError> + Send + 'static>,
+    inner: BoxFuture<'static, Result<T, NewConnectionError>>,
 }
 
 impl<T: Poolable> NewConnection<T> {
     pub fn new<F>(f: F) -> Self
     where
-        F: Future<Item = T, Error = NewConnectionError> + Send + 'static,
+        F: Future<Output = Result<T, NewConnectionError>> + Send + 'static,
     {
-        Self { inner: Box::new(f) }
+        Self { inner: f.boxed() }
     }
 }
 
 impl<T: Poolable> Future for NewConnection<T> {
-    type Item = T;
-    type Error = NewConnectionError;
+    type Output = Result<T, NewConnectionError>;
 
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        self.inner.poll()
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.inner.as_mut().poll(cx)
+    }
+}
+
+/// Initializes a newly created connection before it is put into the pool.
+///
+/// This can be used to issue commands like `AUTH`, `SELECT` or
+/// `CLIENT SETNAME` once on each new connection.
+///
+/// If the initialization fails the connection is discarded and the
+/// creation of the connection is retried according to the `BackoffStrategy`.
+pub struct ConnectionInitializer<T: Poolable> {
+    f: Arc<dyn Fn(T) -> NewConnection<T> + Send + Sync + 'static>,
+}
+
+impl<T: Poolable> ConnectionInitializer<T> {
+    pub fn new<F, R, E>(f: F) -> Self
+    where
+        F: Fn(T) -> R + Send + Sync + 'static,
+        R: Future<Output = Result<T, E>> + Send + 'static,
+        E: StdError + Send + 'static,
+    {
+        Self {
+            f: Arc::new(move |conn| NewConnection::new(f(conn).map_err(NewConnectionError::new))),
+        }
+    }
+
+    pub(crate) fn initialize(&self, conn: T) -> NewConnection<T> {
+        (self.f)(conn)
+    }
+}
+
+impl<T: Poolable> Clone for ConnectionInitializer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            f: Arc::clone(&self.f),
+        }
+    }
+}
+
+impl<T: Poolable> fmt::Debug for ConnectionInitializer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ConnectionInitializer")
     }
 }
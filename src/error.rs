@@ -1,27 +1,99 @@
 use std::error::Error as StdError;
 use std::fmt;
+use std::time::Duration;
 
 use redis::{ErrorKind as RedisErrorKind, RedisError};
 
+use crate::instrumentation::PoolId;
+
 pub type InitializationResult<T> = Result<T, InitializationError>;
 
 /// An error specifying what went wrong
 /// on a failed checkout
+///
+/// If the checkout was attempted on a pool the error also
+/// contains the state of that pool at the time of the failure.
 #[derive(Debug)]
 pub struct CheckoutError {
     kind: CheckoutErrorKind,
+    pub(crate) pool: Option<PoolId>,
+    pub(crate) waited: Option<Duration>,
+    pub(crate) reservations: Option<usize>,
+    pub(crate) last_connection_error: Option<ConnectionFailure>,
 }
 
 /// An error returned from `reool` when a checkout failed
 impl CheckoutError {
     pub(crate) fn new(kind: CheckoutErrorKind) -> Self {
-        Self { kind }
+        Self {
+            kind,
+            pool: None,
+            waited: None,
+            reservations: None,
+            last_connection_error: None,
+        }
     }
 
     /// The kind of the error which can be matched
     pub fn kind(&self) -> CheckoutErrorKind {
         self.kind
     }
+
+    /// The pool the checkout was attempted on
+    ///
+    /// `None` if the checkout failed before a pool was selected.
+    pub fn pool(&self) -> Option<PoolId> {
+        self.pool
+    }
+
+    /// How long the checkout waited before it failed
+    pub fn waited(&self) -> Option<Duration> {
+        self.waited
+    }
+
+    /// The number of checkouts waiting for a connection of
+    /// the pool at the time of the failure
+    pub fn reservations(&self) -> Option<usize> {
+        self.reservations
+    }
+
+    /// The last failed attempt to connect to the node of the pool
+    /// since a connection was created successfully.
+    ///
+    /// Only available for `CheckoutErrorKind::NoConnection` and
+    /// `CheckoutErrorKind::NoPool`. This is also the `source` of the error.
+    pub fn last_connection_error(&self) -> Option<&ConnectionFailure> {
+        self.last_connection_error.as_ref()
+    }
+}
+
+/// A failed attempt to create a connection
+///
+/// Only the message of the original error is kept since
+/// the error of a connection factory does not have to be `Sync`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionFailure {
+    message: String,
+}
+
+impl ConnectionFailure {
+    pub(crate) fn new<E: fmt::Display>(error: &E) -> Self {
+        Self {
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConnectionFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl StdError for ConnectionFailure {
+    fn description(&self) -> &str {
+        "a connection could not be created"
+    }
 }
 
 /// Further specifies the kind of a `CheckoutError`
@@ -46,6 +118,14 @@ pub enum CheckoutErrorKind {
     /// this error is returned. Some `Executor`s might simply
     /// panic.
     TaskExecution,
+    /// The pool is shutting down and does not hand out
+    /// connections anymore
+    ShuttingDown,
+    /// The partition already has as many connections checked
+    /// out as its budget allows
+    PartitionLimitReached,
+    /// There is no partition with the given name
+    UnknownPartition,
 }
 
 impl fmt::Display for CheckoutErrorKind {
@@ -59,6 +139,9 @@ impl fmt::Display for CheckoutErrorKind {
             CheckoutErrorKind::NoPool => "there was no pool available",
             CheckoutErrorKind::CheckoutLimitReached => "checkout limit limit reached",
             CheckoutErrorKind::TaskExecution => "task execution failed",
+            CheckoutErrorKind::ShuttingDown => "the pool is shutting down",
+            CheckoutErrorKind::PartitionLimitReached => "the partition limit has been reached",
+            CheckoutErrorKind::UnknownPartition => "there is no such partition",
         };
         f.write_str(s)
     }
@@ -66,7 +149,21 @@ impl fmt::Display for CheckoutErrorKind {
 
 impl fmt::Display for CheckoutError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(self.description())
+        write!(f, "{}", self.kind)?;
+        if let Some(pool) = self.pool {
+            write!(f, " (pool {}", pool)?;
+            if let Some(waited) = self.waited {
+                write!(f, ", waited {:?}", waited)?;
+            }
+            if let Some(reservations) = self.reservations {
+                write!(f, ", {} reservations", reservations)?;
+            }
+            f.write_str(")")?;
+        }
+        if let Some(ref last_connection_error) = self.last_connection_error {
+            write!(f, ": {}", last_connection_error)?;
+        }
+        Ok(())
     }
 }
 
@@ -81,17 +178,22 @@ impl StdError for CheckoutError {
             CheckoutErrorKind::NoPool => "there was no pool available",
             CheckoutErrorKind::CheckoutLimitReached => "checkout limit limit reached",
             CheckoutErrorKind::TaskExecution => "task execution failed",
+            CheckoutErrorKind::ShuttingDown => "the pool is shutting down",
+            CheckoutErrorKind::PartitionLimitReached => "the partition limit has been reached",
+            CheckoutErrorKind::UnknownPartition => "there is no such partition",
         }
     }
 
-    fn cause(&self) -> Option<&dyn StdError> {
-        None
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.last_connection_error
+            .as_ref()
+            .map(|err| err as &(dyn StdError + 'static))
     }
 }
 
 impl From<CheckoutErrorKind> for CheckoutError {
     fn from(kind: CheckoutErrorKind) -> Self {
-        Self { kind }
+        Self::new(kind)
     }
 }
 
@@ -106,6 +208,105 @@ impl From<CheckoutError> for RedisError {
     }
 }
 
+/// An error of an operation on a pooled connection
+///
+/// Distinguishes a failed checkout from errors returned by Redis
+/// and failures of the connection itself. Returned by
+/// `RedisPool::with_connection`.
+#[derive(Debug)]
+pub enum Error {
+    /// No connection could be checked out. This includes
+    /// checkouts which timed out.
+    Checkout(CheckoutError),
+    /// Redis returned an error, e.g. `WRONGTYPE`, or the response
+    /// could not be converted into the expected type
+    Redis(RedisError),
+    /// The connection failed, e.g. it was dropped by the server
+    Io(RedisError),
+    /// A command timed out
+    Timeout(RedisError),
+}
+
+impl Error {
+    /// The kind of the error if the checkout failed
+    pub fn checkout_error_kind(&self) -> Option<CheckoutErrorKind> {
+        match self {
+            Error::Checkout(err) => Some(err.kind()),
+            _ => None,
+        }
+    }
+
+    /// The error code returned by Redis, e.g. `WRONGTYPE`
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Error::Redis(err) => err.code(),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if either the checkout or a command timed out
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            Error::Checkout(err) => err.kind() == CheckoutErrorKind::CheckoutTimeout,
+            Error::Timeout(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Checkout(err) => write!(f, "checkout failed: {}", err),
+            Error::Redis(err) => write!(f, "redis error: {}", err),
+            Error::Io(err) => write!(f, "connection failed: {}", err),
+            Error::Timeout(err) => write!(f, "command timed out: {}", err),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match self {
+            Error::Checkout(_) => "checkout failed",
+            Error::Redis(_) => "redis error",
+            Error::Io(_) => "connection failed",
+            Error::Timeout(_) => "command timed out",
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Checkout(err) => Some(err),
+            Error::Redis(err) | Error::Io(err) | Error::Timeout(err) => Some(err),
+        }
+    }
+}
+
+impl From<CheckoutError> for Error {
+    fn from(error: CheckoutError) -> Self {
+        Error::Checkout(error)
+    }
+}
+
+impl From<CheckoutErrorKind> for Error {
+    fn from(kind: CheckoutErrorKind) -> Self {
+        Error::Checkout(kind.into())
+    }
+}
+
+impl From<RedisError> for Error {
+    fn from(error: RedisError) -> Self {
+        if error.is_timeout() {
+            Error::Timeout(error)
+        } else if error.kind() == RedisErrorKind::IoError {
+            Error::Io(error)
+        } else {
+            Error::Redis(error)
+        }
+    }
+}
+
 /// An initialization has failed
 #[derive(Debug)]
 pub struct InitializationError {
@@ -144,8 +345,8 @@ impl fmt::Display for InitializationError {
         match (self.message.as_ref(), self.cause.as_ref()) {
             (Some(msg), Some(cause)) => write!(f, "{}: {}", msg, cause),
             (Some(msg), None) => write!(f, "{}", msg),
-            (None, Some(cause)) => write!(f, "{}: {}", self.description(), cause),
-            (None, None) => write!(f, "{}", self.description()),
+            (None, Some(cause)) => write!(f, "initialization failed: {}", cause),
+            (None, None) => write!(f, "initialization failed"),
         }
     }
 }
@@ -159,3 +360,44 @@ impl StdError for InitializationError {
         self.cause.as_ref().map(|cause| &**cause as &dyn StdError)
     }
 }
+
+#[test]
+fn the_context_of_a_checkout_error_is_displayed() {
+    let mut err = CheckoutError::new(CheckoutErrorKind::NoConnection);
+    assert_eq!(err.to_string(), "there are no connections available");
+    assert!(err.source().is_none());
+
+    err.pool = Some(PoolId::new(1));
+    err.waited = Some(Duration::from_millis(30));
+    err.reservations = Some(5);
+    err.last_connection_error = Some(ConnectionFailure::new(&"connection refused"));
+    assert_eq!(
+        err.to_string(),
+        "there are no connections available (pool P0001, waited 30ms, 5 reservations): \
+         connection refused"
+    );
+    assert_eq!(err.source().unwrap().to_string(), "connection refused");
+}
+
+#[test]
+fn redis_errors_are_distinguished_by_their_cause() {
+    use std::io;
+
+    let err: Error = redis::parse_redis_value(b"-WRONGTYPE wrong kind of value\r\n")
+        .unwrap_err()
+        .into();
+    assert_eq!(err.code(), Some("WRONGTYPE"));
+
+    let err: Error = RedisError::from(io::Error::from(io::ErrorKind::ConnectionReset)).into();
+    assert!(matches!(err, Error::Io(_)));
+
+    let err: Error = RedisError::from(io::Error::from(io::ErrorKind::TimedOut)).into();
+    assert!(err.is_timeout());
+
+    let err: Error = CheckoutErrorKind::CheckoutTimeout.into();
+    assert!(err.is_timeout());
+    assert_eq!(
+        err.checkout_error_kind(),
+        Some(CheckoutErrorKind::CheckoutTimeout)
+    );
+}
@@ -1,36 +1,41 @@
 use std::error::Error;
+use std::future::Future;
 
-use futures::future::Future;
-use tokio::executor::{DefaultExecutor, Executor};
-use tokio::runtime::TaskExecutor;
+use tokio::runtime::Handle;
 
 /// Compatibility for different executors
 #[derive(Clone)]
 pub enum ExecutorFlavour {
     Runtime,
-    TokioTaskExecutor(TaskExecutor),
+    TokioHandle(Handle),
 }
 
 impl ExecutorFlavour {
     pub fn spawn<F>(&self, task: F) -> Result<(), Box<dyn Error>>
     where
-        F: Future<Item = (), Error = ()> + Send + 'static,
+        F: Future<Output = ()> + Send + 'static,
     {
+        self.handle()?.spawn(task);
+        Ok(())
+    }
+
+    /// Returns the `Handle` tasks are spawned on.
+    ///
+    /// Fails for `ExecutorFlavour::Runtime` if not called from
+    /// within a runtime.
+    pub fn handle(&self) -> Result<Handle, Box<dyn Error>> {
         match self {
-            ExecutorFlavour::Runtime => DefaultExecutor::current()
-                .spawn(Box::new(task))
-                .map_err(|err| Box::new(err) as Box<dyn Error>),
-            ExecutorFlavour::TokioTaskExecutor(executor) => {
-                executor.spawn(Box::new(task));
-                Ok(())
+            ExecutorFlavour::Runtime => {
+                Handle::try_current().map_err(|err| Box::new(err) as Box<dyn Error>)
             }
+            ExecutorFlavour::TokioHandle(handle) => Ok(handle.clone()),
         }
     }
 }
 
-impl From<TaskExecutor> for ExecutorFlavour {
-    fn from(exec: TaskExecutor) -> Self {
-        ExecutorFlavour::TokioTaskExecutor(exec)
+impl From<Handle> for ExecutorFlavour {
+    fn from(handle: Handle) -> Self {
+        ExecutorFlavour::TokioHandle(handle)
     }
 }
 
@@ -0,0 +1,84 @@
+//! Reports on the health of the nodes of a pool
+//!
+//! Nodes are pinged periodically if a `health_check_interval`
+//! is configured. Nodes whose last `health_check_failure_threshold`
+//! pings failed are unhealthy and pools for multiple nodes make
+//! checkouts on them only if there are no healthy nodes.
+use std::time::{Duration, Instant};
+
+use crate::instrumentation::PoolId;
+use crate::{Ping, PingState};
+
+/// The health of all nodes of a pool
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+    /// One entry per sub pool
+    pub nodes: Vec<NodeHealth>,
+}
+
+impl HealthReport {
+    /// Returns `true` if all nodes are healthy
+    pub fn is_healthy(&self) -> bool {
+        self.nodes.iter().all(|node| node.healthy)
+    }
+
+    /// Returns `true` if at least one node is healthy
+    pub fn is_available(&self) -> bool {
+        self.nodes.iter().any(|node| node.healthy)
+    }
+
+    /// The nodes which are not healthy
+    pub fn unhealthy_nodes(&self) -> impl Iterator<Item = &NodeHealth> {
+        self.nodes.iter().filter(|node| !node.healthy)
+    }
+}
+
+/// The health of the node of a sub pool
+#[derive(Debug, Clone)]
+pub struct NodeHealth {
+    pub pool: PoolId,
+    pub connected_to: String,
+    /// `false` if the last `health_check_failure_threshold` pings failed
+    pub healthy: bool,
+    /// The number of pings that failed since the last successful one
+    pub consecutive_failures: usize,
+    /// The result of the last ping. `None` if the node
+    /// was not pinged yet.
+    pub last_ping: Option<LastPing>,
+}
+
+/// The outcome of the last `Ping` on a node
+#[derive(Debug, Clone)]
+pub struct LastPing {
+    /// When the ping completed
+    pub at: Instant,
+    /// Time to establish a fresh connection
+    pub connect_time: Option<Duration>,
+    /// Time to execute the ping
+    pub latency: Option<Duration>,
+    /// Total elapsed time
+    pub total_time: Duration,
+    /// The error if the ping failed
+    pub error: Option<String>,
+}
+
+impl LastPing {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl From<&Ping> for LastPing {
+    fn from(ping: &Ping) -> Self {
+        Self {
+            at: Instant::now(),
+            connect_time: ping.connect_time,
+            latency: ping.latency,
+            total_time: ping.total_time,
+            error: match ping.state {
+                PingState::Ok => None,
+                PingState::Failed(ref err) => Some(err.to_string()),
+            },
+        }
+    }
+}
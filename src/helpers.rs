@@ -1,8 +1,12 @@
+use std::collections::BTreeMap;
 use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use crate::activation_order::ActivationOrder;
 use crate::config::*;
 use crate::error::{InitializationError, InitializationResult};
+use crate::validation_policy::ValidationPolicy;
 
 fn make_prefix<T: Into<String>>(prefix: Option<T>) -> String {
     prefix
@@ -12,7 +16,7 @@ fn make_prefix<T: Into<String>>(prefix: Option<T>) -> String {
 
 pub fn set_desired_pool_size<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
 where
-    F: FnMut(usize) -> (),
+    F: FnMut(usize),
     T: Into<String>,
 {
     let prefix = make_prefix(prefix);
@@ -29,9 +33,128 @@ where
     }
 }
 
+pub fn set_max_pool_size<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(usize),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "MAX_POOL_SIZE");
+    match env::var(&key) {
+        Ok(s) => {
+            f(s.parse()
+                .map_err(|err| InitializationError::new(key, Some(err)))?);
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_surplus_idle_timeout<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(Duration),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "SURPLUS_IDLE_TIMEOUT_MS");
+    match env::var(&key) {
+        Ok(s) => {
+            f(Duration::from_millis(s.parse().map_err(|err| {
+                InitializationError::new(key, Some(err))
+            })?));
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_idle_timeout<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(Duration),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "IDLE_TIMEOUT_MS");
+    match env::var(&key) {
+        Ok(s) => {
+            f(Duration::from_millis(s.parse().map_err(|err| {
+                InitializationError::new(key, Some(err))
+            })?));
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_max_connection_lifetime<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(Duration),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "MAX_CONNECTION_LIFETIME_MS");
+    match env::var(&key) {
+        Ok(s) => {
+            f(Duration::from_millis(s.parse().map_err(|err| {
+                InitializationError::new(key, Some(err))
+            })?));
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_connection_lifetime_jitter<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(Duration),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "CONNECTION_LIFETIME_JITTER_MS");
+    match env::var(&key) {
+        Ok(s) => {
+            f(Duration::from_millis(s.parse().map_err(|err| {
+                InitializationError::new(key, Some(err))
+            })?));
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_validation_timeout<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(Duration),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "VALIDATION_TIMEOUT_MS");
+    match env::var(&key) {
+        Ok(s) => {
+            f(Duration::from_millis(s.parse().map_err(|err| {
+                InitializationError::new(key, Some(err))
+            })?));
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
 pub fn set_default_checkout_mode<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
 where
-    F: FnMut(DefaultPoolCheckoutMode) -> (),
+    F: FnMut(DefaultPoolCheckoutMode),
     T: Into<String>,
 {
     let prefix = make_prefix(prefix);
@@ -50,7 +173,7 @@ where
 
 pub fn set_reservation_limit<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
 where
-    F: FnMut(usize) -> (),
+    F: FnMut(usize),
     T: Into<String>,
 {
     let prefix = make_prefix(prefix);
@@ -69,7 +192,7 @@ where
 
 pub fn set_min_required_nodes<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
 where
-    F: FnMut(usize) -> (),
+    F: FnMut(usize),
     T: Into<String>,
 {
     let prefix = make_prefix(prefix);
@@ -88,7 +211,7 @@ where
 
 pub fn set_retry_on_checkout_limit<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
 where
-    F: FnMut(bool) -> (),
+    F: FnMut(bool),
     T: Into<String>,
 {
     let prefix = make_prefix(prefix);
@@ -108,7 +231,7 @@ where
 
 pub fn set_activation_order<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
 where
-    F: FnMut(ActivationOrder) -> (),
+    F: FnMut(ActivationOrder),
     T: Into<String>,
 {
     let prefix = make_prefix(prefix);
@@ -125,6 +248,395 @@ where
     }
 }
 
+pub fn set_validation_policy<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(ValidationPolicy),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "VALIDATION_POLICY");
+    match env::var(&key) {
+        Ok(s) => {
+            f(s.parse()
+                .map_err(|err| InitializationError::new(key, Some(err)))?);
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_tls_ca_bundle<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(PathBuf),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "TLS_CA_BUNDLE");
+    match env::var(&key) {
+        Ok(s) => {
+            f(PathBuf::from(s));
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_tls_client_certificate<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(PathBuf),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "TLS_CLIENT_CERTIFICATE");
+    match env::var(&key) {
+        Ok(s) => {
+            f(PathBuf::from(s));
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_tls_client_key<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(PathBuf),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "TLS_CLIENT_KEY");
+    match env::var(&key) {
+        Ok(s) => {
+            f(PathBuf::from(s));
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_tls_domain<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(String),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "TLS_DOMAIN");
+    match env::var(&key) {
+        Ok(s) => {
+            f(s);
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_tls_accept_invalid_hostnames<T, F>(
+    prefix: Option<T>,
+    mut f: F,
+) -> InitializationResult<()>
+where
+    F: FnMut(bool),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "TLS_ACCEPT_INVALID_HOSTNAMES");
+    match env::var(&key) {
+        Ok(s) => {
+            f(s.to_lowercase()
+                .parse()
+                .map_err(|err| InitializationError::new(key, Some(err)))?);
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_sentinel_master_name<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(String),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "SENTINEL_MASTER_NAME");
+    match env::var(&key) {
+        Ok(s) => {
+            f(s);
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn get_sentinels<T>(prefix: Option<T>) -> InitializationResult<Option<Vec<String>>>
+where
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "SENTINELS");
+    match env::var(&key) {
+        Ok(s) => Ok(Some(parse_connect_to(&s))),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_sentinel_connect_to_replicas<T, F>(
+    prefix: Option<T>,
+    mut f: F,
+) -> InitializationResult<()>
+where
+    F: FnMut(bool),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "SENTINEL_CONNECT_TO_REPLICAS");
+    match env::var(&key) {
+        Ok(s) => {
+            f(s.to_lowercase()
+                .parse()
+                .map_err(|err| InitializationError::new(key, Some(err)))?);
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_recycle_on_address_change<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(bool),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "RECYCLE_ON_ADDRESS_CHANGE");
+    match env::var(&key) {
+        Ok(s) => {
+            f(s.to_lowercase()
+                .parse()
+                .map_err(|err| InitializationError::new(key, Some(err)))?);
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_circuit_breaker_threshold<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(usize),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "CIRCUIT_BREAKER_THRESHOLD");
+    match env::var(&key) {
+        Ok(s) => {
+            f(s.parse()
+                .map_err(|err| InitializationError::new(key, Some(err)))?);
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_circuit_breaker_open_duration<T, F>(
+    prefix: Option<T>,
+    mut f: F,
+) -> InitializationResult<()>
+where
+    F: FnMut(Duration),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "CIRCUIT_BREAKER_OPEN_DURATION_MS");
+    match env::var(&key) {
+        Ok(s) => {
+            f(Duration::from_millis(s.parse().map_err(|err| {
+                InitializationError::new(key, Some(err))
+            })?));
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_health_check_interval<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(Duration),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "HEALTH_CHECK_INTERVAL_MS");
+    match env::var(&key) {
+        Ok(s) => {
+            f(Duration::from_millis(s.parse().map_err(|err| {
+                InitializationError::new(key, Some(err))
+            })?));
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_health_check_failure_threshold<T, F>(
+    prefix: Option<T>,
+    mut f: F,
+) -> InitializationResult<()>
+where
+    F: FnMut(usize),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "HEALTH_CHECK_FAILURE_THRESHOLD");
+    match env::var(&key) {
+        Ok(s) => {
+            f(s.parse()
+                .map_err(|err| InitializationError::new(key, Some(err)))?);
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_reserved_for_high_priority<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(usize),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "RESERVED_FOR_HIGH_PRIORITY");
+    match env::var(&key) {
+        Ok(s) => {
+            f(s.parse()
+                .map_err(|err| InitializationError::new(key, Some(err)))?);
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_leak_detection_threshold<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(Duration),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "LEAK_DETECTION_THRESHOLD_MS");
+    match env::var(&key) {
+        Ok(s) => {
+            f(Duration::from_millis(s.parse().map_err(|err| {
+                InitializationError::new(key, Some(err))
+            })?));
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_capture_leak_backtraces<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(bool),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "CAPTURE_LEAK_BACKTRACES");
+    match env::var(&key) {
+        Ok(s) => {
+            f(s.to_lowercase()
+                .parse()
+                .map_err(|err| InitializationError::new(key, Some(err)))?);
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_cluster<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(bool),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "CLUSTER");
+    match env::var(&key) {
+        Ok(s) => {
+            f(s.to_lowercase()
+                .parse()
+                .map_err(|err| InitializationError::new(key, Some(err)))?);
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn get_replicas<T>(prefix: Option<T>) -> InitializationResult<Option<Vec<String>>>
+where
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "REPLICAS");
+    match env::var(&key) {
+        Ok(s) => Ok(Some(parse_connect_to(&s))),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
+pub fn set_replica_fallback_to_primary<T, F>(
+    prefix: Option<T>,
+    mut f: F,
+) -> InitializationResult<()>
+where
+    F: FnMut(bool),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "REPLICA_FALLBACK_TO_PRIMARY");
+    match env::var(&key) {
+        Ok(s) => {
+            f(s.to_lowercase()
+                .parse()
+                .map_err(|err| InitializationError::new(key, Some(err)))?);
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
 pub fn get_connect_to<T>(prefix: Option<T>) -> InitializationResult<Option<Vec<String>>>
 where
     T: Into<String>,
@@ -152,7 +664,7 @@ where
 
 pub fn set_pool_multiplier<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
 where
-    F: FnMut(u32) -> (),
+    F: FnMut(u32),
     T: Into<String>,
 {
     let prefix = make_prefix(prefix);
@@ -169,9 +681,28 @@ where
     }
 }
 
+pub fn set_load_balancing_strategy<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
+where
+    F: FnMut(LoadBalancingStrategy),
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "LOAD_BALANCING_STRATEGY");
+    match env::var(&key) {
+        Ok(s) => {
+            f(s.parse()
+                .map_err(|err| InitializationError::new(key, Some(err)))?);
+            Ok(())
+        }
+        Err(env::VarError::NotPresent) => Ok(()),
+        Err(err) => Err(InitializationError::new(key, Some(err))),
+    }
+}
+
 pub fn set_checkout_queue_size<T, F>(prefix: Option<T>, mut f: F) -> InitializationResult<()>
 where
-    F: FnMut(usize) -> (),
+    F: FnMut(usize),
     T: Into<String>,
 {
     let prefix = make_prefix(prefix);
@@ -188,6 +719,42 @@ where
     }
 }
 
+pub fn get_partitions<T>(prefix: Option<T>) -> InitializationResult<Option<BTreeMap<String, usize>>>
+where
+    T: Into<String>,
+{
+    let prefix = make_prefix(prefix);
+
+    let key = format!("{}_{}", prefix, "PARTITIONS");
+    let s = match env::var(&key) {
+        Ok(s) => s,
+        Err(env::VarError::NotPresent) => return Ok(None),
+        Err(err) => return Err(InitializationError::new(key, Some(err))),
+    };
+
+    let mut partitions = BTreeMap::new();
+    for partition in parse_connect_to(&s) {
+        let mut parts = partition.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(max_checked_out)) => {
+                let max_checked_out = max_checked_out
+                    .trim()
+                    .parse()
+                    .map_err(|err| InitializationError::new(key.clone(), Some(err)))?;
+                partitions.insert(name.trim().to_string(), max_checked_out);
+            }
+            _ => {
+                return Err(InitializationError::message_only(format!(
+                    "{}: '{}' must have the form 'name=max_checked_out'",
+                    key, partition
+                )))
+            }
+        }
+    }
+
+    Ok(Some(partitions))
+}
+
 fn parse_connect_to(what: &str) -> Vec<String> {
     what.split(';')
         .filter(|s| !s.is_empty())
@@ -1,3 +1,4 @@
+use std::panic::Location;
 use std::time::Duration;
 
 use metrix::cockpit::Cockpit;
@@ -5,6 +6,8 @@ use metrix::instruments::*;
 use metrix::processor::{AggregatesProcessors, TelemetryProcessor};
 use metrix::{Decrement, Increment, TelemetryTransmitter, TimeUnit, TransmitsTelemetryData};
 
+use crate::{CircuitState, Priority};
+
 use super::{Instrumentation, PoolId};
 
 /// A configuration for instrumenting with `metrix`
@@ -114,17 +117,25 @@ pub enum Metric {
     ConnectionDropped,
     ConnectionCreated,
     ConnectionCreatedTotalTime,
+    IdleConnectionEvicted,
     ReservationAdded,
     ReservationsChanged,
     ReservationFulfilled,
     ReservationNotFulfilled,
     ReservationLimitReached,
+    PriorityReservationsChanged(Priority),
+    PriorityReservationFulfilled(Priority),
     ConnectionFactoryFailed,
     LifeTime,
     ConnectionsChanged,
     InFlightConnectionsChanged,
     IdleConnectionsChanged,
     PoolCountChanged,
+    CircuitOpened,
+    OpenCircuitsChanged,
+    PartitionCheckedOutChanged,
+    PartitionLimitReached,
+    ConnectionLeakSuspected,
 
     InternalMessageReceived,
     CheckoutMessageReceived,
@@ -194,6 +205,11 @@ impl Instrumentation for MetrixInstrumentation {
             .observed_one_duration_now(Metric::ConnectionCreatedTotalTime, total_time);
     }
 
+    fn idle_connection_evicted(&self, idle_for: Duration, _pool: PoolId) {
+        self.transmitter
+            .observed_one_duration_now(Metric::IdleConnectionEvicted, idle_for);
+    }
+
     fn idle_inc(&self, _pool: PoolId) {
         self.transmitter
             .observed_one_value_now(Metric::IdleConnectionsChanged, Increment);
@@ -214,36 +230,45 @@ impl Instrumentation for MetrixInstrumentation {
             .observed_one_value_now(Metric::InFlightConnectionsChanged, Decrement);
     }
 
-    fn reservation_added(&self, _pool: PoolId) {
+    fn reservation_added(&self, priority: Priority, _pool: PoolId) {
         self.transmitter
             .observed_one_now(Metric::ReservationAdded)
-            .observed_one_value_now(Metric::ReservationsChanged, Increment);
+            .observed_one_value_now(Metric::ReservationsChanged, Increment)
+            .observed_one_value_now(Metric::PriorityReservationsChanged(priority), Increment);
     }
 
     fn reservation_fulfilled(
         &self,
         reservation_time: Duration,
         checkout_request_time: Duration,
+        priority: Priority,
         _pool: PoolId,
     ) {
         self.transmitter
             .observed_one_duration_now(Metric::ReservationFulfilled, reservation_time)
             .observed_one_duration_now(Metric::Fulfillment, checkout_request_time)
-            .observed_one_value_now(Metric::ReservationsChanged, Decrement);
+            .observed_one_value_now(Metric::ReservationsChanged, Decrement)
+            .observed_one_duration_now(
+                Metric::PriorityReservationFulfilled(priority),
+                reservation_time,
+            )
+            .observed_one_value_now(Metric::PriorityReservationsChanged(priority), Decrement);
     }
 
     fn reservation_not_fulfilled(
         &self,
         reservation_time: Duration,
         _checkout_request_time: Duration,
+        priority: Priority,
         _pool: PoolId,
     ) {
         self.transmitter
             .observed_one_duration_now(Metric::ReservationNotFulfilled, reservation_time)
-            .observed_one_value_now(Metric::ReservationsChanged, Decrement);
+            .observed_one_value_now(Metric::ReservationsChanged, Decrement)
+            .observed_one_value_now(Metric::PriorityReservationsChanged(priority), Decrement);
     }
 
-    fn reservation_limit_reached(&self, _pool: PoolId) {
+    fn reservation_limit_reached(&self, _priority: Priority, _pool: PoolId) {
         self.transmitter
             .observed_one_now(Metric::ReservationLimitReached);
     }
@@ -267,6 +292,42 @@ impl Instrumentation for MetrixInstrumentation {
         self.transmitter
             .observed_one_duration_now(Metric::ProcessedRelevantMessage, processing_time);
     }
+
+    fn circuit_state_changed(&self, from: CircuitState, to: CircuitState, _pool: PoolId) {
+        if to == CircuitState::Open {
+            self.transmitter
+                .observed_one_now(Metric::CircuitOpened)
+                .observed_one_value_now(Metric::OpenCircuitsChanged, Increment);
+        } else if from == CircuitState::Open {
+            self.transmitter
+                .observed_one_value_now(Metric::OpenCircuitsChanged, Decrement);
+        }
+    }
+
+    fn partition_checked_out_inc(&self, _partition: &str) {
+        self.transmitter
+            .observed_one_value_now(Metric::PartitionCheckedOutChanged, Increment);
+    }
+
+    fn partition_checked_out_dec(&self, _partition: &str) {
+        self.transmitter
+            .observed_one_value_now(Metric::PartitionCheckedOutChanged, Decrement);
+    }
+
+    fn partition_limit_reached(&self, _partition: &str) {
+        self.transmitter
+            .observed_one_now(Metric::PartitionLimitReached);
+    }
+
+    fn connection_leak_suspected(
+        &self,
+        _held_for: Duration,
+        _checked_out_at: &'static Location<'static>,
+        _pool: PoolId,
+    ) {
+        self.transmitter
+            .observed_one_now(Metric::ConnectionLeakSuspected);
+    }
 }
 
 fn create<A: AggregatesProcessors>(
@@ -276,45 +337,45 @@ fn create<A: AggregatesProcessors>(
     let mut cockpit = Cockpit::without_name();
 
     let mut panel = Panel::named(Metric::CheckOutConnection, "checked_out_connections");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     let mut histogram = Histogram::new_with_defaults("idle_time_us");
     config.configure_histogram(&mut histogram, TimeUnit::Microseconds);
-    panel.set_histogram(histogram);
+    panel.add_histogram(histogram);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::Fulfillment, "fulfillment");
     let mut histogram = Histogram::new_with_defaults("after_us");
     config.configure_histogram(&mut histogram, TimeUnit::Microseconds);
-    panel.set_histogram(histogram);
+    panel.add_histogram(histogram);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(
         Metric::CheckedInReturnedConnection,
         "checked_in_returned_connections",
     );
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     let mut histogram = Histogram::new_with_defaults("flight_time_us");
     config.configure_histogram(&mut histogram, TimeUnit::Microseconds);
-    panel.set_histogram(histogram);
+    panel.add_histogram(histogram);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::CheckedInNewConnection, "checked_in_new_connections");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::ConnectionDropped, "connections_dropped");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     let mut histogram = Histogram::new_with_defaults("flight_time_us");
     config.configure_histogram(&mut histogram, TimeUnit::Microseconds);
-    panel.set_histogram(histogram);
+    panel.add_histogram(histogram);
     config.add_alert(&mut panel);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::ConnectionCreated, "connections_created");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     let mut histogram = Histogram::new_with_defaults("connect_time_us");
     config.configure_histogram(&mut histogram, TimeUnit::Microseconds);
-    panel.set_histogram(histogram);
+    panel.add_histogram(histogram);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(
@@ -323,43 +384,71 @@ fn create<A: AggregatesProcessors>(
     );
     let mut histogram = Histogram::new("time_ms");
     config.configure_histogram(&mut histogram, TimeUnit::Milliseconds);
-    panel.set_histogram(histogram);
+    panel.add_histogram(histogram);
+    cockpit.add_panel(panel);
+
+    let mut panel = Panel::named(Metric::IdleConnectionEvicted, "idle_connections_evicted");
+    panel.add_meter(Meter::new_with_defaults("per_second"));
+    let mut histogram = Histogram::new("idle_time_ms");
+    config.configure_histogram(&mut histogram, TimeUnit::Milliseconds);
+    panel.add_histogram(histogram);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::ReservationAdded, "reservations_added");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::ReservationFulfilled, "reservations_fulfilled");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     let mut histogram = Histogram::new_with_defaults("fulfilled_after_us");
     config.configure_histogram(&mut histogram, TimeUnit::Microseconds);
-    panel.set_histogram(histogram);
+    panel.add_histogram(histogram);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(
         Metric::ReservationNotFulfilled,
         "reservations_not_fulfilled",
     );
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     let mut histogram = Histogram::new_with_defaults("not_fulfilled_after_us");
     config.configure_histogram(&mut histogram, TimeUnit::Microseconds);
-    panel.set_histogram(histogram);
+    panel.add_histogram(histogram);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::ReservationLimitReached, "reservation_limit_reached");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     config.add_alert(&mut panel);
     cockpit.add_panel(panel);
 
+    for &priority in Priority::ALL.iter() {
+        let mut panel = Panel::named(
+            Metric::PriorityReservationsChanged(priority),
+            format!("{}_priority_reservations", priority),
+        );
+        let mut gauge = Gauge::new_with_defaults("count");
+        config.configure_gauge(&mut gauge);
+        panel.add_gauge(gauge);
+        cockpit.add_panel(panel);
+
+        let mut panel = Panel::named(
+            Metric::PriorityReservationFulfilled(priority),
+            format!("{}_priority_reservations_fulfilled", priority),
+        );
+        panel.add_meter(Meter::new_with_defaults("per_second"));
+        let mut histogram = Histogram::new_with_defaults("fulfilled_after_us");
+        config.configure_histogram(&mut histogram, TimeUnit::Microseconds);
+        panel.add_histogram(histogram);
+        cockpit.add_panel(panel);
+    }
+
     let mut panel = Panel::named(Metric::ConnectionFactoryFailed, "connection_factory_failed");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     config.add_alert(&mut panel);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::LifeTime, "life_times");
-    panel.set_meter(Meter::new_with_defaults("lifes_ended_per_second"));
-    panel.set_histogram(
+    panel.add_meter(Meter::new_with_defaults("lifes_ended_per_second"));
+    panel.add_histogram(
         Histogram::new_with_defaults("life_time_ms").display_time_unit(TimeUnit::Milliseconds),
     );
     cockpit.add_panel(panel);
@@ -367,52 +456,79 @@ fn create<A: AggregatesProcessors>(
     let mut panel = Panel::named(Metric::ConnectionsChanged, "connections");
     let mut gauge = Gauge::new_with_defaults("count");
     config.configure_gauge(&mut gauge);
-    panel.set_gauge(gauge);
+    panel.add_gauge(gauge);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::IdleConnectionsChanged, "idle");
     let mut gauge = Gauge::new_with_defaults("count");
     config.configure_gauge(&mut gauge);
-    panel.set_gauge(gauge);
+    panel.add_gauge(gauge);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::InFlightConnectionsChanged, "in_flight");
     let mut gauge = Gauge::new_with_defaults("count");
     config.configure_gauge(&mut gauge);
-    panel.set_gauge(gauge);
+    panel.add_gauge(gauge);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::ReservationsChanged, "reservations");
     let mut gauge = Gauge::new_with_defaults("count");
     config.configure_gauge(&mut gauge);
-    panel.set_gauge(gauge);
+    panel.add_gauge(gauge);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::PoolCountChanged, "pools");
     let mut gauge = Gauge::new_with_defaults("count");
     config.configure_gauge(&mut gauge);
-    panel.set_gauge(gauge);
+    panel.add_gauge(gauge);
+    cockpit.add_panel(panel);
+
+    let mut panel = Panel::named(Metric::CircuitOpened, "circuits_opened");
+    panel.add_meter(Meter::new_with_defaults("per_second"));
+    config.add_alert(&mut panel);
+    cockpit.add_panel(panel);
+
+    let mut panel = Panel::named(Metric::OpenCircuitsChanged, "open_circuits");
+    let mut gauge = Gauge::new_with_defaults("count");
+    config.configure_gauge(&mut gauge);
+    panel.add_gauge(gauge);
+    cockpit.add_panel(panel);
+
+    let mut panel = Panel::named(Metric::PartitionCheckedOutChanged, "partitions_checked_out");
+    let mut gauge = Gauge::new_with_defaults("count");
+    config.configure_gauge(&mut gauge);
+    panel.add_gauge(gauge);
+    cockpit.add_panel(panel);
+
+    let mut panel = Panel::named(Metric::PartitionLimitReached, "partition_limit_reached");
+    panel.add_meter(Meter::new_with_defaults("per_second"));
+    config.add_alert(&mut panel);
+    cockpit.add_panel(panel);
+
+    let mut panel = Panel::named(Metric::ConnectionLeakSuspected, "connection_leak_suspected");
+    panel.add_meter(Meter::new_with_defaults("per_second"));
+    config.add_alert(&mut panel);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::InternalMessageReceived, "internal_messages");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     let mut histogram = Histogram::new_with_defaults("latency_us");
     config.configure_histogram(&mut histogram, TimeUnit::Microseconds);
-    panel.set_histogram(histogram);
+    panel.add_histogram(histogram);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::CheckoutMessageReceived, "checkout_messages");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     let mut histogram = Histogram::new_with_defaults("latency_us");
     config.configure_histogram(&mut histogram, TimeUnit::Microseconds);
-    panel.set_histogram(histogram);
+    panel.add_histogram(histogram);
     cockpit.add_panel(panel);
 
     let mut panel = Panel::named(Metric::ProcessedRelevantMessage, "processed_messages");
-    panel.set_meter(Meter::new_with_defaults("per_second"));
+    panel.add_meter(Meter::new_with_defaults("per_second"));
     let mut histogram = Histogram::new_with_defaults("latency_us");
     config.configure_histogram(&mut histogram, TimeUnit::Microseconds);
-    panel.set_histogram(histogram);
+    panel.add_histogram(histogram);
     cockpit.add_panel(panel);
 
     let (tx, mut rx) = TelemetryProcessor::new_pair_without_name();
@@ -1,8 +1,11 @@
 //! Pluggable instrumentation
 use std::fmt;
+use std::panic::Location;
 use std::sync::Arc;
 use std::time::Duration;
 
+use crate::{CircuitState, Priority};
+
 #[cfg(feature = "metrix")]
 pub use self::metrix::{MetrixConfig, MetrixInstrumentation};
 pub use state_counters::*;
@@ -11,18 +14,51 @@ pub use state_counters::*;
 mod metrix;
 mod state_counters;
 
+/// The role of the node a pool is connected to
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
-pub struct PoolId(usize);
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum PoolRole {
+    /// The pool does not distinguish between its nodes
+    Unspecified,
+    /// The pool is connected to the primary
+    Primary,
+    /// The pool is connected to a replica
+    Replica,
+}
+
+impl fmt::Display for PoolRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            PoolRole::Unspecified => "unspecified",
+            PoolRole::Primary => "primary",
+            PoolRole::Replica => "replica",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PoolId(usize, PoolRole);
 
 impl PoolId {
     pub fn new(id: usize) -> Self {
-        PoolId(id)
+        PoolId(id, PoolRole::Unspecified)
+    }
+
+    /// Creates a `PoolId` for a pool connected to a node with the given role
+    pub fn with_role(id: usize, role: PoolRole) -> Self {
+        PoolId(id, role)
     }
 
     pub fn into_inner(self) -> usize {
         self.0
     }
 
+    pub fn role(self) -> PoolRole {
+        self.1
+    }
+
     pub fn inc(&mut self) {
         self.0 += 1;
     }
@@ -34,9 +70,25 @@ impl From<PoolId> for usize {
     }
 }
 
+/// Serialized as a struct with the fields `id` and `role`
+#[cfg(feature = "serde")]
+impl serde::Serialize for PoolId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut s = serializer.serialize_struct("PoolId", 2)?;
+        s.serialize_field("id", &self.0)?;
+        s.serialize_field("role", &self.1)?;
+        s.end()
+    }
+}
+
 impl fmt::Display for PoolId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "P{:04}", self.0)
+        match self.1 {
+            PoolRole::Unspecified => write!(f, "P{:04}", self.0),
+            role => write!(f, "P{:04}/{}", self.0, role),
+        }
     }
 }
 
@@ -67,6 +119,10 @@ pub trait Instrumentation {
     /// A new connection was created
     fn connection_created(&self, connected_after: Duration, total_time: Duration, pool: PoolId);
 
+    /// An idle connection was closed and will be replaced because it
+    /// was idle for longer than the configured `idle_timeout`
+    fn idle_connection_evicted(&self, idle_for: Duration, pool: PoolId);
+
     /// The number of idle connections increased by 1
     fn idle_inc(&self, pool: PoolId);
 
@@ -79,14 +135,15 @@ pub trait Instrumentation {
     /// The number of in flight connections decreased by 1
     fn in_flight_dec(&self, pool: PoolId);
 
-    /// A reservation has been enqueued
-    fn reservation_added(&self, pool: PoolId);
+    /// A reservation has been enqueued in the lane of the given priority
+    fn reservation_added(&self, priority: Priority, pool: PoolId);
 
     /// A reservation was fulfilled. A connection was available in time.
     fn reservation_fulfilled(
         &self,
         reservation_time: Duration,
         checkout_request_time: Duration,
+        priority: Priority,
         pool: PoolId,
     );
 
@@ -95,12 +152,13 @@ pub trait Instrumentation {
         &self,
         reservation_time: Duration,
         checkout_request_time: Duration,
+        priority: Priority,
         pool: PoolId,
     );
 
     /// The reservation queue has a limit and that limit was just reached.
-    /// This means a checkout has instantaneously failed.
-    fn reservation_limit_reached(&self, pool: PoolId);
+    /// This means a checkout with the given priority has instantaneously failed.
+    fn reservation_limit_reached(&self, priority: Priority, pool: PoolId);
 
     /// The connection factory was asked to create a new connection but it failed to do so.
     fn connection_factory_failed(&self, pool: PoolId);
@@ -111,6 +169,32 @@ pub trait Instrumentation {
     fn checkout_message_received(&self, latency: Duration, pool: PoolId);
 
     fn relevant_message_processed(&self, processing_time: Duration, pool: PoolId);
+
+    /// The circuit breaker of a pool changed its state
+    fn circuit_state_changed(&self, from: CircuitState, to: CircuitState, pool: PoolId);
+
+    /// The number of connections checked out in the
+    /// given partition increased by 1
+    fn partition_checked_out_inc(&self, partition: &str);
+
+    /// The number of connections checked out in the
+    /// given partition decreased by 1
+    fn partition_checked_out_dec(&self, partition: &str);
+
+    /// A checkout in the given partition failed since the
+    /// partition already had its maximum number of connections checked out
+    fn partition_limit_reached(&self, partition: &str);
+
+    /// A connection was held for longer than the configured
+    /// `leak_detection_threshold`. `checked_out_at` is where it was checked out.
+    ///
+    /// This is reported only once for each checkout.
+    fn connection_leak_suspected(
+        &self,
+        held_for: Duration,
+        checked_out_at: &'static Location<'static>,
+        pool: PoolId,
+    );
 }
 
 #[derive(Clone)]
@@ -197,6 +281,14 @@ impl Instrumentation for InstrumentationFlavour {
             }
         }
     }
+    fn idle_connection_evicted(&self, idle_for: Duration, pool: PoolId) {
+        match self {
+            InstrumentationFlavour::NoInstrumentation => {}
+            InstrumentationFlavour::Custom(i) => i.idle_connection_evicted(idle_for, pool),
+            #[cfg(feature = "metrix")]
+            InstrumentationFlavour::Metrix(i) => i.idle_connection_evicted(idle_for, pool),
+        }
+    }
     fn idle_inc(&self, pool: PoolId) {
         match self {
             InstrumentationFlavour::NoInstrumentation => {}
@@ -229,28 +321,29 @@ impl Instrumentation for InstrumentationFlavour {
             InstrumentationFlavour::Metrix(i) => i.in_flight_dec(pool),
         }
     }
-    fn reservation_added(&self, pool: PoolId) {
+    fn reservation_added(&self, priority: Priority, pool: PoolId) {
         match self {
             InstrumentationFlavour::NoInstrumentation => {}
-            InstrumentationFlavour::Custom(i) => i.reservation_added(pool),
+            InstrumentationFlavour::Custom(i) => i.reservation_added(priority, pool),
             #[cfg(feature = "metrix")]
-            InstrumentationFlavour::Metrix(i) => i.reservation_added(pool),
+            InstrumentationFlavour::Metrix(i) => i.reservation_added(priority, pool),
         }
     }
     fn reservation_fulfilled(
         &self,
         reservation_time: Duration,
         checkout_request_time: Duration,
+        priority: Priority,
         pool: PoolId,
     ) {
         match self {
             InstrumentationFlavour::NoInstrumentation => {}
             InstrumentationFlavour::Custom(i) => {
-                i.reservation_fulfilled(reservation_time, checkout_request_time, pool)
+                i.reservation_fulfilled(reservation_time, checkout_request_time, priority, pool)
             }
             #[cfg(feature = "metrix")]
             InstrumentationFlavour::Metrix(i) => {
-                i.reservation_fulfilled(reservation_time, checkout_request_time, pool)
+                i.reservation_fulfilled(reservation_time, checkout_request_time, priority, pool)
             }
         }
     }
@@ -258,25 +351,26 @@ impl Instrumentation for InstrumentationFlavour {
         &self,
         reservation_time: Duration,
         checkout_request_time: Duration,
+        priority: Priority,
         pool: PoolId,
     ) {
         match self {
             InstrumentationFlavour::NoInstrumentation => {}
             InstrumentationFlavour::Custom(i) => {
-                i.reservation_not_fulfilled(reservation_time, checkout_request_time, pool)
+                i.reservation_not_fulfilled(reservation_time, checkout_request_time, priority, pool)
             }
             #[cfg(feature = "metrix")]
             InstrumentationFlavour::Metrix(i) => {
-                i.reservation_not_fulfilled(reservation_time, checkout_request_time, pool)
+                i.reservation_not_fulfilled(reservation_time, checkout_request_time, priority, pool)
             }
         }
     }
-    fn reservation_limit_reached(&self, pool: PoolId) {
+    fn reservation_limit_reached(&self, priority: Priority, pool: PoolId) {
         match self {
             InstrumentationFlavour::NoInstrumentation => {}
-            InstrumentationFlavour::Custom(i) => i.reservation_limit_reached(pool),
+            InstrumentationFlavour::Custom(i) => i.reservation_limit_reached(priority, pool),
             #[cfg(feature = "metrix")]
-            InstrumentationFlavour::Metrix(i) => i.reservation_limit_reached(pool),
+            InstrumentationFlavour::Metrix(i) => i.reservation_limit_reached(priority, pool),
         }
     }
     fn connection_factory_failed(&self, pool: PoolId) {
@@ -318,4 +412,67 @@ impl Instrumentation for InstrumentationFlavour {
             }
         }
     }
+
+    fn circuit_state_changed(&self, from: CircuitState, to: CircuitState, pool: PoolId) {
+        match self {
+            InstrumentationFlavour::NoInstrumentation => {}
+            InstrumentationFlavour::Custom(i) => i.circuit_state_changed(from, to, pool),
+            #[cfg(feature = "metrix")]
+            InstrumentationFlavour::Metrix(i) => i.circuit_state_changed(from, to, pool),
+        }
+    }
+    fn partition_checked_out_inc(&self, partition: &str) {
+        match self {
+            InstrumentationFlavour::NoInstrumentation => {}
+            InstrumentationFlavour::Custom(i) => i.partition_checked_out_inc(partition),
+            #[cfg(feature = "metrix")]
+            InstrumentationFlavour::Metrix(i) => i.partition_checked_out_inc(partition),
+        }
+    }
+    fn partition_checked_out_dec(&self, partition: &str) {
+        match self {
+            InstrumentationFlavour::NoInstrumentation => {}
+            InstrumentationFlavour::Custom(i) => i.partition_checked_out_dec(partition),
+            #[cfg(feature = "metrix")]
+            InstrumentationFlavour::Metrix(i) => i.partition_checked_out_dec(partition),
+        }
+    }
+    fn partition_limit_reached(&self, partition: &str) {
+        match self {
+            InstrumentationFlavour::NoInstrumentation => {}
+            InstrumentationFlavour::Custom(i) => i.partition_limit_reached(partition),
+            #[cfg(feature = "metrix")]
+            InstrumentationFlavour::Metrix(i) => i.partition_limit_reached(partition),
+        }
+    }
+    fn connection_leak_suspected(
+        &self,
+        held_for: Duration,
+        checked_out_at: &'static Location<'static>,
+        pool: PoolId,
+    ) {
+        match self {
+            InstrumentationFlavour::NoInstrumentation => {}
+            InstrumentationFlavour::Custom(i) => {
+                i.connection_leak_suspected(held_for, checked_out_at, pool)
+            }
+            #[cfg(feature = "metrix")]
+            InstrumentationFlavour::Metrix(i) => {
+                i.connection_leak_suspected(held_for, checked_out_at, pool)
+            }
+        }
+    }
+}
+
+#[test]
+fn the_role_is_displayed_if_specified() {
+    assert_eq!(PoolId::new(3).to_string(), "P0003");
+    assert_eq!(
+        PoolId::with_role(0, PoolRole::Primary).to_string(),
+        "P0000/primary"
+    );
+
+    let mut id = PoolId::with_role(1, PoolRole::Replica);
+    id.inc();
+    assert_eq!(id.to_string(), "P0002/replica");
 }
@@ -1,4 +1,5 @@
 use std::io::{self, Write};
+use std::panic::Location;
 use std::sync::{
     atomic::{AtomicUsize, Ordering},
     Arc,
@@ -8,7 +9,7 @@ use std::time::Duration;
 use log::info;
 
 use crate::instrumentation::PoolId;
-use crate::PoolState;
+use crate::{CircuitState, PoolState, Priority};
 
 use super::Instrumentation;
 
@@ -19,6 +20,8 @@ use super::Instrumentation;
 /// * number of in flight connections
 /// * number of reservations
 /// * number of tasks waiting before the lock
+/// * number of open circuits
+/// * number of suspected connection leaks
 ///
 /// This is mostly useful for testing purposes only.
 ///
@@ -38,6 +41,8 @@ pub struct StateCounters {
     in_flight: Arc<AtomicUsize>,
     reservations: Arc<AtomicUsize>,
     pools: Arc<AtomicUsize>,
+    open_circuits: Arc<AtomicUsize>,
+    leaks_suspected: Arc<AtomicUsize>,
     log: bool,
     print: bool,
 }
@@ -49,7 +54,7 @@ impl StateCounters {
     ///
     /// * If an env var "PRINT" is set, counter changes will be printed to stdout.
     /// * If an env var "LOG" or "RUST_LOG" is set counter changes will be logged
-    /// at info level
+    ///   at info level
     pub fn new() -> Self {
         let mut me = Self::default();
         if std::env::var("RUST_LOG").is_ok() {
@@ -69,16 +74,18 @@ impl StateCounters {
 
     /// Log counter changes at info level
     pub fn with_logging() -> Self {
-        let mut me = Self::default();
-        me.log = true;
-        me
+        Self {
+            log: true,
+            ..Self::default()
+        }
     }
 
     /// Print counter changes to stdout
     pub fn with_printing() -> Self {
-        let mut me = Self::default();
-        me.print = true;
-        me
+        Self {
+            print: true,
+            ..Self::default()
+        }
     }
 
     pub fn state(&self) -> PoolState {
@@ -88,6 +95,7 @@ impl StateCounters {
             connections: self.connections.load(Ordering::SeqCst),
             idle: self.idle.load(Ordering::SeqCst),
             pools: self.pools.load(Ordering::SeqCst),
+            open_circuits: self.open_circuits.load(Ordering::SeqCst),
         }
     }
 
@@ -106,6 +114,12 @@ impl StateCounters {
     pub fn pools(&self) -> usize {
         self.pools.load(Ordering::SeqCst)
     }
+    pub fn open_circuits(&self) -> usize {
+        self.open_circuits.load(Ordering::SeqCst)
+    }
+    pub fn leaks_suspected(&self) -> usize {
+        self.leaks_suspected.load(Ordering::SeqCst)
+    }
 
     /// Create the `Instrumentation` to be put into the pool to instrument.
     pub fn instrumentation(&self) -> StateCountersInstrumentation {
@@ -115,6 +129,8 @@ impl StateCounters {
             in_flight: Arc::clone(&self.in_flight),
             reservations: Arc::clone(&self.reservations),
             pools: Arc::clone(&self.pools),
+            open_circuits: Arc::clone(&self.open_circuits),
+            leaks_suspected: Arc::clone(&self.leaks_suspected),
             log: self.log,
             print: self.print,
         }
@@ -127,6 +143,8 @@ pub struct StateCountersInstrumentation {
     in_flight: Arc<AtomicUsize>,
     reservations: Arc<AtomicUsize>,
     pools: Arc<AtomicUsize>,
+    open_circuits: Arc<AtomicUsize>,
+    leaks_suspected: Arc<AtomicUsize>,
     log: bool,
     print: bool,
 }
@@ -147,6 +165,9 @@ impl StateCountersInstrumentation {
     fn pools(&self) -> usize {
         self.pools.load(Ordering::SeqCst)
     }
+    fn open_circuits(&self) -> usize {
+        self.open_circuits.load(Ordering::SeqCst)
+    }
 
     fn output_required(&self) -> bool {
         self.log || self.print
@@ -228,6 +249,12 @@ impl Instrumentation for StateCountersInstrumentation {
     fn connection_created(&self, _connected_after: Duration, _total_time: Duration, _pool: PoolId) {
     }
 
+    fn idle_connection_evicted(&self, _idle_for: Duration, pool: PoolId) {
+        if self.output_required() {
+            self.output(&format!("[{}] idle connection evicted", pool));
+        }
+    }
+
     fn idle_inc(&self, pool: PoolId) {
         self.idle.fetch_add(1, Ordering::SeqCst);
         if self.output_required() {
@@ -256,12 +283,13 @@ impl Instrumentation for StateCountersInstrumentation {
         }
     }
 
-    fn reservation_added(&self, pool: PoolId) {
+    fn reservation_added(&self, priority: Priority, pool: PoolId) {
         self.reservations.fetch_add(1, Ordering::SeqCst);
         if self.output_required() {
             self.output(&format!(
-                "[{}] reservation added (+1): {}",
+                "[{}] {} priority reservation added (+1): {}",
                 pool,
+                priority,
                 self.reservations()
             ));
         }
@@ -271,13 +299,15 @@ impl Instrumentation for StateCountersInstrumentation {
         &self,
         _reservation_time: Duration,
         _checkout_request_time: Duration,
+        priority: Priority,
         pool: PoolId,
     ) {
         self.reservations.fetch_sub(1, Ordering::SeqCst);
         if self.output_required() {
             self.output(&format!(
-                "[{}] reservation fulfilled (-1): {}",
+                "[{}] {} priority reservation fulfilled (-1): {}",
                 pool,
+                priority,
                 self.reservations()
             ));
         }
@@ -287,21 +317,26 @@ impl Instrumentation for StateCountersInstrumentation {
         &self,
         _reservation_time: Duration,
         _checkout_request_time: Duration,
+        priority: Priority,
         pool: PoolId,
     ) {
         self.reservations.fetch_sub(1, Ordering::SeqCst);
         if self.output_required() {
             self.output(&format!(
-                "[{}] reservations not fulfilled (-1): {}",
+                "[{}] {} priority reservations not fulfilled (-1): {}",
                 pool,
+                priority,
                 self.reservations()
             ));
         }
     }
 
-    fn reservation_limit_reached(&self, pool: PoolId) {
+    fn reservation_limit_reached(&self, priority: Priority, pool: PoolId) {
         if self.output_required() {
-            self.output(&format!("[{}] reservation limit reached", pool));
+            self.output(&format!(
+                "[{}] reservation limit reached - {} priority",
+                pool, priority
+            ));
         }
     }
 
@@ -316,4 +351,54 @@ impl Instrumentation for StateCountersInstrumentation {
     fn checkout_message_received(&self, _latency: Duration, _pool: PoolId) {}
 
     fn relevant_message_processed(&self, _processing_time: Duration, _pool: PoolId) {}
+
+    fn circuit_state_changed(&self, from: CircuitState, to: CircuitState, pool: PoolId) {
+        if to == CircuitState::Open {
+            self.open_circuits.fetch_add(1, Ordering::SeqCst);
+        } else if from == CircuitState::Open {
+            self.open_circuits.fetch_sub(1, Ordering::SeqCst);
+        }
+        if self.output_required() {
+            self.output(&format!(
+                "[{}] circuit {} -> {}: {} open",
+                pool,
+                from,
+                to,
+                self.open_circuits()
+            ));
+        }
+    }
+
+    fn partition_checked_out_inc(&self, partition: &str) {
+        if self.output_required() {
+            self.output(&format!("[{}] partition checked out +1", partition));
+        }
+    }
+
+    fn partition_checked_out_dec(&self, partition: &str) {
+        if self.output_required() {
+            self.output(&format!("[{}] partition checked out -1", partition));
+        }
+    }
+
+    fn partition_limit_reached(&self, partition: &str) {
+        if self.output_required() {
+            self.output(&format!("[{}] partition limit reached", partition));
+        }
+    }
+
+    fn connection_leak_suspected(
+        &self,
+        held_for: Duration,
+        checked_out_at: &'static Location<'static>,
+        pool: PoolId,
+    ) {
+        self.leaks_suspected.fetch_add(1, Ordering::SeqCst);
+        if self.output_required() {
+            self.output(&format!(
+                "[{}] connection leak suspected - held for {:?}, checked out at {}",
+                pool, held_for, checked_out_at
+            ));
+        }
+    }
 }
@@ -4,12 +4,25 @@
 //!
 //! Reool is a REdis connection pOOL based on [redis-rs](https://crates.io/crates/redis).
 //!
-//! Reool is aimed at either connecting to a single primary node or
-//! connecting to a replica set using the replicas as read only nodes.
+//! Reool is aimed at either connecting to a single primary node,
+//! connecting to a replica set using the replicas as read only nodes
+//! or connecting to a primary and its replicas and routing reads and
+//! writes with `check_out_replica` and `check_out_primary`.
+//! The nodes can also be discovered via Redis Sentinel in which case
+//! the pool follows the master on a failover.
+//! Reool can also connect to the masters of a Redis Cluster and
+//! check out connections to the master serving a given key.
 //!
-//! Currently Reool is a fixed size connection pool.
+//! Reool keeps `desired_pool_size` connections per node and can optionally
+//! grow up to `max_pool_size` connections under load.
 //! Reool provides an interface for instrumentation.
 //!
+//! Nodes given with a `rediss://` URL are connected via TLS
+//! if the feature `tls` is enabled.
+//!
+//! Reool is based on `std::future` and runs on a `tokio` 1.x runtime.
+//! With the feature `compat` checkouts can also be used as futures 0.1 futures.
+//!
 //! You should also consider multiplexing instead of a pool based upon your needs.
 //!
 //! The `PooledConnection` of `reool` implements the `ConnectionLike`
@@ -24,16 +37,23 @@
 //!
 //! See LICENSE-APACHE and LICENSE-MIT for details.
 //! License: Apache-2.0/MIT
+use std::backtrace::Backtrace;
+use std::future::Future;
+use std::panic::Location;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 
-use futures::{
-    future::{self, Future},
-    try_ready, Async, Poll,
-};
+use futures::future::{self, BoxFuture, FutureExt, TryFutureExt};
 
 use crate::config::Builder;
-use crate::config::DefaultPoolCheckoutMode;
-use crate::pools::pool_internal::CheckoutManaged;
+use crate::config::{DefaultPoolCheckoutMode, Reconfiguration};
+use crate::error::{InitializationError, InitializationResult};
+use crate::health::HealthReport;
+use crate::instrumentation::PoolId;
+use crate::partitions::{PartitionPermit, PartitionState, Partitions};
+use crate::pools::pool_internal::{CheckoutManaged, PoolInternal};
 
 pub mod config;
 pub mod instrumentation;
@@ -43,21 +63,29 @@ pub use redis::{
     ToRedisArgs, Value,
 };
 
-pub use crate::error::{CheckoutError, CheckoutErrorKind};
+pub use crate::error::{CheckoutError, CheckoutErrorKind, ConnectionFailure, Error};
 pub use commands::Commands;
 pub use pool_connection::{ConnectionFlavour, PoolConnection};
+#[cfg(feature = "tls")]
+pub use redis_rs::TlsConnection;
 
 pub mod connection_factory;
 pub(crate) mod executor_flavour;
+pub mod health;
 pub(crate) mod helpers;
+pub mod load_balancing;
+pub mod partitions;
 
 mod activation_order;
 mod backoff_strategy;
 mod commands;
+#[cfg(feature = "compat")]
+mod compat;
 mod error;
 mod pool_connection;
 mod pools;
 mod redis_rs;
+mod validation_policy;
 
 /// Something that can be put into the connection pool
 pub trait Poolable: Send + Sized + 'static {
@@ -74,23 +102,69 @@ pub trait Poolable: Send + Sized + 'static {
 /// * The queue size was limited and the limit was reached
 /// * There are simply no connections available
 /// * There is no connected node
-pub struct Checkout<T: Poolable = ConnectionFlavour>(CheckoutManaged<T>);
+pub struct Checkout<T: Poolable = ConnectionFlavour> {
+    managed: CheckoutManaged<T>,
+    on_redirection: Option<pool_connection::OnRedirection>,
+    partition: Option<PartitionPermit>,
+    /// Where the checkout was made. Reported if the
+    /// connection is suspected to be leaked.
+    location: &'static Location<'static>,
+    /// Only captured if enabled via `Config::capture_leak_backtraces`
+    backtrace: Option<Arc<Backtrace>>,
+}
 
 impl<T: Poolable> Checkout<T> {
+    #[track_caller]
+    fn new(
+        managed: CheckoutManaged<T>,
+        on_redirection: Option<pool_connection::OnRedirection>,
+    ) -> Self {
+        Checkout {
+            managed,
+            on_redirection,
+            partition: None,
+            location: Location::caller(),
+            backtrace: None,
+        }
+    }
+
+    #[track_caller]
     pub fn error<E: Into<CheckoutError>>(err: E) -> Self {
-        Checkout(CheckoutManaged::error(err))
+        Checkout::new(CheckoutManaged::error(err), None)
+    }
+
+    /// Captures a backtrace which is reported if the
+    /// connection is suspected to be leaked
+    fn with_backtrace(mut self) -> Self {
+        self.backtrace = Some(Arc::new(Backtrace::force_capture()));
+        self
+    }
+
+    /// The permit is held by the connection until it is dropped
+    fn in_partition(mut self, permit: PartitionPermit) -> Self {
+        self.partition = Some(permit);
+        self
     }
 }
 
 impl<T: Poolable> Future for Checkout<T> {
-    type Item = PoolConnection<T>;
-    type Error = CheckoutError;
+    type Output = Result<PoolConnection<T>, CheckoutError>;
 
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let managed = try_ready!(self.0.poll());
-        Ok(Async::Ready(PoolConnection {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let managed = match Pin::new(&mut self.managed).poll(cx) {
+            Poll::Ready(Ok(managed)) => managed,
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        };
+        let leak_watch = managed.watch_for_leak(self.location, self.backtrace.take());
+        Poll::Ready(Ok(PoolConnection {
             managed,
             connection_state_ok: true,
+            connection_failed: false,
+            on_redirection: self.on_redirection.clone(),
+            partition: self.partition.take().map(PartitionPermit::in_flight),
+            checked_out_at: self.location,
+            _leak_watch: leak_watch,
         }))
     }
 }
@@ -101,7 +175,7 @@ impl<T: Poolable> Future for Checkout<T> {
 ///
 /// * `Duration`: `Until` with a deadline from now until the durations elapsed.
 /// * `Instant`: `Until` the given instant.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum CheckoutMode {
     /// Expect a connection to be returned immediately.
     /// If there is none available return an error immediately.
@@ -113,6 +187,7 @@ pub enum CheckoutMode {
     /// Wait until there is a connection
     Wait,
     /// Use the default configured for the pool
+    #[default]
     PoolDefault,
     /// Checkout before the given `Instant` is elapsed. If the given timeout is
     /// elapsed, no attempt to checkout a connection will be made.
@@ -127,6 +202,11 @@ impl CheckoutMode {
             _ => false,
         }
     }
+
+    /// Make the checkout with the given `Priority`
+    pub fn with_priority(self, priority: Priority) -> CheckoutOptions {
+        CheckoutOptions::new(self, priority)
+    }
 }
 
 /// Simply a shortcut for `CheckoutMode::Immediately`
@@ -159,12 +239,6 @@ impl From<PoolDefault> for CheckoutMode {
     }
 }
 
-impl Default for CheckoutMode {
-    fn default() -> Self {
-        CheckoutMode::PoolDefault
-    }
-}
-
 impl From<Duration> for CheckoutMode {
     fn from(d: Duration) -> Self {
         let timeout = Instant::now() + d;
@@ -178,10 +252,102 @@ impl From<Instant> for CheckoutMode {
     }
 }
 
+/// The priority of a checkout
+///
+/// Checkouts waiting for a connection are served in the order of
+/// their priority. Checkouts with the same priority are served in
+/// the order they were made.
+///
+/// Connections can be reserved for `High` via
+/// `Config::reserved_for_high_priority`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Priority {
+    /// E.g. background jobs which can wait
+    Low,
+    /// The priority of all checkouts made without a priority
+    #[default]
+    Normal,
+    /// E.g. latency critical request handlers
+    High,
+}
+
+impl Priority {
+    /// All priorities from the highest to the lowest
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Priority::Low => write!(f, "low"),
+            Priority::Normal => write!(f, "normal"),
+            Priority::High => write!(f, "high"),
+        }
+    }
+}
+
+/// A `CheckoutMode` along with the `Priority` of the checkout
+///
+/// Everything that can be turned into a `CheckoutMode` can be
+/// turned into `CheckoutOptions` with `Priority::Normal`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CheckoutOptions {
+    pub mode: CheckoutMode,
+    pub priority: Priority,
+}
+
+impl CheckoutOptions {
+    pub fn new<M: Into<CheckoutMode>>(mode: M, priority: Priority) -> Self {
+        Self {
+            mode: mode.into(),
+            priority,
+        }
+    }
+}
+
+impl From<CheckoutMode> for CheckoutOptions {
+    fn from(mode: CheckoutMode) -> Self {
+        CheckoutOptions::new(mode, Priority::default())
+    }
+}
+
+impl From<Immediately> for CheckoutOptions {
+    fn from(mode: Immediately) -> Self {
+        CheckoutOptions::new(mode, Priority::default())
+    }
+}
+
+impl From<Wait> for CheckoutOptions {
+    fn from(mode: Wait) -> Self {
+        CheckoutOptions::new(mode, Priority::default())
+    }
+}
+
+impl From<PoolDefault> for CheckoutOptions {
+    fn from(mode: PoolDefault) -> Self {
+        CheckoutOptions::new(mode, Priority::default())
+    }
+}
+
+impl From<Duration> for CheckoutOptions {
+    fn from(mode: Duration) -> Self {
+        CheckoutOptions::new(mode, Priority::default())
+    }
+}
+
+impl From<Instant> for CheckoutOptions {
+    fn from(mode: Instant) -> Self {
+        CheckoutOptions::new(mode, Priority::default())
+    }
+}
+
 enum RedisPoolFlavour<T: Poolable> {
     Empty,
     Single(pools::SinglePool<T>),
     PerNode(pools::PoolPerNode<T>),
+    Sentinel(pools::SentinelPool<T>),
+    Cluster(pools::ClusterPool<T>),
+    PrimaryReplica(pools::PrimaryReplicaPool<T>),
 }
 
 impl<T: Poolable> Clone for RedisPoolFlavour<T> {
@@ -191,6 +357,9 @@ impl<T: Poolable> Clone for RedisPoolFlavour<T> {
             Empty => Empty,
             Single(pool) => Single(pool.clone()),
             PerNode(pool) => PerNode(pool.clone()),
+            Sentinel(pool) => Sentinel(pool.clone()),
+            Cluster(pool) => Cluster(pool.clone()),
+            PrimaryReplica(pool) => PrimaryReplica(pool.clone()),
         }
     }
 }
@@ -215,8 +384,10 @@ impl<T: Poolable> Clone for RedisPoolFlavour<T> {
 /// sub pools.
 pub struct RedisPool<T: Poolable = ConnectionFlavour> {
     flavour: RedisPoolFlavour<T>,
-    default_checkout_mode: DefaultPoolCheckoutMode,
+    default_checkout_mode: Arc<RwLock<DefaultPoolCheckoutMode>>,
     retry_on_checkout_limit: bool,
+    capture_leak_backtraces: bool,
+    partitions: Partitions,
 }
 
 impl RedisPool {
@@ -229,49 +400,232 @@ impl<T: Poolable> RedisPool<T> {
     pub fn no_pool() -> Self {
         RedisPool {
             flavour: RedisPoolFlavour::Empty,
-            default_checkout_mode: DefaultPoolCheckoutMode::Wait,
+            default_checkout_mode: Arc::new(RwLock::new(DefaultPoolCheckoutMode::Wait)),
             retry_on_checkout_limit: false,
+            capture_leak_backtraces: false,
+            partitions: Partitions::none(),
         }
     }
 
     /// Checkout a new connection and if the request has to be enqueued
     /// use a timeout as defined by the pool as a default.
+    #[track_caller]
     pub fn check_out_default(&self) -> Checkout<T> {
         self.check_out(CheckoutMode::PoolDefault)
     }
 
     /// Checkout a new connection and choose whether to wait for a connection or not
     /// as defined by the `CheckoutMode`.
-    pub fn check_out<M: Into<CheckoutMode>>(&self, mode: M) -> Checkout<T> {
+    ///
+    /// Pass `CheckoutOptions` to make a checkout with a `Priority` other
+    /// than `Priority::Normal`.
+    #[track_caller]
+    pub fn check_out<M: Into<CheckoutOptions>>(&self, mode: M) -> Checkout<T> {
         let constraint = pools::CheckoutConstraint::from_checkout_mode_and_pool_default(
             mode,
-            self.default_checkout_mode,
+            self.default_checkout_mode(),
         );
         match self.flavour {
-            RedisPoolFlavour::Single(ref pool) => {
-                Checkout(pools::check_out_maybe_retry_on_queue_limit_reached(
+            RedisPoolFlavour::Single(ref pool) => self.checkout(
+                pools::check_out_maybe_retry_on_queue_limit_reached(
                     pool,
                     constraint,
                     self.retry_on_checkout_limit,
-                ))
-            }
-            RedisPoolFlavour::PerNode(ref pool) => {
-                Checkout(pools::check_out_maybe_retry_on_queue_limit_reached(
+                ),
+                None,
+            ),
+            RedisPoolFlavour::PerNode(ref pool) => self.checkout(
+                pools::check_out_maybe_retry_on_queue_limit_reached(
+                    pool,
+                    constraint,
+                    self.retry_on_checkout_limit,
+                ),
+                None,
+            ),
+            RedisPoolFlavour::Sentinel(ref pool) => self.checkout(
+                pools::check_out_maybe_retry_on_queue_limit_reached(
                     pool,
                     constraint,
                     self.retry_on_checkout_limit,
-                ))
+                ),
+                None,
+            ),
+            RedisPoolFlavour::Cluster(ref pool) => self.checkout(
+                pools::check_out_maybe_retry_on_queue_limit_reached(
+                    pool,
+                    constraint,
+                    self.retry_on_checkout_limit,
+                ),
+                Some(pool.on_redirection()),
+            ),
+            RedisPoolFlavour::PrimaryReplica(ref pool) => self.checkout(
+                pools::check_out_maybe_retry_on_queue_limit_reached(
+                    pool,
+                    constraint,
+                    self.retry_on_checkout_limit,
+                ),
+                None,
+            ),
+            RedisPoolFlavour::Empty => {
+                self.checkout(CheckoutManaged::error(CheckoutErrorKind::NoPool), None)
             }
-            RedisPoolFlavour::Empty => Checkout(CheckoutManaged::new(future::err(
-                CheckoutError::new(CheckoutErrorKind::NoPool),
-            ))),
         }
     }
 
+    /// Checkout a connection to the primary.
+    ///
+    /// This only makes a difference when the pool was configured
+    /// with replicas. Otherwise this is the same as `check_out`.
+    #[track_caller]
+    pub fn check_out_primary<M: Into<CheckoutOptions>>(&self, mode: M) -> Checkout<T> {
+        self.check_out(mode)
+    }
+
+    /// Checkout a connection to one of the replicas. Only perform
+    /// read operations on the connection.
+    ///
+    /// The replicas are selected according to the `LoadBalancingStrategy`. If configured
+    /// the checkout is made on the primary if no replica is available.
+    ///
+    /// This only makes a difference when the pool was configured
+    /// with replicas or connects to the replicas discovered via Redis
+    /// Sentinel. Otherwise this is the same as `check_out`.
+    #[track_caller]
+    pub fn check_out_replica<M: Into<CheckoutOptions>>(&self, mode: M) -> Checkout<T> {
+        match self.flavour {
+            RedisPoolFlavour::PrimaryReplica(ref pool) => {
+                let constraint = pools::CheckoutConstraint::from_checkout_mode_and_pool_default(
+                    mode,
+                    self.default_checkout_mode(),
+                );
+                self.checkout(
+                    pools::check_out_maybe_retry_on_queue_limit_reached(
+                        &pool.replicas(),
+                        constraint,
+                        self.retry_on_checkout_limit,
+                    ),
+                    None,
+                )
+            }
+            RedisPoolFlavour::Sentinel(ref pool) => {
+                let constraint = pools::CheckoutConstraint::from_checkout_mode_and_pool_default(
+                    mode,
+                    self.default_checkout_mode(),
+                );
+                self.checkout(
+                    pools::check_out_maybe_retry_on_queue_limit_reached(
+                        &pool.replicas(),
+                        constraint,
+                        self.retry_on_checkout_limit,
+                    ),
+                    None,
+                )
+            }
+            _ => self.check_out(mode),
+        }
+    }
+
+    /// Checkout a connection to the node serving `key`.
+    ///
+    /// This only makes a difference when connected to a Redis Cluster.
+    /// Otherwise this is the same as `check_out`.
+    ///
+    /// If a command on the connection is redirected with `MOVED` or `ASK`
+    /// the pool refreshes its view of the cluster in the background.
+    /// The command is not retried. Retry with a new checkout for the key.
+    #[track_caller]
+    pub fn check_out_for_key<K: AsRef<[u8]>, M: Into<CheckoutOptions>>(
+        &self,
+        key: K,
+        mode: M,
+    ) -> Checkout<T> {
+        match self.flavour {
+            RedisPoolFlavour::Cluster(ref pool) => {
+                let constraint = pools::CheckoutConstraint::from_checkout_mode_and_pool_default(
+                    mode,
+                    self.default_checkout_mode(),
+                );
+                let managed = match pool.pool_for_key(key.as_ref()) {
+                    Some(node_pool) => pools::check_out_maybe_retry_on_queue_limit_reached(
+                        &node_pool,
+                        constraint,
+                        self.retry_on_checkout_limit,
+                    ),
+                    None => CheckoutManaged::error(CheckoutErrorKind::NoPool),
+                };
+                self.checkout(managed, Some(pool.on_redirection()))
+            }
+            _ => self.check_out(mode),
+        }
+    }
+
+    /// Creates a `Checkout` and captures a backtrace if enabled
+    #[track_caller]
+    fn checkout(
+        &self,
+        managed: CheckoutManaged<T>,
+        on_redirection: Option<pool_connection::OnRedirection>,
+    ) -> Checkout<T> {
+        let checkout = Checkout::new(managed, on_redirection);
+        if self.capture_leak_backtraces {
+            checkout.with_backtrace()
+        } else {
+            checkout
+        }
+    }
+
+    /// Checkout a connection in the given partition.
+    ///
+    /// Fails immediately with `CheckoutErrorKind::PartitionLimitReached` if the
+    /// partition already has as many connections checked out as it may have.
+    /// The connection counts towards the partition until it is dropped.
+    ///
+    /// Partitions are declared with `Builder::partition`.
+    #[track_caller]
+    pub fn check_out_in<M: Into<CheckoutOptions>>(&self, partition: &str, mode: M) -> Checkout<T> {
+        match self.partitions.acquire(partition) {
+            Ok(permit) => self.check_out(mode).in_partition(permit),
+            Err(kind) => Checkout::error(kind),
+        }
+    }
+
+    /// Check out a connection and run `f` with it,
+    /// e.g. `pool.with_connection(CheckoutMode::Wait, |conn| conn.get("key"))`.
+    ///
+    /// The connection is returned to the pool once the future returned
+    /// by `f` completes. A failed checkout and an error returned by Redis
+    /// can be told apart by the variant of the returned `Error`.
+    #[track_caller]
+    pub fn with_connection<M, F, R>(
+        &self,
+        mode: M,
+        f: F,
+    ) -> impl Future<Output = Result<R, Error>> + Send
+    where
+        M: Into<CheckoutOptions>,
+        F: for<'a> FnOnce(&'a mut PoolConnection<T>) -> RedisFuture<'a, R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let checkout = self.check_out(mode);
+        async move {
+            let mut conn = checkout.await?;
+            let result = f(&mut conn).await?;
+            Ok(result)
+        }
+    }
+
+    /// The state of each partition
+    pub fn partition_states(&self) -> Vec<PartitionState> {
+        self.partitions.states()
+    }
+
     pub fn connected_to(&self) -> Vec<String> {
         match self.flavour {
             RedisPoolFlavour::Single(ref pool) => vec![pool.connected_to().to_string()],
-            RedisPoolFlavour::PerNode(ref pool) => pool.connected_to().to_vec(),
+            RedisPoolFlavour::PerNode(ref pool) => pool.connected_to(),
+            RedisPoolFlavour::Sentinel(ref pool) => pool.connected_to(),
+            RedisPoolFlavour::Cluster(ref pool) => pool.connected_to(),
+            RedisPoolFlavour::PrimaryReplica(ref pool) => pool.connected_to(),
             RedisPoolFlavour::Empty => vec![],
         }
     }
@@ -280,10 +634,48 @@ impl<T: Poolable> RedisPool<T> {
         match self.flavour {
             RedisPoolFlavour::Single(ref pool) => pool.state(),
             RedisPoolFlavour::PerNode(ref pool) => pool.state(),
+            RedisPoolFlavour::Sentinel(ref pool) => pool.state(),
+            RedisPoolFlavour::Cluster(ref pool) => pool.state(),
+            RedisPoolFlavour::PrimaryReplica(ref pool) => pool.state(),
             RedisPoolFlavour::Empty => PoolState::default(),
         }
     }
 
+    /// The state of each sub pool.
+    ///
+    /// Unlike `state` the states of the sub pools are not added up.
+    pub fn node_states(&self) -> Vec<NodeState> {
+        self.sub_pools()
+            .iter()
+            .map(|pool| pool.node_state())
+            .collect()
+    }
+
+    /// The health of the node of each sub pool.
+    ///
+    /// Nodes are only pinged if a `health_check_interval` is configured.
+    /// Otherwise all nodes are healthy.
+    pub fn health(&self) -> HealthReport {
+        HealthReport {
+            nodes: self.sub_pools().iter().map(|pool| pool.health()).collect(),
+        }
+    }
+
+    /// The state of the circuit breaker of each sub pool.
+    ///
+    /// Checkouts are not made on sub pools with an open circuit
+    /// as long as there are other sub pools.
+    pub fn circuit_states(&self) -> Vec<NodeCircuitState> {
+        self.sub_pools()
+            .iter()
+            .map(|pool| NodeCircuitState {
+                pool: pool.id(),
+                connected_to: pool.connected_to().to_string(),
+                state: pool.circuit_state(),
+            })
+            .collect()
+    }
+
     /// Ping all the nodes which this pool is connected to.
     ///
     /// `timeout` is the maximum time allowed for a ping.
@@ -294,23 +686,144 @@ impl<T: Poolable> RedisPool<T> {
     pub fn ping<TO: Into<Timeout>>(
         &self,
         timeout: TO,
-    ) -> impl Future<Item = Vec<Ping>, Error = ()> + Send {
+    ) -> impl Future<Output = Result<Vec<Ping>, ()>> + Send {
         let deadline = timeout.into().0;
+        let f: BoxFuture<'static, _> = match self.flavour {
+            RedisPoolFlavour::Single(ref pool) => pool.ping(deadline).map_ok(|p| vec![p]).boxed(),
+            RedisPoolFlavour::PerNode(ref pool) => pool.ping(deadline).boxed(),
+            RedisPoolFlavour::Sentinel(ref pool) => pool.ping(deadline).boxed(),
+            RedisPoolFlavour::Cluster(ref pool) => pool.ping(deadline).boxed(),
+            RedisPoolFlavour::PrimaryReplica(ref pool) => pool.ping(deadline).boxed(),
+            RedisPoolFlavour::Empty => future::ok(vec![]).boxed(),
+        };
+        f
+    }
+
+    /// Shut the pool down gracefully.
+    ///
+    /// From now on checkouts fail with `CheckoutErrorKind::ShuttingDown`.
+    /// Checkouts waiting for a connection fail immediately and no
+    /// new connections will be created. Idle connections are closed right
+    /// away and connections in flight are closed once they are returned.
+    ///
+    /// The returned future completes once all connections have been closed
+    /// or `deadline` has elapsed. Connections returned after the deadline
+    /// are simply dropped.
+    ///
+    /// The shutdown affects all clones of this pool.
+    pub fn shutdown<TO: Into<Timeout>>(&self, deadline: TO) -> impl Future<Output = ()> + Send {
+        let deadline = deadline.into().0;
+        let f: BoxFuture<'static, _> = match self.flavour {
+            RedisPoolFlavour::Single(ref pool) => pool.shutdown(deadline).boxed(),
+            RedisPoolFlavour::PerNode(ref pool) => pool.shutdown(deadline).boxed(),
+            RedisPoolFlavour::Sentinel(ref pool) => pool.shutdown(deadline).boxed(),
+            RedisPoolFlavour::Cluster(ref pool) => pool.shutdown(deadline).boxed(),
+            RedisPoolFlavour::PrimaryReplica(ref pool) => pool.shutdown(deadline).boxed(),
+            RedisPoolFlavour::Empty => future::ready(()).boxed(),
+        };
+        f
+    }
+
+    /// Reconfigure the pool while it is running.
+    ///
+    /// Only the values set on the `Reconfiguration` are changed. A larger
+    /// `desired_pool_size` creates the missing connections right away while
+    /// surplus connections are closed once they have been idle
+    /// for the configured `surplus_idle_timeout`.
+    ///
+    /// The reconfiguration affects all clones of this pool.
+    pub fn reconfigure(&self, reconfiguration: Reconfiguration) {
+        if let Some(mode) = reconfiguration.default_checkout_mode {
+            *self.default_checkout_mode.write().unwrap() = mode;
+        }
+
         match self.flavour {
-            RedisPoolFlavour::Single(ref pool) => Box::new(pool.ping(deadline).map(|p| vec![p]))
-                as Box<dyn Future<Item = _, Error = ()> + Send>,
-            RedisPoolFlavour::PerNode(ref pool) => Box::new(pool.ping(deadline)),
-            RedisPoolFlavour::Empty => Box::new(future::ok(vec![])),
+            RedisPoolFlavour::Single(ref pool) => pool.reconfigure(reconfiguration),
+            RedisPoolFlavour::PerNode(ref pool) => pool.reconfigure(reconfiguration),
+            RedisPoolFlavour::Sentinel(ref pool) => pool.reconfigure(reconfiguration),
+            RedisPoolFlavour::Cluster(ref pool) => pool.reconfigure(reconfiguration),
+            RedisPoolFlavour::PrimaryReplica(ref pool) => pool.reconfigure(reconfiguration),
+            RedisPoolFlavour::Empty => {}
         }
     }
+
+    /// Add a node to the pool. Checkouts are made on the new node right away.
+    ///
+    /// This is only supported by pools for multiple nodes. If the pool
+    /// was configured with replicas the node is added as a replica.
+    /// Pools for a single node, Redis Sentinel or a Redis Cluster
+    /// manage their nodes themselves.
+    ///
+    /// Nodes added affect all clones of this pool.
+    pub fn add_node<C: Into<String>>(&self, connect_to: C) -> InitializationResult<()> {
+        let connect_to = connect_to.into();
+        match self.flavour {
+            RedisPoolFlavour::PerNode(ref pool) => pool.add_node(connect_to),
+            RedisPoolFlavour::PrimaryReplica(ref pool) => pool.add_replica(connect_to),
+            _ => Err(nodes_can_not_change()),
+        }
+    }
+
+    /// Remove a node from the pool. No more checkouts are made on the
+    /// node once this method returns.
+    ///
+    /// The returned future completes once the connections to the node
+    /// have been closed or `deadline` has elapsed. The remaining connections
+    /// are closed forcefully if the future is dropped before.
+    ///
+    /// The same restrictions as for `add_node` apply.
+    pub fn remove_node<TO: Into<Timeout>>(
+        &self,
+        connect_to: &str,
+        deadline: TO,
+    ) -> impl Future<Output = InitializationResult<()>> + Send {
+        let deadline = deadline.into().0;
+        let removed: InitializationResult<BoxFuture<'static, ()>> = match self.flavour {
+            RedisPoolFlavour::PerNode(ref pool) => {
+                pool.remove_node(connect_to, deadline).map(FutureExt::boxed)
+            }
+            RedisPoolFlavour::PrimaryReplica(ref pool) => pool
+                .remove_replica(connect_to, deadline)
+                .map(FutureExt::boxed),
+            _ => Err(nodes_can_not_change()),
+        };
+
+        async move {
+            removed?.await;
+            Ok(())
+        }
+    }
+
+    fn default_checkout_mode(&self) -> DefaultPoolCheckoutMode {
+        *self.default_checkout_mode.read().unwrap()
+    }
+
+    fn sub_pools(&self) -> Vec<Arc<PoolInternal<T>>> {
+        match self.flavour {
+            RedisPoolFlavour::Single(ref pool) => pool.sub_pools(),
+            RedisPoolFlavour::PerNode(ref pool) => pool.sub_pools(),
+            RedisPoolFlavour::Sentinel(ref pool) => pool.sub_pools(),
+            RedisPoolFlavour::Cluster(ref pool) => pool.sub_pools(),
+            RedisPoolFlavour::PrimaryReplica(ref pool) => pool.sub_pools(),
+            RedisPoolFlavour::Empty => Vec::new(),
+        }
+    }
+}
+
+fn nodes_can_not_change() -> InitializationError {
+    InitializationError::message_only(
+        "nodes can only be added to or removed from a pool for multiple nodes",
+    )
 }
 
 impl<T: Poolable> Clone for RedisPool<T> {
     fn clone(&self) -> Self {
         RedisPool {
             flavour: self.flavour.clone(),
-            default_checkout_mode: self.default_checkout_mode,
+            default_checkout_mode: Arc::clone(&self.default_checkout_mode),
             retry_on_checkout_limit: self.retry_on_checkout_limit,
+            capture_leak_backtraces: self.capture_leak_backtraces,
+            partitions: self.partitions.clone(),
         }
     }
 }
@@ -382,10 +895,7 @@ pub struct Ping {
 
 impl Ping {
     pub fn is_ok(&self) -> bool {
-        match self.state {
-            PingState::Ok => true,
-            _ => false,
-        }
+        matches!(self.state, PingState::Ok)
     }
 
     pub fn is_failed(&self) -> bool {
@@ -394,7 +904,8 @@ impl Ping {
 }
 
 /// The current state of the pool
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct PoolState {
     /// The number of in flight connections
     pub in_flight: usize,
@@ -406,6 +917,8 @@ pub struct PoolState {
     pub idle: usize,
     /// The number of sub pools
     pub pools: usize,
+    /// The number of sub pools whose circuit breaker is open
+    pub open_circuits: usize,
 }
 
 impl std::ops::Add for PoolState {
@@ -418,18 +931,259 @@ impl std::ops::Add for PoolState {
             connections: self.connections + other.connections,
             idle: self.idle + other.idle,
             pools: self.pools + other.pools,
+            open_circuits: self.open_circuits + other.open_circuits,
         }
     }
 }
 
-impl Default for PoolState {
-    fn default() -> Self {
-        Self {
-            in_flight: 0,
-            reservations: 0,
-            connections: 0,
-            idle: 0,
-            pools: 0,
+/// The state of the circuit breaker of a sub pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum CircuitState {
+    /// Checkouts are made on the sub pool
+    Closed,
+    /// The node failed too often. Checkouts are made on other
+    /// sub pools if possible.
+    Open,
+    /// The circuit was open for long enough. The next success closes
+    /// the circuit and the next failure opens it again.
+    HalfOpen,
+}
+
+impl std::fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CircuitState::Closed => write!(f, "closed"),
+            CircuitState::Open => write!(f, "open"),
+            CircuitState::HalfOpen => write!(f, "half open"),
         }
     }
 }
+
+/// The state of the circuit breaker of a sub pool and the node
+/// the sub pool is connected to
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct NodeCircuitState {
+    pub pool: PoolId,
+    pub connected_to: String,
+    pub state: CircuitState,
+}
+
+/// The state of a single sub pool
+///
+/// Can be serialized with the feature `serde`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct NodeState {
+    pub pool: PoolId,
+    /// The node the sub pool is connected to
+    pub connected_to: String,
+    pub state: PoolState,
+    pub circuit_state: CircuitState,
+    /// The number of failed attempts to create a connection
+    /// since the sub pool was created
+    pub connection_factory_failures: usize,
+    /// The time since the last connection was created. `None`
+    /// if no connection was created yet.
+    pub since_last_connection: Option<Duration>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn with_connection_fails_with_the_kind_of_the_failed_checkout() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let pool: RedisPool = RedisPool::no_pool();
+
+        let result: Result<Option<String>, Error> = runtime
+            .block_on(pool.with_connection(CheckoutMode::Immediately, |conn| conn.get("key")));
+
+        assert_eq!(
+            result.err().and_then(|err| err.checkout_error_kind()),
+            Some(CheckoutErrorKind::NoPool)
+        );
+    }
+
+    /// Replies to `GET` depending on the key: a value for "string",
+    /// a `WRONGTYPE` error for "list" and an IO error for any other key
+    struct ScriptedConnection;
+
+    impl Poolable for ScriptedConnection {
+        fn connected_to(&self) -> &str {
+            "scripted"
+        }
+    }
+
+    impl redis::aio::ConnectionLike for ScriptedConnection {
+        fn req_packed_command<'a>(
+            &'a mut self,
+            cmd: &'a redis::Cmd,
+        ) -> redis::RedisFuture<'a, redis::Value> {
+            let packed = cmd.get_packed_command();
+            let reply = if packed.ends_with(b"$6\r\nstring\r\n") {
+                Ok(redis::Value::Data(b"value".to_vec()))
+            } else if packed.ends_with(b"$4\r\nlist\r\n") {
+                redis::parse_redis_value(
+                    b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+                )
+            } else {
+                Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset).into())
+            };
+            future::ready(reply).boxed()
+        }
+
+        fn req_packed_commands<'a>(
+            &'a mut self,
+            _cmd: &'a redis::Pipeline,
+            _offset: usize,
+            _count: usize,
+        ) -> redis::RedisFuture<'a, Vec<redis::Value>> {
+            let err = (redis::ErrorKind::ClientError, "pipelines are not scripted");
+            future::err(err.into()).boxed()
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+    }
+
+    fn scripted_pool(
+        circuit_breaker_threshold: usize,
+    ) -> (RedisPool<ScriptedConnection>, Arc<AtomicUsize>) {
+        use crate::connection_factory::{ConnectionFactory, NewConnection, NewConnectionError};
+
+        /// Creates a single connection. The node is gone afterwards.
+        struct ScriptedFactory(Arc<AtomicUsize>);
+
+        impl ConnectionFactory for ScriptedFactory {
+            type Connection = ScriptedConnection;
+            fn create_connection(&self) -> NewConnection<Self::Connection> {
+                if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+                    NewConnection::new(future::ok(ScriptedConnection))
+                } else {
+                    let err = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+                    NewConnection::new(future::err(NewConnectionError::new(err)))
+                }
+            }
+            fn connecting_to(&self) -> &str {
+                "scripted"
+            }
+        }
+
+        let created = Arc::new(AtomicUsize::new(0));
+        let factory_created = Arc::clone(&created);
+        let pool = RedisPool::builder()
+            .connect_to_node("scripted")
+            .desired_pool_size(1)
+            .circuit_breaker_threshold(circuit_breaker_threshold)
+            .finish(move |_| Ok(ScriptedFactory(Arc::clone(&factory_created))))
+            .unwrap();
+        (pool, created)
+    }
+
+    #[test]
+    fn with_connection_returns_the_value_and_the_connection_to_the_pool() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let fut = async {
+            let (pool, created) = scripted_pool(0);
+
+            for _ in 0..3 {
+                let value: Option<String> = pool
+                    .with_connection(CheckoutMode::Wait, |conn| conn.get("string"))
+                    .await
+                    .unwrap();
+                assert_eq!(value.as_deref(), Some("value"));
+            }
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            assert_eq!(pool.state().in_flight, 0, "in flight");
+            assert_eq!(pool.state().idle, 1, "idle");
+            assert_eq!(created.load(Ordering::SeqCst), 1, "connections created");
+        };
+
+        runtime
+            .block_on(async { tokio::time::timeout(Duration::from_secs(5), fut).await })
+            .unwrap();
+    }
+
+    #[test]
+    fn with_connection_returns_the_errors_of_redis_and_of_the_connection() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let fut = async {
+            let (pool, created) = scripted_pool(0);
+
+            let err = pool
+                .with_connection(CheckoutMode::Wait, |conn| conn.get::<_, String>("list"))
+                .await
+                .err()
+                .unwrap();
+            assert!(matches!(err, Error::Redis(_)), "{:?}", err);
+            assert_eq!(err.code(), Some("WRONGTYPE"));
+
+            // The connection survives an error replied by Redis
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            assert_eq!(pool.state().idle, 1, "idle");
+            assert_eq!(created.load(Ordering::SeqCst), 1, "connections created");
+
+            let err = pool
+                .with_connection(CheckoutMode::Wait, |conn| conn.get::<_, String>("gone"))
+                .await
+                .err()
+                .unwrap();
+            assert!(matches!(err, Error::Io(_)), "{:?}", err);
+            assert_eq!(err.checkout_error_kind(), None);
+        };
+
+        runtime
+            .block_on(async { tokio::time::timeout(Duration::from_secs(5), fut).await })
+            .unwrap();
+    }
+
+    #[test]
+    fn only_connection_failures_open_the_circuit() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let fut = async {
+            let (pool, _) = scripted_pool(1);
+
+            for _ in 0..3 {
+                let result = pool
+                    .with_connection(CheckoutMode::Wait, |conn| conn.get::<_, String>("list"))
+                    .await;
+                assert!(result.is_err());
+            }
+            assert_eq!(pool.state().open_circuits, 0, "open circuits");
+
+            let result = pool
+                .with_connection(CheckoutMode::Wait, |conn| conn.get::<_, String>("gone"))
+                .await;
+            assert!(result.is_err());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            assert_eq!(pool.state().open_circuits, 1, "open circuits");
+        };
+
+        runtime
+            .block_on(async { tokio::time::timeout(Duration::from_secs(5), fut).await })
+            .unwrap();
+    }
+
+    #[test]
+    fn backtraces_are_only_captured_if_enabled() {
+        let pool: RedisPool = RedisPool::no_pool();
+        assert!(pool.check_out(Wait).backtrace.is_none());
+
+        let pool = RedisPool {
+            capture_leak_backtraces: true,
+            ..pool
+        };
+        assert!(pool.check_out(Wait).backtrace.is_some());
+    }
+}
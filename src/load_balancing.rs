@@ -0,0 +1,459 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::prelude::*;
+
+use crate::PoolState;
+
+/// Selects the sub pool on which a checkout is made when a pool
+/// has multiple sub pools.
+///
+/// Implement this to supply your own strategy via
+/// `LoadBalancingStrategy::Custom`.
+pub trait LoadBalancer: Send + Sync + 'static {
+    /// Returns the index of the node on which the checkout is made first.
+    ///
+    /// If the selected node has no idle connection the other nodes are tried
+    /// in the order following the selected node. `nodes` is never empty.
+    fn select(&self, nodes: &[Node]) -> usize;
+}
+
+/// A sub pool as seen by a `LoadBalancer`
+pub struct Node {
+    connected_to: Arc<str>,
+    state: Arc<dyn Fn() -> PoolState + Send + Sync>,
+    latency: Arc<dyn Fn() -> Option<Duration> + Send + Sync>,
+}
+
+impl Node {
+    pub(crate) fn new<S, L>(connected_to: Arc<str>, state: S, latency: L) -> Self
+    where
+        S: Fn() -> PoolState + Send + Sync + 'static,
+        L: Fn() -> Option<Duration> + Send + Sync + 'static,
+    {
+        Self {
+            connected_to,
+            state: Arc::new(state),
+            latency: Arc::new(latency),
+        }
+    }
+
+    /// The node the sub pool is connected to
+    pub fn connected_to(&self) -> &str {
+        &self.connected_to
+    }
+
+    /// The current state of the sub pool
+    pub fn state(&self) -> PoolState {
+        (self.state)()
+    }
+
+    /// The exponentially weighted moving average of the checkout,
+    /// connect and ping times of the sub pool.
+    ///
+    /// `None` if nothing was measured yet.
+    pub fn latency(&self) -> Option<Duration> {
+        (self.latency)()
+    }
+}
+
+impl Clone for Node {
+    fn clone(&self) -> Self {
+        Self {
+            connected_to: Arc::clone(&self.connected_to),
+            state: Arc::clone(&self.state),
+            latency: Arc::clone(&self.latency),
+        }
+    }
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Node")
+            .field("connected_to", &self.connected_to)
+            .finish()
+    }
+}
+
+/// The `outlier_factor` used for `LoadBalancingStrategy::LatencyAware`
+/// when parsed from a string
+pub const DEFAULT_OUTLIER_FACTOR: f64 = 3.0;
+
+/// Defines how checkouts are distributed among the sub pools of
+/// a pool connected to multiple nodes.
+#[derive(Clone, Default)]
+pub enum LoadBalancingStrategy {
+    /// The sub pools are selected one after the other
+    #[default]
+    RoundRobin,
+    /// A sub pool is selected randomly
+    Random,
+    /// The sub pool with the fewest connections in flight is selected
+    LeastInFlight,
+    /// Two sub pools are selected randomly and the one with
+    /// fewer connections in flight is taken
+    PowerOfTwoChoices,
+    /// A sub pool is selected randomly with a probability proportional
+    /// to the weight of its node.
+    ///
+    /// Nodes without a weight have a weight of 1.
+    Weighted(Vec<(String, u32)>),
+    /// The sub pool with the lowest latency is selected.
+    ///
+    /// The latency is the exponentially weighted moving average of
+    /// checkout, connect and ping times multiplied by the number of
+    /// connections in flight plus one so that a fast node does not
+    /// get all the load.
+    ///
+    /// Nodes with a latency of more than `outlier_factor` times the
+    /// median latency of all nodes are ejected. An ejected node
+    /// is still selected now and then so that it can come back once
+    /// its latency recovered.
+    LatencyAware { outlier_factor: f64 },
+    /// A user supplied strategy
+    Custom(Arc<dyn LoadBalancer>),
+}
+
+impl LoadBalancingStrategy {
+    /// Creates the `LoadBalancer` for a single pool
+    pub(crate) fn create(&self) -> Arc<dyn LoadBalancer> {
+        match self {
+            LoadBalancingStrategy::RoundRobin => Arc::new(RoundRobin::default()),
+            LoadBalancingStrategy::Random => Arc::new(Random),
+            LoadBalancingStrategy::LeastInFlight => Arc::new(LeastInFlight::default()),
+            LoadBalancingStrategy::PowerOfTwoChoices => Arc::new(PowerOfTwoChoices),
+            LoadBalancingStrategy::Weighted(weights) => Arc::new(Weighted(weights.clone())),
+            LoadBalancingStrategy::LatencyAware { outlier_factor } => {
+                Arc::new(LatencyAware::new(*outlier_factor))
+            }
+            LoadBalancingStrategy::Custom(balancer) => Arc::clone(balancer),
+        }
+    }
+}
+
+impl fmt::Debug for LoadBalancingStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadBalancingStrategy::Weighted(weights) => {
+                f.debug_tuple("Weighted").field(weights).finish()
+            }
+            LoadBalancingStrategy::LatencyAware { outlier_factor } => f
+                .debug_struct("LatencyAware")
+                .field("outlier_factor", outlier_factor)
+                .finish(),
+            other => write!(f, "{}", other),
+        }
+    }
+}
+
+impl fmt::Display for LoadBalancingStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadBalancingStrategy::RoundRobin => write!(f, "RoundRobin"),
+            LoadBalancingStrategy::Random => write!(f, "Random"),
+            LoadBalancingStrategy::LeastInFlight => write!(f, "LeastInFlight"),
+            LoadBalancingStrategy::PowerOfTwoChoices => write!(f, "PowerOfTwoChoices"),
+            LoadBalancingStrategy::Weighted(_) => write!(f, "Weighted"),
+            LoadBalancingStrategy::LatencyAware { .. } => write!(f, "LatencyAware"),
+            LoadBalancingStrategy::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+impl std::str::FromStr for LoadBalancingStrategy {
+    type Err = ParseLoadBalancingStrategyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &*s.to_lowercase() {
+            "round_robin" => Ok(LoadBalancingStrategy::RoundRobin),
+            "random" => Ok(LoadBalancingStrategy::Random),
+            "least_in_flight" => Ok(LoadBalancingStrategy::LeastInFlight),
+            "power_of_two_choices" => Ok(LoadBalancingStrategy::PowerOfTwoChoices),
+            "latency_aware" => Ok(LoadBalancingStrategy::LatencyAware {
+                outlier_factor: DEFAULT_OUTLIER_FACTOR,
+            }),
+            invalid => Err(ParseLoadBalancingStrategyError(format!(
+                "'{}' is not a valid LoadBalancingStrategy. Only 'round_robin', 'random', \
+                 'least_in_flight', 'power_of_two_choices' and 'latency_aware' are allowed.",
+                invalid
+            ))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseLoadBalancingStrategyError(String);
+
+impl fmt::Display for ParseLoadBalancingStrategyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Could not parse LoadBalancingStrategy. {}", self.0)
+    }
+}
+
+impl StdError for ParseLoadBalancingStrategyError {
+    fn description(&self) -> &str {
+        "parse load balancing strategy failed"
+    }
+
+    fn cause(&self) -> Option<&dyn StdError> {
+        None
+    }
+}
+
+#[derive(Default)]
+struct RoundRobin(AtomicUsize);
+
+impl LoadBalancer for RoundRobin {
+    fn select(&self, nodes: &[Node]) -> usize {
+        self.0.fetch_add(1, Ordering::SeqCst) % nodes.len()
+    }
+}
+
+struct Random;
+
+impl LoadBalancer for Random {
+    fn select(&self, nodes: &[Node]) -> usize {
+        rand::thread_rng().gen_range(0, nodes.len())
+    }
+}
+
+/// Ties are broken in a round robin fashion so that the same
+/// node is not always selected if all nodes are equally busy
+#[derive(Default)]
+struct LeastInFlight(AtomicUsize);
+
+impl LoadBalancer for LeastInFlight {
+    fn select(&self, nodes: &[Node]) -> usize {
+        let offset = self.0.fetch_add(1, Ordering::SeqCst);
+        (0..nodes.len())
+            .map(|n| (offset + n) % nodes.len())
+            .min_by_key(|&idx| nodes[idx].state().in_flight)
+            .unwrap_or(0)
+    }
+}
+
+struct PowerOfTwoChoices;
+
+impl LoadBalancer for PowerOfTwoChoices {
+    fn select(&self, nodes: &[Node]) -> usize {
+        if nodes.len() == 1 {
+            return 0;
+        }
+
+        let mut rng = rand::thread_rng();
+        let a = rng.gen_range(0, nodes.len());
+        // Never pick the same node twice
+        let b = (a + rng.gen_range(1, nodes.len())) % nodes.len();
+
+        if nodes[b].state().in_flight < nodes[a].state().in_flight {
+            b
+        } else {
+            a
+        }
+    }
+}
+
+struct Weighted(Vec<(String, u32)>);
+
+impl Weighted {
+    fn weight_of(&self, node: &Node) -> u32 {
+        self.0
+            .iter()
+            .find(|(connected_to, _)| connected_to == node.connected_to())
+            .map(|(_, weight)| *weight)
+            .unwrap_or(1)
+    }
+}
+
+impl LoadBalancer for Weighted {
+    fn select(&self, nodes: &[Node]) -> usize {
+        let total: u64 = nodes.iter().map(|n| u64::from(self.weight_of(n))).sum();
+        if total == 0 {
+            return 0;
+        }
+
+        let mut pick = rand::thread_rng().gen_range(0, total);
+        for (idx, node) in nodes.iter().enumerate() {
+            let weight = u64::from(self.weight_of(node));
+            if pick < weight {
+                return idx;
+            }
+            pick -= weight;
+        }
+
+        0
+    }
+}
+
+/// Every `PROBE_INTERVAL`th selection goes to an ejected node
+const PROBE_INTERVAL: usize = 100;
+
+struct LatencyAware {
+    outlier_factor: f64,
+    selections: AtomicUsize,
+}
+
+impl LatencyAware {
+    fn new(outlier_factor: f64) -> Self {
+        Self {
+            outlier_factor,
+            selections: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl LoadBalancer for LatencyAware {
+    fn select(&self, nodes: &[Node]) -> usize {
+        let selection = self.selections.fetch_add(1, Ordering::SeqCst);
+        let latencies: Vec<Option<Duration>> = nodes.iter().map(Node::latency).collect();
+
+        let mut measured: Vec<Duration> = latencies.iter().flatten().copied().collect();
+        measured.sort();
+        let threshold = measured
+            .get(measured.len() / 2)
+            .map(|median| median.as_secs_f64() * self.outlier_factor);
+
+        let is_outlier = |idx: usize| match (latencies[idx], threshold) {
+            (Some(latency), Some(threshold)) => latency.as_secs_f64() > threshold,
+            _ => false,
+        };
+
+        let (outliers, mut candidates): (Vec<usize>, Vec<usize>) =
+            (0..nodes.len()).partition(|&idx| is_outlier(idx));
+
+        if !outliers.is_empty() && selection % PROBE_INTERVAL == PROBE_INTERVAL - 1 {
+            return outliers[(selection / PROBE_INTERVAL) % outliers.len()];
+        }
+
+        // Only possible with an `outlier_factor` below 1
+        if candidates.is_empty() {
+            candidates = outliers;
+        }
+
+        // Nodes without a measurement score 0 so that they get measured.
+        // Ties are broken in a round robin fashion.
+        let score = |idx: usize| {
+            let latency = latencies[idx].map(|l| l.as_secs_f64()).unwrap_or(0.0);
+            latency * (nodes[idx].state().in_flight + 1) as f64
+        };
+        let offset = selection % candidates.len();
+        candidates
+            .iter()
+            .cycle()
+            .skip(offset)
+            .take(candidates.len())
+            .copied()
+            .min_by(|&a, &b| {
+                score(a)
+                    .partial_cmp(&score(b))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+fn nodes_with_in_flight(in_flight: &[usize]) -> Vec<Node> {
+    in_flight
+        .iter()
+        .enumerate()
+        .map(|(idx, &in_flight)| {
+            Node::new(
+                format!("node{}", idx).into(),
+                move || PoolState {
+                    in_flight,
+                    ..PoolState::default()
+                },
+                || None,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn nodes_with_latencies(latencies_ms: &[u64]) -> Vec<Node> {
+    latencies_ms
+        .iter()
+        .enumerate()
+        .map(|(idx, &latency)| {
+            Node::new(
+                format!("node{}", idx).into(),
+                PoolState::default,
+                move || Some(Duration::from_millis(latency)),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn round_robin_selects_the_nodes_one_after_the_other() {
+    let nodes = nodes_with_in_flight(&[0, 0, 0]);
+    let balancer = LoadBalancingStrategy::RoundRobin.create();
+
+    let selected: Vec<_> = (0..4).map(|_| balancer.select(&nodes)).collect();
+
+    assert_eq!(selected, vec![0, 1, 2, 0]);
+}
+
+#[test]
+fn least_in_flight_selects_the_node_with_the_fewest_connections_in_flight() {
+    let nodes = nodes_with_in_flight(&[3, 1, 2]);
+    let balancer = LoadBalancingStrategy::LeastInFlight.create();
+
+    for _ in 0..3 {
+        assert_eq!(balancer.select(&nodes), 1);
+    }
+}
+
+#[test]
+fn power_of_two_choices_never_selects_the_busiest_node() {
+    let nodes = nodes_with_in_flight(&[0, 5, 10]);
+    let balancer = LoadBalancingStrategy::PowerOfTwoChoices.create();
+
+    for _ in 0..100 {
+        assert_ne!(balancer.select(&nodes), 2);
+    }
+}
+
+#[test]
+fn weighted_never_selects_nodes_with_a_weight_of_zero() {
+    let nodes = nodes_with_in_flight(&[0, 0, 0]);
+    let balancer =
+        LoadBalancingStrategy::Weighted(vec![("node0".to_string(), 0), ("node2".to_string(), 3)])
+            .create();
+
+    for _ in 0..100 {
+        assert_ne!(balancer.select(&nodes), 0);
+    }
+}
+
+#[test]
+fn latency_aware_prefers_the_fastest_node() {
+    let nodes = nodes_with_latencies(&[5, 1, 3]);
+    let balancer = LoadBalancingStrategy::LatencyAware {
+        outlier_factor: DEFAULT_OUTLIER_FACTOR,
+    }
+    .create();
+
+    for _ in 0..10 {
+        assert_eq!(balancer.select(&nodes), 1);
+    }
+}
+
+#[test]
+fn latency_aware_only_probes_outliers() {
+    let nodes = nodes_with_latencies(&[10, 1, 1, 1]);
+    let balancer = LoadBalancingStrategy::LatencyAware {
+        outlier_factor: DEFAULT_OUTLIER_FACTOR,
+    }
+    .create();
+
+    let selected_outlier = (0..PROBE_INTERVAL)
+        .filter(|_| balancer.select(&nodes) == 0)
+        .count();
+
+    assert_eq!(selected_outlier, 1);
+}
@@ -0,0 +1,226 @@
+//! Bulkheads within a pool
+//!
+//! A partition limits the number of connections a part of an application
+//! may have checked out at the same time so that it can not exhaust the
+//! pool for all others. Partitions are declared with `Builder::partition`
+//! and used with `RedisPool::check_out_in`.
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use log::trace;
+
+use crate::error::CheckoutErrorKind;
+use crate::instrumentation::{Instrumentation, InstrumentationFlavour};
+
+/// The state of a partition
+///
+/// This is not a `PoolState` because a partition has no connections
+/// of its own. The connections, the idle connections and the sub pools
+/// are those of the pool which all partitions share. `in_flight` and
+/// `reservations` have the same meaning as in `PoolState` but only
+/// count the checkouts made in the partition.
+///
+/// Can be serialized with the feature `serde`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PartitionState {
+    pub name: String,
+    /// The maximum number of connections checked out at the same time
+    pub max_checked_out: usize,
+    /// The number of connections currently checked out
+    /// including the checkouts still waiting for a connection.
+    /// This is `in_flight` plus `reservations`.
+    pub checked_out: usize,
+    /// The number of connections checked out in the partition
+    /// which have not been returned yet
+    pub in_flight: usize,
+    /// The number of checkouts in the partition still waiting for a connection
+    pub reservations: usize,
+    /// The number of checkouts which failed because
+    /// `max_checked_out` was reached
+    pub limit_reached: usize,
+}
+
+/// All partitions of a pool
+#[derive(Clone)]
+pub(crate) struct Partitions {
+    partitions: Arc<BTreeMap<String, Arc<Partition>>>,
+}
+
+impl Partitions {
+    pub fn new(
+        max_checked_out: &BTreeMap<String, usize>,
+        instrumentation: InstrumentationFlavour,
+    ) -> Self {
+        let partitions = max_checked_out
+            .iter()
+            .map(|(name, &max_checked_out)| {
+                let partition = Partition {
+                    name: name.clone(),
+                    max_checked_out,
+                    checked_out: AtomicUsize::new(0),
+                    in_flight: AtomicUsize::new(0),
+                    limit_reached: AtomicUsize::new(0),
+                    instrumentation: instrumentation.clone(),
+                };
+                (name.clone(), Arc::new(partition))
+            })
+            .collect();
+
+        Self {
+            partitions: Arc::new(partitions),
+        }
+    }
+
+    pub fn none() -> Self {
+        Self::new(&BTreeMap::new(), InstrumentationFlavour::NoInstrumentation)
+    }
+
+    /// Take one from the budget of the given partition.
+    ///
+    /// The budget is given back when the `PartitionPermit` is dropped.
+    pub fn acquire(&self, name: &str) -> Result<PartitionPermit, CheckoutErrorKind> {
+        let partition = self
+            .partitions
+            .get(name)
+            .ok_or(CheckoutErrorKind::UnknownPartition)?;
+
+        let acquired = partition
+            .checked_out
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |checked_out| {
+                if checked_out < partition.max_checked_out {
+                    Some(checked_out + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok();
+
+        if !acquired {
+            trace!("partition '{}' - limit reached", name);
+            partition.limit_reached.fetch_add(1, Ordering::SeqCst);
+            partition.instrumentation.partition_limit_reached(name);
+            return Err(CheckoutErrorKind::PartitionLimitReached);
+        }
+
+        partition.instrumentation.partition_checked_out_inc(name);
+
+        Ok(PartitionPermit {
+            partition: Arc::clone(partition),
+            in_flight: false,
+        })
+    }
+
+    pub fn states(&self) -> Vec<PartitionState> {
+        self.partitions
+            .values()
+            .map(|partition| {
+                let checked_out = partition.checked_out.load(Ordering::SeqCst);
+                let in_flight = partition.in_flight.load(Ordering::SeqCst);
+                PartitionState {
+                    name: partition.name.clone(),
+                    max_checked_out: partition.max_checked_out,
+                    checked_out,
+                    in_flight,
+                    reservations: checked_out.saturating_sub(in_flight),
+                    limit_reached: partition.limit_reached.load(Ordering::SeqCst),
+                }
+            })
+            .collect()
+    }
+}
+
+struct Partition {
+    name: String,
+    max_checked_out: usize,
+    checked_out: AtomicUsize,
+    /// The part of `checked_out` which already got a connection
+    in_flight: AtomicUsize,
+    limit_reached: AtomicUsize,
+    instrumentation: InstrumentationFlavour,
+}
+
+/// A part of the budget of a partition. Held by a checkout
+/// and the connection it returns.
+pub(crate) struct PartitionPermit {
+    partition: Arc<Partition>,
+    /// `true` once the checkout got its connection
+    in_flight: bool,
+}
+
+impl PartitionPermit {
+    pub fn name(&self) -> &str {
+        &self.partition.name
+    }
+
+    /// The checkout holding the permit got its connection
+    pub fn in_flight(mut self) -> Self {
+        if !self.in_flight {
+            self.in_flight = true;
+            self.partition.in_flight.fetch_add(1, Ordering::SeqCst);
+        }
+        self
+    }
+}
+
+impl Drop for PartitionPermit {
+    fn drop(&mut self) {
+        if self.in_flight {
+            self.partition.in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
+        self.partition.checked_out.fetch_sub(1, Ordering::SeqCst);
+        self.partition
+            .instrumentation
+            .partition_checked_out_dec(&self.partition.name);
+    }
+}
+
+#[test]
+fn the_budget_of_a_partition_is_given_back_once_the_permit_is_dropped() {
+    let mut max_checked_out = BTreeMap::new();
+    max_checked_out.insert("jobs".to_string(), 1);
+    let partitions = Partitions::new(&max_checked_out, InstrumentationFlavour::NoInstrumentation);
+
+    let permit = partitions.acquire("jobs").unwrap();
+    assert_eq!(
+        partitions.acquire("jobs").err(),
+        Some(CheckoutErrorKind::PartitionLimitReached)
+    );
+    assert_eq!(
+        partitions.acquire("other").err(),
+        Some(CheckoutErrorKind::UnknownPartition)
+    );
+
+    drop(permit);
+    assert!(partitions.acquire("jobs").is_ok());
+
+    let states = partitions.states();
+    assert_eq!(states[0].checked_out, 0);
+    assert_eq!(states[0].limit_reached, 1);
+}
+
+#[test]
+fn checkouts_in_a_partition_are_reservations_until_they_get_a_connection() {
+    let mut max_checked_out = BTreeMap::new();
+    max_checked_out.insert("jobs".to_string(), 2);
+    let partitions = Partitions::new(&max_checked_out, InstrumentationFlavour::NoInstrumentation);
+
+    let waiting = partitions.acquire("jobs").unwrap();
+    let connected = partitions.acquire("jobs").unwrap().in_flight();
+
+    let state = &partitions.states()[0];
+    assert_eq!(state.checked_out, 2, "checked out");
+    assert_eq!(state.in_flight, 1, "in flight");
+    assert_eq!(state.reservations, 1, "reservations");
+
+    drop(connected);
+    let waiting = waiting.in_flight();
+    let state = &partitions.states()[0];
+    assert_eq!(state.checked_out, 1, "checked out");
+    assert_eq!(state.in_flight, 1, "in flight");
+    assert_eq!(state.reservations, 0, "reservations");
+
+    drop(waiting);
+    assert_eq!(partitions.states()[0].in_flight, 0, "in flight");
+}
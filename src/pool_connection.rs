@@ -1,11 +1,18 @@
+use std::panic::Location;
 use std::sync::Arc;
 
-use futures::future::{self, Future};
-use redis::{aio::ConnectionLike, ErrorKind, RedisFuture, Value};
+use futures::future::{self, FutureExt};
+use redis::{aio::ConnectionLike, Cmd, ErrorKind, Pipeline, RedisError, RedisFuture, Value};
 
-use crate::pools::pool_internal::Managed;
+use crate::partitions::PartitionPermit;
+use crate::pools::pool_internal::{Managed, Watched};
+use crate::redis_rs::cluster::is_redirection;
 use crate::Poolable;
 
+/// Called when a Redis Cluster redirected a command
+/// with `MOVED` or `ASK`
+pub(crate) type OnRedirection = Arc<dyn Fn() + Send + Sync + 'static>;
+
 /// A connection that has been taken from the pool.
 ///
 /// The connection returns when dropped unless there was an error.
@@ -19,54 +26,83 @@ pub struct PoolConnection<T: Poolable = ConnectionFlavour> {
     /// is not in a valid state anymore. For stateless connections this
     /// field is useless.
     pub(crate) connection_state_ok: bool,
+    /// Set once a command failed because of the connection itself.
+    ///
+    /// Only these failures count towards opening the circuit of the node.
+    pub(crate) connection_failed: bool,
     pub(crate) managed: Managed<T>,
+    pub(crate) on_redirection: Option<OnRedirection>,
+    /// Gives back the budget of the partition the
+    /// connection was checked out in once dropped
+    pub(crate) partition: Option<PartitionPermit>,
+    /// Where the connection was checked out
+    pub(crate) checked_out_at: &'static Location<'static>,
+    /// Stops watching the connection for a leak once dropped
+    pub(crate) _leak_watch: Option<Watched>,
 }
 
 impl<T: Poolable> PoolConnection<T> {
     pub fn connected_to(&self) -> &str {
         self.managed.connected_to()
     }
+
+    /// The partition the connection was checked out in
+    pub fn partition(&self) -> Option<&str> {
+        self.partition.as_ref().map(PartitionPermit::name)
+    }
+
+    /// The location in the code where the connection was checked out
+    pub fn checked_out_at(&self) -> &'static Location<'static> {
+        self.checked_out_at
+    }
 }
 
 impl<T: Poolable> ConnectionLike for PoolConnection<T>
 where
-    T: ConnectionLike,
+    T: ConnectionLike + Send,
 {
-    fn req_packed_command(mut self, cmd: Vec<u8>) -> RedisFuture<(Self, Value)> {
-        if let Some(conn) = self.managed.value.take() {
-            self.connection_state_ok = false;
-            Box::new(conn.req_packed_command(cmd).map(|(conn, value)| {
-                self.managed.value = Some(conn);
-                self.connection_state_ok = true;
-                (self, value)
-            }))
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        let on_redirection = self.on_redirection.clone();
+        // Only if the future completes the connection is known to be in a valid state
+        // unless it failed because of the connection itself
+        self.connection_state_ok = false;
+        let connection_state_ok = &mut self.connection_state_ok;
+        let connection_failed = &mut self.connection_failed;
+        if let Some(conn) = self.managed.value.as_mut() {
+            async move {
+                let result = conn.req_packed_command(cmd).await;
+                *connection_failed = matches!(result, Err(ref err) if is_connection_failure(err));
+                *connection_state_ok = !*connection_failed;
+                result.map_err(|err| notify_on_redirection(err, on_redirection))
+            }
+            .boxed()
         } else {
-            Box::new(future::err(
-                (ErrorKind::IoError, "no connection - this is a bug of reool").into(),
-            ))
+            no_connection().boxed()
         }
     }
 
-    fn req_packed_commands(
-        mut self,
-        cmd: Vec<u8>,
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
         offset: usize,
         count: usize,
-    ) -> RedisFuture<(Self, Vec<Value>)> {
-        if let Some(conn) = self.managed.value.take() {
-            self.connection_state_ok = false;
-            Box::new(
-                conn.req_packed_commands(cmd, offset, count)
-                    .map(|(conn, values)| {
-                        self.managed.value = Some(conn);
-                        self.connection_state_ok = true;
-                        (self, values)
-                    }),
-            )
+    ) -> RedisFuture<'a, Vec<Value>> {
+        let on_redirection = self.on_redirection.clone();
+        // Only if the future completes the connection is known to be in a valid state
+        // unless it failed because of the connection itself
+        self.connection_state_ok = false;
+        let connection_state_ok = &mut self.connection_state_ok;
+        let connection_failed = &mut self.connection_failed;
+        if let Some(conn) = self.managed.value.as_mut() {
+            async move {
+                let result = conn.req_packed_commands(cmd, offset, count).await;
+                *connection_failed = matches!(result, Err(ref err) if is_connection_failure(err));
+                *connection_state_ok = !*connection_failed;
+                result.map_err(|err| notify_on_redirection(err, on_redirection))
+            }
+            .boxed()
         } else {
-            Box::new(future::err(
-                (ErrorKind::IoError, "no connection - this is a bug of reool").into(),
-            ))
+            no_connection().boxed()
         }
     }
 
@@ -79,9 +115,31 @@ where
     }
 }
 
+fn no_connection<T>() -> future::Ready<Result<T, RedisError>> {
+    future::err((ErrorKind::IoError, "no connection - this is a bug of reool").into())
+}
+
+/// Errors replied by the server (e.g. `WRONGTYPE` or a `MOVED` redirection)
+/// leave the connection in a valid state
+fn is_connection_failure(err: &RedisError) -> bool {
+    err.is_io_error() || err.is_connection_dropped()
+}
+
+fn notify_on_redirection(err: RedisError, on_redirection: Option<OnRedirection>) -> RedisError {
+    if let Some(on_redirection) = on_redirection {
+        if is_redirection(&err) {
+            on_redirection();
+        }
+    }
+    err
+}
+
 impl<T: Poolable> Drop for PoolConnection<T> {
     fn drop(&mut self) {
         if !self.connection_state_ok {
+            if self.connection_failed {
+                self.managed.connection_broken();
+            }
             self.managed.value.take();
         }
     }
@@ -89,83 +147,73 @@ impl<T: Poolable> Drop for PoolConnection<T> {
 
 pub enum ConnectionFlavour {
     RedisRs(redis::aio::Connection, Arc<String>),
-    // Tls(?)
+    #[cfg(feature = "tls")]
+    Tls(crate::redis_rs::TlsConnection, Arc<String>),
 }
 
 impl Poolable for ConnectionFlavour {
     fn connected_to(&self) -> &str {
         match self {
-            ConnectionFlavour::RedisRs(_, c) => &c,
+            ConnectionFlavour::RedisRs(_, c) => c,
+            #[cfg(feature = "tls")]
+            ConnectionFlavour::Tls(_, c) => c,
         }
     }
 }
 
 impl ConnectionLike for ConnectionFlavour {
-    fn req_packed_command(self, cmd: Vec<u8>) -> RedisFuture<(Self, Value)> {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
         match self {
-            ConnectionFlavour::RedisRs(conn, c) => Box::new(
-                conn.req_packed_command(cmd)
-                    .map(|(conn, v)| (ConnectionFlavour::RedisRs(conn, c), v)),
-            ),
+            ConnectionFlavour::RedisRs(conn, _) => conn.req_packed_command(cmd),
+            #[cfg(feature = "tls")]
+            ConnectionFlavour::Tls(conn, _) => conn.req_packed_command(cmd),
         }
     }
 
-    fn req_packed_commands(
-        self,
-        cmd: Vec<u8>,
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
         offset: usize,
         count: usize,
-    ) -> RedisFuture<(Self, Vec<Value>)> {
+    ) -> RedisFuture<'a, Vec<Value>> {
         match self {
-            ConnectionFlavour::RedisRs(conn, c) => Box::new(
-                conn.req_packed_commands(cmd, offset, count)
-                    .map(|(conn, v)| (ConnectionFlavour::RedisRs(conn, c), v)),
-            ),
+            ConnectionFlavour::RedisRs(conn, _) => conn.req_packed_commands(cmd, offset, count),
+            #[cfg(feature = "tls")]
+            ConnectionFlavour::Tls(conn, _) => conn.req_packed_commands(cmd, offset, count),
         }
     }
 
     fn get_db(&self) -> i64 {
         match self {
             ConnectionFlavour::RedisRs(ref conn, _) => conn.get_db(),
+            #[cfg(feature = "tls")]
+            ConnectionFlavour::Tls(ref conn, _) => conn.get_db(),
         }
     }
 }
 
 impl<T: Poolable> ConnectionLike for Managed<T>
 where
-    T: ConnectionLike,
+    T: ConnectionLike + Send,
 {
-    fn req_packed_command(mut self, cmd: Vec<u8>) -> RedisFuture<(Self, Value)> {
-        if let Some(conn) = self.value.take() {
-            Box::new(conn.req_packed_command(cmd).map(|(conn, value)| {
-                self.value = Some(conn);
-                (self, value)
-            }))
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        if let Some(conn) = self.value.as_mut() {
+            conn.req_packed_command(cmd)
         } else {
-            Box::new(future::err(
-                (ErrorKind::IoError, "no connection - this is a bug of reool").into(),
-            ))
+            no_connection().boxed()
         }
     }
 
-    fn req_packed_commands(
-        mut self,
-        cmd: Vec<u8>,
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
         offset: usize,
         count: usize,
-    ) -> RedisFuture<(Self, Vec<Value>)> {
-        if let Some(conn) = self.value.take() {
-            Box::new(
-                conn.req_packed_commands(cmd, offset, count)
-                    .map(|(conn, values)| {
-                        self.value = Some(conn);
-                        (self, values)
-                    }),
-            )
+    ) -> RedisFuture<'a, Vec<Value>> {
+        if let Some(conn) = self.value.as_mut() {
+            conn.req_packed_commands(cmd, offset, count)
         } else {
-            Box::new(future::err(
-                (ErrorKind::IoError, "no connection - this is a bug of reool").into(),
-            ))
+            no_connection().boxed()
         }
     }
 
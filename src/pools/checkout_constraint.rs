@@ -1,24 +1,40 @@
 use std::time::{Duration, Instant};
 
 use crate::config::DefaultPoolCheckoutMode;
-use crate::{CheckoutMode, Immediately, Wait};
+use crate::{CheckoutMode, CheckoutOptions, Immediately, Priority, Wait};
 
 /// The checkout options a pool really has
 #[derive(Debug, Copy, Clone)]
-pub enum CheckoutConstraint {
+pub struct CheckoutConstraint {
+    pub wait: WaitConstraint,
+    pub priority: Priority,
+}
+
+/// How long a checkout may wait for a connection
+#[derive(Debug, Copy, Clone)]
+pub enum WaitConstraint {
     Immediately,
     Until(Instant),
     Wait,
 }
 
+impl From<WaitConstraint> for CheckoutConstraint {
+    fn from(wait: WaitConstraint) -> Self {
+        CheckoutConstraint {
+            wait,
+            priority: Priority::default(),
+        }
+    }
+}
+
 impl From<Immediately> for CheckoutConstraint {
     fn from(_: Immediately) -> Self {
-        CheckoutConstraint::Immediately
+        WaitConstraint::Immediately.into()
     }
 }
 impl From<Wait> for CheckoutConstraint {
     fn from(_: Wait) -> Self {
-        CheckoutConstraint::Wait
+        WaitConstraint::Wait.into()
     }
 }
 
@@ -31,14 +47,22 @@ impl From<Duration> for CheckoutConstraint {
 
 impl From<Instant> for CheckoutConstraint {
     fn from(until: Instant) -> Self {
-        CheckoutConstraint::Until(until)
+        WaitConstraint::Until(until).into()
     }
 }
 
 impl CheckoutConstraint {
+    /// The same constraint but without waiting for a connection
+    pub fn immediately(self) -> Self {
+        CheckoutConstraint {
+            wait: WaitConstraint::Immediately,
+            ..self
+        }
+    }
+
     pub fn is_deadline_elapsed(self) -> bool {
-        match self {
-            CheckoutConstraint::Until(deadline) => deadline < Instant::now(),
+        match self.wait {
+            WaitConstraint::Until(deadline) => deadline < Instant::now(),
             _ => false,
         }
     }
@@ -46,36 +70,40 @@ impl CheckoutConstraint {
     /// Returns `true` if we can still wait for e.g. another attempt to dispatch
     /// a message to the inner pool
     pub fn can_wait_for_dispatch(&self) -> bool {
-        match self {
-            CheckoutConstraint::Until(deadline) => *deadline > Instant::now(),
-            CheckoutConstraint::Immediately => false,
-            CheckoutConstraint::Wait => true,
+        match self.wait {
+            WaitConstraint::Until(deadline) => deadline > Instant::now(),
+            WaitConstraint::Immediately => false,
+            WaitConstraint::Wait => true,
         }
     }
 
     pub fn deadline_and_reservation_allowed(self) -> (Option<Instant>, bool) {
-        match self {
-            CheckoutConstraint::Until(deadline) => (Some(deadline), true),
-            CheckoutConstraint::Immediately => (None, false),
-            CheckoutConstraint::Wait => (None, true),
+        match self.wait {
+            WaitConstraint::Until(deadline) => (Some(deadline), true),
+            WaitConstraint::Immediately => (None, false),
+            WaitConstraint::Wait => (None, true),
         }
     }
 
-    pub fn from_checkout_mode_and_pool_default<T: Into<CheckoutMode>>(
+    pub fn from_checkout_mode_and_pool_default<T: Into<CheckoutOptions>>(
         m: T,
         default: DefaultPoolCheckoutMode,
     ) -> Self {
-        match m.into() {
-            CheckoutMode::Immediately => CheckoutConstraint::Immediately,
-            CheckoutMode::Wait => CheckoutConstraint::Wait,
-            CheckoutMode::Until(d) => CheckoutConstraint::Until(d),
+        let options = m.into();
+        let wait = match options.mode {
+            CheckoutMode::Immediately => WaitConstraint::Immediately,
+            CheckoutMode::Wait => WaitConstraint::Wait,
+            CheckoutMode::Until(d) => WaitConstraint::Until(d),
             CheckoutMode::PoolDefault => match default {
-                DefaultPoolCheckoutMode::Immediately => CheckoutConstraint::Immediately,
-                DefaultPoolCheckoutMode::Wait => CheckoutConstraint::Wait,
-                DefaultPoolCheckoutMode::WaitAtMost(d) => {
-                    CheckoutConstraint::Until(Instant::now() + d)
-                }
+                DefaultPoolCheckoutMode::Immediately => WaitConstraint::Immediately,
+                DefaultPoolCheckoutMode::Wait => WaitConstraint::Wait,
+                DefaultPoolCheckoutMode::WaitAtMost(d) => WaitConstraint::Until(Instant::now() + d),
             },
+        };
+
+        CheckoutConstraint {
+            wait,
+            priority: options.priority,
         }
     }
 }
@@ -1,20 +1,25 @@
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
-use futures::future::{self, Future, Loop};
-use log::error;
-use tokio::timer::Delay;
+use futures::future::TryFutureExt;
+use tokio::time;
 
 use crate::{CheckoutError, CheckoutErrorKind, Poolable};
 
 use pool_internal::CheckoutManaged;
 
 mod checkout_constraint;
+pub(crate) mod cluster_pool;
 pub(crate) mod pool_internal;
 mod pool_per_node;
+mod primary_replica_pool;
+mod sentinel_pool;
 mod single_pool;
 
 pub(crate) use self::checkout_constraint::*;
+pub(crate) use self::cluster_pool::ClusterPool;
 pub(crate) use self::pool_per_node::PoolPerNode;
+pub(crate) use self::primary_replica_pool::PrimaryReplicaPool;
+pub(crate) use self::sentinel_pool::SentinelPool;
 pub(crate) use self::single_pool::SinglePool;
 
 /// Something that can checkout
@@ -43,9 +48,9 @@ where
         let constraint = constraint.into();
         CheckoutManaged::new(pool.check_out(constraint).or_else(move |err| {
             if err.kind() != CheckoutErrorKind::CheckoutLimitReached {
-                CheckoutManaged::error(err.kind())
+                CheckoutManaged::error(err)
             } else {
-                retry_on_queue_limit_reached(pool, constraint, err.kind())
+                retry_on_queue_limit_reached(pool, constraint, err)
             }
         }))
     }
@@ -54,36 +59,29 @@ where
 fn retry_on_queue_limit_reached<P, T>(
     pool: P,
     constraint: CheckoutConstraint,
-    last_err: CheckoutErrorKind,
+    last_err: CheckoutError,
 ) -> CheckoutManaged<T>
 where
     P: CanCheckout<T> + Send + 'static,
     T: Poolable,
 {
-    CheckoutManaged::new(future::loop_fn(
-        (pool, last_err),
-        move |(pool, last_err)| {
+    CheckoutManaged::new(async move {
+        let mut last_err = last_err;
+        loop {
             if !constraint.can_wait_for_dispatch() {
-                Box::new(future::err(CheckoutError::from(last_err)))
-            } else {
-                let f = pool.check_out(constraint).then(|r| match r {
-                    Ok(conn) => Box::new(future::ok(Loop::Break(conn))),
-                    Err(err) => {
-                        if err.kind() != CheckoutErrorKind::CheckoutLimitReached {
-                            Box::new(future::err(err))
-                        } else {
-                            let delayed = Delay::new(Instant::now() + Duration::from_millis(1))
-                                .map_err(|err| {
-                                    error!("A timer error occurred: {}", err);
-                                    CheckoutError::new(CheckoutErrorKind::TaskExecution)
-                                })
-                                .map(move |()| Loop::Continue((pool, err.kind())));
-                            Box::new(delayed) as Box<dyn Future<Item = _, Error = _> + Send>
-                        }
+                return Err(last_err);
+            }
+
+            match pool.check_out(constraint).await {
+                Ok(conn) => return Ok(conn),
+                Err(err) => {
+                    if err.kind() != CheckoutErrorKind::CheckoutLimitReached {
+                        return Err(err);
                     }
-                });
-                Box::new(f) as Box<dyn Future<Item = _, Error = _> + Send>
+                    last_err = err;
+                    time::sleep(Duration::from_millis(1)).await;
+                }
             }
-        },
-    ))
+        }
+    })
 }
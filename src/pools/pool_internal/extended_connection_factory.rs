@@ -1,21 +1,55 @@
 //! A pimped connection factory
-use std::sync::Arc;
-use std::time::Instant;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
-use futures::future::{self, Future, Loop};
-use log::{trace, warn};
+use futures::future::FutureExt;
+use log::{debug, trace, warn};
 use tokio::sync::mpsc;
-use tokio::{self, timer::Delay};
+use tokio::time;
 
 use crate::backoff_strategy::BackoffStrategy;
-use crate::connection_factory::ConnectionFactory;
-use crate::{Ping, Poolable};
+use crate::connection_factory::{ConnectionFactory, ConnectionInitializer};
+use crate::error::ConnectionFailure;
+use crate::executor_flavour::ExecutorFlavour;
+use crate::validation_policy::ValidationPolicy;
+use crate::{Ping, PingState, Poolable};
 
 use super::inner_pool::PoolMessage;
 
+use super::health_check::HealthCheck;
 use super::instrumentation::PoolInstrumentation;
+use super::leak_detector::LeakDetector;
 use super::{Managed, PoolMessageEnvelope};
 
+/// State shared by all `ExtendedConnectionFactory`s of a pool
+pub(crate) struct SharedState {
+    /// Once set no more connections will be created
+    shutting_down: AtomicBool,
+    back_off_strategy: RwLock<BackoffStrategy>,
+    health_check: HealthCheck,
+    leak_detector: Arc<LeakDetector>,
+    validation_timeout: Duration,
+}
+
+impl SharedState {
+    pub fn new(
+        back_off_strategy: BackoffStrategy,
+        health_check_failure_threshold: usize,
+        leak_detection_threshold: Option<Duration>,
+        validation_timeout: Duration,
+    ) -> Self {
+        Self {
+            shutting_down: AtomicBool::new(false),
+            back_off_strategy: RwLock::new(back_off_strategy),
+            health_check: HealthCheck::new(health_check_failure_threshold),
+            leak_detector: Arc::new(LeakDetector::new(leak_detection_threshold)),
+            validation_timeout,
+        }
+    }
+}
+
 /// A connection factory that uses a retry logic when creating connections. As long
 /// as the pool is there, it will retry to create a connection.
 ///
@@ -27,7 +61,11 @@ pub(crate) struct ExtendedConnectionFactory<T: Poolable> {
     inner_factory: Arc<dyn ConnectionFactory<Connection = T> + Send + Sync + 'static>,
     send_back: mpsc::UnboundedSender<PoolMessageEnvelope<T>>,
     pub instrumentation: PoolInstrumentation,
-    back_off_strategy: BackoffStrategy,
+    pub validation_policy: ValidationPolicy,
+    connection_initializer: Option<ConnectionInitializer<T>>,
+    /// Used to spawn tasks even if not called from within a runtime
+    pub executor: ExecutorFlavour,
+    shared: Arc<SharedState>,
 }
 
 impl<T: Poolable> ExtendedConnectionFactory<T> {
@@ -35,16 +73,55 @@ impl<T: Poolable> ExtendedConnectionFactory<T> {
         inner_factory: Arc<dyn ConnectionFactory<Connection = T> + Send + Sync + 'static>,
         send_back: mpsc::UnboundedSender<PoolMessageEnvelope<T>>,
         instrumentation: PoolInstrumentation,
-        back_off_strategy: BackoffStrategy,
+        validation_policy: ValidationPolicy,
+        connection_initializer: Option<ConnectionInitializer<T>>,
+        executor: ExecutorFlavour,
+        shared: Arc<SharedState>,
     ) -> Self {
         Self {
             inner_factory,
             send_back,
             instrumentation,
-            back_off_strategy,
+            validation_policy,
+            connection_initializer,
+            executor,
+            shared,
         }
     }
 
+    /// Stop creating connections for the pool
+    pub fn begin_shutdown(&self) {
+        self.shared.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shared.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Changes the `BackoffStrategy` for all factories of the pool
+    pub fn set_back_off_strategy(&self, back_off_strategy: BackoffStrategy) {
+        *self.shared.back_off_strategy.write().unwrap() = back_off_strategy;
+    }
+
+    fn back_off_strategy(&self) -> BackoffStrategy {
+        *self.shared.back_off_strategy.read().unwrap()
+    }
+
+    /// The results of the health checks on the node
+    pub fn health_check(&self) -> &HealthCheck {
+        &self.shared.health_check
+    }
+
+    /// Keeps track of the connections checked out of the pool
+    pub fn leak_detector(&self) -> &Arc<LeakDetector> {
+        &self.shared.leak_detector
+    }
+
+    /// Returns `true` once the pool stopped receiving messages
+    pub fn is_pool_gone(&self) -> bool {
+        self.send_back.is_closed()
+    }
+
     /// Returns a cloned version of the sender to the internal channel
     pub fn send_back_cloned(&self) -> mpsc::UnboundedSender<PoolMessageEnvelope<T>> {
         self.send_back.clone()
@@ -54,103 +131,193 @@ impl<T: Poolable> ExtendedConnectionFactory<T> {
     ///
     /// The message will be wrapped automatically and being unwrapped when
     /// the message could not be sent.
-    pub fn send_message(&mut self, message: PoolMessage<T>) -> Result<(), PoolMessage<T>> {
-        message.send_on_internal_channel(&mut self.send_back)
+    #[allow(clippy::result_large_err)]
+    pub fn send_message(&self, message: PoolMessage<T>) -> Result<(), PoolMessage<T>> {
+        message.send_on_internal_channel(&self.send_back)
     }
 
     /// Create a new connections and try as long as the pool is there.
     ///
     /// Before each attempt this functions tries to send a probing message to the pool. If
     /// sending the message fails the channel to the pool is disconnected which
-    /// means that the pool has been dropped.
+    /// means that the pool has been dropped. No attempts are made once the
+    /// pool is shutting down.
     pub fn create_connection(self, initiated_at: Instant) {
-        let f = future::loop_fn((self, 1), move |(mut factory, attempt)| {
-            // Probe the channel to the inner pool
-            if factory
-                .send_message(PoolMessage::CheckAlive(Instant::now()))
-                .is_err()
+        self.spawn_create_connection(initiated_at, false)
+    }
+
+    /// Create a new connection like `create_connection` does
+    /// which is marked as growing the pool.
+    pub fn create_connection_to_grow_pool(self, initiated_at: Instant) {
+        self.spawn_create_connection(initiated_at, true)
+    }
+
+    fn spawn_create_connection(self, initiated_at: Instant, grows_pool: bool) {
+        let executor = self.executor.clone();
+        let f = async move {
+            let mut factory = self;
+            let mut attempt = 1;
+            loop {
+                if factory.is_shutting_down() {
+                    debug!("Create connection stopped: Pool is shutting down.");
+                    return;
+                }
+
+                // Probe the channel to the inner pool
+                if factory
+                    .send_message(PoolMessage::CheckAlive(Instant::now()))
+                    .is_err()
+                {
+                    warn!("Create connection finally failed: Pool is gone.");
+                    return;
+                }
+
+                match factory.do_a_create_connection_attempt(initiated_at).await {
+                    Ok(mut managed) => {
+                        managed.grows_pool = grows_pool;
+                        drop(managed); // We send it to the pool by dropping it
+                        trace!("Dropped newly created connection to be sent to pool");
+                        return;
+                    }
+                    Err(failed_factory) => {
+                        factory = failed_factory;
+                        delay_by_backoff_strategy(&factory, attempt).await;
+                        attempt += 1;
+                    }
+                }
+            }
+        };
+
+        if let Err(err) = executor.spawn(f) {
+            warn!("Create connection failed: Could not spawn task: {}", err);
+        }
+    }
+
+    pub fn connecting_to(&self) -> &str {
+        self.inner_factory.connecting_to()
+    }
+
+    /// Pings the node. The latency of a successful ping is recorded.
+    pub fn ping(&self, timeout: Instant) -> impl Future<Output = Result<Ping, ()>> + Send {
+        let instrumentation = self.instrumentation.clone();
+        self.inner_factory.ping(timeout).map(move |ping| {
+            if let Ok(Ping {
+                latency: Some(latency),
+                state: PingState::Ok,
+                ..
+            }) = ping
             {
-                Box::new(future::err("Pool is gone.".to_string()))
-            } else {
-                Box::new(factory.do_a_create_connection_attempt(initiated_at).then(
-                    move |r| match r {
-                        Ok(managed) => {
-                            drop(managed); // We send it to the pool by dropping it
-                            trace!("Dropped newly created connection to be sent to pool");
-                            Box::new(future::ok(Loop::Break(())))
-                        }
-                        Err(factory) => Box::new(delayed_by_backoff_strategy(factory, attempt))
-                            as Box<dyn Future<Item = _, Error = String> + Send>,
-                    },
-                )) as Box<dyn Future<Item = _, Error = String> + Send>
+                instrumentation.ping_succeeded(latency);
             }
+            ping
         })
-        .then(|r| {
-            if let Err(err) = r {
-                warn!("Create connection finally failed: {}", err);
+    }
+
+    /// Validate the given connection with the inner factory
+    ///
+    /// A validation not finished before `timeout` failed. The timeout
+    /// is only set up once the returned future is polled.
+    pub fn validate(
+        &self,
+        conn: T,
+        timeout: Instant,
+    ) -> impl Future<Output = Result<T, ()>> + Send {
+        let validation = self.inner_factory.validate(conn);
+        async move {
+            match time::timeout_at(timeout.into(), validation).await {
+                Ok(validated) => validated,
+                Err(_elapsed) => {
+                    debug!("validation timed out");
+                    Err(())
+                }
             }
-            Ok(())
-        });
+        }
+    }
 
-        tokio::spawn(f);
+    /// The time after which a validation counts as failed
+    pub fn validation_timeout(&self) -> Duration {
+        self.shared.validation_timeout
     }
 
-    pub fn connecting_to(&self) -> &str {
-        self.inner_factory.connecting_to()
+    /// The current generation of connections of the inner factory
+    pub fn generation(&self) -> u64 {
+        self.inner_factory.generation()
     }
 
-    pub fn ping(&self, timeout: Instant) -> impl Future<Item = Ping, Error = ()> + Send {
-        self.inner_factory.ping(timeout)
+    /// Close the given connection with the inner factory
+    pub fn close(&self, conn: T) -> impl Future<Output = ()> + Send {
+        self.inner_factory.close(conn)
     }
 
     /// Do one attempt on the inner connection factory to
     /// get a new connection
-    fn do_a_create_connection_attempt(
+    ///
+    /// If there is a `ConnectionInitializer` it is applied to the new
+    /// connection. A failed initialization counts as a failed attempt.
+    async fn do_a_create_connection_attempt(
         self,
         initiated_at: Instant,
-    ) -> impl Future<Item = Managed<T>, Error = Self> + Send {
+    ) -> Result<Managed<T>, Self> {
         let start_connect = Instant::now();
-        let inner_factory = Arc::clone(&self.inner_factory);
-        inner_factory
-            .create_connection()
-            .then(move |res| match res {
-                Ok(conn) => {
-                    trace!("new connection created");
-                    self.instrumentation
-                        .connection_created(initiated_at.elapsed(), start_connect.elapsed());
-                    future::ok(Managed::fresh(conn, self))
+        let res = match self.inner_factory.create_connection().await {
+            Ok(conn) => {
+                if let Some(ref connection_initializer) = self.connection_initializer {
+                    trace!("initializing new connection");
+                    connection_initializer.initialize(conn).await
+                } else {
+                    Ok(conn)
                 }
-                Err(err) => {
-                    self.instrumentation.connection_factory_failed();
-                    warn!("Connection factory failed: {}", err);
-                    future::err(self)
-                }
-            })
+            }
+            Err(err) => Err(err),
+        };
+
+        match res {
+            Ok(conn) => {
+                trace!("new connection created");
+                self.instrumentation
+                    .connection_created(initiated_at.elapsed(), start_connect.elapsed());
+                Ok(Managed::fresh(conn, self))
+            }
+            Err(err) => {
+                warn!("Connection factory failed: {}", err);
+                self.instrumentation
+                    .connection_factory_failed(ConnectionFailure::new(&err));
+                Err(self)
+            }
+        }
+    }
+}
+
+impl<T: Poolable> Clone for ExtendedConnectionFactory<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner_factory: Arc::clone(&self.inner_factory),
+            send_back: self.send_back.clone(),
+            instrumentation: self.instrumentation.clone(),
+            validation_policy: self.validation_policy,
+            connection_initializer: self.connection_initializer.clone(),
+            executor: self.executor.clone(),
+            shared: Arc::clone(&self.shared),
+        }
     }
 }
 
 /// Applies a delay based on the backoff strategy. If there is no
 /// backoff we retry immediately.
-fn delayed_by_backoff_strategy<T: Poolable>(
-    factory: ExtendedConnectionFactory<T>,
+async fn delay_by_backoff_strategy<T: Poolable>(
+    factory: &ExtendedConnectionFactory<T>,
     attempt: usize,
-) -> impl Future<Item = Loop<(), (ExtendedConnectionFactory<T>, usize)>, Error = String> + Send {
-    if let Some(backoff) = factory.back_off_strategy.get_next_backoff(attempt) {
-        let delay = Delay::new(Instant::now() + backoff);
+) {
+    if let Some(backoff) = factory.back_off_strategy().get_next_backoff(attempt) {
         warn!(
             "Retry on in to create connection after attempt {} in {:?}",
             attempt, backoff
         );
-        Box::new(
-            delay
-                .map_err(|err| err.to_string())
-                .and_then(move |()| future::ok(Loop::Continue((factory, attempt + 1)))),
-        ) as Box<dyn Future<Item = _, Error = _> + Send>
+        time::sleep(backoff).await;
     } else {
         warn!(
             "Retry on in to create connection after attempt {} immediately",
             attempt
         );
-        Box::new(future::ok(Loop::Continue((factory, attempt + 1))))
     }
 }
@@ -0,0 +1,72 @@
+//! Keeps track of the pings made on the node of a pool
+use std::sync::Mutex;
+
+use log::{info, warn};
+
+use crate::health::{LastPing, NodeHealth};
+use crate::instrumentation::PoolId;
+use crate::Ping;
+
+/// The results of the health checks of a single pool
+pub(crate) struct HealthCheck {
+    /// Zero means that the node never becomes unhealthy
+    failure_threshold: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    consecutive_failures: usize,
+    last_ping: Option<LastPing>,
+}
+
+impl HealthCheck {
+    pub fn new(failure_threshold: usize) -> Self {
+        Self {
+            failure_threshold,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn record(&self, ping: Ping) {
+        let mut state = self.state.lock().unwrap();
+        let was_healthy = self.is_healthy_with(&state);
+
+        if ping.is_ok() {
+            state.consecutive_failures = 0;
+        } else {
+            state.consecutive_failures += 1;
+        }
+
+        let is_healthy = self.is_healthy_with(&state);
+        if was_healthy && !is_healthy {
+            warn!(
+                "node '{}' is unhealthy after {} failed pings",
+                ping.uri, state.consecutive_failures
+            );
+        } else if !was_healthy && is_healthy {
+            info!("node '{}' is healthy again", ping.uri);
+        }
+
+        state.last_ping = Some(LastPing::from(&ping));
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.is_healthy_with(&self.state.lock().unwrap())
+    }
+
+    pub fn node_health(&self, pool: PoolId, connected_to: &str) -> NodeHealth {
+        let state = self.state.lock().unwrap();
+        NodeHealth {
+            pool,
+            connected_to: connected_to.to_string(),
+            healthy: self.is_healthy_with(&state),
+            consecutive_failures: state.consecutive_failures,
+            last_ping: state.last_ping.clone(),
+        }
+    }
+
+    fn is_healthy_with(&self, state: &State) -> bool {
+        self.failure_threshold == 0 || state.consecutive_failures < self.failure_threshold
+    }
+}
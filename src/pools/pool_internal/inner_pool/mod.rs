@@ -1,18 +1,24 @@
 use std::collections::VecDeque;
 use std::time::{Duration, Instant};
 
-use log::{debug, trace};
+use futures::future::{self, BoxFuture, FutureExt};
+use log::{debug, trace, warn};
+use rand::prelude::*;
 use tokio::sync::oneshot;
 
-use crate::config::ActivationOrder;
+use crate::config::{ActivationOrder, Reconfiguration};
 use crate::error::{CheckoutError, CheckoutErrorKind};
-use crate::Poolable;
+use crate::{Poolable, Priority};
 
+use super::extended_connection_factory::ExtendedConnectionFactory;
 use super::instrumentation::PoolInstrumentation;
 use super::{Config, Managed};
 
 /// An internal interval sent regularly to clean up reservations
 pub(super) const CLEANUP_INTERVAL: Duration = Duration::from_millis(100);
+/// The number of outdated idle connections replaced per cleanup so that
+/// a change of the addresses does not reconnect all of them at once
+const OUTDATED_REPLACED_PER_CLEANUP: usize = 1;
 
 /// A message that can be sent to the inner pool via a stream
 pub(crate) enum PoolMessage<T: Poolable> {
@@ -32,6 +38,22 @@ pub(crate) enum PoolMessage<T: Poolable> {
     },
     CleanupReservations(Instant),
     CheckAlive(Instant),
+    /// Change the configuration of the running pool
+    Reconfigure {
+        /// Timestamp of message creation
+        created_at: Instant,
+        /// The values to change. They must already be
+        /// adjusted to a single pool.
+        reconfiguration: Reconfiguration,
+    },
+    /// Stop handing out connections and close all connections
+    /// once they are returned
+    Shutdown {
+        /// Timestamp of message creation
+        created_at: Instant,
+        /// Notified once all connections have been closed
+        notify: oneshot::Sender<()>,
+    },
 }
 
 impl<T: Poolable> PoolMessage<T> {
@@ -41,6 +63,8 @@ impl<T: Poolable> PoolMessage<T> {
             PoolMessage::CheckIn { created_at, .. } => (false, *created_at),
             PoolMessage::CleanupReservations(created_at) => (false, *created_at),
             PoolMessage::CheckAlive(created_at) => (false, *created_at),
+            PoolMessage::Reconfigure { created_at, .. } => (false, *created_at),
+            PoolMessage::Shutdown { created_at, .. } => (false, *created_at),
         }
     }
 }
@@ -54,34 +78,119 @@ pub(crate) struct CheckoutPayload<T: Poolable> {
     pub checkout_requested_at: Instant,
     /// `true` if it is allowed to create a reservation.
     pub reservation_allowed: bool,
+    /// Determines the lane of the reservation and whether
+    /// connections reserved for `Priority::High` may be taken
+    pub priority: Priority,
 }
 
 pub(crate) struct InnerPool<T: Poolable> {
     /// Stores the idle connections ready to be checked out
     idle: IdleConnections<Managed<T>>,
     /// Reservations waiting for an incoming connection
-    reservations: VecDeque<Reservation<T>>,
+    reservations: Reservations<T>,
+    /// The maximum number of reservations of all priorities
+    reservation_limit: usize,
+    /// The number of idle connections only checkouts
+    /// with `Priority::High` may take
+    reserved_for_high_priority: usize,
+    /// The `reserved_for_high_priority` as configured which may
+    /// not leave connections for others after a reconfiguration
+    configured_reserved_for_high_priority: usize,
     instrumentation: PoolInstrumentation,
     /// Timestamp when the last clean up was done. Used
     /// to only do a cleanup if `CLEANUP_INTERVAL` has already elapsed unless
     /// the reservation queue is already full in which case a cleanup attempt is
     /// always made.
     last_cleanup: Instant,
+    /// Used to create additional connections if the pool has to grow
+    factory: ExtendedConnectionFactory<T>,
+    /// The number of connections the pool will not shrink below
+    desired_pool_size: usize,
+    /// The number of connections the pool may grow to
+    max_pool_size: usize,
+    /// The `max_pool_size` as configured which may be
+    /// less than `desired_pool_size`
+    configured_max_pool_size: Option<usize>,
+    /// Connections exceeding `desired_pool_size` idle for longer
+    /// than this will be closed
+    surplus_idle_timeout: Duration,
+    /// Idle connections idle for longer than this will be
+    /// closed and replaced
+    idle_timeout: Option<Duration>,
+    /// Connections older than this will be replaced when checked in
+    max_connection_lifetime: Option<Duration>,
+    /// The maximum amount by which the lifetime of a connection is randomly shortened
+    connection_lifetime_jitter: Duration,
+    /// The number of connections this pool maintains including
+    /// those which are currently being created
+    pool_size: usize,
+    /// The number of connections requested to grow the pool
+    /// which have not yet arrived
+    pending_growth: usize,
+    /// `Some` once the pool is shutting down
+    shutdown: Option<Shutdown>,
+}
+
+/// Leaves at least one of `desired_pool_size` connections for checkouts
+/// without `Priority::High`
+fn reserved_for_high_priority(configured: usize, desired_pool_size: usize) -> usize {
+    std::cmp::min(configured, desired_pool_size.saturating_sub(1))
+}
+
+/// The state of a pool which is shutting down
+struct Shutdown {
+    /// Notified once all connections in flight have been returned and closed
+    notify: Vec<oneshot::Sender<()>>,
+    /// Connections which are being closed
+    closing: Vec<BoxFuture<'static, ()>>,
+    /// `true` once all connections have been closed
+    finished: bool,
 }
 
 impl<T> InnerPool<T>
 where
     T: Poolable,
 {
-    pub fn new(config: &Config, instrumentation: PoolInstrumentation) -> Self {
+    pub fn new(config: &Config, factory: ExtendedConnectionFactory<T>) -> Self {
+        let max_pool_size = config
+            .max_pool_size
+            .map(|max| std::cmp::max(max, config.desired_pool_size))
+            .unwrap_or(config.desired_pool_size);
+
         Self {
-            idle: IdleConnections::new(config.desired_pool_size, config.activation_order),
-            reservations: VecDeque::with_capacity(config.reservation_limit),
-            instrumentation,
+            idle: IdleConnections::new(max_pool_size, config.activation_order),
+            reservations: Reservations::with_capacity(config.reservation_limit),
+            reservation_limit: config.reservation_limit,
+            reserved_for_high_priority: reserved_for_high_priority(
+                config.reserved_for_high_priority,
+                config.desired_pool_size,
+            ),
+            configured_reserved_for_high_priority: config.reserved_for_high_priority,
+            instrumentation: factory.instrumentation.clone(),
             last_cleanup: Instant::now(),
+            factory,
+            desired_pool_size: config.desired_pool_size,
+            max_pool_size,
+            configured_max_pool_size: config.max_pool_size,
+            surplus_idle_timeout: config.surplus_idle_timeout,
+            idle_timeout: config.idle_timeout,
+            max_connection_lifetime: config.max_connection_lifetime,
+            connection_lifetime_jitter: config.connection_lifetime_jitter,
+            pool_size: config.desired_pool_size,
+            pending_growth: 0,
+            shutdown: None,
         }
     }
 
+    /// Returns `true` once a shutdown has finished and
+    /// no more messages need to be processed
+    pub fn is_shut_down(&self) -> bool {
+        self.shutdown
+            .as_ref()
+            .map(|shutdown| shutdown.finished)
+            .unwrap_or(false)
+    }
+
     /// Process a PoolMessage
     pub fn process(&mut self, message: PoolMessage<T>) {
         let started_at = Instant::now();
@@ -108,10 +217,172 @@ where
             }
             PoolMessage::CleanupReservations(_) => {
                 self.cleanup_reservations();
+                if self.shutdown.is_some() {
+                    // Connections dropped without a value do not
+                    // return to the pool so we have to check here
+                    self.finish_shutdown_if_drained();
+                } else {
+                    self.close_idle_surplus();
+                    self.evict_idle_timed_out();
+                    self.replace_idle_expired();
+                    self.replace_idle_outdated();
+                }
                 self.instrumentation
                     .relevant_message_processed(started_at.elapsed());
             }
             PoolMessage::CheckAlive(_) => {}
+            PoolMessage::Reconfigure {
+                reconfiguration, ..
+            } => {
+                self.reconfigure(reconfiguration);
+                self.instrumentation
+                    .relevant_message_processed(started_at.elapsed());
+            }
+            PoolMessage::Shutdown { notify, .. } => {
+                self.shut_down(notify);
+                self.instrumentation
+                    .relevant_message_processed(started_at.elapsed());
+            }
+        }
+    }
+
+    fn reconfigure(&mut self, reconfiguration: Reconfiguration) {
+        debug!(
+            "[{}] reconfiguring: {:?}",
+            self.instrumentation.id, reconfiguration
+        );
+
+        if let Some(desired_pool_size) = reconfiguration.desired_pool_size {
+            self.set_desired_pool_size(desired_pool_size);
+        }
+
+        if let Some(reservation_limit) = reconfiguration.reservation_limit {
+            self.reservation_limit = reservation_limit;
+        }
+
+        if let Some(activation_order) = reconfiguration.activation_order {
+            self.idle.set_activation_order(activation_order);
+        }
+
+        if let Some(backoff_strategy) = reconfiguration.backoff_strategy {
+            self.factory.set_back_off_strategy(backoff_strategy);
+        }
+    }
+
+    /// Grows the pool immediately. Shrinking is done by
+    /// closing idle surplus connections.
+    fn set_desired_pool_size(&mut self, desired_pool_size: usize) {
+        self.desired_pool_size = desired_pool_size;
+        self.max_pool_size = self
+            .configured_max_pool_size
+            .map(|max| std::cmp::max(max, desired_pool_size))
+            .unwrap_or(desired_pool_size);
+        self.reserved_for_high_priority = reserved_for_high_priority(
+            self.configured_reserved_for_high_priority,
+            desired_pool_size,
+        );
+        if self.reserved_for_high_priority < self.configured_reserved_for_high_priority {
+            warn!(
+                "[{}] only {} of {} connections reserved for high priority checkouts \
+                 to leave connections for others",
+                self.instrumentation.id,
+                self.reserved_for_high_priority,
+                self.configured_reserved_for_high_priority
+            );
+        }
+
+        while self.pool_size < desired_pool_size {
+            self.pool_size += 1;
+            self.factory.clone().create_connection(Instant::now());
+        }
+
+        debug!(
+            "[{}] desired pool size is {} - current pool size is {}",
+            self.instrumentation.id, self.desired_pool_size, self.pool_size
+        );
+    }
+
+    /// Fail all reservations and close the idle connections.
+    ///
+    /// Connections returned from now on will be closed.
+    fn shut_down(&mut self, notify: oneshot::Sender<()>) {
+        if let Some(ref mut shutdown) = self.shutdown {
+            trace!("shutdown - already shutting down");
+            shutdown.notify.push(notify);
+            return;
+        }
+
+        debug!(
+            "[{}] shutting down - {} connections in flight",
+            self.instrumentation.id,
+            self.instrumentation.in_flight()
+        );
+
+        for reservation in self.reservations.drain() {
+            self.instrumentation.reservation_not_fulfilled(
+                reservation.created_at.elapsed(),
+                reservation.checkout_requested_at.elapsed(),
+                reservation.priority,
+            );
+            let _ = reservation
+                .sender
+                .send(Err(CheckoutErrorKind::ShuttingDown.into()));
+        }
+
+        let mut closing = Vec::new();
+        let idle: Vec<_> = self.idle.drain().collect();
+        for slot in idle {
+            self.instrumentation.idle_dec();
+            self.instrumentation
+                .connection_dropped(None, slot.conn.created_at.elapsed());
+            closing.push(self.close(slot.conn));
+        }
+
+        self.shutdown = Some(Shutdown {
+            notify: vec![notify],
+            closing,
+            finished: false,
+        });
+
+        self.finish_shutdown_if_drained();
+    }
+
+    /// Close the connection and orphanize it so that no new one gets created
+    fn close(&self, mut managed: Managed<T>) -> BoxFuture<'static, ()> {
+        let conn = managed.value.take();
+        managed.drop_orphanized();
+        match conn {
+            Some(conn) => self.factory.close(conn).boxed(),
+            None => future::ready(()).boxed(),
+        }
+    }
+
+    /// Notify the shutdown once all connections in flight have
+    /// been returned and all connections have been closed
+    fn finish_shutdown_if_drained(&mut self) {
+        if self.instrumentation.in_flight() > 0 {
+            return;
+        }
+
+        let shutdown = match self.shutdown {
+            Some(ref mut shutdown) if !shutdown.finished => shutdown,
+            _ => return,
+        };
+
+        shutdown.finished = true;
+        let closing = future::join_all(std::mem::take(&mut shutdown.closing));
+        let notify = std::mem::take(&mut shutdown.notify);
+        let notify_when_closed = async move {
+            closing.await;
+            notify.into_iter().for_each(|notify| {
+                let _ = notify.send(());
+            });
+        };
+        if let Err(err) = self.factory.executor.spawn(notify_when_closed) {
+            warn!(
+                "could not spawn closing of connections on shutdown: {}",
+                err
+            );
         }
     }
 
@@ -126,6 +397,36 @@ where
         } else {
             trace!("check in - new connection");
             self.instrumentation.checked_in_new_connection();
+            if managed.grows_pool {
+                managed.grows_pool = false;
+                self.pending_growth = self.pending_growth.saturating_sub(1);
+            }
+            managed.expires_at = self.expires_at(managed.created_at);
+        }
+
+        if self.shutdown.is_some() {
+            trace!("check in - shutting down - closing connection");
+            self.instrumentation.connection_dropped(
+                checked_out_at.map(|d| d.elapsed()),
+                managed.created_at.elapsed(),
+            );
+            let closing = self.close(managed);
+            if let Some(ref mut shutdown) = self.shutdown {
+                shutdown.closing.push(closing);
+            }
+            self.finish_shutdown_if_drained();
+            return;
+        }
+
+        if managed.is_expired() || managed.generation < self.factory.generation() {
+            trace!("check in - connection expired or outdated");
+            self.instrumentation.connection_dropped(
+                checked_out_at.map(|d| d.elapsed()),
+                managed.created_at.elapsed(),
+            );
+            managed.drop_orphanized();
+            self.factory.clone().create_connection(Instant::now());
+            return;
         }
 
         if self.reservations.is_empty() {
@@ -137,7 +438,15 @@ where
         } else {
             // Do not let this one get dropped!
             let mut ready_for_fulfillment = managed;
-            while let Some(one_waiting) = self.reservations.pop_front() {
+            ready_for_fulfillment.validate_on_check_out = self
+                .factory
+                .validation_policy
+                .validates_on_check_out(Duration::from_secs(0));
+            // Lower priorities must leave the connections
+            // reserved for high priority checkouts idle
+            let all_priorities = self.idle.len() >= self.reserved_for_high_priority;
+            while let Some(one_waiting) = self.reservations.pop_next(all_priorities) {
+                let priority = one_waiting.priority;
                 match one_waiting.try_fulfill(ready_for_fulfillment) {
                     Fulfillment::Fulfilled {
                         reservation_time,
@@ -149,8 +458,11 @@ where
                             Duration::from_secs(0),
                             time_since_checkout_request,
                         );
-                        self.instrumentation
-                            .reservation_fulfilled(reservation_time, time_since_checkout_request);
+                        self.instrumentation.reservation_fulfilled(
+                            reservation_time,
+                            time_since_checkout_request,
+                            priority,
+                        );
                         self.instrumentation.in_flight_inc();
 
                         return;
@@ -164,6 +476,7 @@ where
                         self.instrumentation.reservation_not_fulfilled(
                             reservation_time,
                             time_since_checkout_request,
+                            priority,
                         );
 
                         ready_for_fulfillment = conn;
@@ -184,9 +497,26 @@ where
             return;
         }
 
-        if let Some((mut managed, idle_since)) = self.get_idle() {
+        if self.shutdown.is_some() {
+            let _ = payload
+                .sender
+                .send(Err(CheckoutErrorKind::ShuttingDown.into()));
+            return;
+        }
+
+        let idle = if self.may_take_idle(payload.priority) {
+            self.get_idle()
+        } else {
+            None
+        };
+
+        if let Some((mut managed, idle_since)) = idle {
             trace!("check out - checking out idle connection");
             managed.checked_out_at = Some(Instant::now());
+            managed.validate_on_check_out = self
+                .factory
+                .validation_policy
+                .validates_on_check_out(idle_since);
 
             if let Err(Ok(not_send)) = payload.sender.send(Ok(managed)) {
                 // The sender is already closed. Put the connection back to
@@ -201,7 +531,11 @@ where
             trace!("check out - no idle connection");
 
             if payload.reservation_allowed {
-                self.create_reservation(payload.sender, payload.checkout_requested_at)
+                self.create_reservation(
+                    payload.sender,
+                    payload.checkout_requested_at,
+                    payload.priority,
+                )
             } else {
                 // There was no connection to delivery immediately...
                 let _ = payload
@@ -211,29 +545,173 @@ where
         }
     }
 
+    /// `true` if a checkout with the given priority may take an idle connection
+    fn may_take_idle(&self, priority: Priority) -> bool {
+        priority == Priority::High || self.idle.len() > self.reserved_for_high_priority
+    }
+
     fn create_reservation(
         &mut self,
         sender: oneshot::Sender<Result<Managed<T>, CheckoutError>>,
         checkout_requested_at: Instant,
+        priority: Priority,
     ) {
-        if self.reservations.capacity() == 0 {
+        if self.reservation_limit == 0 {
             let _ = sender.send(Err(CheckoutErrorKind::NoConnection.into()));
             return;
         }
 
-        if self.reservations.len() == self.reservations.capacity() {
+        if self.reservations.len() >= self.reservation_limit {
             self.cleanup_reservations();
-            if self.reservations.len() == self.reservations.capacity() {
-                self.instrumentation.reservation_limit_reached();
-                let _ = sender.send(Err(CheckoutErrorKind::ReservationLimitReached.into()));
-                return;
+            if self.reservations.len() >= self.reservation_limit {
+                // Make room by failing the most recent reservation with a lower
+                // priority so that low priority checkouts can not crowd out others
+                if let Some(displaced) = self.reservations.pop_lower_than(priority) {
+                    trace!("create reservation - displacing a reservation");
+                    self.instrumentation.reservation_not_fulfilled(
+                        displaced.created_at.elapsed(),
+                        displaced.checkout_requested_at.elapsed(),
+                        displaced.priority,
+                    );
+                    self.instrumentation
+                        .reservation_limit_reached(displaced.priority);
+                    let _ = displaced
+                        .sender
+                        .send(Err(CheckoutErrorKind::ReservationLimitReached.into()));
+                } else {
+                    self.instrumentation.reservation_limit_reached(priority);
+                    let _ = sender.send(Err(CheckoutErrorKind::ReservationLimitReached.into()));
+                    return;
+                }
             }
         }
 
-        let reservation = Reservation::new(sender, checkout_requested_at);
+        let reservation = Reservation::new(sender, checkout_requested_at, priority);
         self.reservations.push_back(reservation);
 
-        self.instrumentation.reservation_added();
+        self.instrumentation.reservation_added(priority);
+
+        self.grow_if_reservations_pile_up();
+    }
+
+    /// Determine when a connection created at `created_at` shall expire.
+    fn expires_at(&self, created_at: Instant) -> Option<Instant> {
+        let lifetime = self.max_connection_lifetime?;
+
+        let jitter_ms = self.connection_lifetime_jitter.as_millis() as u64;
+        let jitter = if jitter_ms > 0 {
+            Duration::from_millis(rand::thread_rng().gen_range(0, jitter_ms))
+        } else {
+            Duration::from_secs(0)
+        };
+
+        Some(created_at + lifetime.checked_sub(jitter).unwrap_or_default())
+    }
+
+    /// Request a new connection if there are more reservations than
+    /// connections already requested and the pool may still grow.
+    fn grow_if_reservations_pile_up(&mut self) {
+        if self.pool_size >= self.max_pool_size || self.reservations.len() <= self.pending_growth {
+            return;
+        }
+
+        self.pool_size += 1;
+        self.pending_growth += 1;
+        debug!(
+            "[{}] growing pool to {} connections",
+            self.instrumentation.id, self.pool_size
+        );
+        self.factory
+            .clone()
+            .create_connection_to_grow_pool(Instant::now());
+    }
+
+    /// Close idle connections exceeding `desired_pool_size`
+    /// which have been idle for at least `surplus_idle_timeout`.
+    fn close_idle_surplus(&mut self) {
+        if self.pool_size <= self.desired_pool_size {
+            return;
+        }
+
+        let surplus = self.pool_size - self.desired_pool_size;
+        let expired = self.idle.remove_expired(self.surplus_idle_timeout, surplus);
+        for slot in expired {
+            self.pool_size -= 1;
+            self.instrumentation.idle_dec();
+            self.instrumentation
+                .connection_dropped(None, slot.conn.created_at.elapsed());
+            slot.conn.drop_orphanized();
+        }
+
+        trace!("shrunk pool to {} connections", self.pool_size);
+    }
+
+    /// Close connections which have been idle for longer than `idle_timeout`
+    /// and request a new connection for each of them unless the pool
+    /// exceeds `desired_pool_size`.
+    fn evict_idle_timed_out(&mut self) {
+        let idle_timeout = if let Some(idle_timeout) = self.idle_timeout {
+            idle_timeout
+        } else {
+            return;
+        };
+
+        let expired = self.idle.remove_expired(idle_timeout, self.idle.len());
+        for slot in expired {
+            trace!("evicting idle connection");
+            self.instrumentation.idle_dec();
+            self.instrumentation
+                .connection_dropped(None, slot.conn.created_at.elapsed());
+            self.instrumentation
+                .idle_connection_evicted(slot.idle_since.elapsed());
+            slot.conn.drop_orphanized();
+            if self.pool_size > self.desired_pool_size {
+                // Surplus connections are not replaced so that the pool shrinks
+                self.pool_size -= 1;
+            } else {
+                self.factory.clone().create_connection(Instant::now());
+            }
+        }
+    }
+
+    /// Close idle connections which expired and request a
+    /// new connection for each of them.
+    ///
+    /// Otherwise connections which are never checked out would never be
+    /// recycled since this is usually done on check in.
+    fn replace_idle_expired(&mut self) {
+        let expired = self.idle.remove_where(|managed| managed.is_expired());
+        for slot in expired {
+            trace!("replacing expired idle connection");
+            self.instrumentation.idle_dec();
+            self.instrumentation
+                .connection_dropped(None, slot.conn.created_at.elapsed());
+            slot.conn.drop_orphanized();
+            self.factory.clone().create_connection(Instant::now());
+        }
+    }
+
+    /// Close at most `OUTDATED_REPLACED_PER_CLEANUP` idle connections of an
+    /// older generation and request a new connection for each of them.
+    fn replace_idle_outdated(&mut self) {
+        let generation = self.factory.generation();
+        let mut remaining = OUTDATED_REPLACED_PER_CLEANUP;
+        let outdated = self.idle.remove_where(|managed| {
+            if remaining > 0 && managed.generation < generation {
+                remaining -= 1;
+                true
+            } else {
+                false
+            }
+        });
+        for slot in outdated {
+            trace!("replacing outdated idle connection");
+            self.instrumentation.idle_dec();
+            self.instrumentation
+                .connection_dropped(None, slot.conn.created_at.elapsed());
+            slot.conn.drop_orphanized();
+            self.factory.clone().create_connection(Instant::now());
+        }
     }
 
     pub fn get_idle(&mut self) -> Option<(Managed<T>, Duration)> {
@@ -258,7 +736,7 @@ where
             return;
         }
 
-        let cleanup_necessary = self.reservations.len() == self.reservations.capacity()
+        let cleanup_necessary = self.reservations.len() >= self.reservation_limit
             || self.last_cleanup.elapsed() > CLEANUP_INTERVAL;
 
         if cleanup_necessary {
@@ -268,6 +746,7 @@ where
                     instrumentation.reservation_not_fulfilled(
                         reservation.created_at.elapsed(),
                         reservation.checkout_requested_at.elapsed(),
+                        reservation.priority,
                     );
                     return false;
                 }
@@ -311,7 +790,78 @@ impl<T: Poolable> Drop for InnerPool<T> {
     }
 }
 
-/// ===== RESERVATION =====
+// ===== RESERVATION =====
+
+/// The reservations waiting for a connection with a lane for each `Priority`.
+///
+/// Higher priorities are fulfilled first. Within a lane reservations
+/// are fulfilled in the order they were made.
+pub(super) struct Reservations<T: Poolable> {
+    /// Ordered like `Priority::ALL` from the highest to the lowest priority
+    lanes: [VecDeque<Reservation<T>>; 3],
+}
+
+impl<T: Poolable> Reservations<T> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        // Most checkouts are made with the default priority
+        Reservations {
+            lanes: [
+                VecDeque::new(),
+                VecDeque::with_capacity(capacity),
+                VecDeque::new(),
+            ],
+        }
+    }
+
+    fn lane(priority: Priority) -> usize {
+        match priority {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        }
+    }
+
+    /// The number of reservations of all priorities
+    pub fn len(&self) -> usize {
+        self.lanes.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lanes.iter().all(VecDeque::is_empty)
+    }
+
+    pub fn push_back(&mut self, reservation: Reservation<T>) {
+        self.lanes[Self::lane(reservation.priority)].push_back(reservation);
+    }
+
+    /// Removes the reservation to be fulfilled next.
+    ///
+    /// Only reservations with `Priority::High` are considered
+    /// unless `all_priorities` is `true`.
+    pub fn pop_next(&mut self, all_priorities: bool) -> Option<Reservation<T>> {
+        let lanes = if all_priorities { self.lanes.len() } else { 1 };
+        self.lanes[..lanes].iter_mut().find_map(VecDeque::pop_front)
+    }
+
+    /// Removes the most recent reservation of the lowest
+    /// priority below the given priority
+    pub fn pop_lower_than(&mut self, priority: Priority) -> Option<Reservation<T>> {
+        let lane = Self::lane(priority);
+        self.lanes[lane + 1..]
+            .iter_mut()
+            .rev()
+            .find_map(VecDeque::pop_back)
+    }
+
+    pub fn retain<F: FnMut(&Reservation<T>) -> bool>(&mut self, mut f: F) {
+        self.lanes.iter_mut().for_each(|lane| lane.retain(&mut f));
+    }
+
+    /// Removes all reservations in the order they would have been fulfilled
+    pub fn drain(&mut self) -> impl Iterator<Item = Reservation<T>> + '_ {
+        self.lanes.iter_mut().flat_map(|lane| lane.drain(..))
+    }
+}
 
 /// A reservations waits for a connection to be checked in so that
 /// the reservation can be fulfilled
@@ -322,17 +872,21 @@ pub(super) struct Reservation<T: Poolable> {
     pub created_at: Instant,
     /// The Instant the initial checkout was created at
     pub checkout_requested_at: Instant,
+    /// The priority of the checkout
+    pub priority: Priority,
 }
 
 impl<T: Poolable> Reservation<T> {
     pub fn new(
         sender: oneshot::Sender<Result<Managed<T>, CheckoutError>>,
         checkout_requested_at: Instant,
+        priority: Priority,
     ) -> Self {
         Reservation {
             sender,
             created_at: Instant::now(),
             checkout_requested_at,
+            priority,
         }
     }
 }
@@ -359,6 +913,7 @@ impl<T: Poolable> Reservation<T> {
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 pub(super) enum Fulfillment<T: Poolable> {
     Fulfilled {
         /// The time it took until the reservation was processed
@@ -423,6 +978,21 @@ impl<T> IdleConnections<T> {
         .map(|IdleSlot { conn, idle_since }| (conn, idle_since.elapsed()))
     }
 
+    /// Keeps the idle connections. The connections idle
+    /// for the longest time stay at the front.
+    pub fn set_activation_order(&mut self, activation_order: ActivationOrder) {
+        let current = std::mem::replace(self, IdleConnections::LiFo(Vec::new()));
+        *self = match (current, activation_order) {
+            (IdleConnections::FiFo(idle), ActivationOrder::LiFo) => {
+                IdleConnections::LiFo(idle.into())
+            }
+            (IdleConnections::LiFo(idle), ActivationOrder::FiFo) => {
+                IdleConnections::FiFo(idle.into())
+            }
+            (current, _) => current,
+        };
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         match self {
@@ -431,6 +1001,41 @@ impl<T> IdleConnections<T> {
         }
     }
 
+    /// Removes at most `limit` connections which have been idle for at least `timeout`.
+    ///
+    /// The connections idle for the longest time are always at the front.
+    pub fn remove_expired(&mut self, timeout: Duration, limit: usize) -> Vec<IdleSlot<T>> {
+        let is_expired = |slot: &&IdleSlot<T>| slot.idle_since.elapsed() >= timeout;
+        match self {
+            IdleConnections::FiFo(idle) => {
+                let n = idle.iter().take(limit).take_while(is_expired).count();
+                idle.drain(..n).collect()
+            }
+            IdleConnections::LiFo(idle) => {
+                let n = idle.iter().take(limit).take_while(is_expired).count();
+                idle.drain(..n).collect()
+            }
+        }
+    }
+
+    /// Removes all connections for which `f` returns `true`
+    pub fn remove_where<F: FnMut(&T) -> bool>(&mut self, mut f: F) -> Vec<IdleSlot<T>> {
+        match self {
+            IdleConnections::FiFo(idle) => {
+                let (removed, kept): (VecDeque<_>, VecDeque<_>) =
+                    idle.drain(..).partition(|slot| f(&slot.conn));
+                *idle = kept;
+                removed.into()
+            }
+            IdleConnections::LiFo(idle) => {
+                let (removed, kept): (Vec<_>, Vec<_>) =
+                    idle.drain(..).partition(|slot| f(&slot.conn));
+                *idle = kept;
+                removed
+            }
+        }
+    }
+
     pub fn drain<'a>(&'a mut self) -> impl Iterator<Item = IdleSlot<T>> + 'a {
         match self {
             IdleConnections::FiFo(ref mut idle) => Box::new(idle.drain(..)),
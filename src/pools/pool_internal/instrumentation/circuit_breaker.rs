@@ -0,0 +1,178 @@
+//! Keeps track of whether a node keeps failing
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::CircuitState;
+
+/// A change of the `CircuitState` from the first to the second one
+pub(crate) type Transition = (CircuitState, CircuitState);
+
+/// The circuit breaker of a single pool.
+///
+/// The circuit opens after `threshold` consecutive failures. Once it has
+/// been open for `open_duration` it becomes half open. Then the next
+/// success closes it and the next failure opens it again.
+///
+/// A `threshold` of zero disables the circuit breaker.
+pub(crate) struct CircuitBreaker {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    threshold: usize,
+    open_duration: Duration,
+    state: CircuitState,
+    consecutive_failures: usize,
+    opened_at: Instant,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: usize, open_duration: Duration) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                threshold,
+                open_duration,
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                opened_at: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn state(&self) -> (CircuitState, Option<Transition>) {
+        let mut inner = self.inner.lock().unwrap();
+        let transition = inner.half_open_if_elapsed();
+        (inner.state, transition)
+    }
+
+    /// Successes while the circuit is open and not yet half open
+    /// are ignored.
+    pub fn success(&self) -> Option<Transition> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state == CircuitState::Open && inner.opened_at.elapsed() < inner.open_duration {
+            return None;
+        }
+
+        inner.consecutive_failures = 0;
+        inner.transition_to(CircuitState::Closed)
+    }
+
+    pub fn failure(&self) -> Option<Transition> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.threshold == 0 {
+            return None;
+        }
+
+        inner.consecutive_failures += 1;
+
+        match inner.state {
+            CircuitState::Closed if inner.consecutive_failures >= inner.threshold => {
+                inner.opened_at = Instant::now();
+                inner.transition_to(CircuitState::Open)
+            }
+            CircuitState::HalfOpen => {
+                inner.opened_at = Instant::now();
+                inner.transition_to(CircuitState::Open)
+            }
+            CircuitState::Open if inner.opened_at.elapsed() >= inner.open_duration => {
+                // It would have been half open and the failure opens it again
+                inner.opened_at = Instant::now();
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Changing the threshold resets the count of consecutive failures.
+    /// A disabled circuit breaker is closed.
+    pub fn configure(&self, threshold: usize, open_duration: Duration) -> Option<Transition> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.open_duration = open_duration;
+        if inner.threshold != threshold {
+            inner.threshold = threshold;
+            inner.consecutive_failures = 0;
+        }
+        if threshold == 0 {
+            inner.transition_to(CircuitState::Closed)
+        } else {
+            None
+        }
+    }
+}
+
+impl Inner {
+    fn half_open_if_elapsed(&mut self) -> Option<Transition> {
+        if self.state == CircuitState::Open && self.opened_at.elapsed() >= self.open_duration {
+            self.transition_to(CircuitState::HalfOpen)
+        } else {
+            None
+        }
+    }
+
+    fn transition_to(&mut self, state: CircuitState) -> Option<Transition> {
+        if self.state == state {
+            return None;
+        }
+
+        let transition = (self.state, state);
+        self.state = state;
+        Some(transition)
+    }
+}
+
+#[test]
+fn the_circuit_opens_after_consecutive_failures() {
+    let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+
+    assert_eq!(breaker.failure(), None);
+    assert_eq!(breaker.success(), None);
+    assert_eq!(breaker.failure(), None);
+    assert_eq!(
+        breaker.failure(),
+        Some((CircuitState::Closed, CircuitState::Open))
+    );
+    assert_eq!(breaker.state(), (CircuitState::Open, None));
+}
+
+#[test]
+fn a_half_open_circuit_is_closed_by_a_success() {
+    let breaker = CircuitBreaker::new(1, Duration::from_secs(0));
+
+    assert_eq!(
+        breaker.failure(),
+        Some((CircuitState::Closed, CircuitState::Open))
+    );
+    assert_eq!(
+        breaker.state(),
+        (
+            CircuitState::HalfOpen,
+            Some((CircuitState::Open, CircuitState::HalfOpen))
+        )
+    );
+    assert_eq!(
+        breaker.success(),
+        Some((CircuitState::HalfOpen, CircuitState::Closed))
+    );
+}
+
+#[test]
+fn a_success_does_not_close_an_open_circuit_before_it_is_half_open() {
+    let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+
+    assert_eq!(
+        breaker.failure(),
+        Some((CircuitState::Closed, CircuitState::Open))
+    );
+    assert_eq!(breaker.success(), None);
+    assert_eq!(breaker.state(), (CircuitState::Open, None));
+}
+
+#[test]
+fn a_threshold_of_zero_disables_the_circuit_breaker() {
+    let breaker = CircuitBreaker::new(0, Duration::from_secs(60));
+
+    for _ in 0..10 {
+        assert_eq!(breaker.failure(), None);
+    }
+    assert_eq!(breaker.state(), (CircuitState::Closed, None));
+}
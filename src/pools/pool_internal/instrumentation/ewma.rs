@@ -0,0 +1,58 @@
+//! An exponentially weighted moving average of latencies
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The weight of a new sample
+const SMOOTHING: f64 = 0.2;
+
+/// Marks that there was no sample yet. Never the bits of a valid average.
+const NO_SAMPLE: u64 = u64::MAX;
+
+/// An exponentially weighted moving average of latencies
+/// which can be updated concurrently
+pub(crate) struct Ewma {
+    /// The bits of an `f64` of nanoseconds
+    nanos: AtomicU64,
+}
+
+impl Ewma {
+    pub fn new() -> Self {
+        Self {
+            nanos: AtomicU64::new(NO_SAMPLE),
+        }
+    }
+
+    pub fn add(&self, sample: Duration) {
+        let sample = sample.as_nanos() as f64;
+        let _ = self
+            .nanos
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+                let next = if current == NO_SAMPLE {
+                    sample
+                } else {
+                    SMOOTHING * sample + (1.0 - SMOOTHING) * f64::from_bits(current)
+                };
+                Some(next.to_bits())
+            });
+    }
+
+    /// Returns `None` if there was no sample yet
+    pub fn get(&self) -> Option<Duration> {
+        match self.nanos.load(Ordering::SeqCst) {
+            NO_SAMPLE => None,
+            bits => Some(Duration::from_nanos(f64::from_bits(bits) as u64)),
+        }
+    }
+}
+
+#[test]
+fn the_average_moves_towards_new_samples() {
+    let ewma = Ewma::new();
+    assert_eq!(ewma.get(), None);
+
+    ewma.add(Duration::from_millis(10));
+    assert_eq!(ewma.get(), Some(Duration::from_millis(10)));
+
+    ewma.add(Duration::from_millis(20));
+    assert_eq!(ewma.get(), Some(Duration::from_millis(12)));
+}
@@ -1,12 +1,20 @@
 //! Pluggable instrumentation
+use std::panic::Location;
 use std::sync::{
     atomic::{AtomicUsize, Ordering},
-    Arc,
+    Arc, Mutex,
 };
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use crate::error::{CheckoutError, CheckoutErrorKind, ConnectionFailure};
 use crate::instrumentation::{Instrumentation, InstrumentationFlavour, PoolId};
-use crate::PoolState;
+use crate::{CircuitState, PoolState, Priority};
+
+use self::circuit_breaker::{CircuitBreaker, Transition};
+use self::ewma::Ewma;
+
+mod circuit_breaker;
+mod ewma;
 
 /// Instrumentation for a single pool
 ///
@@ -22,6 +30,14 @@ pub(crate) struct PoolInstrumentation {
     connections: Arc<AtomicUsize>,
     idle: Arc<AtomicUsize>,
     pools: Arc<AtomicUsize>,
+    /// Checkout, connect and ping times
+    latency: Arc<Ewma>,
+    /// Driven by failures to create connections and broken connections
+    circuit_breaker: Arc<CircuitBreaker>,
+    connection_factory_failures: Arc<AtomicUsize>,
+    last_connection_created_at: Arc<Mutex<Option<Instant>>>,
+    /// Reset once a connection was created
+    last_connection_error: Arc<Mutex<Option<ConnectionFailure>>>,
 }
 
 impl PoolInstrumentation {
@@ -34,6 +50,11 @@ impl PoolInstrumentation {
             reservations: Arc::new(AtomicUsize::new(0)),
             connections: Arc::new(AtomicUsize::new(0)),
             idle: Arc::new(AtomicUsize::new(0)),
+            latency: Arc::new(Ewma::new()),
+            circuit_breaker: Arc::new(CircuitBreaker::new(0, Duration::from_secs(0))),
+            connection_factory_failures: Arc::new(AtomicUsize::new(0)),
+            last_connection_created_at: Arc::new(Mutex::new(None)),
+            last_connection_error: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -44,6 +65,7 @@ impl PoolInstrumentation {
             connections: self.connections.load(Ordering::SeqCst),
             idle: self.idle.load(Ordering::SeqCst),
             pools: self.pools.load(Ordering::SeqCst),
+            open_circuits: (self.circuit_state() == CircuitState::Open) as usize,
         }
     }
 
@@ -51,6 +73,71 @@ impl PoolInstrumentation {
         self.in_flight.load(Ordering::SeqCst)
     }
 
+    /// The exponentially weighted moving average of the checkout,
+    /// connect and ping times. `None` if nothing was measured yet.
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency.get()
+    }
+
+    /// The number of failed attempts to create a connection
+    pub fn connection_factory_failures(&self) -> usize {
+        self.connection_factory_failures.load(Ordering::SeqCst)
+    }
+
+    /// The time since the last connection was created
+    pub fn since_last_connection(&self) -> Option<Duration> {
+        self.last_connection_created_at
+            .lock()
+            .unwrap()
+            .map(|at| at.elapsed())
+    }
+
+    /// Add the state of this pool to the error of a failed checkout
+    pub fn checkout_failed(
+        &self,
+        mut error: CheckoutError,
+        checkout_requested_at: Instant,
+    ) -> CheckoutError {
+        error.pool = Some(self.id);
+        error.waited = Some(checkout_requested_at.elapsed());
+        error.reservations = Some(self.reservations.load(Ordering::SeqCst));
+        if let CheckoutErrorKind::NoConnection | CheckoutErrorKind::NoPool = error.kind() {
+            error.last_connection_error = self.last_connection_error.lock().unwrap().clone();
+        }
+        error
+    }
+
+    /// The state of the circuit breaker. Open circuits become half open
+    /// once they have been open for long enough.
+    pub fn circuit_state(&self) -> CircuitState {
+        let (state, transition) = self.circuit_breaker.state();
+        self.circuit_state_changed(transition);
+        state
+    }
+
+    /// A `threshold` of zero disables the circuit breaker
+    pub fn configure_circuit_breaker(&self, threshold: usize, open_duration: Duration) {
+        let transition = self.circuit_breaker.configure(threshold, open_duration);
+        self.circuit_state_changed(transition);
+    }
+
+    /// A connection was found to be broken
+    pub fn connection_broken(&self) {
+        let transition = self.circuit_breaker.failure();
+        self.circuit_state_changed(transition);
+    }
+
+    fn circuit_state_changed(&self, transition: Option<Transition>) {
+        if let Some((from, to)) = transition {
+            self.flavour.circuit_state_changed(from, to, self.id)
+        }
+    }
+
+    /// A ping on the node of this pool succeeded
+    pub fn ping_succeeded(&self, latency: Duration) {
+        self.latency.add(latency);
+    }
+
     pub fn pool_added(&self) {
         self.pools.fetch_add(1, Ordering::SeqCst);
         self.flavour.pool_added(self.id)
@@ -71,6 +158,7 @@ impl PoolInstrumentation {
         idle_for: Duration,
         time_since_checkout_request: Duration,
     ) {
+        self.latency.add(time_since_checkout_request);
         self.flavour
             .checked_out_connection(idle_for, time_since_checkout_request, self.id)
     }
@@ -89,7 +177,16 @@ impl PoolInstrumentation {
             .connection_dropped(flight_time, lifetime, self.id)
     }
 
+    pub fn idle_connection_evicted(&self, idle_for: Duration) {
+        self.flavour.idle_connection_evicted(idle_for, self.id)
+    }
+
     pub fn connection_created(&self, connected_after: Duration, total_time: Duration) {
+        self.latency.add(total_time);
+        *self.last_connection_created_at.lock().unwrap() = Some(Instant::now());
+        *self.last_connection_error.lock().unwrap() = None;
+        let transition = self.circuit_breaker.success();
+        self.circuit_state_changed(transition);
         self.flavour
             .connection_created(connected_after, total_time, self.id)
     }
@@ -112,34 +209,49 @@ impl PoolInstrumentation {
         self.flavour.in_flight_dec(self.id)
     }
 
-    pub fn reservation_added(&self) {
+    pub fn reservation_added(&self, priority: Priority) {
         self.reservations.fetch_add(1, Ordering::SeqCst);
-        self.flavour.reservation_added(self.id)
+        self.flavour.reservation_added(priority, self.id)
     }
     pub fn reservation_fulfilled(
         &self,
         reservation_time: Duration,
         checkout_request_time: Duration,
+        priority: Priority,
     ) {
         self.reservations.fetch_sub(1, Ordering::SeqCst);
-        self.flavour
-            .reservation_fulfilled(reservation_time, checkout_request_time, self.id)
+        self.flavour.reservation_fulfilled(
+            reservation_time,
+            checkout_request_time,
+            priority,
+            self.id,
+        )
     }
 
     pub fn reservation_not_fulfilled(
         &self,
         reservation_time: Duration,
         checkout_request_time: Duration,
+        priority: Priority,
     ) {
         self.reservations.fetch_sub(1, Ordering::SeqCst);
-        self.flavour
-            .reservation_not_fulfilled(reservation_time, checkout_request_time, self.id)
+        self.flavour.reservation_not_fulfilled(
+            reservation_time,
+            checkout_request_time,
+            priority,
+            self.id,
+        )
     }
 
-    pub fn reservation_limit_reached(&self) {
-        self.flavour.reservation_limit_reached(self.id)
+    pub fn reservation_limit_reached(&self, priority: Priority) {
+        self.flavour.reservation_limit_reached(priority, self.id)
     }
-    pub fn connection_factory_failed(&self) {
+    pub fn connection_factory_failed(&self, failure: ConnectionFailure) {
+        self.connection_factory_failures
+            .fetch_add(1, Ordering::SeqCst);
+        *self.last_connection_error.lock().unwrap() = Some(failure);
+        let transition = self.circuit_breaker.failure();
+        self.circuit_state_changed(transition);
         self.flavour.connection_factory_failed(self.id)
     }
 
@@ -155,4 +267,13 @@ impl PoolInstrumentation {
         self.flavour
             .relevant_message_processed(processing_time, self.id)
     }
+
+    pub fn connection_leak_suspected(
+        &self,
+        held_for: Duration,
+        checked_out_at: &'static Location<'static>,
+    ) {
+        self.flavour
+            .connection_leak_suspected(held_for, checked_out_at, self.id)
+    }
 }
@@ -0,0 +1,135 @@
+//! Keeps track of the connections checked out of a pool to find leaks
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The connections checked out of a single pool
+pub(crate) struct LeakDetector {
+    /// If `None` no connections are watched
+    threshold: Option<Duration>,
+    next_id: AtomicU64,
+    checked_out: Mutex<HashMap<u64, CheckedOut>>,
+}
+
+struct CheckedOut {
+    at: Instant,
+    location: &'static Location<'static>,
+    /// Only captured if enabled via `Config::capture_leak_backtraces`
+    backtrace: Option<Arc<Backtrace>>,
+    /// Each leak is reported only once
+    reported: bool,
+}
+
+/// A connection held for longer than the threshold
+pub(crate) struct Suspect {
+    pub held_for: Duration,
+    /// Where the connection was checked out
+    pub location: &'static Location<'static>,
+    backtrace: Option<Arc<Backtrace>>,
+}
+
+impl Suspect {
+    /// The backtrace if one was captured
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_deref()
+    }
+}
+
+impl LeakDetector {
+    pub fn new(threshold: Option<Duration>) -> Self {
+        Self {
+            threshold,
+            next_id: AtomicU64::new(0),
+            checked_out: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start watching a connection checked out at `location`
+    /// until the returned `Watched` is dropped.
+    ///
+    /// The `backtrace` must have been captured where the connection
+    /// was checked out. Returns `None` if leak detection is disabled.
+    pub fn watch(
+        self: &Arc<Self>,
+        location: &'static Location<'static>,
+        backtrace: Option<Arc<Backtrace>>,
+    ) -> Option<Watched> {
+        self.threshold?;
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let checked_out = CheckedOut {
+            at: Instant::now(),
+            location,
+            backtrace,
+            reported: false,
+        };
+        self.checked_out.lock().unwrap().insert(id, checked_out);
+
+        Some(Watched {
+            id,
+            detector: Arc::clone(self),
+        })
+    }
+
+    /// The connections held for longer than the threshold
+    /// which have not been returned as suspects before
+    pub fn suspects(&self) -> Vec<Suspect> {
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return Vec::new(),
+        };
+
+        self.checked_out
+            .lock()
+            .unwrap()
+            .values_mut()
+            .filter(|checked_out| !checked_out.reported && checked_out.at.elapsed() > threshold)
+            .map(|checked_out| {
+                checked_out.reported = true;
+                Suspect {
+                    held_for: checked_out.at.elapsed(),
+                    location: checked_out.location,
+                    backtrace: checked_out.backtrace.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// A connection watched by a `LeakDetector`. It is no
+/// longer watched once this is dropped.
+pub(crate) struct Watched {
+    id: u64,
+    detector: Arc<LeakDetector>,
+}
+
+impl Drop for Watched {
+    fn drop(&mut self) {
+        self.detector.checked_out.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[test]
+fn connections_held_for_too_long_are_suspected_once() {
+    let detector = Arc::new(LeakDetector::new(Some(Duration::from_millis(5))));
+
+    let watched = detector.watch(Location::caller(), None).unwrap();
+    assert!(detector.suspects().is_empty());
+
+    std::thread::sleep(Duration::from_millis(10));
+    assert_eq!(detector.suspects().len(), 1);
+    assert!(detector.suspects().is_empty());
+
+    drop(watched);
+    assert!(detector.checked_out.lock().unwrap().is_empty());
+}
+
+#[test]
+fn nothing_is_watched_if_leak_detection_is_disabled() {
+    let detector = Arc::new(LeakDetector::new(None));
+
+    assert!(detector.watch(Location::caller(), None).is_none());
+}
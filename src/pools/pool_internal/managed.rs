@@ -1,12 +1,16 @@
+use std::backtrace::Backtrace;
+use std::panic::Location;
+use std::sync::Arc;
 use std::time::Instant;
 
-use log::{debug, trace};
+use log::{debug, trace, warn};
 
 use crate::Poolable;
 
 use super::inner_pool::PoolMessage;
 
 use super::extended_connection_factory::ExtendedConnectionFactory;
+use super::leak_detector::Watched;
 
 /// Contains a connection. This is the essential part of `Reool`.
 ///
@@ -21,6 +25,17 @@ pub(crate) struct Managed<T: Poolable> {
     pub created_at: Instant,
     /// Is `Some` taken from the pool otherwise fresh connection
     pub checked_out_at: Option<Instant>,
+    /// If `Some` the connection may not return to the pool
+    /// once this `Instant` has passed
+    pub expires_at: Option<Instant>,
+    /// If `true` the connection has to be validated before
+    /// it is handed out to the client
+    pub validate_on_check_out: bool,
+    /// The generation of the factory when the connection was created
+    pub generation: u64,
+    /// If `true` the connection was requested to grow the pool
+    /// and has not yet arrived at the pool
+    pub grows_pool: bool,
     /// The actual connection. If `None` this
     /// `Managed` may not return to the pool and
     /// a new connection shall be created
@@ -36,6 +51,10 @@ impl<T: Poolable> Managed<T> {
             value: Some(value),
             created_at: Instant::now(),
             checked_out_at: None,
+            expires_at: None,
+            validate_on_check_out: false,
+            generation: factory.generation(),
+            grows_pool: false,
             factory: Some(factory),
         }
     }
@@ -47,6 +66,32 @@ impl<T: Poolable> Managed<T> {
             .connected_to()
     }
 
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at <= Instant::now())
+            .unwrap_or(false)
+    }
+
+    /// Report a failure of the connection to the circuit breaker of the node
+    pub fn connection_broken(&self) {
+        if let Some(factory) = self.factory.as_ref() {
+            factory.instrumentation.connection_broken();
+        }
+    }
+
+    /// Watch the connection for being held too long by the client.
+    ///
+    /// Returns `None` if leak detection is disabled.
+    pub fn watch_for_leak(
+        &self,
+        location: &'static Location<'static>,
+        backtrace: Option<Arc<Backtrace>>,
+    ) -> Option<Watched> {
+        self.factory
+            .as_ref()
+            .and_then(|factory| factory.leak_detector().watch(location, backtrace))
+    }
+
     /// This must be called before finally dropping a connection
     /// to prevent an infinite loop when dropping
     pub fn drop_orphanized(mut self) {
@@ -64,23 +109,25 @@ impl<T: Poolable> Drop for Managed<T> {
         }
 
         let factory = self.factory.take().unwrap();
-        let mut send_back = factory.send_back_cloned();
         if let Some(value) = self.value.take() {
-            let msg = PoolMessage::CheckIn {
-                created_at: Instant::now(),
-                conn: Managed {
-                    value: Some(value),
-                    created_at: self.created_at,
-                    checked_out_at: self.checked_out_at,
-                    factory: Some(factory), // Keeps it active
-                },
+            let managed = Managed {
+                value: Some(value),
+                created_at: self.created_at,
+                checked_out_at: self.checked_out_at,
+                expires_at: self.expires_at,
+                validate_on_check_out: false,
+                generation: self.generation,
+                grows_pool: self.grows_pool,
+                factory: Some(factory), // Keeps it active
             };
-            if let Err(msg) = msg.send_on_internal_channel(&mut send_back) {
-                debug!("inner pool gone - simply dropping");
-                // We must "orphanize" the connection to avoid a drop loop
-                drop_connection_orphanized(msg);
+            if managed.checked_out_at.is_some()
+                && factory_of(&managed)
+                    .validation_policy
+                    .validates_on_check_in()
+            {
+                validate_and_send_to_pool(managed);
             } else {
-                debug!("sent connection to pool");
+                send_to_pool(managed);
             }
         } else {
             factory.instrumentation.connection_dropped(
@@ -101,6 +148,70 @@ impl<T: Poolable> Drop for Managed<T> {
     }
 }
 
+fn factory_of<T: Poolable>(managed: &Managed<T>) -> &ExtendedConnectionFactory<T> {
+    managed
+        .factory
+        .as_ref()
+        .expect("no factory in managed - this is a bug")
+}
+
+/// Send the connection back to the pool via the internal channel
+fn send_to_pool<T: Poolable>(managed: Managed<T>) {
+    let send_back = factory_of(&managed).send_back_cloned();
+    let msg = PoolMessage::CheckIn {
+        created_at: Instant::now(),
+        conn: managed,
+    };
+    if let Err(msg) = msg.send_on_internal_channel(&send_back) {
+        debug!("inner pool gone - simply dropping");
+        // We must "orphanize" the connection to avoid a drop loop
+        drop_connection_orphanized(msg);
+    } else {
+        debug!("sent connection to pool");
+    }
+}
+
+/// Validate the connection before it is sent back to the pool.
+///
+/// If the validation fails the connection is dropped without a value
+/// which triggers the creation of a new connection. If the validation
+/// can not be spawned the connection is sent back without validation.
+fn validate_and_send_to_pool<T: Poolable>(mut managed: Managed<T>) {
+    let handle = match factory_of(&managed).executor.handle() {
+        Ok(handle) => handle,
+        Err(err) => {
+            warn!(
+                "could not spawn validation - sending back unvalidated: {}",
+                err
+            );
+            send_to_pool(managed);
+            return;
+        }
+    };
+
+    let conn = managed
+        .value
+        .take()
+        .expect("no value in managed - this is a bug");
+    let factory = factory_of(&managed);
+    let validation = factory.validate(conn, Instant::now() + factory.validation_timeout());
+    let f = async move {
+        match validation.await {
+            Ok(conn) => {
+                managed.value = Some(conn);
+                send_to_pool(managed);
+            }
+            Err(()) => {
+                debug!("returned connection failed validation");
+                managed.connection_broken();
+                drop(managed);
+            }
+        }
+    };
+
+    handle.spawn(f);
+}
+
 /// Drops the connection stored in a message. Even though in the context of `Managed`
 /// there can only be one type of message we try all of them to prevent future errors.
 ///
@@ -112,6 +223,8 @@ fn drop_connection_orphanized<T: Poolable>(msg: PoolMessage<T>) {
         PoolMessage::CheckOut { .. } => return,
         PoolMessage::CleanupReservations(_) => return,
         PoolMessage::CheckAlive(_) => return,
+        PoolMessage::Reconfigure { .. } => return,
+        PoolMessage::Shutdown { .. } => return,
     };
 
     conn.drop_orphanized()
@@ -1,53 +1,70 @@
 use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Instant;
-
-use futures::{
-    future::{self, Future},
-    stream::Stream,
-    Poll,
-};
-use log::{error, trace};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::future::{self, BoxFuture, FutureExt, TryFutureExt};
+use log::{debug, trace, warn};
 use tokio::sync::{mpsc, oneshot};
-use tokio::{
-    self,
-    timer::{Interval, Timeout},
-};
+use tokio::{self, time};
 
 use crate::activation_order::ActivationOrder;
 use crate::backoff_strategy::BackoffStrategy;
-use crate::connection_factory::ConnectionFactory;
+use crate::config::Reconfiguration;
+use crate::connection_factory::{ConnectionFactory, ConnectionInitializer};
 use crate::error::{CheckoutError, CheckoutErrorKind};
 use crate::executor_flavour::*;
+use crate::health::NodeHealth;
 #[cfg(test)]
-use crate::instrumentation::{InstrumentationFlavour, PoolId};
-use crate::PoolState;
-use crate::{Ping, Poolable};
+use crate::instrumentation::InstrumentationFlavour;
+use crate::instrumentation::PoolId;
+use crate::validation_policy::ValidationPolicy;
+use crate::{CircuitState, NodeState, PoolState};
+use crate::{Ping, Poolable, Priority};
 
 use super::CheckoutConstraint;
 use inner_pool::{CheckoutPayload, InnerPool, PoolMessage};
 
 mod extended_connection_factory;
+mod health_check;
 mod inner_pool;
 pub(crate) mod instrumentation;
+mod leak_detector;
 mod managed;
 
-use self::extended_connection_factory::ExtendedConnectionFactory;
+use self::extended_connection_factory::{ExtendedConnectionFactory, SharedState};
 use self::instrumentation::PoolInstrumentation;
+pub(crate) use self::leak_detector::Watched;
 pub(crate) use self::managed::Managed;
 
 #[derive(Debug, Clone)]
 pub(crate) struct Config {
     pub desired_pool_size: usize,
+    pub max_pool_size: Option<usize>,
+    pub surplus_idle_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+    pub max_connection_lifetime: Option<Duration>,
+    pub connection_lifetime_jitter: Duration,
+    pub validation_policy: ValidationPolicy,
+    pub validation_timeout: Duration,
     pub backoff_strategy: BackoffStrategy,
     pub reservation_limit: usize,
     pub activation_order: ActivationOrder,
     pub checkout_queue_size: usize,
+    pub circuit_breaker_threshold: usize,
+    pub circuit_breaker_open_duration: Duration,
+    pub health_check_interval: Option<Duration>,
+    pub health_check_failure_threshold: usize,
+    pub reserved_for_high_priority: usize,
+    pub leak_detection_threshold: Option<Duration>,
 }
 
 /// A wrapper for a pool message so that we can also send a
 /// stop message over the stream without the need of the inner pool
 /// to know about this message
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum PoolMessageEnvelope<T: Poolable> {
     /// Pass the content of this to the inner pool
     PoolMessage(PoolMessage<T>),
@@ -64,13 +81,14 @@ pub(crate) struct CheckoutRequest<T: Poolable> {
 impl<T: Poolable> PoolMessage<T> {
     /// Wrap the message, send it on the channel and in case of a failure
     /// return the original message
+    #[allow(clippy::result_large_err)]
     fn send_on_internal_channel(
         self,
-        channel: &mut mpsc::UnboundedSender<PoolMessageEnvelope<T>>,
+        channel: &mpsc::UnboundedSender<PoolMessageEnvelope<T>>,
     ) -> Result<(), PoolMessage<T>> {
         let wrapped = PoolMessageEnvelope::PoolMessage(self);
-        channel.try_send(wrapped).map_err(|err| {
-            if let PoolMessageEnvelope::PoolMessage(msg) = err.into_inner() {
+        channel.send(wrapped).map_err(|err| {
+            if let PoolMessageEnvelope::PoolMessage(msg) = err.0 {
                 msg
             } else {
                 panic!("Did not send a PoolMessage - THIS IS A BUG");
@@ -86,6 +104,36 @@ impl Config {
         self
     }
 
+    pub fn max_pool_size(mut self, v: usize) -> Self {
+        self.max_pool_size = Some(v);
+        self
+    }
+
+    pub fn surplus_idle_timeout(mut self, v: Duration) -> Self {
+        self.surplus_idle_timeout = v;
+        self
+    }
+
+    pub fn idle_timeout(mut self, v: Duration) -> Self {
+        self.idle_timeout = Some(v);
+        self
+    }
+
+    pub fn max_connection_lifetime(mut self, v: Duration) -> Self {
+        self.max_connection_lifetime = Some(v);
+        self
+    }
+
+    pub fn validation_policy(mut self, v: ValidationPolicy) -> Self {
+        self.validation_policy = v;
+        self
+    }
+
+    pub fn validation_timeout(mut self, v: Duration) -> Self {
+        self.validation_timeout = v;
+        self
+    }
+
     pub fn backoff_strategy(mut self, v: BackoffStrategy) -> Self {
         self.backoff_strategy = v;
         self
@@ -95,16 +143,44 @@ impl Config {
         self.reservation_limit = v;
         self
     }
+
+    pub fn checkout_queue_size(mut self, v: usize) -> Self {
+        self.checkout_queue_size = v;
+        self
+    }
+
+    pub fn reserved_for_high_priority(mut self, v: usize) -> Self {
+        self.reserved_for_high_priority = v;
+        self
+    }
+
+    pub fn leak_detection_threshold(mut self, v: Duration) -> Self {
+        self.leak_detection_threshold = Some(v);
+        self
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             desired_pool_size: 20,
+            max_pool_size: None,
+            surplus_idle_timeout: Duration::from_secs(60),
+            idle_timeout: None,
+            max_connection_lifetime: None,
+            connection_lifetime_jitter: Duration::from_secs(0),
+            validation_policy: ValidationPolicy::default(),
+            validation_timeout: Duration::from_secs(1),
             backoff_strategy: BackoffStrategy::default(),
             reservation_limit: 100,
             activation_order: ActivationOrder::default(),
             checkout_queue_size: 100,
+            circuit_breaker_threshold: 0,
+            circuit_breaker_open_duration: Duration::from_secs(10),
+            health_check_interval: None,
+            health_check_failure_threshold: 3,
+            reserved_for_high_priority: 0,
+            leak_detection_threshold: None,
         }
     }
 }
@@ -114,13 +190,6 @@ pub(crate) struct PoolInternal<T: Poolable> {
     checkout_sink: mpsc::Sender<CheckoutRequest<T>>,
 }
 
-/// We use an bounded and unbounded channel and both have
-/// different error type. Map them to this type to make them the same.
-pub enum RecvError {
-    Unbounded(mpsc::error::UnboundedRecvError),
-    Bounded(mpsc::error::RecvError),
-}
-
 impl<T> PoolInternal<T>
 where
     T: Poolable,
@@ -130,24 +199,44 @@ where
         connection_factory: C,
         executor: ExecutorFlavour,
         instrumentation: PoolInstrumentation,
+        connection_initializer: Option<ConnectionInitializer<T>>,
     ) -> Self
     where
         C: ConnectionFactory<Connection = T> + Send + Sync + 'static,
     {
         // We want to send inner messages in an unbounded manner.
-        let (mut internal_tx, internal_receiver) =
-            mpsc::unbounded_channel::<PoolMessageEnvelope<T>>();
+        let (internal_tx, internal_receiver) = mpsc::unbounded_channel::<PoolMessageEnvelope<T>>();
         // Checkout messages should be capped so that we do not get flooded.
         let (checkout_sink, checkout_receiver) =
             mpsc::channel::<CheckoutRequest<T>>(config.checkout_queue_size);
 
-        let inner_pool = InnerPool::new(&config, instrumentation.clone());
-        start_inner_pool_consumer(inner_pool, checkout_receiver, internal_receiver, &executor);
-
         // We need to access it from multiple places since we are
         // going to put it into multiple `ExtendedConnectionFactory`s
         let wrapped_connection_factory = Arc::new(connection_factory)
             as Arc<dyn ConnectionFactory<Connection = T> + Send + Sync + 'static>;
+        let shared = Arc::new(SharedState::new(
+            config.backoff_strategy,
+            config.health_check_failure_threshold,
+            config.leak_detection_threshold,
+            config.validation_timeout,
+        ));
+        instrumentation.configure_circuit_breaker(
+            config.circuit_breaker_threshold,
+            config.circuit_breaker_open_duration,
+        );
+
+        // The inner pool needs its own factory to grow beyond `desired_pool_size`
+        let inner_pool_factory = ExtendedConnectionFactory::new(
+            Arc::clone(&wrapped_connection_factory),
+            internal_tx.clone(),
+            instrumentation.clone(),
+            config.validation_policy,
+            connection_initializer.clone(),
+            executor.clone(),
+            Arc::clone(&shared),
+        );
+        let inner_pool = InnerPool::new(&config, inner_pool_factory);
+        start_inner_pool_consumer(inner_pool, checkout_receiver, internal_receiver, &executor);
 
         // Create the initial connections
         (0..config.desired_pool_size).for_each(|_| {
@@ -156,12 +245,14 @@ where
                 Arc::clone(&wrapped_connection_factory),
                 internal_tx.clone(),
                 instrumentation.clone(),
-                config.backoff_strategy,
+                config.validation_policy,
+                connection_initializer.clone(),
+                executor.clone(),
+                Arc::clone(&shared),
             );
-            let f = future::lazy(move || {
+            let f = async move {
                 extended_connection_factory.create_connection(Instant::now());
-                Ok(())
-            });
+            };
 
             // This triggers the creation of a connection.
             // Once these connections fail they will recreate themselves
@@ -173,34 +264,46 @@ where
             Arc::clone(&wrapped_connection_factory),
             internal_tx.clone(),
             instrumentation,
-            config.backoff_strategy,
+            config.validation_policy,
+            connection_initializer,
+            executor.clone(),
+            shared,
         ));
 
         // A stream driven by an interval to send periodic messages to the inner pool.
         // Since this stream tries to send to the pool stream it will
         // end once it fails to send a message to the pool stream.
-        let cleanup_ticker = Interval::new_interval(self::inner_pool::CLEANUP_INTERVAL)
-            .map_err(|err| {
-                error!("timer error in cleanup ticker: {}", err);
-            })
-            .for_each(move |_| {
+        let cleanup_ticker = async move {
+            let mut interval = time::interval(self::inner_pool::CLEANUP_INTERVAL);
+            loop {
+                interval.tick().await;
                 let wrapped = PoolMessageEnvelope::PoolMessage(PoolMessage::CleanupReservations(
                     Instant::now(),
                 ));
-                if let Err(_err) = internal_tx.try_send(wrapped) {
+                if let Err(_err) = internal_tx.send(wrapped) {
                     trace!("pool gone - cleanup ticker stopping");
-                    Err(())
-                } else {
-                    Ok(())
+                    break;
                 }
-            })
-            .then(move |_r| {
-                trace!("cleanup ticker stream stopped");
-                Ok(())
-            });
+            }
+            trace!("cleanup ticker stream stopped");
+        };
 
         let _ = executor.spawn(cleanup_ticker);
 
+        if let Some(interval) = config.health_check_interval {
+            let _ = executor.spawn(health_checker(
+                Arc::clone(&extended_connection_factory),
+                interval,
+            ));
+        }
+
+        if let Some(threshold) = config.leak_detection_threshold {
+            let _ = executor.spawn(leak_detector(
+                Arc::clone(&extended_connection_factory),
+                threshold,
+            ));
+        }
+
         trace!("PoolInternal created");
 
         // The counterpart is triggered in `Self::drop`.
@@ -234,68 +337,56 @@ where
         checkout_requested_at: Instant,
         constraint: M,
     ) -> Result<CheckoutManaged<T>, FailedCheckout> {
+        let instrumentation = self.extended_connection_factory.instrumentation.clone();
+
+        if self.extended_connection_factory.is_shutting_down() {
+            return Err(FailedCheckout {
+                error: instrumentation.checkout_failed(
+                    CheckoutErrorKind::ShuttingDown.into(),
+                    checkout_requested_at,
+                ),
+                checkout_requested_at,
+            });
+        }
+
         let constraint = constraint.into();
         if constraint.is_deadline_elapsed() {
-            return Ok(CheckoutManaged::new(future::err(
+            return Ok(CheckoutManaged::error(instrumentation.checkout_failed(
                 CheckoutErrorKind::CheckoutTimeout.into(),
+                checkout_requested_at,
             )));
         }
 
         let (deadline, reservation_allowed) = constraint.deadline_and_reservation_allowed();
 
-        let (tx, rx) = oneshot::channel();
-
-        // This will be passed to the client as a `Future`
-        let rx = rx.then(|r| match r {
-            Ok(from_pool) => from_pool,
-            Err(_receive_error) => {
-                // The pool dropped the reservation because it was dropped itself
-                Err(CheckoutErrorKind::NoPool.into())
-            }
-        });
+        let rx = send_checkout_request(
+            self.checkout_sink.clone(),
+            checkout_requested_at,
+            reservation_allowed,
+            constraint.priority,
+        )
+        .map_err(|mut failed_checkout| {
+            failed_checkout.error =
+                instrumentation.checkout_failed(failed_checkout.error, checkout_requested_at);
+            failed_checkout
+        })?;
 
         // Maybe we need to wrap it in a timeout ...
         let rx = if let Some(deadline) = deadline {
-            Box::new(Timeout::new_at(rx, deadline).map_err(|err| {
-                if err.is_inner() {
-                    return err.into_inner().unwrap();
-                }
-
-                if err.is_elapsed() {
-                    return CheckoutError::new(CheckoutErrorKind::CheckoutTimeout);
+            async move {
+                match time::timeout_at(deadline.into(), rx).await {
+                    Ok(r) => r,
+                    Err(_elapsed) => Err(CheckoutError::new(CheckoutErrorKind::CheckoutTimeout)),
                 }
-
-                CheckoutError::new(CheckoutErrorKind::TaskExecution)
-            }))
+            }
+            .boxed()
         } else {
-            Box::new(rx) as Box<dyn Future<Item = _, Error = _> + Send>
+            rx
         };
 
-        // We need a future to return to the client and a `CheckoutPackage`
-        // to send to the inner pool to complete the checkout
-        let checkout = CheckoutManaged::new(rx);
-        let payload = CheckoutPayload {
-            checkout_requested_at,
-            sender: tx,
-            reservation_allowed,
-        };
-        let mut checkout_sink = self.checkout_sink.clone();
-        if let Err(err) = checkout_sink.try_send(CheckoutRequest {
-            created_at: Instant::now(),
-            payload,
-        }) {
-            let error_kind = if err.is_full() {
-                CheckoutErrorKind::CheckoutLimitReached
-            } else {
-                CheckoutErrorKind::NoPool
-            };
-
-            let CheckoutRequest { payload, .. } = err.into_inner();
-
-            return Err(FailedCheckout::new(payload, error_kind));
-        }
-
-        Ok(checkout)
+        Ok(CheckoutManaged::new(rx.map_err(move |err| {
+            instrumentation.checkout_failed(err, checkout_requested_at)
+        })))
     }
 
     pub fn connected_to(&self) -> &str {
@@ -306,10 +397,104 @@ where
         self.extended_connection_factory.instrumentation.state()
     }
 
-    pub fn ping(&self, timeout: Instant) -> impl Future<Item = Ping, Error = ()> + Send {
+    pub fn id(&self) -> PoolId {
+        self.extended_connection_factory.instrumentation.id
+    }
+
+    pub fn node_state(&self) -> NodeState {
+        let instrumentation = &self.extended_connection_factory.instrumentation;
+        NodeState {
+            pool: self.id(),
+            connected_to: self.connected_to().to_string(),
+            state: instrumentation.state(),
+            circuit_state: instrumentation.circuit_state(),
+            connection_factory_failures: instrumentation.connection_factory_failures(),
+            since_last_connection: instrumentation.since_last_connection(),
+        }
+    }
+
+    pub fn circuit_state(&self) -> CircuitState {
+        self.extended_connection_factory
+            .instrumentation
+            .circuit_state()
+    }
+
+    /// The smoothed latency of checkouts, connects and pings
+    pub fn latency(&self) -> Option<Duration> {
+        self.extended_connection_factory.instrumentation.latency()
+    }
+
+    pub fn ping(&self, timeout: Instant) -> impl Future<Output = Result<Ping, ()>> + Send {
         self.extended_connection_factory.ping(timeout)
     }
 
+    /// Returns `false` if the last pings of the health check failed
+    pub fn is_healthy(&self) -> bool {
+        self.extended_connection_factory.health_check().is_healthy()
+    }
+
+    pub fn health(&self) -> NodeHealth {
+        self.extended_connection_factory
+            .health_check()
+            .node_health(self.id(), self.connected_to())
+    }
+
+    /// Change the configuration of the running pool.
+    ///
+    /// The values must already be adjusted to a single pool.
+    pub fn reconfigure(&self, reconfiguration: Reconfiguration) {
+        if self
+            .extended_connection_factory
+            .send_message(PoolMessage::Reconfigure {
+                created_at: Instant::now(),
+                reconfiguration,
+            })
+            .is_err()
+        {
+            debug!("pool gone - could not reconfigure");
+        }
+    }
+
+    /// Shut the pool down.
+    ///
+    /// Checkouts fail with `CheckoutErrorKind::ShuttingDown` from now on
+    /// and no new connections will be created. The returned future completes
+    /// once all connections have been returned to the pool and closed. If
+    /// that does not happen until `deadline` the pool is stopped and the connections
+    /// still in flight will be dropped once they are returned.
+    pub fn shutdown(&self, deadline: Instant) -> impl Future<Output = ()> + Send {
+        let factory = &self.extended_connection_factory;
+        factory.begin_shutdown();
+
+        let (tx, rx) = oneshot::channel();
+        let sent = factory
+            .send_message(PoolMessage::Shutdown {
+                created_at: Instant::now(),
+                notify: tx,
+            })
+            .is_ok();
+        let send_back = factory.send_back_cloned();
+        let id = factory.instrumentation.id;
+
+        async move {
+            if !sent {
+                trace!("[{}] pool already stopped", id);
+                return;
+            }
+
+            match time::timeout_at(deadline.into(), rx).await {
+                Ok(_) => debug!("[{}] pool shut down", id),
+                Err(_elapsed) => {
+                    warn!(
+                        "[{}] pool did not shut down in time - stopping it with connections in flight",
+                        id
+                    );
+                    let _ = send_back.send(PoolMessageEnvelope::Stop);
+                }
+            }
+        }
+    }
+
     #[cfg(test)]
     pub fn custom_instrumentation<C, I>(
         config: Config,
@@ -329,6 +514,7 @@ where
                 InstrumentationFlavour::Custom(Arc::new(instrumentation)),
                 PoolId::new(0),
             ),
+            None,
         )
     }
 
@@ -346,6 +532,7 @@ where
             connection_factory,
             executor,
             PoolInstrumentation::new(InstrumentationFlavour::NoInstrumentation, PoolId::new(0)),
+            None,
         )
     }
 }
@@ -353,10 +540,10 @@ where
 impl<T: Poolable> Drop for PoolInternal<T> {
     fn drop(&mut self) {
         trace!("Dropping PoolInternal {}", self.connected_to());
-        let mut sender = self.extended_connection_factory.send_back_cloned();
+        let sender = self.extended_connection_factory.send_back_cloned();
         // Stop the internal stream manually and forcefully. Otherwise it
         // will stay alive as long as a client does not return a connection.
-        let _ = sender.try_send(PoolMessageEnvelope::Stop);
+        let _ = sender.send(PoolMessageEnvelope::Stop);
         self.extended_connection_factory
             .instrumentation
             .pool_removed();
@@ -371,7 +558,7 @@ impl<T: Poolable> fmt::Debug for PoolInternal<T> {
 
 /// An attempt to send a checkout to the inner pool failed.
 pub(crate) struct FailedCheckout {
-    pub error_kind: CheckoutErrorKind,
+    pub error: CheckoutError,
     pub checkout_requested_at: Instant,
 }
 
@@ -379,24 +566,22 @@ impl FailedCheckout {
     pub fn new<T: Poolable>(payload: CheckoutPayload<T>, error_kind: CheckoutErrorKind) -> Self {
         Self {
             checkout_requested_at: payload.checkout_requested_at,
-            error_kind,
+            error: error_kind.into(),
         }
     }
 }
 
 /// A future containing a checked out connection or an error
 pub(crate) struct CheckoutManaged<T: Poolable> {
-    inner: Box<dyn Future<Item = Managed<T>, Error = CheckoutError> + Send + 'static>,
+    inner: BoxFuture<'static, Result<Managed<T>, CheckoutError>>,
 }
 
 impl<T: Poolable> CheckoutManaged<T> {
     pub fn new<F>(fut: F) -> Self
     where
-        F: Future<Item = Managed<T>, Error = CheckoutError> + Send + 'static,
+        F: Future<Output = Result<Managed<T>, CheckoutError>> + Send + 'static,
     {
-        Self {
-            inner: Box::new(fut),
-        }
+        Self { inner: fut.boxed() }
     }
 
     pub fn error<E: Into<CheckoutError>>(err: E) -> Self {
@@ -405,52 +590,218 @@ impl<T: Poolable> CheckoutManaged<T> {
 }
 
 impl<T: Poolable> Future for CheckoutManaged<T> {
-    type Item = Managed<T>;
-    type Error = CheckoutError;
+    type Output = Result<Managed<T>, CheckoutError>;
 
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        self.inner.poll()
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.inner.as_mut().poll(cx)
+    }
+}
+
+/// Send a checkout request to the inner pool and return a future
+/// which completes once the inner pool responded.
+///
+/// If the inner pool marked the connection for validation it will
+/// be validated before the future completes. A connection failing the validation
+/// is discarded and a new checkout request will be sent.
+fn send_checkout_request<T: Poolable>(
+    checkout_sink: mpsc::Sender<CheckoutRequest<T>>,
+    checkout_requested_at: Instant,
+    reservation_allowed: bool,
+    priority: Priority,
+) -> Result<BoxFuture<'static, Result<Managed<T>, CheckoutError>>, FailedCheckout> {
+    let (tx, rx) = oneshot::channel();
+
+    let payload = CheckoutPayload {
+        checkout_requested_at,
+        sender: tx,
+        reservation_allowed,
+        priority,
+    };
+    if let Err(err) = checkout_sink.try_send(CheckoutRequest {
+        created_at: Instant::now(),
+        payload,
+    }) {
+        let (error_kind, request) = match err {
+            mpsc::error::TrySendError::Full(request) => {
+                (CheckoutErrorKind::CheckoutLimitReached, request)
+            }
+            mpsc::error::TrySendError::Closed(request) => (CheckoutErrorKind::NoPool, request),
+        };
+
+        return Err(FailedCheckout::new(request.payload, error_kind));
+    }
+
+    // This will be passed to the client as a `Future`
+    let rx = async move {
+        let managed = match rx.await {
+            Ok(from_pool) => from_pool?,
+            Err(_receive_error) => {
+                // The pool dropped the reservation because it was dropped itself
+                return Err(CheckoutErrorKind::NoPool.into());
+            }
+        };
+
+        validate_checked_out(
+            managed,
+            checkout_sink,
+            checkout_requested_at,
+            reservation_allowed,
+            priority,
+        )
+        .await
+    };
+
+    Ok(rx.boxed())
+}
+
+/// Validate a connection if the inner pool requested it.
+///
+/// If the validation fails the connection gets dropped which triggers the creation
+/// of a new connection. Then another attempt to check out a connection is made.
+fn validate_checked_out<T: Poolable>(
+    mut managed: Managed<T>,
+    checkout_sink: mpsc::Sender<CheckoutRequest<T>>,
+    checkout_requested_at: Instant,
+    reservation_allowed: bool,
+    priority: Priority,
+) -> BoxFuture<'static, Result<Managed<T>, CheckoutError>> {
+    if !managed.validate_on_check_out {
+        return future::ok(managed).boxed();
+    }
+    managed.validate_on_check_out = false;
+
+    let validation = match (managed.factory.as_ref(), managed.value.take()) {
+        (Some(factory), Some(conn)) => {
+            factory.validate(conn, Instant::now() + factory.validation_timeout())
+        }
+        (_, conn) => {
+            managed.value = conn;
+            return future::ok(managed).boxed();
+        }
+    };
+
+    async move {
+        match validation.await {
+            Ok(conn) => {
+                managed.value = Some(conn);
+                Ok(managed)
+            }
+            Err(()) => {
+                debug!("checked out connection failed validation - retrying");
+                managed.connection_broken();
+                // Dropping it without a value triggers the creation of a new connection
+                drop(managed);
+                match send_checkout_request(
+                    checkout_sink,
+                    checkout_requested_at,
+                    reservation_allowed,
+                    priority,
+                ) {
+                    Ok(retry) => retry.await,
+                    Err(failed_checkout) => Err(failed_checkout.error),
+                }
+            }
+        }
+    }
+    .boxed()
+}
+
+/// Pings the node every `interval` and records the results
+/// until the pool is gone
+async fn health_checker<T: Poolable>(
+    factory: Arc<ExtendedConnectionFactory<T>>,
+    interval: Duration,
+) {
+    let mut ticker = time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if factory.is_pool_gone() {
+            trace!("pool gone - health checker stopping");
+            break;
+        }
+
+        // Factories which do not support pings fail with `()`
+        if let Ok(ping) = factory.ping(Instant::now() + interval).await {
+            factory.health_check().record(ping);
+        }
+    }
+}
+
+/// Periodically reports the connections held
+/// for longer than `threshold` as suspected leaks
+async fn leak_detector<T: Poolable>(
+    factory: Arc<ExtendedConnectionFactory<T>>,
+    threshold: Duration,
+) {
+    let interval = std::cmp::max(threshold / 2, Duration::from_millis(1));
+    let mut ticker = time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if factory.is_pool_gone() {
+            trace!("pool gone - leak detector stopping");
+            break;
+        }
+
+        for suspect in factory.leak_detector().suspects() {
+            if let Some(backtrace) = suspect.backtrace() {
+                warn!(
+                    "[{}] connection to '{}' held for {:?} - checked out at {}\n{}",
+                    factory.instrumentation.id,
+                    factory.connecting_to(),
+                    suspect.held_for,
+                    suspect.location,
+                    backtrace
+                );
+            } else {
+                warn!(
+                    "[{}] connection to '{}' held for {:?} - checked out at {}",
+                    factory.instrumentation.id,
+                    factory.connecting_to(),
+                    suspect.held_for,
+                    suspect.location
+                );
+            }
+            factory
+                .instrumentation
+                .connection_leak_suspected(suspect.held_for, suspect.location);
+        }
     }
 }
 
 fn start_inner_pool_consumer<T: Poolable>(
     mut pool: InnerPool<T>,
-    checkout_receiver: mpsc::Receiver<CheckoutRequest<T>>,
-    internal_receiver: mpsc::UnboundedReceiver<PoolMessageEnvelope<T>>,
+    mut checkout_receiver: mpsc::Receiver<CheckoutRequest<T>>,
+    mut internal_receiver: mpsc::UnboundedReceiver<PoolMessageEnvelope<T>>,
     executor: &ExecutorFlavour,
 ) {
-    let checkout_stream = checkout_receiver
-        .map(|rq| {
-            PoolMessageEnvelope::PoolMessage(PoolMessage::CheckOut {
-                created_at: rq.created_at,
-                payload: rq.payload,
-            })
-        })
-        .map_err(RecvError::Bounded)
-        .fuse();
-
-    let internal_stream = internal_receiver.map_err(RecvError::Unbounded).fuse();
-
-    let merged_stream = internal_stream
-        .select(checkout_stream)
-        .fuse()
-        .map_err(|_err| ());
+    let consumer_fut = async move {
+        loop {
+            let message = tokio::select! {
+                Some(message) = internal_receiver.recv() => message,
+                Some(rq) = checkout_receiver.recv() => {
+                    PoolMessageEnvelope::PoolMessage(PoolMessage::CheckOut {
+                        created_at: rq.created_at,
+                        payload: rq.payload,
+                    })
+                }
+                else => break,
+            };
 
-    let consumer_fut = merged_stream
-        .for_each(move |message| match message {
-            PoolMessageEnvelope::PoolMessage(message) => {
-                pool.process(message);
-                Ok(())
+            match message {
+                PoolMessageEnvelope::PoolMessage(message) => pool.process(message),
+                PoolMessageEnvelope::Stop => {
+                    trace!("Stopping message stream");
+                    break;
+                }
             }
-            PoolMessageEnvelope::Stop => {
-                trace!("Stopping message stream");
-                Err(())
+
+            if pool.is_shut_down() {
+                trace!("Pool shut down - stopping message stream");
+                break;
             }
-        })
-        .then(move |_r| {
-            trace!("pool message stream stopped");
-            Ok(())
-        });
+        }
+        trace!("pool message stream stopped");
+    };
 
     let _ = executor.spawn(consumer_fut);
 }
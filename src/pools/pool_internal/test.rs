@@ -1,22 +1,24 @@
 use std::error::Error as StdError;
 use std::fmt;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
-use futures::future::{self, Future};
-use pretty_env_logger;
+use futures::future::{self, BoxFuture, FutureExt, TryFutureExt};
 use tokio::sync::oneshot;
-use tokio::{self, runtime::Runtime};
-use tokio_timer::Delay;
+use tokio::{self, runtime::Runtime, time};
 
 use crate::backoff_strategy::BackoffStrategy;
-use crate::connection_factory::{NewConnection, NewConnectionError};
+use crate::config::Reconfiguration;
+use crate::connection_factory::{ConnectionInitializer, NewConnection, NewConnectionError};
 use crate::error::CheckoutErrorKind;
 use crate::executor_flavour::ExecutorFlavour;
-use crate::instrumentation::StateCounters;
+use crate::instrumentation::{InstrumentationFlavour, PoolId, StateCounters};
+use crate::pools::pool_internal::instrumentation::PoolInstrumentation;
 use crate::pools::pool_internal::{Config, ConnectionFactory, PoolInternal};
-use crate::pools::CheckoutConstraint;
+use crate::pools::{CanCheckout, CheckoutConstraint, PoolPerNode, WaitConstraint};
+use crate::validation_policy::ValidationPolicy;
 use crate::*;
 
 fn check_out_fut<T: Poolable, M: Into<CheckoutConstraint>>(
@@ -24,7 +26,7 @@ fn check_out_fut<T: Poolable, M: Into<CheckoutConstraint>>(
     constraint: M,
 ) -> CheckoutManaged<T> {
     match pool.check_out(constraint) {
-        Err(failure_package) => CheckoutManaged::error(failure_package.error_kind),
+        Err(failure_package) => CheckoutManaged::error(failure_package.error),
         Ok(checkout) => checkout,
     }
 }
@@ -38,32 +40,31 @@ fn given_no_runtime_the_pool_can_sill_be_created() {
 #[test]
 fn given_a_runtime_the_pool_can_be_created() {
     let _ = pretty_env_logger::try_init();
-    let mut runtime = Runtime::new().unwrap();
+    let runtime = Runtime::new().unwrap();
 
     let (tx, rx) = oneshot::channel();
 
-    let fut = future::lazy(move || {
+    let fut = async move {
         let pool = PoolInternal::no_instrumentation(
             Config::default().desired_pool_size(1),
             UnitFactory,
             ExecutorFlavour::Runtime,
         );
         let _ = tx.send(pool);
-        Ok(())
-    });
+    };
 
     runtime.spawn(fut);
 
-    let pool = rx.wait().unwrap();
+    let pool = runtime.block_on(rx).unwrap();
     drop(pool);
-    runtime.shutdown_on_idle().wait().unwrap();
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
 #[test]
 fn given_an_explicit_executor_a_pool_can_be_created_and_initialized() {
     let _ = pretty_env_logger::try_init();
     let runtime = Runtime::new().unwrap();
-    let executor = runtime.executor().into();
+    let executor = runtime.handle().clone().into();
 
     let counters = StateCounters::new();
     let pool = PoolInternal::custom_instrumentation(
@@ -92,14 +93,14 @@ fn given_an_explicit_executor_a_pool_can_be_created_and_initialized() {
     assert_eq!(state.in_flight, 0, "in_flight");
     assert_eq!(state.reservations, 0, "reservations");
 
-    runtime.shutdown_on_idle().wait().unwrap();
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
 #[test]
 fn the_pool_shuts_down_cleanly_even_if_connections_cannot_be_created() {
     let _ = pretty_env_logger::try_init();
     let runtime = Runtime::new().unwrap();
-    let executor = runtime.executor().into();
+    let executor = runtime.handle().clone().into();
 
     let counters = StateCounters::new();
     let pool = PoolInternal::custom_instrumentation(
@@ -133,14 +134,50 @@ fn the_pool_shuts_down_cleanly_even_if_connections_cannot_be_created() {
     assert_eq!(state.in_flight, 0, "in_flight");
     assert_eq!(state.reservations, 0, "reservations");
 
-    runtime.shutdown_on_idle().wait().unwrap();
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn a_failed_checkout_contains_the_state_of_the_pool_and_the_last_connection_error() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone().into();
+
+    let pool = PoolInternal::no_instrumentation(
+        Config::default()
+            .desired_pool_size(1)
+            .backoff_strategy(BackoffStrategy::Constant {
+                fixed: Duration::from_millis(1),
+                jitter: false,
+            }),
+        UnitFactoryAlwaysFails,
+        executor,
+    );
+
+    thread::sleep(Duration::from_millis(10));
+
+    let err = runtime
+        .block_on(check_out_fut(&pool, Immediately))
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), CheckoutErrorKind::NoConnection);
+    assert_eq!(err.pool(), Some(pool.id()));
+    assert_eq!(err.reservations(), Some(0));
+    assert!(err.waited().is_some());
+    assert_eq!(
+        err.source().map(|source| source.to_string()),
+        Some("could not create a new connection: i have no connections".to_string())
+    );
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
 #[test]
 fn checkout_one() {
     let _ = pretty_env_logger::try_init();
-    let mut runtime = Runtime::new().unwrap();
-    let executor = runtime.executor();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
     let config = Config::default().desired_pool_size(1);
 
     let counters = StateCounters::new();
@@ -153,7 +190,7 @@ fn checkout_one() {
 
     thread::sleep(Duration::from_millis(10));
 
-    let checked_out = check_out_fut(&pool, Wait).map(|c| c.value.unwrap());
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|c| c.value.unwrap());
     let v = runtime.block_on(checked_out).unwrap();
 
     assert_eq!(v, 0);
@@ -161,14 +198,14 @@ fn checkout_one() {
     thread::sleep(Duration::from_millis(50));
 
     drop(pool);
-    runtime.shutdown_on_idle().wait().unwrap();
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
 #[test]
 fn checkout_twice_with_one_not_reusable() {
     let _ = pretty_env_logger::try_init();
-    let mut runtime = Runtime::new().unwrap();
-    let executor = runtime.executor().into();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone().into();
     let config = Config::default().desired_pool_size(1);
 
     let pool = PoolInternal::no_instrumentation(config, U32Factory::default(), executor);
@@ -176,50 +213,73 @@ fn checkout_twice_with_one_not_reusable() {
     thread::sleep(Duration::from_millis(10));
 
     // We do not return the conn with managed by taking it
-    let checked_out = check_out_fut(&pool, Wait).map(|mut c| c.value.take().unwrap());
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|mut c| c.value.take().unwrap());
     let v = runtime.block_on(checked_out).unwrap();
 
     assert_eq!(v, 0);
 
-    let checked_out = check_out_fut(&pool, Wait).map(|c| c.value.unwrap());
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|c| c.value.unwrap());
+    let v = runtime.block_on(checked_out).unwrap();
+
+    assert_eq!(v, 1);
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn broken_connections_can_be_dropped_outside_of_the_runtime() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone().into();
+    let config = Config::default().desired_pool_size(1);
+
+    let pool = PoolInternal::no_instrumentation(config, U32Factory::default(), executor);
+
+    // Dropped without a value on this thread which is not part of the runtime
+    let mut checked_out = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    assert_eq!(checked_out.value.take(), Some(0));
+    drop(checked_out);
+
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|c| c.value.unwrap());
     let v = runtime.block_on(checked_out).unwrap();
 
     assert_eq!(v, 1);
 
     drop(pool);
-    runtime.shutdown_on_idle().wait().unwrap();
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
 #[test]
 fn checkout_twice_with_delay_factory_with_one_not_reusable() {
     let _ = pretty_env_logger::try_init();
-    let mut runtime = Runtime::new().unwrap();
-    let executor = runtime.executor().into();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone().into();
     let config = Config::default().desired_pool_size(1);
 
     let pool = PoolInternal::no_instrumentation(config, U32DelayFactory::default(), executor);
 
     // We do not return the con with managed
-    let checked_out = check_out_fut(&pool, Wait).map(|mut c| c.value.take().unwrap());
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|mut c| c.value.take().unwrap());
 
     let v = runtime.block_on(checked_out).unwrap();
 
     assert_eq!(v, 0);
 
-    let checked_out = check_out_fut(&pool, Wait).map(|c| c.value.unwrap());
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|c| c.value.unwrap());
     let v = runtime.block_on(checked_out).unwrap();
 
     assert_eq!(v, 1);
 
     drop(pool);
-    runtime.shutdown_on_idle().wait().unwrap();
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
 #[test]
 fn with_empty_pool_checkout_returns_timeout() {
     let _ = pretty_env_logger::try_init();
-    let mut runtime = Runtime::new().unwrap();
-    let executor = runtime.executor().into();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone().into();
     let config = Config::default().desired_pool_size(0);
 
     let pool = PoolInternal::no_instrumentation(config, UnitFactory, executor);
@@ -229,14 +289,14 @@ fn with_empty_pool_checkout_returns_timeout() {
     assert_eq!(err.kind(), CheckoutErrorKind::CheckoutTimeout);
 
     drop(pool);
-    runtime.shutdown_on_idle().wait().unwrap();
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
 #[test]
 fn create_connection_fails_some_times() {
     let _ = pretty_env_logger::try_init();
-    let mut runtime = Runtime::new().unwrap();
-    let executor = runtime.executor().into();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone().into();
     let config = Config::default().desired_pool_size(1);
 
     let pool = PoolInternal::no_instrumentation(
@@ -247,29 +307,30 @@ fn create_connection_fails_some_times() {
 
     thread::sleep(Duration::from_millis(10));
 
-    let checked_out = check_out_fut(&pool, Wait).map(|mut c| c.value.take().unwrap());
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|mut c| c.value.take().unwrap());
     let v = runtime.block_on(checked_out).unwrap();
 
     assert_eq!(v, 4);
 
-    let checked_out = check_out_fut(&pool, Wait).map(|c| c.value.unwrap());
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|c| c.value.unwrap());
     let v = runtime.block_on(checked_out).unwrap();
 
     assert_eq!(v, 8);
 
     drop(pool);
-    runtime.shutdown_on_idle().wait().unwrap();
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
 #[test]
 fn reservations_should_be_fulfilled() {
     (1..=2).for_each(|num_conns| {
         let _ = pretty_env_logger::try_init();
-        let mut runtime = Runtime::new().unwrap();
-        let executor = runtime.executor();
+        let runtime = Runtime::new().unwrap();
+        let executor = runtime.handle().clone();
         let config = Config::default()
             .desired_pool_size(num_conns)
-            .reservation_limit(1_000_000);
+            .reservation_limit(1_000_000)
+            .checkout_queue_size(10_000);
 
         let counters = StateCounters::default();
         let pool = PoolInternal::custom_instrumentation(
@@ -282,7 +343,7 @@ fn reservations_should_be_fulfilled() {
         thread::sleep(Duration::from_millis(10));
 
         while counters.reservations() < 1_000 {
-            let checked_out = check_out_fut(&pool, Wait).map(|_c| ()).map_err(|_| ());
+            let checked_out = check_out_fut(&pool, Wait).map(|_c| ());
             runtime.spawn(checked_out);
         }
 
@@ -297,185 +358,1327 @@ fn reservations_should_be_fulfilled() {
         assert_eq!(counters.reservations(), 0, "reservations");
 
         drop(pool);
-        runtime.shutdown_on_idle().wait().unwrap();
+        runtime.shutdown_timeout(Duration::from_secs(1));
     });
 }
 
-/*
 #[test]
-fn put_and_checkout_do_not_race() {
-    let n = 10000;
+fn the_pool_grows_up_to_max_pool_size_if_reservations_pile_up() {
     let _ = pretty_env_logger::try_init();
     let runtime = Runtime::new().unwrap();
-    for _ in 0..n {
-        let executor = runtime.executor().into();
-        let config = Config::default().desired_pool_size(0);
+    let executor = runtime.handle().clone();
+    let config = Config::default().desired_pool_size(1).max_pool_size(3);
 
-        let pool = Pool::new(config.clone(), U32Factory::default(), executor);
-        let inner_pool = pool.inner_pool().clone();
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32Factory::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
 
-        thread::spawn(move || {
-            let managed = Managed {
-                created_at: Instant::now(),
-                takeoff_at: None,
-                value: Some(0),
-                inner_pool: Arc::downgrade(&inner_pool),
-                marked_for_kill: false,
-            };
+    thread::sleep(Duration::from_millis(10));
 
-            inner_pool.put(managed);
-        });
+    // Keep the connections checked out so that subsequent checkouts have to wait
+    let first = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    let second = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    let third = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
 
-        let checked_out = pool.inner_pool().checkout(None).map(|c| c.value.unwrap());
-        let v = checked_out.wait().unwrap();
+    thread::sleep(Duration::from_millis(10));
 
-        assert_eq!(v, 0);
+    assert_eq!(counters.connections(), 3, "connections");
+    assert_eq!(counters.in_flight(), 3, "in_flight");
 
-        drop(pool);
-    }
-    runtime.shutdown_on_idle().wait().unwrap();
-}
-*/
+    let err = runtime
+        .block_on(check_out_fut(&pool, Duration::from_millis(20)))
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), CheckoutErrorKind::CheckoutTimeout);
+    assert_eq!(counters.connections(), 3, "connections");
 
-impl Poolable for () {
-    fn connected_to(&self) -> &str {
-        ""
-    }
+    drop(first);
+    drop(second);
+    drop(third);
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
-struct UnitFactory;
-impl ConnectionFactory for UnitFactory {
-    type Connection = ();
-    fn create_connection(&self) -> NewConnection<Self::Connection> {
-        NewConnection::new(future::ok(()))
-    }
-    fn connecting_to(&self) -> &str {
-        ""
-    }
-}
+#[test]
+fn replaced_connections_do_not_count_as_growth() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default().desired_pool_size(2).max_pool_size(4);
+
+    // The connection requested to grow the pool arrives late
+    let factory = U32FactoryOneSlowConnection {
+        created: Arc::new(AtomicU32::new(0)),
+        slow: 2,
+        delay: Duration::from_millis(300),
+    };
+    let created = Arc::clone(&factory.created);
+    let pool = PoolInternal::no_instrumentation(config, factory, executor.into());
 
-impl Poolable for u32 {
-    fn connected_to(&self) -> &str {
-        ""
-    }
-}
+    thread::sleep(Duration::from_millis(10));
 
-struct U32Factory {
-    counter: AtomicU32,
-}
+    let mut first = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    let _second = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
 
-impl Default for U32Factory {
-    fn default() -> Self {
-        Self {
-            counter: AtomicU32::new(0),
-        }
-    }
-}
+    let third = runtime.spawn(check_out_fut(&pool, Wait));
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(created.load(Ordering::SeqCst), 3, "growth requested");
 
-impl ConnectionFactory for U32Factory {
-    type Connection = u32;
-    fn create_connection(&self) -> NewConnection<Self::Connection> {
-        NewConnection::new(future::ok(self.counter.fetch_add(1, Ordering::SeqCst)))
-    }
-    fn connecting_to(&self) -> &str {
-        ""
-    }
+    // The replacement of a broken connection arrives before the growth
+    first.value = None;
+    runtime.block_on(async move { drop(first) });
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(created.load(Ordering::SeqCst), 4, "replacement requested");
+
+    let fourth = runtime.spawn(check_out_fut(&pool, Wait));
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(
+        created.load(Ordering::SeqCst),
+        4,
+        "the pending growth serves the next checkout"
+    );
+
+    let third = runtime
+        .block_on(async { time::timeout(Duration::from_secs(1), third).await })
+        .unwrap()
+        .unwrap();
+    assert!(third.is_ok(), "third");
+    let fourth = runtime
+        .block_on(async { time::timeout(Duration::from_secs(1), fourth).await })
+        .unwrap()
+        .unwrap();
+    assert!(fourth.is_ok(), "fourth");
+    assert_eq!(created.load(Ordering::SeqCst), 4, "connections created");
+
+    drop(third);
+    drop(fourth);
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
-struct U32FactoryFailsThreeTimesInARow(AtomicU32);
-impl ConnectionFactory for U32FactoryFailsThreeTimesInARow {
-    type Connection = u32;
-    fn create_connection(&self) -> NewConnection<Self::Connection> {
-        #[derive(Debug)]
-        struct MyError;
+#[test]
+fn idle_connections_exceeding_desired_pool_size_are_closed() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default()
+        .desired_pool_size(1)
+        .max_pool_size(2)
+        .surplus_idle_timeout(Duration::from_millis(10));
 
-        impl fmt::Display for MyError {
-            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                f.write_str(self.description())
-            }
-        }
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32Factory::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
 
-        impl StdError for MyError {
-            fn description(&self) -> &str {
-                "hups, no connection"
-            }
+    thread::sleep(Duration::from_millis(10));
 
-            fn cause(&self) -> Option<&dyn StdError> {
-                None
-            }
-        }
+    let first = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    let second = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    assert_eq!(counters.connections(), 2, "connections");
 
-        let current_count = self.0.fetch_add(1, Ordering::SeqCst);
-        if current_count % 4 == 0 {
-            NewConnection::new(future::ok(current_count))
-        } else {
-            NewConnection::new(future::err(NewConnectionError::new(MyError)))
-        }
-    }
-    fn connecting_to(&self) -> &str {
-        ""
-    }
-}
-impl Default for U32FactoryFailsThreeTimesInARow {
-    fn default() -> Self {
-        Self(AtomicU32::new(1))
-    }
+    drop(first);
+    drop(second);
+
+    thread::sleep(Duration::from_millis(300));
+
+    assert_eq!(counters.connections(), 1, "connections");
+    assert_eq!(counters.idle(), 1, "idle");
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
-struct UnitFactoryAlwaysFails;
-impl ConnectionFactory for UnitFactoryAlwaysFails {
-    type Connection = u32;
-    fn create_connection(&self) -> NewConnection<Self::Connection> {
-        #[derive(Debug)]
-        struct MyError;
+#[test]
+fn the_pool_grows_and_shrinks_when_reconfigured() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default()
+        .desired_pool_size(1)
+        .surplus_idle_timeout(Duration::from_millis(10));
 
-        impl fmt::Display for MyError {
-            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                f.write_str(self.description())
-            }
-        }
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32Factory::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
 
-        impl StdError for MyError {
-            fn description(&self) -> &str {
-                "i have no connections"
-            }
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(counters.connections(), 1, "connections");
 
-            fn cause(&self) -> Option<&dyn StdError> {
-                None
-            }
-        }
+    pool.reconfigure(Reconfiguration::default().desired_pool_size(3));
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(counters.connections(), 3, "connections");
+    assert_eq!(counters.idle(), 3, "idle");
 
-        NewConnection::new(future::err(NewConnectionError::new(MyError)))
-    }
-    fn connecting_to(&self) -> &str {
-        ""
-    }
+    pool.reconfigure(Reconfiguration::default().desired_pool_size(2));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(counters.connections(), 2, "connections");
+    assert_eq!(counters.idle(), 2, "idle");
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
-struct U32DelayFactory {
-    counter: AtomicU32,
-    delay: Duration,
+#[test]
+fn a_reconfigured_reservation_limit_is_enforced() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default().desired_pool_size(1).reservation_limit(10);
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32Factory::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
+
+    thread::sleep(Duration::from_millis(10));
+
+    let conn = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+
+    pool.reconfigure(Reconfiguration::default().reservation_limit(1));
+    thread::sleep(Duration::from_millis(10));
+
+    let waiting = runtime.spawn(check_out_fut(&pool, Wait));
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(counters.reservations(), 1, "reservations");
+
+    let err = runtime.block_on(check_out_fut(&pool, Wait)).err().unwrap();
+    assert_eq!(err.kind(), CheckoutErrorKind::ReservationLimitReached);
+
+    drop(conn);
+    assert!(runtime.block_on(waiting).unwrap().is_ok());
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
-impl Default for U32DelayFactory {
-    fn default() -> Self {
-        Self {
-            counter: AtomicU32::new(0),
-            delay: Duration::from_millis(20),
-        }
-    }
+#[test]
+fn reservations_with_a_higher_priority_are_fulfilled_first() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default().desired_pool_size(1).reservation_limit(2);
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32Factory::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
+
+    thread::sleep(Duration::from_millis(10));
+
+    let wait_with = |priority| CheckoutConstraint {
+        wait: WaitConstraint::Wait,
+        priority,
+    };
+
+    let conn = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+
+    let low = runtime.spawn(check_out_fut(&pool, wait_with(Priority::Low)));
+    let normal = runtime.spawn(check_out_fut(&pool, wait_with(Priority::Normal)));
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(counters.reservations(), 2, "reservations");
+
+    // The queue is full so the low priority reservation has to make room
+    let high = runtime.spawn(check_out_fut(&pool, wait_with(Priority::High)));
+    let err = runtime.block_on(low).unwrap().err().unwrap();
+    assert_eq!(err.kind(), CheckoutErrorKind::ReservationLimitReached);
+
+    drop(conn);
+    let conn = runtime.block_on(high).unwrap().unwrap();
+    assert_eq!(counters.reservations(), 1, "reservations");
+
+    drop(conn);
+    assert!(runtime.block_on(normal).unwrap().is_ok());
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
 }
 
-impl ConnectionFactory for U32DelayFactory {
+#[test]
+fn a_reconfigured_desired_pool_size_leaves_connections_for_others() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default()
+        .desired_pool_size(3)
+        .surplus_idle_timeout(Duration::from_millis(10))
+        .reserved_for_high_priority(2);
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32Factory::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
+
+    pool.reconfigure(Reconfiguration::default().desired_pool_size(1));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(counters.idle(), 1, "idle");
+
+    let normal = CheckoutConstraint {
+        wait: WaitConstraint::Immediately,
+        priority: Priority::Normal,
+    };
+    assert!(runtime.block_on(check_out_fut(&pool, normal)).is_ok());
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn connections_reserved_for_high_priority_are_not_checked_out_by_others() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default()
+        .desired_pool_size(2)
+        .reserved_for_high_priority(1);
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32Factory::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
+
+    thread::sleep(Duration::from_millis(10));
+
+    let immediately_with = |priority| CheckoutConstraint {
+        wait: WaitConstraint::Immediately,
+        priority,
+    };
+
+    let normal = runtime
+        .block_on(check_out_fut(&pool, immediately_with(Priority::Normal)))
+        .unwrap();
+
+    let err = runtime
+        .block_on(check_out_fut(&pool, immediately_with(Priority::Normal)))
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), CheckoutErrorKind::NoConnection);
+
+    let waiting = runtime.spawn(check_out_fut(&pool, Wait));
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(counters.reservations(), 1, "reservations");
+
+    let high = runtime
+        .block_on(check_out_fut(&pool, immediately_with(Priority::High)))
+        .unwrap();
+
+    // The returned connection stays reserved
+    drop(high);
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(counters.reservations(), 1, "reservations");
+    assert_eq!(counters.idle(), 1, "idle");
+
+    drop(normal);
+    assert!(runtime.block_on(waiting).unwrap().is_ok());
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn connections_held_for_longer_than_the_leak_detection_threshold_are_reported() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default()
+        .desired_pool_size(1)
+        .leak_detection_threshold(Duration::from_millis(10));
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32Factory::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
+
+    thread::sleep(Duration::from_millis(10));
+
+    let managed = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    let watched = managed.watch_for_leak(std::panic::Location::caller(), None);
+    assert!(watched.is_some());
+
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(counters.leaks_suspected(), 1, "leaks suspected");
+
+    drop(watched);
+    drop(managed);
+    let managed = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    let _watched = managed.watch_for_leak(std::panic::Location::caller(), None);
+    thread::sleep(Duration::from_millis(5));
+    assert_eq!(counters.leaks_suspected(), 1, "leaks suspected");
+
+    drop(managed);
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn nodes_can_be_added_to_and_removed_from_a_pool_per_node() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = crate::config::Config::default()
+        .desired_pool_size(1)
+        .connect_to_nodes(vec!["a".to_string(), "b".to_string()]);
+
+    let counters = StateCounters::new();
+    let pool = PoolPerNode::new(
+        config,
+        |_| Ok(U32Factory::default()),
+        executor.into(),
+        InstrumentationFlavour::Custom(Arc::new(counters.instrumentation())),
+        None,
+    )
+    .unwrap();
+
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(counters.pools(), 2, "pools");
+    assert_eq!(counters.connections(), 2, "connections");
+
+    pool.add_node("c".to_string()).unwrap();
+    assert!(pool.add_node("c".to_string()).is_err());
+
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(pool.connected_to(), vec!["a", "b", "c"]);
+    assert_eq!(counters.pools(), 3, "pools");
+    assert_eq!(counters.connections(), 3, "connections");
+
+    let removed = pool
+        .remove_node("a", Instant::now() + Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(pool.connected_to(), vec!["b", "c"]);
+    runtime.block_on(removed);
+
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(counters.pools(), 2, "pools");
+    assert_eq!(counters.connections(), 2, "connections");
+    assert!(pool
+        .remove_node("d", Instant::now() + Duration::from_secs(1))
+        .is_err());
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn checkouts_skip_nodes_with_an_open_circuit() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = crate::config::Config::default()
+        .desired_pool_size(1)
+        .circuit_breaker_threshold(3)
+        .backoff_strategy(BackoffStrategy::Constant {
+            fixed: Duration::from_millis(1),
+            jitter: false,
+        })
+        .connect_to_nodes(vec!["down".to_string(), "up".to_string()]);
+
+    let counters = StateCounters::new();
+    let pool = PoolPerNode::new(
+        config,
+        |node| Ok(U32FactoryFailsOnNodeDown(node)),
+        executor.into(),
+        InstrumentationFlavour::Custom(Arc::new(counters.instrumentation())),
+        None,
+    )
+    .unwrap();
+
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(counters.open_circuits(), 1, "open circuits");
+    assert_eq!(pool.state().open_circuits, 1, "open circuits");
+    let circuit_states: Vec<_> = pool.sub_pools().iter().map(|p| p.circuit_state()).collect();
+    assert_eq!(
+        circuit_states,
+        vec![CircuitState::Open, CircuitState::Closed]
+    );
+
+    for _ in 0..4 {
+        let checked_out = pool.check_out(Duration::from_millis(100));
+        let v = runtime.block_on(checked_out).unwrap();
+        assert_eq!(v.value, Some(0));
+        drop(v);
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn the_state_of_each_sub_pool_is_reported() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = crate::config::Config::default()
+        .desired_pool_size(1)
+        .backoff_strategy(BackoffStrategy::Constant {
+            fixed: Duration::from_millis(1),
+            jitter: false,
+        })
+        .connect_to_nodes(vec!["down".to_string(), "up".to_string()]);
+
+    let pool = PoolPerNode::new(
+        config,
+        |node| Ok(U32FactoryFailsOnNodeDown(node)),
+        executor.into(),
+        InstrumentationFlavour::NoInstrumentation,
+        None,
+    )
+    .unwrap();
+
+    thread::sleep(Duration::from_millis(10));
+    let node_states: Vec<_> = pool.sub_pools().iter().map(|p| p.node_state()).collect();
+
+    assert_eq!(node_states[0].connected_to, "down");
+    assert_eq!(node_states[0].state.connections, 0);
+    assert!(node_states[0].connection_factory_failures > 0);
+    assert!(node_states[0].since_last_connection.is_none());
+
+    assert_eq!(node_states[1].connected_to, "up");
+    assert_eq!(node_states[1].state.connections, 1);
+    assert_eq!(node_states[1].connection_factory_failures, 0);
+    assert!(node_states[1].since_last_connection.is_some());
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn checkouts_skip_unhealthy_nodes() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = crate::config::Config::default()
+        .desired_pool_size(1)
+        .health_check_interval(Duration::from_millis(5))
+        .health_check_failure_threshold(2)
+        .connect_to_nodes(vec!["a".to_string(), "b".to_string()]);
+
+    let pool = PoolPerNode::new(
+        config,
+        |node| Ok(U32FactoryPingFailsOnNodeB(node)),
+        executor.into(),
+        InstrumentationFlavour::NoInstrumentation,
+        None,
+    )
+    .unwrap();
+
+    thread::sleep(Duration::from_millis(30));
+    let health: Vec<_> = pool.sub_pools().iter().map(|p| p.health()).collect();
+    assert!(health[0].healthy, "a is healthy");
+    assert!(health[0].last_ping.as_ref().unwrap().is_ok());
+    assert!(!health[1].healthy, "b is unhealthy");
+    assert!(health[1].consecutive_failures >= 2);
+    assert!(!health[1].last_ping.as_ref().unwrap().is_ok());
+
+    for _ in 0..4 {
+        let checked_out = pool.check_out(Duration::from_millis(100));
+        let v = runtime.block_on(checked_out).unwrap();
+        assert_eq!(v.value, Some(0));
+        drop(v);
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn connections_idle_for_too_long_are_replaced() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default()
+        .desired_pool_size(1)
+        .idle_timeout(Duration::from_millis(10));
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32Factory::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
+
+    thread::sleep(Duration::from_millis(300));
+
+    assert_eq!(counters.connections(), 1, "connections");
+
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|c| c.value.unwrap());
+    let v = runtime.block_on(checked_out).unwrap();
+
+    assert!(v > 0, "the initial connection should have been replaced");
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn idle_connections_exceeding_desired_pool_size_are_not_replaced() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default()
+        .desired_pool_size(1)
+        .max_pool_size(2)
+        .idle_timeout(Duration::from_millis(10));
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32Factory::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
+
+    thread::sleep(Duration::from_millis(10));
+
+    let first = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    let second = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    assert_eq!(counters.connections(), 2, "connections");
+
+    drop(first);
+    drop(second);
+
+    thread::sleep(Duration::from_millis(300));
+
+    assert_eq!(counters.connections(), 1, "connections");
+    assert_eq!(counters.idle(), 1, "idle");
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn expired_connections_are_replaced_on_check_in() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default()
+        .desired_pool_size(1)
+        .max_connection_lifetime(Duration::from_millis(10));
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32Factory::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
+
+    thread::sleep(Duration::from_millis(10));
+
+    let checked_out = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    assert_eq!(checked_out.value, Some(0));
+
+    thread::sleep(Duration::from_millis(20));
+    drop(checked_out);
+
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|c| c.value.unwrap());
+    let v = runtime.block_on(checked_out).unwrap();
+
+    assert_eq!(v, 1);
+    assert_eq!(counters.connections(), 1, "connections");
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn outdated_connections_are_replaced_on_check_in() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default().desired_pool_size(1);
+
+    let factory = U32FactoryWithGeneration::default();
+    let generation = Arc::clone(&factory.generation);
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        factory,
+        executor.into(),
+        counters.instrumentation(),
+    );
+
+    thread::sleep(Duration::from_millis(10));
+
+    let checked_out = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    assert_eq!(checked_out.value, Some(0));
+
+    generation.fetch_add(1, Ordering::SeqCst);
+    drop(checked_out);
+
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|c| c.value.unwrap());
+    let v = runtime.block_on(checked_out).unwrap();
+
+    assert_eq!(v, 1);
+    assert_eq!(counters.connections(), 1, "connections");
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn idle_connections_are_replaced_once_expired() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default()
+        .desired_pool_size(1)
+        .max_connection_lifetime(Duration::from_millis(10));
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32Factory::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
+
+    thread::sleep(Duration::from_millis(300));
+
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|c| c.value.unwrap());
+    let v = runtime.block_on(checked_out).unwrap();
+
+    assert!(v > 0, "the initial connection should have been replaced");
+    assert_eq!(counters.connections(), 1, "connections");
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn outdated_idle_connections_are_replaced() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default().desired_pool_size(1);
+
+    let factory = U32FactoryWithGeneration::default();
+    let generation = Arc::clone(&factory.generation);
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        factory,
+        executor.into(),
+        counters.instrumentation(),
+    );
+
+    thread::sleep(Duration::from_millis(10));
+    generation.fetch_add(1, Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(300));
+
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|c| c.value.unwrap());
+    let v = runtime.block_on(checked_out).unwrap();
+
+    assert_eq!(v, 1);
+    assert_eq!(counters.connections(), 1, "connections");
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn connections_failing_validation_on_check_out_are_replaced() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default()
+        .desired_pool_size(1)
+        .validation_policy(ValidationPolicy::OnCheckOut);
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32FactoryEvenValuesAreInvalid::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
+
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|c| c.value.unwrap());
+    let v = runtime.block_on(checked_out).unwrap();
+
+    assert_eq!(v, 1);
+    assert_eq!(counters.connections(), 1, "connections");
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn validations_not_finished_within_the_timeout_fail() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default()
+        .desired_pool_size(1)
+        .validation_policy(ValidationPolicy::OnCheckOut)
+        .validation_timeout(Duration::from_millis(10));
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32FactoryEvenValuesNeverValidate::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
+
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|c| c.value.unwrap());
+    let v = runtime.block_on(checked_out).unwrap();
+
+    assert_eq!(v, 1);
+    assert_eq!(counters.connections(), 1, "connections");
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn connections_validated_on_check_in_can_be_returned_outside_of_the_runtime() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default()
+        .desired_pool_size(1)
+        .validation_policy(ValidationPolicy::OnCheckIn);
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32FactoryEvenValuesAreInvalid::default(),
+        executor.into(),
+        counters.instrumentation(),
+    );
+
+    // Dropped on this thread which is not part of the runtime
+    let checked_out = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    assert_eq!(checked_out.value, Some(0));
+    drop(checked_out);
+
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|c| c.value.unwrap());
+    let v = runtime.block_on(checked_out).unwrap();
+
+    assert_eq!(v, 1);
+    assert_eq!(counters.connections(), 1, "connections");
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn connections_are_initialized_before_they_are_put_into_the_pool() {
+    #[derive(Debug)]
+    struct MyError;
+
+    impl fmt::Display for MyError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("initialization failed")
+        }
+    }
+
+    impl StdError for MyError {}
+
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone();
+    let config = Config::default().desired_pool_size(1);
+
+    let initializer = ConnectionInitializer::new(|conn: u32| {
+        future::ready(if conn.is_multiple_of(2) {
+            Err(MyError)
+        } else {
+            Ok(conn * 10)
+        })
+    });
+
+    let counters = StateCounters::new();
+    let pool = PoolInternal::new(
+        config,
+        U32Factory::default(),
+        executor.into(),
+        PoolInstrumentation::new(
+            InstrumentationFlavour::Custom(Arc::new(counters.instrumentation())),
+            PoolId::new(0),
+        ),
+        Some(initializer),
+    );
+
+    let checked_out = check_out_fut(&pool, Wait).map_ok(|c| c.value.unwrap());
+    let v = runtime.block_on(checked_out).unwrap();
+
+    assert_eq!(v, 10);
+    assert_eq!(counters.connections(), 1, "connections");
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn shutdown_closes_all_connections_once_they_are_returned() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone().into();
+    let config = Config::default().desired_pool_size(2);
+
+    let counters = StateCounters::default();
+    let factory = U32FactoryCountsClosed::default();
+    let closed = Arc::clone(&factory.closed);
+    let pool =
+        PoolInternal::custom_instrumentation(config, factory, executor, counters.instrumentation());
+
+    thread::sleep(Duration::from_millis(10));
+
+    let conn = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(counters.in_flight(), 1, "in_flight");
+
+    let shutdown = runtime.spawn(pool.shutdown(Instant::now() + Duration::from_secs(1)));
+
+    let err = runtime
+        .block_on(check_out_fut(&pool, Immediately))
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), CheckoutErrorKind::ShuttingDown);
+
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(closed.load(Ordering::SeqCst), 1, "closed idle");
+    assert_eq!(counters.idle(), 0, "idle");
+
+    drop(conn);
+    runtime.block_on(shutdown).unwrap();
+
+    assert_eq!(closed.load(Ordering::SeqCst), 2, "closed");
+    assert_eq!(counters.connections(), 0, "connections");
+    assert_eq!(counters.in_flight(), 0, "in_flight");
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn shutdown_fails_waiting_checkouts() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone().into();
+    let config = Config::default().desired_pool_size(1);
+
+    let counters = StateCounters::default();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32Factory::default(),
+        executor,
+        counters.instrumentation(),
+    );
+
+    thread::sleep(Duration::from_millis(10));
+
+    let conn = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+    let waiting = runtime.spawn(check_out_fut(&pool, Wait));
+
+    while counters.reservations() == 0 {
+        thread::yield_now();
+    }
+
+    let shutdown = runtime.spawn(pool.shutdown(Instant::now() + Duration::from_secs(1)));
+
+    let err = runtime.block_on(waiting).unwrap().err().unwrap();
+    assert_eq!(err.kind(), CheckoutErrorKind::ShuttingDown);
+    assert_eq!(counters.reservations(), 0, "reservations");
+
+    drop(conn);
+    runtime.block_on(shutdown).unwrap();
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+fn shutdown_completes_at_the_deadline_with_connections_in_flight() {
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    let executor = runtime.handle().clone().into();
+    let config = Config::default().desired_pool_size(1);
+
+    let counters = StateCounters::default();
+    let pool = PoolInternal::custom_instrumentation(
+        config,
+        U32Factory::default(),
+        executor,
+        counters.instrumentation(),
+    );
+
+    thread::sleep(Duration::from_millis(10));
+
+    let conn = runtime.block_on(check_out_fut(&pool, Wait)).unwrap();
+
+    let started = Instant::now();
+    runtime.block_on(pool.shutdown(Instant::now() + Duration::from_millis(50)));
+    assert!(started.elapsed() >= Duration::from_millis(50));
+
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(counters.in_flight(), 0, "in_flight");
+
+    drop(conn);
+
+    drop(pool);
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+/*
+#[test]
+fn put_and_checkout_do_not_race() {
+    let n = 10000;
+    let _ = pretty_env_logger::try_init();
+    let runtime = Runtime::new().unwrap();
+    for _ in 0..n {
+        let executor = runtime.handle().clone().into();
+        let config = Config::default().desired_pool_size(0);
+
+        let pool = Pool::new(config.clone(), U32Factory::default(), executor);
+        let inner_pool = pool.inner_pool().clone();
+
+        thread::spawn(move || {
+            let managed = Managed {
+                created_at: Instant::now(),
+                takeoff_at: None,
+                value: Some(0),
+                inner_pool: Arc::downgrade(&inner_pool),
+                marked_for_kill: false,
+            };
+
+            inner_pool.put(managed);
+        });
+
+        let checked_out = pool.inner_pool().checkout(None).map(|c| c.value.unwrap());
+        let v = checked_out.wait().unwrap();
+
+        assert_eq!(v, 0);
+
+        drop(pool);
+    }
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+*/
+
+impl Poolable for () {
+    fn connected_to(&self) -> &str {
+        ""
+    }
+}
+
+struct UnitFactory;
+impl ConnectionFactory for UnitFactory {
+    type Connection = ();
+    fn create_connection(&self) -> NewConnection<Self::Connection> {
+        NewConnection::new(future::ok(()))
+    }
+    fn connecting_to(&self) -> &str {
+        ""
+    }
+}
+
+impl Poolable for u32 {
+    fn connected_to(&self) -> &str {
+        ""
+    }
+}
+
+struct U32Factory {
+    counter: AtomicU32,
+}
+
+impl Default for U32Factory {
+    fn default() -> Self {
+        Self {
+            counter: AtomicU32::new(0),
+        }
+    }
+}
+
+impl ConnectionFactory for U32Factory {
+    type Connection = u32;
+    fn create_connection(&self) -> NewConnection<Self::Connection> {
+        NewConnection::new(future::ok(self.counter.fetch_add(1, Ordering::SeqCst)))
+    }
+    fn connecting_to(&self) -> &str {
+        ""
+    }
+}
+
+#[derive(Default)]
+struct U32FactoryCountsClosed {
+    counter: AtomicU32,
+    closed: Arc<AtomicU32>,
+}
+
+impl ConnectionFactory for U32FactoryCountsClosed {
+    type Connection = u32;
+    fn create_connection(&self) -> NewConnection<Self::Connection> {
+        NewConnection::new(future::ok(self.counter.fetch_add(1, Ordering::SeqCst)))
+    }
+    fn connecting_to(&self) -> &str {
+        ""
+    }
+    fn close(&self, _conn: u32) -> BoxFuture<'static, ()> {
+        self.closed.fetch_add(1, Ordering::SeqCst);
+        future::ready(()).boxed()
+    }
+}
+
+#[derive(Default)]
+struct U32FactoryWithGeneration {
+    counter: AtomicU32,
+    generation: Arc<AtomicU64>,
+}
+
+impl ConnectionFactory for U32FactoryWithGeneration {
+    type Connection = u32;
+    fn create_connection(&self) -> NewConnection<Self::Connection> {
+        NewConnection::new(future::ok(self.counter.fetch_add(1, Ordering::SeqCst)))
+    }
+    fn connecting_to(&self) -> &str {
+        ""
+    }
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+}
+
+struct U32FactoryEvenValuesAreInvalid {
+    counter: AtomicU32,
+}
+
+impl Default for U32FactoryEvenValuesAreInvalid {
+    fn default() -> Self {
+        Self {
+            counter: AtomicU32::new(0),
+        }
+    }
+}
+
+impl ConnectionFactory for U32FactoryEvenValuesAreInvalid {
+    type Connection = u32;
+    fn create_connection(&self) -> NewConnection<Self::Connection> {
+        NewConnection::new(future::ok(self.counter.fetch_add(1, Ordering::SeqCst)))
+    }
+    fn connecting_to(&self) -> &str {
+        ""
+    }
+    fn validate(&self, conn: u32) -> BoxFuture<'static, Result<u32, ()>> {
+        if conn.is_multiple_of(2) {
+            future::err(()).boxed()
+        } else {
+            future::ok(conn).boxed()
+        }
+    }
+}
+
+#[derive(Default)]
+struct U32FactoryEvenValuesNeverValidate {
+    counter: AtomicU32,
+}
+
+impl ConnectionFactory for U32FactoryEvenValuesNeverValidate {
+    type Connection = u32;
+    fn create_connection(&self) -> NewConnection<Self::Connection> {
+        NewConnection::new(future::ok(self.counter.fetch_add(1, Ordering::SeqCst)))
+    }
+    fn connecting_to(&self) -> &str {
+        ""
+    }
+    fn validate(&self, conn: u32) -> BoxFuture<'static, Result<u32, ()>> {
+        if conn.is_multiple_of(2) {
+            future::pending().boxed()
+        } else {
+            future::ok(conn).boxed()
+        }
+    }
+}
+
+struct U32FactoryFailsThreeTimesInARow(AtomicU32);
+impl ConnectionFactory for U32FactoryFailsThreeTimesInARow {
+    type Connection = u32;
+    fn create_connection(&self) -> NewConnection<Self::Connection> {
+        #[derive(Debug)]
+        struct MyError;
+
+        impl fmt::Display for MyError {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("hups, no connection")
+            }
+        }
+
+        impl StdError for MyError {}
+
+        let current_count = self.0.fetch_add(1, Ordering::SeqCst);
+        if current_count.is_multiple_of(4) {
+            NewConnection::new(future::ok(current_count))
+        } else {
+            NewConnection::new(future::err(NewConnectionError::new(MyError)))
+        }
+    }
+    fn connecting_to(&self) -> &str {
+        ""
+    }
+}
+impl Default for U32FactoryFailsThreeTimesInARow {
+    fn default() -> Self {
+        Self(AtomicU32::new(1))
+    }
+}
+
+struct UnitFactoryAlwaysFails;
+impl ConnectionFactory for UnitFactoryAlwaysFails {
+    type Connection = u32;
+    fn create_connection(&self) -> NewConnection<Self::Connection> {
+        #[derive(Debug)]
+        struct MyError;
+
+        impl fmt::Display for MyError {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("i have no connections")
+            }
+        }
+
+        impl StdError for MyError {}
+
+        NewConnection::new(future::err(NewConnectionError::new(MyError)))
+    }
+    fn connecting_to(&self) -> &str {
+        ""
+    }
+}
+
+/// Never connects to the node "down"
+struct U32FactoryFailsOnNodeDown(String);
+impl ConnectionFactory for U32FactoryFailsOnNodeDown {
+    type Connection = u32;
+    fn create_connection(&self) -> NewConnection<Self::Connection> {
+        #[derive(Debug)]
+        struct MyError;
+
+        impl fmt::Display for MyError {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("node is down")
+            }
+        }
+
+        impl StdError for MyError {}
+
+        if self.0 == "down" {
+            NewConnection::new(future::err(NewConnectionError::new(MyError)))
+        } else {
+            NewConnection::new(future::ok(0))
+        }
+    }
+    fn connecting_to(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Connections to "a" are 0 and connections to "b" are 1.
+/// Pings on "b" fail.
+struct U32FactoryPingFailsOnNodeB(String);
+impl ConnectionFactory for U32FactoryPingFailsOnNodeB {
+    type Connection = u32;
+    fn create_connection(&self) -> NewConnection<Self::Connection> {
+        NewConnection::new(future::ok(if self.0 == "b" { 1 } else { 0 }))
+    }
+    fn connecting_to(&self) -> &str {
+        &self.0
+    }
+    fn ping(&self, _timeout: Instant) -> BoxFuture<'static, Result<Ping, ()>> {
+        let state = if self.0 == "b" {
+            PingState::failed_msg("node is down")
+        } else {
+            PingState::Ok
+        };
+        future::ok(Ping {
+            connect_time: None,
+            latency: Some(Duration::from_millis(1)),
+            total_time: Duration::from_millis(1),
+            uri: self.0.clone(),
+            state,
+        })
+        .boxed()
+    }
+}
+
+struct U32DelayFactory {
+    counter: AtomicU32,
+    delay: Duration,
+}
+
+impl Default for U32DelayFactory {
+    fn default() -> Self {
+        Self {
+            counter: AtomicU32::new(0),
+            delay: Duration::from_millis(20),
+        }
+    }
+}
+
+impl ConnectionFactory for U32DelayFactory {
     type Connection = u32;
     fn create_connection(&self) -> NewConnection<Self::Connection> {
-        let delay = Delay::new(Instant::now() + self.delay);
+        let delay = self.delay;
         let next = self.counter.fetch_add(1, Ordering::SeqCst);
-        NewConnection::new(
-            delay
-                .map_err(NewConnectionError::new)
-                .and_then(move |()| future::ok(next)),
-        )
+        NewConnection::new(async move {
+            time::sleep(delay).await;
+            Ok(next)
+        })
+    }
+    fn connecting_to(&self) -> &str {
+        ""
+    }
+}
+
+/// Creates the connection with the index `slow` after `delay`
+/// and all others immediately
+struct U32FactoryOneSlowConnection {
+    created: Arc<AtomicU32>,
+    slow: u32,
+    delay: Duration,
+}
+
+impl ConnectionFactory for U32FactoryOneSlowConnection {
+    type Connection = u32;
+    fn create_connection(&self) -> NewConnection<Self::Connection> {
+        let next = self.created.fetch_add(1, Ordering::SeqCst);
+        let delay = if next == self.slow {
+            self.delay
+        } else {
+            Duration::from_secs(0)
+        };
+        NewConnection::new(async move {
+            time::sleep(delay).await;
+            Ok(next)
+        })
     }
     fn connecting_to(&self) -> &str {
         ""
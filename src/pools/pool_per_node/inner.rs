@@ -1,25 +1,100 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Instant;
 
-use futures::future::{self, Future};
+use std::future::Future;
+use std::ops::Deref;
+
+use futures::future::{self, FutureExt};
 use log::{debug, info};
 
-use crate::config::Config;
-use crate::connection_factory::ConnectionFactory;
-use crate::error::{CheckoutError, CheckoutErrorKind};
+use crate::config::{Config, Reconfiguration};
+use crate::connection_factory::{ConnectionFactory, ConnectionInitializer};
+use crate::error::CheckoutErrorKind;
 use crate::error::{InitializationError, InitializationResult};
 use crate::executor_flavour::ExecutorFlavour;
 use crate::instrumentation::{InstrumentationFlavour, PoolId};
+use crate::load_balancing::{LoadBalancer, Node};
 use crate::pools::pool_internal::instrumentation::PoolInstrumentation;
 use crate::pools::pool_internal::{CheckoutManaged, Config as PoolConfig, PoolInternal};
-use crate::{Ping, PoolState, Poolable};
+use crate::{CircuitState, Ping, PoolState, Poolable};
 
 use super::super::CheckoutConstraint;
 
+type CreatePool<T> =
+    Box<dyn Fn(&str, PoolId) -> InitializationResult<PoolInternal<T>> + Send + Sync + 'static>;
+
 pub(crate) struct Inner<T: Poolable> {
-    count: AtomicUsize,
-    pub(crate) pools: Arc<Vec<PoolInternal<T>>>,
+    balancer: Arc<dyn LoadBalancer>,
+    multiplier: usize,
+    /// Replaced as a whole when nodes are added or removed
+    /// so that checkouts always see a consistent set of sub pools
+    nodes: RwLock<Arc<Nodes<T>>>,
+    create_pool: CreatePool<T>,
+    /// The config for sub pools of nodes added later on
+    pool_config: Arc<Mutex<PoolConfig>>,
+    next_pool_id: Mutex<PoolId>,
+}
+
+struct Nodes<T: Poolable> {
+    connected_to: Vec<String>,
+    pools: Vec<NodePool<T>>,
+    /// The sub pools as seen by the `LoadBalancer`. Same order as `pools`.
+    balanced: Vec<Node>,
+}
+
+impl<T: Poolable> Nodes<T> {
+    fn new(connected_to: Vec<String>, pools: Vec<NodePool<T>>) -> Self {
+        let balanced = pools
+            .iter()
+            .map(|p| {
+                let pool = Arc::downgrade(&p.pool);
+                let pool_latency = pool.clone();
+                Node::new(
+                    Arc::clone(&p.connect_to),
+                    move || pool.upgrade().map(|p| p.state()).unwrap_or_default(),
+                    move || pool_latency.upgrade().and_then(|p| p.latency()),
+                )
+            })
+            .collect();
+
+        Self {
+            connected_to,
+            pools,
+            balanced,
+        }
+    }
+}
+
+/// A sub pool and the node it is connected to
+struct NodePool<T: Poolable> {
+    connect_to: Arc<str>,
+    pool: Arc<PoolInternal<T>>,
+}
+
+impl<T: Poolable> NodePool<T> {
+    fn new(connect_to: &str, pool: PoolInternal<T>) -> Self {
+        Self {
+            connect_to: connect_to.into(),
+            pool: Arc::new(pool),
+        }
+    }
+}
+
+impl<T: Poolable> Clone for NodePool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            connect_to: Arc::clone(&self.connect_to),
+            pool: Arc::clone(&self.pool),
+        }
+    }
+}
+
+impl<T: Poolable> Deref for NodePool<T> {
+    type Target = PoolInternal<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pool
+    }
 }
 
 impl<T: Poolable> Inner<T> {
@@ -28,10 +103,12 @@ impl<T: Poolable> Inner<T> {
         create_connection_factory: F,
         executor_flavour: ExecutorFlavour,
         instrumentation: InstrumentationFlavour,
+        connection_initializer: Option<ConnectionInitializer<T>>,
+        first_pool_id: PoolId,
     ) -> InitializationResult<Self>
     where
         CF: ConnectionFactory<Connection = T> + Send + Sync + 'static,
-        F: Fn(String) -> InitializationResult<CF>,
+        F: Fn(String) -> InitializationResult<CF> + Send + Sync + 'static,
     {
         if config.pool_multiplier == 0 {
             return Err(InitializationError::message_only(
@@ -41,12 +118,12 @@ impl<T: Poolable> Inner<T> {
 
         let multiplier = config.pool_multiplier as usize;
         if multiplier != 1 {
-            let new_connections_per_pool = config.desired_pool_size / multiplier + 1;
-            let new_reservation_limit = if config.reservation_limit == 0 {
-                config.reservation_limit
-            } else {
-                config.reservation_limit / multiplier + 1
-            };
+            let new_connections_per_pool = size_per_pool(config.desired_pool_size, multiplier);
+            let new_max_pool_size = config
+                .max_pool_size
+                .map(|max| size_per_pool(max, multiplier));
+            let new_reservation_limit =
+                reservation_limit_per_pool(config.reservation_limit, multiplier);
 
             info!(
                 "Pool per node multiplier is {}. Connections per pool will be {}(config: {}) \
@@ -59,32 +136,54 @@ impl<T: Poolable> Inner<T> {
             );
 
             config.desired_pool_size = new_connections_per_pool;
+            config.max_pool_size = new_max_pool_size;
             config.reservation_limit = new_reservation_limit;
         }
 
-        let mut pools = Vec::new();
-        let mut id = PoolId::new(0);
-        for _ in 0..multiplier {
-            for connect_to in &config.connect_to_nodes {
+        let pool_config = Arc::new(Mutex::new(PoolConfig {
+            desired_pool_size: config.desired_pool_size,
+            max_pool_size: config.max_pool_size,
+            surplus_idle_timeout: config.surplus_idle_timeout,
+            idle_timeout: config.idle_timeout,
+            max_connection_lifetime: config.max_connection_lifetime,
+            connection_lifetime_jitter: config.connection_lifetime_jitter,
+            validation_policy: config.validation_policy,
+            validation_timeout: config.validation_timeout,
+            backoff_strategy: config.backoff_strategy,
+            reservation_limit: config.reservation_limit,
+            activation_order: config.activation_order,
+            checkout_queue_size: config.checkout_queue_size,
+            circuit_breaker_threshold: config.circuit_breaker_threshold,
+            circuit_breaker_open_duration: config.circuit_breaker_open_duration,
+            health_check_interval: config.health_check_interval,
+            health_check_failure_threshold: config.health_check_failure_threshold,
+            reserved_for_high_priority: config.reserved_for_high_priority,
+            leak_detection_threshold: config.leak_detection_threshold,
+        }));
+
+        let create_pool = {
+            let pool_config = Arc::clone(&pool_config);
+            move |connect_to: &str, id: PoolId| {
                 let connection_factory = create_connection_factory(connect_to.to_string())?;
-                let pool_conf = PoolConfig {
-                    desired_pool_size: config.desired_pool_size,
-                    backoff_strategy: config.backoff_strategy,
-                    reservation_limit: config.reservation_limit,
-                    activation_order: config.activation_order,
-                    checkout_queue_size: config.checkout_queue_size,
-                };
+                let pool_conf = pool_config.lock().unwrap().clone();
 
                 let indexed_instrumentation = PoolInstrumentation::new(instrumentation.clone(), id);
 
-                let pool = PoolInternal::new(
+                Ok(PoolInternal::new(
                     pool_conf,
                     connection_factory,
                     executor_flavour.clone(),
                     indexed_instrumentation,
-                );
+                    connection_initializer.clone(),
+                ))
+            }
+        };
 
-                pools.push(pool);
+        let mut pools = Vec::new();
+        let mut id = first_pool_id;
+        for _ in 0..multiplier {
+            for connect_to in &config.connect_to_nodes {
+                pools.push(NodePool::new(connect_to, create_pool(connect_to, id)?));
                 id.inc();
             }
         }
@@ -92,63 +191,84 @@ impl<T: Poolable> Inner<T> {
         debug!("pool per node has {} nodes", pools.len());
 
         let inner = Inner {
-            count: AtomicUsize::new(0),
-            pools: Arc::new(pools),
+            balancer: config.load_balancing_strategy.create(),
+            multiplier,
+            nodes: RwLock::new(Arc::new(Nodes::new(config.connect_to_nodes, pools))),
+            create_pool: Box::new(create_pool),
+            pool_config,
+            next_pool_id: Mutex::new(id),
         };
 
         Ok(inner)
     }
 
     pub fn check_out(&self, constraint: CheckoutConstraint) -> CheckoutManaged<T> {
-        if self.pools.is_empty() {
-            return CheckoutManaged::new(future::err(CheckoutError::new(
-                CheckoutErrorKind::NoPool,
-            )));
+        let nodes = self.nodes();
+        let pools = &nodes.pools;
+
+        if pools.is_empty() {
+            return CheckoutManaged::error(CheckoutErrorKind::NoPool);
         }
 
         if constraint.is_deadline_elapsed() {
-            return CheckoutManaged::new(future::err(CheckoutError::new(
-                CheckoutErrorKind::CheckoutTimeout,
-            )));
+            return CheckoutManaged::error(CheckoutErrorKind::CheckoutTimeout);
         }
 
-        let position = self.count.fetch_add(1, Ordering::SeqCst);
-        let first_pool_index = position % self.pools.len();
+        let position = if pools.len() == 1 {
+            0
+        } else {
+            self.balancer.select(&nodes.balanced) % pools.len()
+        };
+
+        // Skip the sub pools with an open circuit or an unhealthy
+        // node unless there are no others
+        let mut order: Vec<usize> = (position..position + pools.len())
+            .map(|position| position % pools.len())
+            .collect();
+        if pools.len() > 1 {
+            let available: Vec<usize> = order
+                .iter()
+                .copied()
+                .filter(|&idx| {
+                    pools[idx].is_healthy() && pools[idx].circuit_state() != CircuitState::Open
+                })
+                .collect();
+            if !available.is_empty() {
+                order = available;
+            }
+        }
 
         // Do the first attempt as Immediate since we can still apply original constraint
         // later if we have more then one pool
         let mut last_failed_checkout = {
-            let effective_constraint = if self.pools.len() == 1 {
+            let effective_constraint = if order.len() == 1 {
                 constraint
             } else {
-                CheckoutConstraint::Immediately
+                constraint.immediately()
             };
-            match self.pools[first_pool_index].check_out(effective_constraint) {
+            match pools[order[0]].check_out(effective_constraint) {
                 Ok(checkout) => return checkout,
-                Err(failed_checkout) if self.pools.len() == 1 => {
-                    return CheckoutManaged::error(failed_checkout.error_kind)
+                Err(failed_checkout) if order.len() == 1 => {
+                    return CheckoutManaged::error(failed_checkout.error)
                 }
                 Err(failed_checkout) => failed_checkout,
             }
         };
 
-        let iteration_bound = position + self.pools.len();
-        let last_iteration = iteration_bound - 1;
+        let last_iteration = order.len() - 1;
         // Iterate over all but the first pool because we already tried that.
-        for position in position + 1..iteration_bound {
+        for (iteration, &idx) in order.iter().enumerate().skip(1) {
             if constraint.is_deadline_elapsed() {
                 return CheckoutManaged::error(CheckoutErrorKind::CheckoutTimeout);
             }
 
-            let idx = position % self.pools.len();
-
-            let current_constraint = if position >= last_iteration {
+            let current_constraint = if iteration >= last_iteration {
                 constraint
             } else {
-                CheckoutConstraint::Immediately
+                constraint.immediately()
             };
 
-            match self.pools[idx].check_out2(
+            match pools[idx].check_out2(
                 last_failed_checkout.checkout_requested_at,
                 current_constraint,
             ) {
@@ -159,18 +279,178 @@ impl<T: Poolable> Inner<T> {
             }
         }
 
-        CheckoutManaged::error(last_failed_checkout.error_kind)
+        CheckoutManaged::error(last_failed_checkout.error)
+    }
+
+    pub fn connected_to(&self) -> Vec<String> {
+        self.nodes().connected_to.clone()
+    }
+
+    pub fn sub_pools(&self) -> Vec<Arc<PoolInternal<T>>> {
+        self.nodes()
+            .pools
+            .iter()
+            .map(|p| Arc::clone(&p.pool))
+            .collect()
     }
 
     pub fn state(&self) -> PoolState {
-        self.pools
+        self.nodes()
+            .pools
             .iter()
             .map(|p| p.state())
             .fold(PoolState::default(), |a, b| a + b)
     }
 
-    pub fn ping(&self, timeout: Instant) -> impl Future<Item = Vec<Ping>, Error = ()> + Send {
-        let futs: Vec<_> = self.pools.iter().map(|p| p.ping(timeout)).collect();
-        future::join_all(futs)
+    pub fn ping(&self, timeout: Instant) -> impl Future<Output = Result<Vec<Ping>, ()>> + Send {
+        let futs: Vec<_> = self.nodes().pools.iter().map(|p| p.ping(timeout)).collect();
+        future::try_join_all(futs)
+    }
+
+    /// Splits the sizes among the sub pools of a node like it
+    /// was done when the pool was created
+    pub fn reconfigure(&self, mut reconfiguration: Reconfiguration) {
+        if self.multiplier != 1 {
+            let multiplier = self.multiplier;
+            reconfiguration.desired_pool_size = reconfiguration
+                .desired_pool_size
+                .map(|v| size_per_pool(v, multiplier));
+            reconfiguration.reservation_limit = reconfiguration
+                .reservation_limit
+                .map(|v| reservation_limit_per_pool(v, multiplier));
+        }
+
+        {
+            let mut pool_config = self.pool_config.lock().unwrap();
+            if let Some(v) = reconfiguration.desired_pool_size {
+                pool_config.desired_pool_size = v;
+            }
+            if let Some(v) = reconfiguration.reservation_limit {
+                pool_config.reservation_limit = v;
+            }
+            if let Some(v) = reconfiguration.activation_order {
+                pool_config.activation_order = v;
+            }
+            if let Some(v) = reconfiguration.backoff_strategy {
+                pool_config.backoff_strategy = v;
+            }
+        }
+
+        self.nodes()
+            .pools
+            .iter()
+            .for_each(|p| p.reconfigure(reconfiguration.clone()));
+    }
+
+    /// Creates the sub pools for a new node
+    pub fn add_node(&self, connect_to: String) -> InitializationResult<()> {
+        // Holding the lock makes adding and removing nodes sequential
+        let mut nodes = self.nodes.write().unwrap();
+
+        if nodes.connected_to.contains(&connect_to) {
+            return Err(InitializationError::message_only(format!(
+                "already connected to node '{}'",
+                connect_to
+            )));
+        }
+
+        let mut new_pools = Vec::with_capacity(self.multiplier);
+        {
+            let mut id = self.next_pool_id.lock().unwrap();
+            for _ in 0..self.multiplier {
+                new_pools.push(NodePool::new(
+                    &connect_to,
+                    (self.create_pool)(&connect_to, *id)?,
+                ));
+                id.inc();
+            }
+        }
+
+        info!("adding node '{}'", connect_to);
+
+        let mut connected_to = nodes.connected_to.clone();
+        connected_to.push(connect_to);
+        let mut pools = nodes.pools.clone();
+        pools.extend(new_pools);
+
+        *nodes = Arc::new(Nodes::new(connected_to, pools));
+
+        Ok(())
+    }
+
+    /// Removes the sub pools of a node so that no more checkouts are
+    /// made on them.
+    ///
+    /// The returned future shuts the removed sub pools down and completes
+    /// once they are drained or `deadline` has elapsed.
+    pub fn remove_node(
+        &self,
+        connect_to: &str,
+        deadline: Instant,
+    ) -> InitializationResult<impl Future<Output = ()> + Send> {
+        let removed = {
+            let mut nodes = self.nodes.write().unwrap();
+
+            if !nodes.connected_to.iter().any(|node| node == connect_to) {
+                return Err(InitializationError::message_only(format!(
+                    "not connected to node '{}'",
+                    connect_to
+                )));
+            }
+
+            info!("removing node '{}'", connect_to);
+
+            let connected_to = nodes
+                .connected_to
+                .iter()
+                .filter(|node| *node != connect_to)
+                .cloned()
+                .collect();
+            let (removed, pools): (Vec<_>, Vec<_>) = nodes
+                .pools
+                .iter()
+                .cloned()
+                .partition(|p| &*p.connect_to == connect_to);
+
+            *nodes = Arc::new(Nodes::new(connected_to, pools));
+
+            removed
+        };
+
+        let futs: Vec<_> = removed.iter().map(|p| p.shutdown(deadline)).collect();
+        // Keep the removed sub pools alive until they are drained. Dropping
+        // them stops them right away.
+        Ok(future::join_all(futs).map(move |_| drop(removed)))
+    }
+
+    pub fn shutdown(&self, deadline: Instant) -> impl Future<Output = ()> + Send {
+        let futs: Vec<_> = self
+            .nodes()
+            .pools
+            .iter()
+            .map(|p| p.shutdown(deadline))
+            .collect();
+        future::join_all(futs).map(|_| ())
+    }
+}
+
+impl<T: Poolable> Inner<T> {
+    fn nodes(&self) -> Arc<Nodes<T>> {
+        Arc::clone(&self.nodes.read().unwrap())
+    }
+}
+
+/// The size of a single sub pool if there are `multiplier` sub pools per node
+fn size_per_pool(size: usize, multiplier: usize) -> usize {
+    size / multiplier + 1
+}
+
+/// The reservation limit of a single sub pool if there are `multiplier`
+/// sub pools per node. A limit of zero stays zero.
+fn reservation_limit_per_pool(limit: usize, multiplier: usize) -> usize {
+    if limit == 0 {
+        0
+    } else {
+        limit / multiplier + 1
     }
 }
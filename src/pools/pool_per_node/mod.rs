@@ -2,15 +2,15 @@
 use std::sync::Arc;
 use std::time::Instant;
 
-use futures::future::Future;
 use log::info;
+use std::future::Future;
 
-use crate::config::Config;
-use crate::connection_factory::ConnectionFactory;
+use crate::config::{Config, Reconfiguration};
+use crate::connection_factory::{ConnectionFactory, ConnectionInitializer};
 use crate::error::InitializationResult;
 use crate::executor_flavour::ExecutorFlavour;
-use crate::instrumentation::InstrumentationFlavour;
-use crate::pools::pool_internal::CheckoutManaged;
+use crate::instrumentation::{InstrumentationFlavour, PoolId};
+use crate::pools::pool_internal::{CheckoutManaged, PoolInternal};
 use crate::{Ping, PoolState, Poolable};
 
 use super::{CanCheckout, CheckoutConstraint};
@@ -27,12 +27,12 @@ use self::inner::*;
 /// You should only perform read operations on the
 /// connections received from this kind of pool.
 ///
-/// The replicas are selected in a round robin fashion.
+/// The replicas are selected according to the configured `LoadBalancingStrategy`.
 ///
 /// The pool is cloneable and all clones share their connections.
 /// Once the last instance drops the shared connections will be dropped.
 pub(crate) struct PoolPerNode<T: Poolable> {
-    inner: Arc<(Inner<T>, Vec<String>)>,
+    inner: Arc<Inner<T>>,
 }
 
 impl<T: Poolable> PoolPerNode<T> {
@@ -41,46 +41,98 @@ impl<T: Poolable> PoolPerNode<T> {
         create_connection_factory: F,
         executor_flavour: ExecutorFlavour,
         instrumentation: InstrumentationFlavour,
+        connection_initializer: Option<ConnectionInitializer<T>>,
     ) -> InitializationResult<PoolPerNode<T>>
     where
         CF: ConnectionFactory<Connection = T> + Send + Sync + 'static,
-        F: Fn(String) -> InitializationResult<CF>,
+        F: Fn(String) -> InitializationResult<CF> + Send + Sync + 'static,
+    {
+        Self::with_first_pool_id(
+            config,
+            create_connection_factory,
+            executor_flavour,
+            instrumentation,
+            connection_initializer,
+            PoolId::new(0),
+        )
+    }
+
+    /// Creates a pool whose sub pools report to the instrumentation
+    /// with ids counting up from `first_pool_id`
+    pub fn with_first_pool_id<F, CF>(
+        config: Config,
+        create_connection_factory: F,
+        executor_flavour: ExecutorFlavour,
+        instrumentation: InstrumentationFlavour,
+        connection_initializer: Option<ConnectionInitializer<T>>,
+        first_pool_id: PoolId,
+    ) -> InitializationResult<PoolPerNode<T>>
+    where
+        CF: ConnectionFactory<Connection = T> + Send + Sync + 'static,
+        F: Fn(String) -> InitializationResult<CF> + Send + Sync + 'static,
     {
         info!(
             "Creating pool per node for {:?} nodes",
             config.connect_to_nodes
         );
 
-        let connected_to = config.connect_to_nodes.clone();
-
         let inner = Inner::new(
             config,
             create_connection_factory,
             executor_flavour,
             instrumentation,
+            connection_initializer,
+            first_pool_id,
         )?;
 
         Ok(PoolPerNode {
-            inner: Arc::new((inner, connected_to)),
+            inner: Arc::new(inner),
         })
     }
 
-    pub fn connected_to(&self) -> &[String] {
-        &(self.inner.1)
+    pub fn connected_to(&self) -> Vec<String> {
+        self.inner.connected_to()
     }
 
     pub fn state(&self) -> PoolState {
-        self.inner.0.state()
+        self.inner.state()
+    }
+
+    pub fn sub_pools(&self) -> Vec<Arc<PoolInternal<T>>> {
+        self.inner.sub_pools()
+    }
+
+    pub fn ping(&self, timeout: Instant) -> impl Future<Output = Result<Vec<Ping>, ()>> + Send {
+        self.inner.ping(timeout)
+    }
+
+    pub fn reconfigure(&self, reconfiguration: Reconfiguration) {
+        self.inner.reconfigure(reconfiguration)
+    }
+
+    /// Adds a node. Checkouts are made on the new node right away.
+    pub fn add_node(&self, connect_to: String) -> InitializationResult<()> {
+        self.inner.add_node(connect_to)
+    }
+
+    /// Removes a node. The returned future completes once the
+    /// connections to the node have been closed or `deadline` has elapsed.
+    pub fn remove_node(
+        &self,
+        connect_to: &str,
+        deadline: Instant,
+    ) -> InitializationResult<impl Future<Output = ()> + Send> {
+        self.inner.remove_node(connect_to, deadline)
     }
 
-    pub fn ping(&self, timeout: Instant) -> impl Future<Item = Vec<Ping>, Error = ()> + Send {
-        self.inner.0.ping(timeout)
+    pub fn shutdown(&self, deadline: Instant) -> impl Future<Output = ()> + Send {
+        self.inner.shutdown(deadline)
     }
 }
 
 impl<T: Poolable> CanCheckout<T> for PoolPerNode<T> {
     fn check_out<M: Into<CheckoutConstraint>>(&self, constraint: M) -> CheckoutManaged<T> {
-        self.inner.0.check_out(constraint.into())
+        self.inner.check_out(constraint.into())
     }
 }
 
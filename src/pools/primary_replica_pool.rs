@@ -0,0 +1,232 @@
+//! A pool for a primary and its replicas
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures::future::{self, FutureExt, TryFutureExt};
+use log::{debug, info};
+
+use crate::config::{Config, Reconfiguration};
+use crate::connection_factory::{ConnectionFactory, ConnectionInitializer};
+use crate::error::{InitializationError, InitializationResult};
+use crate::executor_flavour::ExecutorFlavour;
+use crate::instrumentation::{InstrumentationFlavour, PoolId, PoolRole};
+use crate::{CheckoutErrorKind, Ping, PoolState, Poolable};
+
+use super::pool_internal::{CheckoutManaged, PoolInternal};
+use super::{CanCheckout, CheckoutConstraint, PoolPerNode, SinglePool};
+
+/// A pool with a sub pool for the primary and a sub pool
+/// for each of the replicas.
+///
+/// Checkouts made via `CanCheckout` go to the primary. Use
+/// `replicas` to check out a connection to a replica.
+///
+/// The primary reports to the instrumentation with the `PoolId` 0
+/// and the replicas with the ids following it. The role of the
+/// node is part of the `PoolId`.
+///
+/// The pool is cloneable and all clones share their connections.
+/// Once the last instance drops the shared connections will be dropped.
+pub(crate) struct PrimaryReplicaPool<T: Poolable> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T: Poolable> {
+    primary: SinglePool<T>,
+    replicas: PoolPerNode<T>,
+    fallback_to_primary: bool,
+}
+
+impl<T: Poolable> PrimaryReplicaPool<T> {
+    pub fn new<F, CF>(
+        mut config: Config,
+        create_connection_factory: F,
+        executor_flavour: ExecutorFlavour,
+        instrumentation: InstrumentationFlavour,
+        connection_initializer: Option<ConnectionInitializer<T>>,
+    ) -> InitializationResult<PrimaryReplicaPool<T>>
+    where
+        CF: ConnectionFactory<Connection = T> + Send + Sync + 'static,
+        F: Fn(String) -> InitializationResult<CF> + Send + Sync + 'static,
+    {
+        if config.connect_to_nodes.len() != 1 {
+            return Err(InitializationError::message_only(format!(
+                "there must be exactly 1 primary given - found {}",
+                config.connect_to_nodes.len()
+            )));
+        }
+
+        if config.replicas.is_empty() {
+            return Err(InitializationError::message_only(
+                "there must be at least one replica",
+            ));
+        }
+
+        info!(
+            "Creating primary replica pool for primary {:?} and replicas {:?}",
+            config.connect_to_nodes, config.replicas
+        );
+
+        let fallback_to_primary = config.replica_fallback_to_primary;
+        let replicas = std::mem::take(&mut config.replicas);
+
+        let mut primary_config = config.clone();
+        primary_config.pool_multiplier = 1;
+
+        let create_connection_factory = Arc::new(create_connection_factory);
+
+        let primary = SinglePool::with_pool_id(
+            primary_config,
+            &*create_connection_factory,
+            executor_flavour.clone(),
+            instrumentation.clone(),
+            connection_initializer.clone(),
+            PoolId::with_role(0, PoolRole::Primary),
+        )?;
+
+        config.connect_to_nodes = replicas;
+
+        let replicas = PoolPerNode::with_first_pool_id(
+            config,
+            move |connect_to| create_connection_factory(connect_to),
+            executor_flavour,
+            instrumentation,
+            connection_initializer,
+            PoolId::with_role(1, PoolRole::Replica),
+        )?;
+
+        Ok(PrimaryReplicaPool {
+            inner: Arc::new(Inner {
+                primary,
+                replicas,
+                fallback_to_primary,
+            }),
+        })
+    }
+
+    /// Returns something to check out connections to the replicas
+    pub fn replicas(&self) -> Replicas<T> {
+        Replicas {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+
+    /// The primary first and then the replicas
+    pub fn connected_to(&self) -> Vec<String> {
+        let mut connected_to = vec![self.inner.primary.connected_to().to_string()];
+        connected_to.extend(self.inner.replicas.connected_to());
+        connected_to
+    }
+
+    pub fn state(&self) -> PoolState {
+        self.inner.primary.state() + self.inner.replicas.state()
+    }
+
+    /// The sub pool of the primary first and then the ones of the replicas
+    pub fn sub_pools(&self) -> Vec<Arc<PoolInternal<T>>> {
+        let mut sub_pools = self.inner.primary.sub_pools();
+        sub_pools.extend(self.inner.replicas.sub_pools());
+        sub_pools
+    }
+
+    pub fn ping(&self, timeout: Instant) -> impl Future<Output = Result<Vec<Ping>, ()>> + Send {
+        future::try_join(
+            self.inner.primary.ping(timeout),
+            self.inner.replicas.ping(timeout),
+        )
+        .map_ok(|(primary, mut replicas)| {
+            replicas.insert(0, primary);
+            replicas
+        })
+    }
+
+    /// Applies the reconfiguration to the primary and to each of the replicas
+    pub fn reconfigure(&self, reconfiguration: Reconfiguration) {
+        self.inner.primary.reconfigure(reconfiguration.clone());
+        self.inner.replicas.reconfigure(reconfiguration);
+    }
+
+    pub fn add_replica(&self, connect_to: String) -> InitializationResult<()> {
+        self.inner.replicas.add_node(connect_to)
+    }
+
+    pub fn remove_replica(
+        &self,
+        connect_to: &str,
+        deadline: Instant,
+    ) -> InitializationResult<impl Future<Output = ()> + Send> {
+        self.inner.replicas.remove_node(connect_to, deadline)
+    }
+
+    pub fn shutdown(&self, deadline: Instant) -> impl Future<Output = ()> + Send {
+        future::join(
+            self.inner.primary.shutdown(deadline),
+            self.inner.replicas.shutdown(deadline),
+        )
+        .map(|_| ())
+    }
+}
+
+impl<T: Poolable> CanCheckout<T> for PrimaryReplicaPool<T> {
+    fn check_out<M: Into<CheckoutConstraint>>(&self, constraint: M) -> CheckoutManaged<T> {
+        self.inner.primary.check_out(constraint)
+    }
+}
+
+impl<T: Poolable> Clone for PrimaryReplicaPool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+/// Checks out connections to the replicas of a `PrimaryReplicaPool`.
+///
+/// If enabled, checkouts fall back to the primary when no
+/// replica has a connection or the checkout on the replicas failed.
+pub(crate) struct Replicas<T: Poolable> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T: Poolable> CanCheckout<T> for Replicas<T> {
+    fn check_out<M: Into<CheckoutConstraint>>(&self, constraint: M) -> CheckoutManaged<T> {
+        let constraint = constraint.into();
+
+        if !self.inner.fallback_to_primary {
+            return self.inner.replicas.check_out(constraint);
+        }
+
+        if self.inner.replicas.state().connections == 0 {
+            debug!("no connections to any replica - checking out from the primary");
+            return self.inner.primary.check_out(constraint);
+        }
+
+        let inner = Arc::clone(&self.inner);
+        CheckoutManaged::new(
+            self.inner
+                .replicas
+                .check_out(constraint)
+                .or_else(move |err| {
+                    if err.kind() == CheckoutErrorKind::TaskExecution {
+                        CheckoutManaged::error(err)
+                    } else {
+                        debug!(
+                            "checkout on the replicas failed ({}) - checking out from the primary",
+                            err
+                        );
+                        inner.primary.check_out(constraint)
+                    }
+                }),
+        )
+    }
+}
+
+impl<T: Poolable> Clone for Replicas<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
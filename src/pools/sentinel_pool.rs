@@ -0,0 +1,759 @@
+//! A pool which discovers its nodes via Redis Sentinel
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant};
+
+use futures::future::{self, BoxFuture, Either, FutureExt};
+use log::{debug, info, warn};
+use tokio::sync::oneshot;
+use tokio::time;
+
+use crate::config::{Config, Reconfiguration, SentinelConfig};
+use crate::connection_factory::{ConnectionFactory, ConnectionInitializer};
+use crate::error::{InitializationError, InitializationResult};
+use crate::executor_flavour::ExecutorFlavour;
+use crate::instrumentation::InstrumentationFlavour;
+use crate::redis_rs::{sentinel, RedisRsFactory};
+use crate::{CheckoutErrorKind, Ping, PoolState, Poolable};
+
+use super::pool_internal::{CheckoutManaged, PoolInternal};
+use super::{CanCheckout, CheckoutConstraint, PoolPerNode, PrimaryReplicaPool};
+
+/// The time to wait before asking the next sentinel
+/// after a sentinel failed
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The time after which the sentinel is asked for the nodes again
+/// if it did not announce anything. Announcements may get lost
+/// since pub/sub does not deliver messages reliably.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The time the connections of a pool replaced after a failover
+/// and of a removed replica have to be returned before they are closed
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+type CreatePool<T> =
+    Box<dyn Fn(&Discovered) -> InitializationResult<Nodes<T>> + Send + Sync + 'static>;
+
+/// A pool whose nodes are discovered via Redis Sentinel.
+///
+/// A background task subscribes to the announcements of the sentinels.
+/// Once the sentinels announce a new master a new pool for the new
+/// nodes replaces the current one. The replaced pool is drained and
+/// shut down in the background.
+///
+/// If enabled, the pool also connects to the replicas of the master.
+/// Replicas which were added, went down or came back are picked up
+/// without replacing the pool.
+///
+/// Until the nodes have been discovered there are no connections
+/// and checkouts fail with `CheckoutErrorKind::NoPool`.
+///
+/// The pool is cloneable and all clones share their connections.
+/// Once the last instance drops or the pool is shut down the
+/// background task stops.
+pub(crate) struct SentinelPool<T: Poolable> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T: Poolable> {
+    current: RwLock<Option<Current<T>>>,
+    create_pool: CreatePool<T>,
+    /// The config for pools created after a failover
+    config: Arc<RwLock<Config>>,
+    /// Drains replaced pools and removed replicas
+    executor_flavour: ExecutorFlavour,
+    connect_to_replicas: bool,
+    // Stops the background task once dropped or taken
+    stop_watching: Mutex<Option<oneshot::Sender<()>>>,
+    shutting_down: AtomicBool,
+}
+
+impl<T: Poolable> SentinelPool<T> {
+    pub fn new<F, CF>(
+        config: Config,
+        sentinel_config: SentinelConfig,
+        sentinels: Vec<RedisRsFactory>,
+        create_connection_factory: F,
+        executor_flavour: ExecutorFlavour,
+        instrumentation: InstrumentationFlavour,
+        connection_initializer: Option<ConnectionInitializer<T>>,
+    ) -> InitializationResult<SentinelPool<T>>
+    where
+        CF: ConnectionFactory<Connection = T> + Send + Sync + 'static,
+        F: Fn(String) -> InitializationResult<CF> + Send + Sync + 'static,
+    {
+        if sentinel_config.master_name.is_empty() {
+            return Err(InitializationError::message_only(
+                "the name of the master must not be empty",
+            ));
+        }
+
+        if sentinels.is_empty() {
+            return Err(InitializationError::message_only(
+                "there must be at least one sentinel",
+            ));
+        }
+
+        info!(
+            "Creating sentinel pool for master '{}' with sentinels {:?}",
+            sentinel_config.master_name, sentinel_config.sentinels
+        );
+
+        let config = Arc::new(RwLock::new(config));
+        let create_pool = create_pool(
+            Arc::clone(&config),
+            create_connection_factory,
+            executor_flavour.clone(),
+            instrumentation,
+            connection_initializer,
+        );
+
+        let (stop_tx, stop_rx) = oneshot::channel();
+
+        let inner = Arc::new(Inner {
+            current: RwLock::new(None),
+            create_pool,
+            config,
+            executor_flavour: executor_flavour.clone(),
+            connect_to_replicas: sentinel_config.connect_to_replicas,
+            stop_watching: Mutex::new(Some(stop_tx)),
+            shutting_down: AtomicBool::new(false),
+        });
+
+        let watcher = future::select(
+            watch_sentinels(Arc::downgrade(&inner), sentinel_config, sentinels).boxed(),
+            stop_rx,
+        )
+        .map(|_| debug!("stopped watching the sentinels"));
+
+        executor_flavour.spawn(watcher).map_err(|err| {
+            InitializationError::message_only(format!(
+                "could not spawn the task watching the sentinels: {}",
+                err
+            ))
+        })?;
+
+        Ok(SentinelPool { inner })
+    }
+
+    pub fn connected_to(&self) -> Vec<String> {
+        self.current_pool()
+            .map(|pool| pool.connected_to())
+            .unwrap_or_default()
+    }
+
+    pub fn state(&self) -> PoolState {
+        self.current_pool()
+            .map(|pool| pool.state())
+            .unwrap_or_default()
+    }
+
+    pub fn sub_pools(&self) -> Vec<Arc<PoolInternal<T>>> {
+        self.current_pool()
+            .map(|pool| pool.sub_pools())
+            .unwrap_or_default()
+    }
+
+    pub fn ping(&self, timeout: Instant) -> impl Future<Output = Result<Vec<Ping>, ()>> + Send {
+        if let Some(pool) = self.current_pool() {
+            Either::Left(pool.ping(timeout))
+        } else {
+            Either::Right(future::ok(Vec::new()))
+        }
+    }
+
+    /// Reconfigures the current pool. Pools created after
+    /// a failover will also use the new values.
+    pub fn reconfigure(&self, reconfiguration: Reconfiguration) {
+        reconfiguration.apply_to(&mut self.inner.config.write().unwrap());
+        if let Some(pool) = self.current_pool() {
+            pool.reconfigure(reconfiguration);
+        }
+    }
+
+    /// Stop watching the sentinels and shut down the current pool
+    pub fn shutdown(&self, deadline: Instant) -> impl Future<Output = ()> + Send {
+        self.inner.shutting_down.store(true, Ordering::SeqCst);
+        self.inner.stop_watching.lock().unwrap().take();
+
+        if let Some(pool) = self.current_pool() {
+            Either::Left(pool.shutdown(deadline))
+        } else {
+            Either::Right(future::ready(()))
+        }
+    }
+
+    /// Returns something to check out connections to the replicas
+    pub fn replicas(&self) -> Replicas<T> {
+        Replicas {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+
+    fn current_pool(&self) -> Option<Nodes<T>> {
+        self.inner
+            .current
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|current| current.pool.clone())
+    }
+}
+
+/// Creates the pool for the discovered nodes with the current config
+fn create_pool<T, F, CF>(
+    config: Arc<RwLock<Config>>,
+    create_connection_factory: F,
+    executor_flavour: ExecutorFlavour,
+    instrumentation: InstrumentationFlavour,
+    connection_initializer: Option<ConnectionInitializer<T>>,
+) -> CreatePool<T>
+where
+    T: Poolable,
+    CF: ConnectionFactory<Connection = T> + Send + Sync + 'static,
+    F: Fn(String) -> InitializationResult<CF> + Send + Sync + 'static,
+{
+    let create_connection_factory = Arc::new(create_connection_factory);
+
+    Box::new(move |discovered: &Discovered| {
+        let mut config = config.read().unwrap().clone();
+        config.connect_to_nodes = vec![discovered.master.clone()];
+        config.replicas = discovered.replicas.clone();
+        let create_connection_factory = Arc::clone(&create_connection_factory);
+        let create_connection_factory = move |connect_to| create_connection_factory(connect_to);
+        if config.replicas.is_empty() {
+            PoolPerNode::new(
+                config,
+                create_connection_factory,
+                executor_flavour.clone(),
+                instrumentation.clone(),
+                connection_initializer.clone(),
+            )
+            .map(Nodes::Master)
+        } else {
+            PrimaryReplicaPool::new(
+                config,
+                create_connection_factory,
+                executor_flavour.clone(),
+                instrumentation.clone(),
+                connection_initializer.clone(),
+            )
+            .map(Nodes::MasterAndReplicas)
+        }
+    })
+}
+
+impl<T: Poolable> Inner<T> {
+    /// Replaces the current pool if the master changed and
+    /// adds or removes replicas if only they changed
+    fn update_nodes(&self, mut discovered: Discovered) -> Result<(), String> {
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return Err("the pool is shutting down".to_string());
+        }
+
+        discovered.replicas.sort();
+
+        let mut current = self.current.write().unwrap();
+
+        if let Some(ref mut current) = *current {
+            if current.discovered == discovered {
+                debug!("nodes did not change: {:?}", discovered);
+                return Ok(());
+            }
+
+            if let Nodes::MasterAndReplicas(ref pool) = current.pool {
+                if current.discovered.master == discovered.master {
+                    info!("replicas changed to {:?}", discovered.replicas);
+                    self.update_replicas(pool, &current.discovered.replicas, &discovered.replicas)?;
+                    current.discovered = discovered;
+                    return Ok(());
+                }
+            }
+        }
+
+        info!("nodes changed to {:?} - creating a new pool", discovered);
+
+        let pool = (self.create_pool)(&discovered).map_err(|err| err.to_string())?;
+        let replaced = current.replace(Current { discovered, pool });
+        drop(current);
+
+        if let Some(replaced) = replaced {
+            self.drain(replaced.pool.shutdown(Instant::now() + DRAIN_TIMEOUT));
+        }
+
+        Ok(())
+    }
+
+    /// Adds the new replicas before the removed ones
+    /// are removed so that there is always a replica
+    fn update_replicas(
+        &self,
+        pool: &PrimaryReplicaPool<T>,
+        current: &[String],
+        discovered: &[String],
+    ) -> Result<(), String> {
+        for replica in discovered.iter().filter(|r| !current.contains(r)) {
+            pool.add_replica(replica.clone())
+                .map_err(|err| err.to_string())?;
+        }
+
+        for replica in current.iter().filter(|r| !discovered.contains(r)) {
+            let removed = pool
+                .remove_replica(replica, Instant::now() + DRAIN_TIMEOUT)
+                .map_err(|err| err.to_string())?;
+            self.drain(removed.boxed());
+        }
+
+        Ok(())
+    }
+
+    /// The checkout if there is no pool for the nodes (yet)
+    fn no_pool(&self) -> CheckoutManaged<T> {
+        if self.shutting_down.load(Ordering::SeqCst) {
+            CheckoutManaged::error(CheckoutErrorKind::ShuttingDown)
+        } else {
+            CheckoutManaged::error(CheckoutErrorKind::NoPool)
+        }
+    }
+
+    /// Closes the connections of a replaced pool or a removed replica in the background
+    fn drain(&self, shutdown: BoxFuture<'static, ()>) {
+        if let Err(err) = self.executor_flavour.spawn(shutdown) {
+            warn!("could not spawn the task draining a replaced pool: {}", err);
+        }
+    }
+}
+
+/// The nodes last announced by a sentinel
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Discovered {
+    master: String,
+    /// Sorted. Empty if the pool does not connect to the replicas
+    /// or the sentinel knows no replicas.
+    replicas: Vec<String>,
+}
+
+/// The pool for the currently announced nodes
+struct Current<T: Poolable> {
+    discovered: Discovered,
+    pool: Nodes<T>,
+}
+
+/// A pool for the master only or for the master and its replicas
+enum Nodes<T: Poolable> {
+    Master(PoolPerNode<T>),
+    MasterAndReplicas(PrimaryReplicaPool<T>),
+}
+
+impl<T: Poolable> Nodes<T> {
+    fn connected_to(&self) -> Vec<String> {
+        match self {
+            Nodes::Master(pool) => pool.connected_to(),
+            Nodes::MasterAndReplicas(pool) => pool.connected_to(),
+        }
+    }
+
+    fn state(&self) -> PoolState {
+        match self {
+            Nodes::Master(pool) => pool.state(),
+            Nodes::MasterAndReplicas(pool) => pool.state(),
+        }
+    }
+
+    fn sub_pools(&self) -> Vec<Arc<PoolInternal<T>>> {
+        match self {
+            Nodes::Master(pool) => pool.sub_pools(),
+            Nodes::MasterAndReplicas(pool) => pool.sub_pools(),
+        }
+    }
+
+    fn ping(&self, timeout: Instant) -> BoxFuture<'static, Result<Vec<Ping>, ()>> {
+        match self {
+            Nodes::Master(pool) => pool.ping(timeout).boxed(),
+            Nodes::MasterAndReplicas(pool) => pool.ping(timeout).boxed(),
+        }
+    }
+
+    fn reconfigure(&self, reconfiguration: Reconfiguration) {
+        match self {
+            Nodes::Master(pool) => pool.reconfigure(reconfiguration),
+            Nodes::MasterAndReplicas(pool) => pool.reconfigure(reconfiguration),
+        }
+    }
+
+    /// The returned future keeps the pool alive until it is drained
+    fn shutdown(self, deadline: Instant) -> BoxFuture<'static, ()> {
+        match self {
+            Nodes::Master(pool) => {
+                let shutdown = pool.shutdown(deadline);
+                async move {
+                    shutdown.await;
+                    drop(pool);
+                }
+                .boxed()
+            }
+            Nodes::MasterAndReplicas(pool) => {
+                let shutdown = pool.shutdown(deadline);
+                async move {
+                    shutdown.await;
+                    drop(pool);
+                }
+                .boxed()
+            }
+        }
+    }
+}
+
+impl<T: Poolable> CanCheckout<T> for Nodes<T> {
+    fn check_out<M: Into<CheckoutConstraint>>(&self, constraint: M) -> CheckoutManaged<T> {
+        match self {
+            Nodes::Master(pool) => pool.check_out(constraint),
+            Nodes::MasterAndReplicas(pool) => pool.check_out(constraint),
+        }
+    }
+}
+
+impl<T: Poolable> Clone for Nodes<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Nodes::Master(pool) => Nodes::Master(pool.clone()),
+            Nodes::MasterAndReplicas(pool) => Nodes::MasterAndReplicas(pool.clone()),
+        }
+    }
+}
+
+impl<T: Poolable> CanCheckout<T> for SentinelPool<T> {
+    fn check_out<M: Into<CheckoutConstraint>>(&self, constraint: M) -> CheckoutManaged<T> {
+        if let Some(pool) = self.current_pool() {
+            pool.check_out(constraint)
+        } else {
+            self.inner.no_pool()
+        }
+    }
+}
+
+impl<T: Poolable> Clone for SentinelPool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+/// Checks out connections to the replicas of a `SentinelPool`.
+///
+/// If the pool does not connect to the replicas this is the same as
+/// checking out from the pool. Otherwise checkouts fall back to the
+/// master as configured with `replica_fallback_to_primary`.
+pub(crate) struct Replicas<T: Poolable> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T: Poolable> CanCheckout<T> for Replicas<T> {
+    fn check_out<M: Into<CheckoutConstraint>>(&self, constraint: M) -> CheckoutManaged<T> {
+        let pool = self
+            .inner
+            .current
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|current| current.pool.clone());
+        match pool {
+            Some(Nodes::MasterAndReplicas(pool)) => pool.replicas().check_out(constraint),
+            Some(Nodes::Master(pool))
+                if !self.inner.connect_to_replicas
+                    || self
+                        .inner
+                        .config
+                        .read()
+                        .unwrap()
+                        .replica_fallback_to_primary =>
+            {
+                pool.check_out(constraint)
+            }
+            Some(Nodes::Master(_)) | None => self.inner.no_pool(),
+        }
+    }
+}
+
+impl<T: Poolable> Clone for Replicas<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+/// Watch the sentinels one after the other as long as the pool is there.
+async fn watch_sentinels<T: Poolable>(
+    inner: Weak<Inner<T>>,
+    sentinel_config: SentinelConfig,
+    sentinels: Vec<RedisRsFactory>,
+) {
+    let sentinel_config = Arc::new(sentinel_config);
+    let mut attempt = 0;
+    loop {
+        let watcher = Watcher {
+            inner: inner.clone(),
+            sentinel: sentinels[attempt % sentinels.len()].clone(),
+            sentinel_config: Arc::clone(&sentinel_config),
+        };
+
+        if let Err(err) = watcher.watch().await {
+            warn!("Watching the sentinel failed: {}", err);
+        }
+
+        if inner.upgrade().is_none() {
+            return;
+        }
+
+        time::sleep(RETRY_DELAY).await;
+        attempt += 1;
+    }
+}
+
+/// Watches a single sentinel
+struct Watcher<T: Poolable> {
+    inner: Weak<Inner<T>>,
+    sentinel: RedisRsFactory,
+    sentinel_config: Arc<SentinelConfig>,
+}
+
+impl<T: Poolable> Watcher<T> {
+    /// Subscribe to the announcements of the sentinel and update the
+    /// nodes of the pool after each announcement concerning the master
+    /// or, if the pool connects to them, its replicas.
+    ///
+    /// The nodes are also updated right after subscribing
+    /// and after `POLL_INTERVAL` without an announcement
+    /// so that no failover is missed.
+    async fn watch(self) -> Result<(), String> {
+        debug!("watching sentinel '{}'", self.sentinel.connecting_to());
+        let conn = self
+            .sentinel
+            .create_connection()
+            .await
+            .map_err(|err| err.to_string())?;
+        let mut announcements =
+            sentinel::subscribe_to_announcements(conn, self.sentinel_config.connect_to_replicas)
+                .await
+                .map_err(|err| err.to_string())?;
+
+        self.update_nodes().await?;
+
+        loop {
+            let next_announcement =
+                sentinel::next_announcement(&mut announcements, &self.sentinel_config.master_name);
+            let announcement = match time::timeout(POLL_INTERVAL, next_announcement).await {
+                Ok(announcement) => announcement.map_err(|err| err.to_string())?,
+                Err(_elapsed) => {
+                    debug!(
+                        "no announcement from sentinel '{}' - asking for the nodes",
+                        self.sentinel.connecting_to()
+                    );
+                    self.update_nodes().await?;
+                    continue;
+                }
+            };
+            info!(
+                "sentinel '{}' announced '{}' for '{}'",
+                self.sentinel.connecting_to(),
+                announcement,
+                self.sentinel_config.master_name
+            );
+            self.update_nodes().await?;
+        }
+    }
+
+    /// Ask the sentinel for the nodes and update the pool
+    async fn update_nodes(&self) -> Result<(), String> {
+        let sentinel_config = &self.sentinel_config;
+        let tls = self.sentinel.connecting_to().starts_with("rediss://");
+
+        let mut conn = self
+            .sentinel
+            .create_connection()
+            .await
+            .map_err(|err| err.to_string())?;
+
+        let (host, port) = sentinel::get_master_addr(&mut conn, &sentinel_config.master_name)
+            .await
+            .map_err(|err| err.to_string())?
+            .ok_or_else(|| "the sentinel does not know the master".to_string())?;
+
+        let replicas = if sentinel_config.connect_to_replicas {
+            sentinel::get_replica_addrs(&mut conn, &sentinel_config.master_name)
+                .await
+                .map_err(|err| err.to_string())?
+        } else {
+            Vec::new()
+        };
+
+        let discovered = Discovered {
+            master: node_url(tls, &host, port, sentinel_config),
+            replicas: replicas
+                .into_iter()
+                .map(|(host, port)| node_url(tls, &host, port, sentinel_config))
+                .collect(),
+        };
+
+        match self.inner.upgrade() {
+            Some(inner) => inner.update_nodes(discovered),
+            None => Err("the pool is gone".to_string()),
+        }
+    }
+}
+
+fn node_url(tls: bool, host: &str, port: u16, sentinel_config: &SentinelConfig) -> String {
+    let scheme = if tls { "rediss" } else { "redis" };
+    let host = if host.contains(':') {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    };
+    let credentials = sentinel_config
+        .node_password
+        .as_ref()
+        .map(|password| format!(":{}@", percent_encode(password)))
+        .unwrap_or_default();
+    format!(
+        "{}://{}{}:{}/{}",
+        scheme, credentials, host, port, sentinel_config.node_db
+    )
+}
+
+/// Encodes all but the unreserved characters of RFC 3986 so that
+/// a password can be part of a URL
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[test]
+fn node_urls_are_built_from_the_announced_addresses() {
+    let sentinel_config = SentinelConfig::new("mymaster", vec![]);
+    assert_eq!(
+        node_url(false, "10.0.0.1", 6379, &sentinel_config),
+        "redis://10.0.0.1:6379/0"
+    );
+
+    let sentinel_config = sentinel_config.node_password("secret").node_db(2);
+    assert_eq!(
+        node_url(true, "::1", 6380, &sentinel_config),
+        "rediss://:secret@[::1]:6380/2"
+    );
+
+    let sentinel_config = sentinel_config.node_password("p@ss:w/rd%");
+    assert_eq!(
+        node_url(false, "10.0.0.1", 6379, &sentinel_config),
+        "redis://:p%40ss%3Aw%2Frd%25@10.0.0.1:6379/2"
+    );
+}
+
+#[test]
+fn the_master_is_kept_if_only_the_replicas_change_and_replaced_pools_are_drained() {
+    use std::sync::atomic::AtomicUsize;
+
+    use crate::connection_factory::NewConnection;
+
+    struct NodeFactory {
+        node: String,
+        closed: Arc<AtomicUsize>,
+    }
+
+    impl ConnectionFactory for NodeFactory {
+        type Connection = ();
+        fn create_connection(&self) -> NewConnection<Self::Connection> {
+            NewConnection::new(future::ok(()))
+        }
+        fn connecting_to(&self) -> &str {
+            &self.node
+        }
+        fn close(&self, _conn: ()) -> BoxFuture<'static, ()> {
+            self.closed.fetch_add(1, Ordering::SeqCst);
+            future::ready(()).boxed()
+        }
+    }
+
+    fn discovered(master: &str, replicas: &[&str]) -> Discovered {
+        Discovered {
+            master: master.to_string(),
+            replicas: replicas.iter().map(|r| r.to_string()).collect(),
+        }
+    }
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let fut = async {
+        let closed = Arc::new(AtomicUsize::new(0));
+        let config = Arc::new(RwLock::new(Config::default().desired_pool_size(1)));
+        let create_connection_factory = {
+            let closed = Arc::clone(&closed);
+            move |node| {
+                Ok(NodeFactory {
+                    node,
+                    closed: Arc::clone(&closed),
+                })
+            }
+        };
+        let inner = Inner {
+            current: RwLock::new(None),
+            create_pool: create_pool(
+                Arc::clone(&config),
+                create_connection_factory,
+                ExecutorFlavour::Runtime,
+                InstrumentationFlavour::NoInstrumentation,
+                None,
+            ),
+            config,
+            executor_flavour: ExecutorFlavour::Runtime,
+            connect_to_replicas: true,
+            stop_watching: Mutex::new(None),
+            shutting_down: AtomicBool::new(false),
+        };
+        let pool = SentinelPool {
+            inner: Arc::new(inner),
+        };
+
+        pool.inner.update_nodes(discovered("A", &["B"])).unwrap();
+        assert_eq!(pool.connected_to(), vec!["A", "B"]);
+        let master = Arc::clone(&pool.sub_pools()[0]);
+
+        pool.inner
+            .update_nodes(discovered("A", &["C", "B"]))
+            .unwrap();
+        let mut connected_to = pool.connected_to();
+        connected_to[1..].sort();
+        assert_eq!(connected_to, vec!["A", "B", "C"]);
+        assert!(Arc::ptr_eq(&master, &pool.sub_pools()[0]), "master kept");
+
+        time::sleep(Duration::from_millis(50)).await;
+        pool.inner.update_nodes(discovered("A", &["C"])).unwrap();
+        assert_eq!(pool.connected_to(), vec!["A", "C"]);
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(closed.load(Ordering::SeqCst), 1, "removed replica drained");
+
+        pool.inner.update_nodes(discovered("A", &[])).unwrap();
+        assert_eq!(pool.connected_to(), vec!["A"]);
+        assert!(Arc::ptr_eq(&master, &pool.sub_pools()[0]), "master kept");
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(closed.load(Ordering::SeqCst), 2, "last replica drained");
+
+        pool.inner.update_nodes(discovered("D", &[])).unwrap();
+        assert_eq!(pool.connected_to(), vec!["D"]);
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(closed.load(Ordering::SeqCst), 3, "replaced pool drained");
+    };
+
+    runtime
+        .block_on(async { time::timeout(Duration::from_secs(5), fut).await })
+        .unwrap();
+}
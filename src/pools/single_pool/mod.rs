@@ -2,11 +2,11 @@
 use std::sync::Arc;
 use std::time::Instant;
 
-use futures::future::{self, Future};
 use log::info;
+use std::future::Future;
 
-use crate::config::Config;
-use crate::connection_factory::ConnectionFactory;
+use crate::config::{Config, Reconfiguration};
+use crate::connection_factory::{ConnectionFactory, ConnectionInitializer};
 use crate::error::{InitializationError, InitializationResult};
 use crate::executor_flavour::ExecutorFlavour;
 use crate::instrumentation::{InstrumentationFlavour, PoolId};
@@ -30,10 +30,34 @@ pub(crate) struct SinglePool<T: Poolable> {
 
 impl<T: Poolable> SinglePool<T> {
     pub fn new<F, CF>(
+        config: Config,
+        create_connection_factory: F,
+        executor_flavour: ExecutorFlavour,
+        instrumentation: InstrumentationFlavour,
+        connection_initializer: Option<ConnectionInitializer<T>>,
+    ) -> InitializationResult<SinglePool<T>>
+    where
+        CF: ConnectionFactory<Connection = T> + Send + Sync + 'static,
+        F: Fn(String) -> InitializationResult<CF>,
+    {
+        Self::with_pool_id(
+            config,
+            create_connection_factory,
+            executor_flavour,
+            instrumentation,
+            connection_initializer,
+            PoolId::new(0),
+        )
+    }
+
+    /// Creates a pool which reports to the instrumentation as `pool_id`
+    pub fn with_pool_id<F, CF>(
         mut config: Config,
         create_connection_factory: F,
         executor_flavour: ExecutorFlavour,
         instrumentation: InstrumentationFlavour,
+        connection_initializer: Option<ConnectionInitializer<T>>,
+        pool_id: PoolId,
     ) -> InitializationResult<SinglePool<T>>
     where
         CF: ConnectionFactory<Connection = T> + Send + Sync + 'static,
@@ -47,10 +71,23 @@ impl<T: Poolable> SinglePool<T> {
 
         let pool_conf = PoolConfig {
             desired_pool_size: config.desired_pool_size,
+            max_pool_size: config.max_pool_size,
+            surplus_idle_timeout: config.surplus_idle_timeout,
+            idle_timeout: config.idle_timeout,
+            max_connection_lifetime: config.max_connection_lifetime,
+            connection_lifetime_jitter: config.connection_lifetime_jitter,
+            validation_policy: config.validation_policy,
+            validation_timeout: config.validation_timeout,
             backoff_strategy: config.backoff_strategy,
             reservation_limit: config.reservation_limit,
             activation_order: config.activation_order,
             checkout_queue_size: config.checkout_queue_size,
+            circuit_breaker_threshold: config.circuit_breaker_threshold,
+            circuit_breaker_open_duration: config.circuit_breaker_open_duration,
+            health_check_interval: config.health_check_interval,
+            health_check_failure_threshold: config.health_check_failure_threshold,
+            reserved_for_high_priority: config.reserved_for_high_priority,
+            leak_detection_threshold: config.leak_detection_threshold,
         };
 
         let connection_factory = if config.connect_to_nodes.len() == 1 {
@@ -72,7 +109,8 @@ impl<T: Poolable> SinglePool<T> {
             pool_conf,
             connection_factory,
             executor_flavour,
-            PoolInstrumentation::new(instrumentation, PoolId::new(0)),
+            PoolInstrumentation::new(instrumentation, pool_id),
+            connection_initializer,
         );
 
         Ok(SinglePool {
@@ -88,18 +126,28 @@ impl<T: Poolable> SinglePool<T> {
         self.pool.state()
     }
 
-    pub fn ping(&self, timeout: Instant) -> impl Future<Item = Ping, Error = ()> + Send {
+    pub fn sub_pools(&self) -> Vec<Arc<PoolInternal<T>>> {
+        vec![Arc::clone(&self.pool)]
+    }
+
+    pub fn ping(&self, timeout: Instant) -> impl Future<Output = Result<Ping, ()>> + Send {
         self.pool.ping(timeout)
     }
+
+    pub fn reconfigure(&self, reconfiguration: Reconfiguration) {
+        self.pool.reconfigure(reconfiguration)
+    }
+
+    pub fn shutdown(&self, deadline: Instant) -> impl Future<Output = ()> + Send {
+        self.pool.shutdown(deadline)
+    }
 }
 
 impl<T: Poolable> CanCheckout<T> for SinglePool<T> {
     fn check_out<M: Into<CheckoutConstraint>>(&self, constraint: M) -> CheckoutManaged<T> {
         match self.pool.check_out(constraint) {
             Ok(checkout_managed) => checkout_managed,
-            Err(error_package) => {
-                CheckoutManaged::new(future::err(error_package.error_kind.into()))
-            }
+            Err(error_package) => CheckoutManaged::error(error_package.error),
         }
     }
 }
@@ -0,0 +1,101 @@
+//! Talking to a Redis Cluster
+use redis::{ErrorKind, RedisError, RedisResult, Value};
+
+use crate::pool_connection::ConnectionFlavour;
+
+/// A range of hash slots served by a master
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotRange {
+    pub start: u16,
+    pub end: u16,
+    pub host: String,
+    pub port: u16,
+}
+
+/// Ask a node of the cluster which master serves which slots
+pub async fn get_slot_ranges(conn: &mut ConnectionFlavour) -> RedisResult<Vec<SlotRange>> {
+    let value: Value = redis::cmd("CLUSTER").arg("SLOTS").query_async(conn).await?;
+    parse_slot_ranges(&value)
+}
+
+/// Parses the reply of `CLUSTER SLOTS`.
+///
+/// Each entry has the format `[start, end, [host, port, ...], replicas...]`.
+fn parse_slot_ranges(value: &Value) -> RedisResult<Vec<SlotRange>> {
+    let invalid = || RedisError::from((ErrorKind::TypeError, "invalid reply to CLUSTER SLOTS"));
+
+    let entries = match value {
+        Value::Bulk(entries) => entries,
+        _ => return Err(invalid()),
+    };
+
+    entries
+        .iter()
+        .map(|entry| match entry {
+            Value::Bulk(items) if items.len() >= 3 => {
+                let start = redis::from_redis_value(&items[0])?;
+                let end = redis::from_redis_value(&items[1])?;
+                let (host, port) = match items[2] {
+                    Value::Bulk(ref master) if master.len() >= 2 => (
+                        redis::from_redis_value(&master[0])?,
+                        redis::from_redis_value(&master[1])?,
+                    ),
+                    _ => return Err(invalid()),
+                };
+                Ok(SlotRange {
+                    start,
+                    end,
+                    host,
+                    port,
+                })
+            }
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+/// Returns `true` if the error is a `MOVED` or `ASK` redirection
+pub fn is_redirection(err: &RedisError) -> bool {
+    matches!(err.code(), Some("MOVED") | Some("ASK"))
+}
+
+#[test]
+fn the_reply_to_cluster_slots_is_parsed() {
+    let value = Value::Bulk(vec![
+        Value::Bulk(vec![
+            Value::Int(0),
+            Value::Int(5460),
+            Value::Bulk(vec![
+                Value::Data(b"10.0.0.1".to_vec()),
+                Value::Int(7000),
+                Value::Data(b"09dbe9720cda62f7865eabc5fd8857c5d2678366".to_vec()),
+            ]),
+            Value::Bulk(vec![Value::Data(b"10.0.0.4".to_vec()), Value::Int(7003)]),
+        ]),
+        Value::Bulk(vec![
+            Value::Int(5461),
+            Value::Int(16383),
+            Value::Bulk(vec![Value::Data(b"10.0.0.2".to_vec()), Value::Int(7001)]),
+        ]),
+    ]);
+
+    let slot_ranges = parse_slot_ranges(&value).unwrap();
+
+    assert_eq!(
+        slot_ranges,
+        vec![
+            SlotRange {
+                start: 0,
+                end: 5460,
+                host: "10.0.0.1".to_string(),
+                port: 7000,
+            },
+            SlotRange {
+                start: 5461,
+                end: 16383,
+                host: "10.0.0.2".to_string(),
+                port: 7001,
+            },
+        ]
+    );
+}
@@ -0,0 +1,198 @@
+//! Resolving the addresses of a node
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use trust_dns_resolver::TokioAsyncResolver;
+
+/// Addresses are not looked up again before this
+/// has elapsed even if their TTL is shorter
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Resolves the host of a node and keeps track of its addresses.
+///
+/// The resolver is created once and caches the addresses
+/// as long as their TTL allows it.
+///
+/// The generation is increased each time the resolved addresses
+/// differ from the ones resolved before.
+pub(crate) struct Dns {
+    host: String,
+    resolver: Mutex<Option<TokioAsyncResolver>>,
+    resolved: Mutex<Resolved>,
+    refreshing: AtomicBool,
+    generation: AtomicU64,
+}
+
+#[derive(Default)]
+struct Resolved {
+    /// Sorted so that they can be compared
+    addrs: Vec<IpAddr>,
+    valid_until: Option<Instant>,
+}
+
+impl Dns {
+    pub fn new(host: String) -> Self {
+        Self {
+            host,
+            resolver: Mutex::new(None),
+            resolved: Mutex::new(Resolved::default()),
+            refreshing: AtomicBool::new(false),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Look up the addresses of the host.
+    ///
+    /// The addresses are returned in the order in which connection
+    /// attempts should be made.
+    pub async fn resolve(&self) -> Result<Vec<IpAddr>, String> {
+        let resolver = self.resolver()?;
+
+        let lookup = resolver
+            .lookup_ip(self.host.as_str())
+            .await
+            .map_err(|err| format!("Failed to look up address: {}", err))?;
+
+        let addrs: Vec<IpAddr> = lookup.iter().collect();
+        if addrs.is_empty() {
+            return Err("No addresses were returned".to_string());
+        }
+
+        self.update(&addrs, lookup.valid_until());
+
+        Ok(connect_order(addrs))
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Look up the addresses in the background once they
+    /// are no longer valid.
+    ///
+    /// Nothing happens if there is no runtime to run the lookup on.
+    pub fn refresh_if_expired(self: &Arc<Self>) {
+        let expired = self
+            .resolved
+            .lock()
+            .unwrap()
+            .valid_until
+            .map(|valid_until| valid_until <= Instant::now())
+            .unwrap_or(false);
+
+        if !expired || self.refreshing.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => {
+                self.refreshing.store(false, Ordering::SeqCst);
+                return;
+            }
+        };
+
+        let dns = Arc::clone(self);
+        handle.spawn(async move {
+            if let Err(err) = dns.resolve().await {
+                warn!("Refreshing the addresses of '{}' failed: {}", dns.host, err);
+            }
+            dns.refreshing.store(false, Ordering::SeqCst);
+        });
+    }
+
+    fn resolver(&self) -> Result<TokioAsyncResolver, String> {
+        let mut resolver = self.resolver.lock().unwrap();
+        if let Some(ref resolver) = *resolver {
+            return Ok(resolver.clone());
+        }
+
+        let new_resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .map_err(|err| format!("Cannot create resolver: {}", err))?;
+        *resolver = Some(new_resolver.clone());
+        Ok(new_resolver)
+    }
+
+    fn update(&self, addrs: &[IpAddr], valid_until: Instant) {
+        let mut addrs = addrs.to_vec();
+        addrs.sort();
+
+        let mut resolved = self.resolved.lock().unwrap();
+        resolved.valid_until = Some(std::cmp::max(
+            valid_until,
+            Instant::now() + MIN_REFRESH_INTERVAL,
+        ));
+
+        if resolved.addrs == addrs {
+            return;
+        }
+
+        if resolved.addrs.is_empty() {
+            debug!("'{}' resolved to {:?}", self.host, addrs);
+        } else {
+            info!(
+                "addresses of '{}' changed from {:?} to {:?}",
+                self.host, resolved.addrs, addrs
+            );
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
+
+        resolved.addrs = addrs;
+    }
+}
+
+/// Alternate between the address families starting with the
+/// family of the first address like "Happy Eyeballs" (RFC 8305) does.
+fn connect_order(addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+    let prefer_v6 = addrs.first().map(IpAddr::is_ipv6).unwrap_or(false);
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == prefer_v6);
+
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+#[test]
+fn address_families_alternate_starting_with_the_first_one() {
+    let addrs: Vec<IpAddr> = vec![
+        "::1".parse().unwrap(),
+        "::2".parse().unwrap(),
+        "::3".parse().unwrap(),
+        "10.0.0.1".parse().unwrap(),
+    ];
+
+    let expected: Vec<IpAddr> = vec![
+        "::1".parse().unwrap(),
+        "10.0.0.1".parse().unwrap(),
+        "::2".parse().unwrap(),
+        "::3".parse().unwrap(),
+    ];
+
+    assert_eq!(connect_order(addrs), expected);
+}
+
+#[test]
+fn a_change_of_addresses_increases_the_generation() {
+    let dns = Dns::new("localhost".to_string());
+    let later = Instant::now() + Duration::from_secs(60);
+
+    dns.update(&["10.0.0.1".parse().unwrap()], later);
+    assert_eq!(dns.generation(), 0);
+
+    dns.update(&["10.0.0.1".parse().unwrap()], later);
+    assert_eq!(dns.generation(), 0);
+
+    dns.update(&["10.0.0.2".parse().unwrap()], later);
+    assert_eq!(dns.generation(), 1);
+}
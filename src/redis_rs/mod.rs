@@ -1,155 +1,427 @@
 use std::error::Error;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use futures::future::{self, Future, IntoFuture};
-use redis::IntoConnectionInfo;
-use tokio::timer::Timeout;
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::debug;
+use redis::{ConnectionInfo, IntoConnectionInfo, RedisResult};
+use tokio::time;
 
-use crate::connection_factory::{ConnectionFactory, NewConnection, NewConnectionError};
-use crate::error::InitializationResult;
-use crate::executor_flavour::ExecutorFlavour;
+use crate::config::TlsConfig;
+use crate::connection_factory::{ConnectionFactory, NewConnection};
+use crate::error::{InitializationError, InitializationResult};
 use crate::pool_connection::ConnectionFlavour;
 use crate::{Ping, PingState};
 
+#[cfg(feature = "tls")]
+pub use self::tls::TlsConnection;
+#[cfg(feature = "tls")]
+use self::tls::TlsSettings;
+
+use self::dns::Dns;
+
+pub(crate) mod cluster;
+mod dns;
+pub(crate) mod sentinel;
+#[cfg(feature = "tls")]
+mod tls;
+
+const TLS_SCHEME: &str = "rediss://";
+
+/// The time to wait for a connection to an address
+/// before also trying the next one
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Clone)]
 pub struct RedisRsFactory {
     connects_to: Arc<String>,
+    /// `None` for unix domain sockets
+    dns: Option<Arc<Dns>>,
+    recycle_on_address_change: bool,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsSettings>,
 }
 
 impl RedisRsFactory {
-    pub fn new(connect_to: String) -> InitializationResult<Self> {
+    #[cfg(feature = "tls")]
+    pub fn new(connect_to: String, tls: &TlsConfig) -> InitializationResult<Self> {
+        let tls = if connect_to.starts_with(TLS_SCHEME) {
+            let url =
+                redis::parse_redis_url(&without_tls_scheme(&connect_to)).ok_or_else(|| {
+                    InitializationError::message_only(format!("Invalid redis url: {}", connect_to))
+                })?;
+            let host = url.host_str().ok_or_else(|| {
+                InitializationError::message_only(format!(
+                    "Redis url has no host part: {}",
+                    connect_to
+                ))
+            })?;
+            Some(TlsSettings::new(tls, host)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            dns: dns_for(&connect_to),
+            connects_to: (Arc::new(connect_to)),
+            recycle_on_address_change: false,
+            tls,
+        })
+    }
+
+    #[cfg(not(feature = "tls"))]
+    pub fn new(connect_to: String, _tls: &TlsConfig) -> InitializationResult<Self> {
+        if connect_to.starts_with(TLS_SCHEME) {
+            return Err(InitializationError::message_only(format!(
+                "connecting via TLS requires the feature 'tls': {}",
+                connect_to
+            )));
+        }
+
         Ok(Self {
+            dns: dns_for(&connect_to),
             connects_to: (Arc::new(connect_to)),
+            recycle_on_address_change: false,
         })
     }
+
+    /// If `true` pooled connections are replaced once they are checked in
+    /// after the resolved addresses of the node changed.
+    ///
+    /// The addresses are looked up again once their TTL expired.
+    pub fn recycle_on_address_change(mut self, v: bool) -> Self {
+        self.recycle_on_address_change = v;
+        self
+    }
+
+    #[cfg(feature = "tls")]
+    async fn connect(&self, connection_info: ConnectionInfo) -> RedisResult<ConnectionFlavour> {
+        let connects_to = Arc::clone(&self.connects_to);
+        if let Some(ref tls) = self.tls {
+            let connection = tls.connect(connection_info).await?;
+            Ok(ConnectionFlavour::Tls(connection, connects_to))
+        } else {
+            let connection = redis::Client::open(connection_info)?
+                .get_tokio_connection()
+                .await?;
+            Ok(ConnectionFlavour::RedisRs(connection, connects_to))
+        }
+    }
+
+    #[cfg(not(feature = "tls"))]
+    async fn connect(&self, connection_info: ConnectionInfo) -> RedisResult<ConnectionFlavour> {
+        let connects_to = Arc::clone(&self.connects_to);
+        let connection = redis::Client::open(connection_info)?
+            .get_tokio_connection()
+            .await?;
+        Ok(ConnectionFlavour::RedisRs(connection, connects_to))
+    }
+
+    /// Connects to the first address which accepts a connection.
+    ///
+    /// Like "Happy Eyeballs" (RFC 8305) does, the next address is tried
+    /// once an attempt failed or did not succeed within
+    /// `CONNECTION_ATTEMPT_DELAY` while the earlier attempts continue.
+    /// So an address which does not answer at all does not delay
+    /// connecting to the other ones until the connect times out.
+    async fn connect_to_any(
+        &self,
+        connection_infos: Vec<ConnectionInfo>,
+    ) -> Result<ConnectionFlavour, Box<dyn Error + Send + Sync>> {
+        let mut last_err: Box<dyn Error + Send + Sync> = "No addresses to connect to".into();
+        let mut connection_infos = connection_infos.into_iter();
+        let mut attempts = FuturesUnordered::new();
+
+        loop {
+            if let Some(connection_info) = connection_infos.next() {
+                attempts.push(self.connect(connection_info));
+            }
+
+            let more_addresses = connection_infos.len() > 0;
+            tokio::select! {
+                attempt = attempts.next() => match attempt {
+                    Some(Ok(connection)) => return Ok(connection),
+                    Some(Err(err)) => {
+                        debug!("connecting to '{}' failed: {}", self.connects_to, err);
+                        last_err = err.into();
+                    }
+                    None => return Err(last_err),
+                },
+                _ = time::sleep(CONNECTION_ATTEMPT_DELAY), if more_addresses => {
+                    debug!(
+                        "connecting to '{}' takes longer than {:?} - trying the next address",
+                        self.connects_to, CONNECTION_ATTEMPT_DELAY
+                    );
+                }
+            }
+        }
+    }
+
+    /// Resolves the host of the URL this factory connects to and
+    /// returns a connection info for each of the resolved addresses
+    /// in the order in which they should be tried.
+    async fn resolve(&self) -> Result<Vec<ConnectionInfo>, Box<dyn Error + Send + Sync>> {
+        let url = redis::parse_redis_url(&without_tls_scheme(&self.connects_to))
+            .ok_or_else(|| format!("Invalid redis url: {}", self.connects_to))?;
+
+        // There is nothing to resolve for unix domain sockets
+        let dns = match self.dns {
+            Some(ref dns) if !is_unix_socket_scheme(url.scheme()) => dns,
+            _ => return Ok(vec![into_connection_info(url)?]),
+        };
+
+        let mut connection_infos = Vec::new();
+        for addr in dns.resolve().await? {
+            let mut url = url.clone();
+            url.set_ip_host(addr).ok();
+            connection_infos.push(into_connection_info(url)?);
+        }
+
+        Ok(connection_infos)
+    }
+}
+
+fn into_connection_info<U: IntoConnectionInfo>(url: U) -> Result<ConnectionInfo, String> {
+    url.into_connection_info()
+        .map_err(|err| format!("Failed to turn redis url into connection info: {}", err))
+}
+
+/// Returns the `Dns` for the host of `connect_to` unless
+/// it is the URL of a unix domain socket
+fn dns_for(connect_to: &str) -> Option<Arc<Dns>> {
+    let url = redis::parse_redis_url(&without_tls_scheme(connect_to))?;
+    if is_unix_socket_scheme(url.scheme()) {
+        return None;
+    }
+    let host = url.host_str()?;
+    // IPv6 hosts are enclosed in brackets within an URL
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Some(Arc::new(Dns::new(host.to_string())))
+}
+
+fn is_unix_socket_scheme(scheme: &str) -> bool {
+    scheme == "unix" || scheme == "redis+unix"
+}
+
+/// Returns the path of the socket if `connect_to` is
+/// an URL for a unix domain socket
+fn unix_socket_path(connect_to: &str) -> Option<String> {
+    let url = redis::parse_redis_url(connect_to)?;
+    if is_unix_socket_scheme(url.scheme()) {
+        Some(url.path().to_string())
+    } else {
+        None
+    }
+}
+
+/// The redis crate does not know about `rediss://` so we
+/// parse TLS URLs as plain URLs.
+pub(crate) fn without_tls_scheme(connect_to: &str) -> String {
+    if let Some(rest) = connect_to.strip_prefix(TLS_SCHEME) {
+        format!("redis://{}", rest)
+    } else {
+        connect_to.to_string()
+    }
 }
 
 impl ConnectionFactory for RedisRsFactory {
     type Connection = ConnectionFlavour;
 
     fn create_connection(&self) -> NewConnection<Self::Connection> {
-        let connects_to1 = self.connects_to.clone();
-        let connects_to2 = self.connects_to.clone();
-
-        // FIXME: Doesn't work with URLs without host (e.g. unix sockets)
-        // This should ideally be implemented in the redis crate.
-        let connection_future = future::lazy(move || -> Result<_, Box<dyn Error + Send + Sync>> {
-            let mut url = redis::parse_redis_url(&connects_to1)
-                .map_err(|_| format!("Invalid redis url: {}", connects_to1))?;
-
-            let (resolver, background_task) = trust_dns_resolver::AsyncResolver::from_system_conf()
-                .map_err(|err| format!("Cannot create resolver: {}", err))?;
-
-            ExecutorFlavour::Runtime
-                .spawn(background_task)
-                .map_err(|err| format!("Failed to spawn resolver background task: {}", err))?;
-
-            let host = url.host_str().ok_or_else(|| "Redis url has no host part")?;
-
-            let url_future = resolver
-                .lookup_ip(host)
-                .map_err(|err| format!("Failed to look up address: {}", err))
-                .map_err(Box::<dyn Error + Send + Sync>::from)
-                .and_then(|addrs| {
-                    addrs
-                        .into_iter()
-                        .next()
-                        .ok_or_else(|| "No addresses were returned")
-                        .into_future()
-                        .from_err()
-                })
-                .map(move |addr| {
-                    url.set_ip_host(addr).ok();
-                    url
-                });
-
-            Ok(url_future)
-        })
-        .flatten()
-        .and_then(|url| {
-            url.into_connection_info()
-                .map_err(|err| format!("Failed to turn redis url into connection info: {}", err))
-                .into_future()
-                .from_err()
-        })
-        .and_then(|connection_info| redis::aio::connect(connection_info).from_err())
-        .map(|connection| ConnectionFlavour::RedisRs(connection, connects_to2))
-        .map_err(NewConnectionError::from);
+        let factory = self.clone();
 
-        NewConnection::new(connection_future)
+        NewConnection::new(async move {
+            let connection_infos = factory.resolve().await?;
+            Ok(factory.connect_to_any(connection_infos).await?)
+        })
     }
 
     fn connecting_to(&self) -> &str {
-        &*self.connects_to
+        &self.connects_to
+    }
+
+    fn generation(&self) -> u64 {
+        match self.dns {
+            Some(ref dns) if self.recycle_on_address_change => {
+                dns.refresh_if_expired();
+                dns.generation()
+            }
+            _ => 0,
+        }
     }
 
-    fn ping(&self, timeout: Instant) -> Box<dyn Future<Item = Ping, Error = ()> + Send> {
+    fn validate(
+        &self,
+        mut conn: Self::Connection,
+    ) -> BoxFuture<'static, Result<Self::Connection, ()>> {
+        use crate::commands::Commands;
+
+        let connects_to = Arc::clone(&self.connects_to);
+        async move {
+            match conn.ping().await {
+                Ok(()) => Ok(conn),
+                Err(err) => {
+                    debug!(
+                        "validation of connection to '{}' failed: {}",
+                        connects_to, err
+                    );
+                    Err(())
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn close(&self, mut conn: Self::Connection) -> BoxFuture<'static, ()> {
+        let connects_to = Arc::clone(&self.connects_to);
+        async move {
+            if let Err(err) = redis::cmd("QUIT").query_async::<_, ()>(&mut conn).await {
+                debug!("closing connection to '{}' failed: {}", connects_to, err);
+            }
+        }
+        .boxed()
+    }
+
+    fn ping(&self, timeout: Instant) -> BoxFuture<'static, Result<Ping, ()>> {
         use crate::commands::Commands;
 
         let started_at = Instant::now();
 
-        let uri = self.connecting_to().to_string();
-
-        let f = self
-            .create_connection()
-            .then({
-                let uri = uri.clone();
-                move |conn| {
-                    let connect_time = Some(started_at.elapsed());
-                    match conn {
-                        Ok(conn) => {
-                            let ping_started_at = Instant::now();
-                            let f = conn
-                                .ping()
-                                .then(move |r| {
-                                    let latency = Some(ping_started_at.elapsed());
-                                    let state = if let Err(err) = r {
-                                        PingState::failed_msg(format!("ping failed: {}", err))
-                                    } else {
-                                        PingState::Ok
-                                    };
-
-                                    Ok(Ping {
-                                        uri,
-                                        state,
-                                        connect_time,
-                                        latency,
-                                        total_time: started_at.elapsed(),
-                                    })
-                                })
-                                .map_err(|_: ()| ());
-                            Box::new(f) as Box<dyn Future<Item = _, Error = _> + Send>
-                        }
-                        Err(err) => Box::new(future::ok(Ping {
+        let uri = unix_socket_path(self.connecting_to())
+            .unwrap_or_else(|| self.connecting_to().to_string());
+
+        let new_connection = self.create_connection();
+        let f = {
+            let uri = uri.clone();
+            async move {
+                let conn = new_connection.await;
+                let connect_time = Some(started_at.elapsed());
+                match conn {
+                    Ok(mut conn) => {
+                        let ping_started_at = Instant::now();
+                        let state = if let Err(err) = conn.ping().await {
+                            PingState::failed_msg(format!("ping failed: {}", err))
+                        } else {
+                            PingState::Ok
+                        };
+
+                        Ping {
                             uri,
-                            state: PingState::failed_msg(format!(
-                                "failed to create connection: {}",
-                                err
-                            )),
+                            state,
                             connect_time,
-                            latency: None,
+                            latency: Some(ping_started_at.elapsed()),
                             total_time: started_at.elapsed(),
-                        })),
+                        }
                     }
+                    Err(err) => Ping {
+                        uri,
+                        state: PingState::failed_msg(format!(
+                            "failed to create connection: {}",
+                            err
+                        )),
+                        connect_time,
+                        latency: None,
+                        total_time: started_at.elapsed(),
+                    },
                 }
-            })
-            .map_err(|_| ());
-
-        let f = Timeout::new_at(f, timeout).or_else(move |_| {
-            let total_time = started_at.elapsed();
-            let state = PingState::failed_msg(format!(
-                "ping to '{}' timed out after {:?}",
-                uri, total_time
-            ));
-            Ok(Ping {
-                uri,
-                latency: None,
-                connect_time: None,
-                total_time,
-                state,
-            })
-        });
-
-        Box::new(f)
+            }
+        };
+
+        async move {
+            match time::timeout_at(timeout.into(), f).await {
+                Ok(ping) => Ok(ping),
+                Err(_elapsed) => {
+                    let total_time = started_at.elapsed();
+                    let state = PingState::failed_msg(format!(
+                        "ping to '{}' timed out after {:?}",
+                        uri, total_time
+                    ));
+                    Ok(Ping {
+                        uri,
+                        latency: None,
+                        connect_time: None,
+                        total_time,
+                        state,
+                    })
+                }
+            }
+        }
+        .boxed()
     }
 }
+
+#[test]
+fn unix_socket_path_is_extracted_from_socket_urls() {
+    assert_eq!(
+        unix_socket_path("unix:///var/run/redis.sock"),
+        Some("/var/run/redis.sock".to_string())
+    );
+    assert_eq!(
+        unix_socket_path("redis+unix:///var/run/redis.sock"),
+        Some("/var/run/redis.sock".to_string())
+    );
+}
+
+#[test]
+fn tcp_urls_have_no_unix_socket_path() {
+    assert_eq!(unix_socket_path("redis://127.0.0.1:6379"), None);
+}
+
+/// Accepts connections and answers each request with `reply` if given
+#[cfg(test)]
+async fn serve(reply: Option<&'static [u8]>) -> u16 {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0; 64];
+                while let Ok(n) = stream.read(&mut buf).await {
+                    if n == 0 {
+                        return;
+                    }
+                    if let Some(reply) = reply {
+                        stream.write_all(reply).await.unwrap();
+                    }
+                }
+            });
+        }
+    });
+    port
+}
+
+#[test]
+fn addresses_which_do_not_answer_do_not_delay_the_next_one() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let fut = async {
+        let silent = serve(None).await;
+        let answering = serve(Some(b"+OK\r\n")).await;
+
+        let factory = RedisRsFactory::new(
+            format!("redis://localhost:{}", answering),
+            &TlsConfig::default(),
+        )
+        .unwrap();
+        // The db makes redis-rs send a `SELECT` and wait for the reply.
+        // 10.255.255.1 is not routable so connecting either hangs or fails.
+        let connection_infos = vec![
+            into_connection_info("redis://10.255.255.1:6379/1").unwrap(),
+            into_connection_info(format!("redis://127.0.0.1:{}/1", silent)).unwrap(),
+            into_connection_info(format!("redis://127.0.0.1:{}/1", answering)).unwrap(),
+        ];
+
+        let started_at = Instant::now();
+        factory.connect_to_any(connection_infos).await.unwrap();
+        started_at.elapsed()
+    };
+
+    let elapsed = runtime
+        .block_on(async { time::timeout(Duration::from_secs(5), fut).await })
+        .unwrap();
+    assert!(elapsed < Duration::from_secs(2), "took {:?}", elapsed);
+}
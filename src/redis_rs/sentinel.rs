@@ -0,0 +1,185 @@
+//! Talking to Redis Sentinel
+use std::collections::HashMap;
+use std::pin::Pin;
+
+use futures::stream::{Stream, StreamExt};
+use redis::{Msg, RedisError, RedisResult};
+
+use crate::pool_connection::ConnectionFlavour;
+
+/// The channel on which the sentinels announce a new master
+const SWITCH_MASTER_CHANNEL: &str = "+switch-master";
+
+/// The channels on which the sentinels announce new replicas
+/// and replicas which went down or came back
+const REPLICA_CHANNELS: &[&str] = &["+slave", "+sdown", "-sdown"];
+
+/// Flags of a replica which indicate that it should not be used
+const UNUSABLE_REPLICA_FLAGS: &[&str] = &["s_down", "o_down", "disconnected"];
+
+/// The announcements received from a sentinel
+pub type Announcements = Pin<Box<dyn Stream<Item = Msg> + Send>>;
+
+/// Ask the sentinel for the address of the current master
+pub async fn get_master_addr(
+    conn: &mut ConnectionFlavour,
+    master_name: &str,
+) -> RedisResult<Option<(String, u16)>> {
+    redis::cmd("SENTINEL")
+        .arg("get-master-addr-by-name")
+        .arg(master_name)
+        .query_async(conn)
+        .await
+}
+
+/// Ask the sentinel for the addresses of all usable replicas of the master
+pub async fn get_replica_addrs(
+    conn: &mut ConnectionFlavour,
+    master_name: &str,
+) -> RedisResult<Vec<(String, u16)>> {
+    // `SLAVES` is still supported by all versions of Redis
+    // while `REPLICAS` is only supported since Redis 5
+    let replicas: Vec<HashMap<String, String>> = redis::cmd("SENTINEL")
+        .arg("slaves")
+        .arg(master_name)
+        .query_async(conn)
+        .await?;
+    Ok(replicas.iter().filter_map(usable_replica_addr).collect())
+}
+
+/// Subscribe to the announcements of new masters and, if
+/// `with_replicas` is `true`, to the announcements about replicas.
+///
+/// The connection is turned into a stream of the announcements
+/// which can be consumed with `next_announcement`.
+pub async fn subscribe_to_announcements(
+    conn: ConnectionFlavour,
+    with_replicas: bool,
+) -> RedisResult<Announcements> {
+    let conn = match conn {
+        ConnectionFlavour::RedisRs(conn, _) => conn,
+        #[cfg(feature = "tls")]
+        ConnectionFlavour::Tls(conn, _) => conn,
+    };
+
+    let mut pubsub = conn.into_pubsub();
+    pubsub.subscribe(SWITCH_MASTER_CHANNEL).await?;
+    if with_replicas {
+        for channel in REPLICA_CHANNELS {
+            pubsub.subscribe(*channel).await?;
+        }
+    }
+    Ok(Box::pin(pubsub.into_on_message()))
+}
+
+/// Wait until the sentinel announced something about `master_name`
+/// or one of its replicas and return the channel of the announcement.
+///
+/// Fails once the connection to the sentinel is closed.
+pub async fn next_announcement(
+    announcements: &mut Announcements,
+    master_name: &str,
+) -> RedisResult<String> {
+    while let Some(message) = announcements.next().await {
+        if is_announcement_for(&message, master_name) {
+            return Ok(message.get_channel_name().to_string());
+        }
+    }
+
+    Err(RedisError::from((
+        redis::ErrorKind::IoError,
+        "the connection to the sentinel was closed",
+    )))
+}
+
+/// Checks whether the message is an announcement for the given master.
+///
+/// The payload of a `+switch-master` announcement has the format
+/// `<master name> <old ip> <old port> <new ip> <new port>`.
+///
+/// The payload of the other announcements has the format
+/// `<instance type> <name> <ip> <port> @ <master name> <master ip> <master port>`
+/// where the part following the `@` is omitted if the instance is a master.
+fn is_announcement_for(message: &Msg, master_name: &str) -> bool {
+    let payload = match message.get_payload::<String>() {
+        Ok(payload) => payload,
+        Err(_) => return false,
+    };
+    let mut parts = payload.split_whitespace();
+
+    match message.get_channel_name() {
+        SWITCH_MASTER_CHANNEL => parts.next() == Some(master_name),
+        channel if REPLICA_CHANNELS.contains(&channel) => match parts.next() {
+            Some("master") => parts.next() == Some(master_name),
+            Some(_) => parts.skip_while(|part| *part != "@").nth(1) == Some(master_name),
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+fn usable_replica_addr(replica: &HashMap<String, String>) -> Option<(String, u16)> {
+    let flags = replica.get("flags").map(String::as_str).unwrap_or("");
+    if flags
+        .split(',')
+        .any(|flag| UNUSABLE_REPLICA_FLAGS.contains(&flag))
+    {
+        return None;
+    }
+
+    let ip = replica.get("ip")?;
+    let port = replica.get("port")?.parse().ok()?;
+
+    Some((ip.clone(), port))
+}
+
+#[cfg(test)]
+fn message(channel: &str, payload: &str) -> Msg {
+    use redis::Value;
+
+    Msg::from_value(&Value::Bulk(vec![
+        Value::Data(b"message".to_vec()),
+        Value::Data(channel.as_bytes().to_vec()),
+        Value::Data(payload.as_bytes().to_vec()),
+    ]))
+    .unwrap()
+}
+
+#[test]
+fn switch_master_for_the_master_is_detected() {
+    let message = message("+switch-master", "mymaster 10.0.0.1 6379 10.0.0.2 6379");
+
+    assert!(is_announcement_for(&message, "mymaster"));
+    assert!(!is_announcement_for(&message, "othermaster"));
+}
+
+#[test]
+fn announcements_about_replicas_of_the_master_are_detected() {
+    let replica_down = message(
+        "+sdown",
+        "slave 10.0.0.3:6380 10.0.0.3 6380 @ mymaster 10.0.0.1 6379",
+    );
+
+    assert!(is_announcement_for(&replica_down, "mymaster"));
+    assert!(!is_announcement_for(&replica_down, "othermaster"));
+
+    let master_up = message("-sdown", "master mymaster 10.0.0.1 6379");
+    assert!(is_announcement_for(&master_up, "mymaster"));
+}
+
+#[test]
+fn replicas_which_are_down_are_not_used() {
+    let mut replica = HashMap::new();
+    replica.insert("ip".to_string(), "10.0.0.3".to_string());
+    replica.insert("port".to_string(), "6380".to_string());
+    replica.insert("flags".to_string(), "slave".to_string());
+
+    assert_eq!(
+        usable_replica_addr(&replica),
+        Some(("10.0.0.3".to_string(), 6380))
+    );
+
+    replica.insert("flags".to_string(), "slave,s_down".to_string());
+
+    assert_eq!(usable_replica_addr(&replica), None);
+}
@@ -0,0 +1,297 @@
+//! Connections to Redis secured with TLS
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+
+use native_tls::{Certificate, Identity};
+use redis::aio::AsyncStream;
+use redis::{ConnectionAddr, ConnectionInfo, ErrorKind, RedisError, RedisResult};
+use tokio::net::TcpStream;
+use tokio_native_tls::TlsConnector;
+
+use crate::config::TlsConfig;
+use crate::error::{InitializationError, InitializationResult};
+
+/// A connection to a Redis node secured with TLS
+///
+/// This is a regular asynchronous connection of redis-rs
+/// running on top of a TLS stream.
+pub type TlsConnection = redis::aio::Connection;
+
+/// Everything needed to establish TLS connections to a node
+#[derive(Clone)]
+pub(crate) struct TlsSettings {
+    connector: TlsConnector,
+    domain: String,
+}
+
+impl TlsSettings {
+    /// Creates the settings for connecting to `host`.
+    ///
+    /// Certificates and keys are read from the file system here
+    /// so that errors show up when the pool is created.
+    pub fn new(config: &TlsConfig, host: &str) -> InitializationResult<Self> {
+        let mut builder = native_tls::TlsConnector::builder();
+
+        if let Some(ref path) = config.ca_bundle {
+            let pem = fs::read(path).map_err(|err| {
+                InitializationError::new(format!("could not read {}", path.display()), Some(err))
+            })?;
+            let certificates = Certificate::stack_from_pem(&pem).map_err(|err| {
+                InitializationError::new(format!("invalid CA bundle {}", path.display()), Some(err))
+            })?;
+            if certificates.is_empty() {
+                return Err(InitializationError::message_only(format!(
+                    "no certificates in CA bundle {}",
+                    path.display()
+                )));
+            }
+            for certificate in certificates {
+                builder.add_root_certificate(certificate);
+            }
+        }
+
+        match (&config.client_certificate, &config.client_key) {
+            (Some(certificate_path), Some(key_path)) => {
+                let certificate = fs::read(certificate_path).map_err(|err| {
+                    InitializationError::new(
+                        format!("could not read {}", certificate_path.display()),
+                        Some(err),
+                    )
+                })?;
+                let key = fs::read(key_path).map_err(|err| {
+                    InitializationError::new(
+                        format!("could not read {}", key_path.display()),
+                        Some(err),
+                    )
+                })?;
+                let identity = Identity::from_pkcs8(&certificate, &key).map_err(|err| {
+                    InitializationError::new("invalid client certificate or key", Some(err))
+                })?;
+                builder.identity(identity);
+            }
+            (None, None) => {}
+            _ => {
+                return Err(InitializationError::message_only(
+                    "a client certificate and a client key must be given together",
+                ))
+            }
+        }
+
+        builder.danger_accept_invalid_hostnames(config.accept_invalid_hostnames);
+
+        let connector = builder
+            .build()
+            .map_err(|err| InitializationError::new("could not create TLS connector", Some(err)))?;
+
+        Ok(Self {
+            connector: connector.into(),
+            domain: config.domain.clone().unwrap_or_else(|| host.to_string()),
+        })
+    }
+
+    /// Opens a connection and performs the TLS handshake.
+    ///
+    /// The host of `connection_info` must already be resolved to an IP address.
+    /// Authentication and selecting the database is done by redis-rs.
+    pub async fn connect(&self, connection_info: ConnectionInfo) -> RedisResult<TlsConnection> {
+        let socket_addr = match connection_info.addr {
+            ConnectionAddr::Tcp(ref host, port) => {
+                match host
+                    .trim_matches(|c| c == '[' || c == ']')
+                    .parse::<IpAddr>()
+                {
+                    Ok(ip) => SocketAddr::new(ip, port),
+                    Err(_) => {
+                        return Err(RedisError::from((
+                            ErrorKind::InvalidClientConfig,
+                            "host was not resolved to an IP address",
+                        )))
+                    }
+                }
+            }
+            _ => {
+                return Err(RedisError::from((
+                    ErrorKind::InvalidClientConfig,
+                    "TLS is only supported on TCP connections",
+                )))
+            }
+        };
+
+        let stream = TcpStream::connect(&socket_addr).await?;
+        let stream = self
+            .connector
+            .connect(&self.domain, stream)
+            .await
+            .map_err(|err| RedisError::from(io::Error::other(err)))?;
+        let stream = Box::pin(stream) as Pin<Box<dyn AsyncStream + Send + Sync>>;
+
+        redis::aio::Connection::new(&connection_info.redis, stream).await
+    }
+}
+
+#[cfg(test)]
+fn test_file(name: &str) -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/tls")
+        .join(name)
+}
+
+#[cfg(test)]
+fn initialization_error(config: &TlsConfig) -> String {
+    match TlsSettings::new(config, "localhost") {
+        Ok(_) => panic!("the settings should be invalid"),
+        Err(err) => err.to_string(),
+    }
+}
+
+#[test]
+fn a_client_certificate_requires_a_key() {
+    let config = TlsConfig {
+        client_certificate: Some(test_file("localhost.pem")),
+        ..TlsConfig::default()
+    };
+
+    assert_eq!(
+        initialization_error(&config),
+        "a client certificate and a client key must be given together"
+    );
+}
+
+#[test]
+fn a_client_certificate_and_key_are_loaded() {
+    let config = TlsConfig {
+        client_certificate: Some(test_file("localhost.pem")),
+        client_key: Some(test_file("localhost.key")),
+        ..TlsConfig::default()
+    };
+
+    assert!(TlsSettings::new(&config, "localhost").is_ok());
+}
+
+#[test]
+fn an_unreadable_ca_bundle_is_rejected() {
+    let config = TlsConfig {
+        ca_bundle: Some(test_file("missing.pem")),
+        ..TlsConfig::default()
+    };
+
+    assert!(initialization_error(&config).starts_with("could not read"));
+}
+
+#[test]
+fn a_ca_bundle_without_certificates_is_rejected() {
+    let config = TlsConfig {
+        ca_bundle: Some(test_file("localhost.key")),
+        ..TlsConfig::default()
+    };
+
+    assert!(initialization_error(&config).starts_with("no certificates in CA bundle"));
+}
+
+#[test]
+fn the_domain_overrides_the_host() {
+    let settings = TlsSettings::new(&TlsConfig::default(), "10.0.0.1")
+        .ok()
+        .unwrap();
+    assert_eq!(settings.domain, "10.0.0.1");
+
+    let config = TlsConfig {
+        domain: Some("redis.example.com".to_string()),
+        ..TlsConfig::default()
+    };
+    let settings = TlsSettings::new(&config, "10.0.0.1").ok().unwrap();
+    assert_eq!(settings.domain, "redis.example.com");
+}
+
+/// Runs a TLS terminator with the self signed certificate
+/// in `tests/tls` which answers a single `PING`
+#[cfg(test)]
+async fn serve_ping() -> u16 {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    let certificate = fs::read(test_file("localhost.pem")).unwrap();
+    let key = fs::read(test_file("localhost.key")).unwrap();
+    let identity = Identity::from_pkcs8(&certificate, &key).unwrap();
+    let acceptor: tokio_native_tls::TlsAcceptor =
+        native_tls::TlsAcceptor::new(identity).unwrap().into();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = match acceptor.accept(stream).await {
+            Ok(stream) => stream,
+            Err(_) => return,
+        };
+        let mut request = Vec::new();
+        let mut buf = [0; 64];
+        while !request.ends_with(b"PING\r\n") {
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => request.extend_from_slice(&buf[..n]),
+            }
+        }
+        stream.write_all(b"+PONG\r\n").await.unwrap();
+    });
+
+    port
+}
+
+#[cfg(test)]
+fn connection_info(port: u16) -> ConnectionInfo {
+    ConnectionInfo {
+        addr: ConnectionAddr::Tcp("127.0.0.1".to_string(), port),
+        redis: Default::default(),
+    }
+}
+
+#[test]
+fn connects_to_a_tls_terminator_with_a_self_signed_certificate() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let config = TlsConfig {
+        ca_bundle: Some(test_file("localhost.pem")),
+        domain: Some("localhost".to_string()),
+        ..TlsConfig::default()
+    };
+    let settings = TlsSettings::new(&config, "127.0.0.1").ok().unwrap();
+
+    let fut = async {
+        let port = serve_ping().await;
+        let mut conn = settings.connect(connection_info(port)).await.unwrap();
+        redis::cmd("PING")
+            .query_async::<_, String>(&mut conn)
+            .await
+            .unwrap()
+    };
+
+    let pong = runtime
+        .block_on(async { tokio::time::timeout(std::time::Duration::from_secs(5), fut).await })
+        .unwrap();
+    assert_eq!(pong, "PONG");
+}
+
+#[test]
+fn the_certificate_is_verified_against_the_domain() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let config = TlsConfig {
+        ca_bundle: Some(test_file("localhost.pem")),
+        ..TlsConfig::default()
+    };
+    let settings = TlsSettings::new(&config, "127.0.0.1").ok().unwrap();
+
+    let fut = async {
+        let port = serve_ping().await;
+        settings.connect(connection_info(port)).await.err().unwrap()
+    };
+
+    let err = runtime
+        .block_on(async { tokio::time::timeout(std::time::Duration::from_secs(5), fut).await })
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::IoError);
+}
@@ -0,0 +1,94 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::time::Duration;
+
+/// Defines when connections are validated before they are used
+/// or put back into the pool.
+///
+/// A connection is validated by the `ConnectionFactory` which created it.
+/// For Redis connections this is a `PING`. Connections which fail the
+/// validation are discarded and replaced by a new connection.
+///
+/// The default is `ValidationPolicy::Never`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationPolicy {
+    /// Never validate connections
+    #[default]
+    Never,
+    /// Validate a connection before it is checked out
+    /// if it has been idle for at least the given `Duration`
+    OnCheckOutIfIdleFor(Duration),
+    /// Validate a connection every time before it is checked out
+    OnCheckOut,
+    /// Validate a connection every time it is returned to the pool
+    OnCheckIn,
+}
+
+impl ValidationPolicy {
+    pub(crate) fn validates_on_check_out(self, idle_for: Duration) -> bool {
+        match self {
+            ValidationPolicy::OnCheckOutIfIdleFor(min_idle) => idle_for >= min_idle,
+            ValidationPolicy::OnCheckOut => true,
+            ValidationPolicy::Never | ValidationPolicy::OnCheckIn => false,
+        }
+    }
+
+    pub(crate) fn validates_on_check_in(self) -> bool {
+        self == ValidationPolicy::OnCheckIn
+    }
+}
+
+impl fmt::Display for ValidationPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationPolicy::Never => write!(f, "never"),
+            ValidationPolicy::OnCheckOutIfIdleFor(d) => write!(f, "{}", d.as_millis()),
+            ValidationPolicy::OnCheckOut => write!(f, "on_check_out"),
+            ValidationPolicy::OnCheckIn => write!(f, "on_check_in"),
+        }
+    }
+}
+
+/// Parses a `ValidationPolicy`.
+///
+/// Valid values are `never`, `on_check_out`, `on_check_in` or
+/// a number of milliseconds for `OnCheckOutIfIdleFor`.
+impl std::str::FromStr for ValidationPolicy {
+    type Err = ParseValidationPolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &*s.to_lowercase() {
+            "never" => Ok(ValidationPolicy::Never),
+            "on_check_out" => Ok(ValidationPolicy::OnCheckOut),
+            "on_check_in" => Ok(ValidationPolicy::OnCheckIn),
+            milliseconds => Ok(ValidationPolicy::OnCheckOutIfIdleFor(
+                Duration::from_millis(milliseconds.parse::<u64>().map_err(|_| {
+                    ParseValidationPolicyError(format!(
+                        "'{}' is not a valid ValidationPolicy. Only 'never', 'on_check_out', \
+                         'on_check_in' or milliseconds are allowed.",
+                        milliseconds
+                    ))
+                })?),
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseValidationPolicyError(String);
+
+impl fmt::Display for ParseValidationPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Could not parse ValidationPolicy. {}", self.0)
+    }
+}
+
+impl StdError for ParseValidationPolicyError {
+    fn description(&self) -> &str {
+        "parse validation policy failed"
+    }
+
+    fn cause(&self) -> Option<&dyn StdError> {
+        None
+    }
+}